@@ -223,7 +223,18 @@ pub unsafe extern "C" fn unhandled_interrupt() {
     panic!("Unhandled Interrupt. ISR {} is active.", interrupt_number);
 }
 
-/// Assembly function to initialize the .bss and .data sections in RAM.
+/// Tell the kernel where its stack is, so that it can report how much of it
+/// is used. Called by `initialize_ram_jump_to_main()` before `main()`.
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+unsafe extern "C" fn assign_kernel_stack() {
+    kernel::debug::assign_kernel_stack(
+        &_sstack as *const u32 as *const u8,
+        &_estack as *const u32 as *const u8,
+    );
+}
+
+/// Assembly function to fill the kernel stack and initialize the .bss and
+/// .data sections in RAM.
 ///
 /// We need to (unfortunately) do these operations in assembly because it is
 /// not valid to run Rust code without RAM initialized.
@@ -234,7 +245,22 @@ pub unsafe extern "C" fn unhandled_interrupt() {
 pub unsafe extern "C" fn initialize_ram_jump_to_main() {
     asm!(
         "
-    // Start by initializing .bss memory. The Tock linker script defines
+    // Fill the kernel stack with `kernel::debug::KERNEL_STACK_CANARY`, so that
+    // the kernel can find out how much of the stack it has used. Nothing is on
+    // the stack yet.
+    ldr r0, ={sstack}   // r0 = first address of the stack
+    ldr r1, ={estack}   // r1 = first address after the stack
+    ldr r2, =0xDEADC0DE // r2 = canary
+
+  300: // stack_paint_loop
+    cmp r1, r0          // Stop once r0 reaches the end of the stack.
+    beq 301f
+    stm r0!, {{r2}}     // *(r0) = r2, r0 += 1.
+    b 300b
+
+  301: // stack_paint_done
+
+    // Now initialize .bss memory. The Tock linker script defines
     // `_szero` and `_ezero` to mark the .bss segment.
     ldr r0, ={sbss}     // r0 = first address of .bss
     ldr r1, ={ebss}     // r1 = first address after .bss
@@ -271,10 +297,14 @@ pub unsafe extern "C" fn initialize_ram_jump_to_main() {
 
   201: // data_init_done
 
-    // Now that memory has been initialized, we can jump to main() where the
-    // board initialization takes place and Rust code starts.
+    // Now that memory has been initialized, tell the kernel where its stack
+    // is, and then jump to main() where the board initialization takes place.
+    bl {assign_kernel_stack}
     bl main
     ",
+        assign_kernel_stack = sym assign_kernel_stack,
+        sstack = sym _sstack,
+        estack = sym _estack,
         sbss = sym _szero,
         ebss = sym _ezero,
         sdata = sym _srelocate,
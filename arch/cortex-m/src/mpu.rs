@@ -326,6 +326,27 @@ impl CortexMRegion {
         }
     }
 
+    /// Create a region that denies all unprivileged accesses. `start` must be
+    /// aligned to `size`, which must be a power of two.
+    fn no_access(start: *const u8, size: usize, region_num: usize) -> CortexMRegion {
+        let base_address = RegionBaseAddress::ADDR.val((start as u32) >> 5)
+            + RegionBaseAddress::VALID::UseRBAR
+            + RegionBaseAddress::REGION.val(region_num as u32);
+
+        let size_value = math::log_base_two(size as u32) - 1;
+
+        let attributes = RegionAttributes::ENABLE::SET
+            + RegionAttributes::SIZE.val(size_value)
+            + RegionAttributes::AP::NoAccess
+            + RegionAttributes::XN::Disable;
+
+        CortexMRegion {
+            location: Some((start, size)),
+            base_address: base_address,
+            attributes: attributes,
+        }
+    }
+
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
@@ -544,6 +565,31 @@ impl<const NUM_REGIONS: usize, const MIN_REGION_SIZE: usize> mpu::MPU
         Ok(())
     }
 
+    fn allocate_guard_region(
+        &self,
+        memory_start: *const u8,
+        min_region_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        // Every region has a higher number than the app memory region, so it
+        // takes precedence where they overlap.
+        let region_num = config.unused_region_number()?;
+
+        // Without subregions, the region must be a power of two in size and
+        // start at a multiple of its size.
+        let size =
+            math::closest_power_of_two(cmp::max(min_region_size, MIN_REGION_SIZE) as u32) as usize;
+        let mut start = memory_start as usize;
+        if start % size != 0 {
+            start += size - (start % size);
+        }
+
+        config.regions[region_num] = CortexMRegion::no_access(start as *const u8, size, region_num);
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
     fn allocate_app_memory_region(
         &self,
         unallocated_memory_start: *const u8,
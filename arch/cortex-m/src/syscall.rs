@@ -431,4 +431,13 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    fn restore_context(
+        &self,
+        state: &mut CortexMStoredState,
+        stored: &[u8],
+    ) -> Result<(), ErrorCode> {
+        *state = CortexMStoredState::try_from(stored)?;
+        Ok(())
+    }
 }
@@ -24,6 +24,8 @@ extern "C" {
     // Where the end of the stack region is (and hence where the stack should
     // start).
     static _estack: usize;
+    // Where the stack region starts.
+    static _sstack: usize;
 
     // Boundaries of the .bss section.
     static mut _szero: usize;
@@ -42,14 +44,18 @@ extern "C" {
 
 /// Entry point of all programs (`_start`).
 ///
-/// This assembly does three functions:
+/// This assembly does four functions:
 ///
 /// 1. It initializes the stack pointer, the frame pointer (needed for closures
 ///    to work in start_rust) and the global pointer.
-/// 2. It initializes the .bss and .data RAM segments. This must be done before
-///    any Rust code runs. See https://github.com/tock/tock/issues/2222 for more
+/// 2. It fills the kernel stack with `kernel::debug::KERNEL_STACK_CANARY`, so
+///    that the kernel can find out how much of the stack it has used, and
+///    initializes the .bss and .data RAM segments. This must be done before any
+///    Rust code runs. See https://github.com/tock/tock/issues/2222 for more
 ///    information.
-/// 3. Finally it calls `main()`, the main entry point for Tock boards.
+/// 3. It tells the kernel where its stack is, so that it can report how much
+///    of it is used.
+/// 4. Finally it calls `main()`, the main entry point for Tock boards.
 #[cfg(all(target_arch = "riscv32", target_os = "none"))]
 #[link_section = ".riscv.start"]
 #[export_name = "_start"]
@@ -84,7 +90,21 @@ pub extern "C" fn _start() {
 
             // INITIALIZE MEMORY
 
-            // Start by initializing .bss memory. The Tock linker script defines
+            // Fill the kernel stack with a known value. Nothing is on the
+            // stack yet.
+            la a0, {sstack}             // a0 = first address of the stack
+            la a1, {estack}             // a1 = first address after the stack
+            li a2, 0xDEADC0DE           // a2 = canary
+
+          300: // stack_paint_loop
+            beq  a0, a1, 301f           // If a0 == a1, we are done.
+            sw   a2, 0(a0)              // *a0 = canary.
+            addi a0, a0, 4              // a0 = a0 + 4. Increment pointer to next word.
+            j 300b                      // Continue the loop.
+
+          301: // stack_paint_done
+
+            // Now initialize .bss memory. The Tock linker script defines
             // `_szero` and `_ezero` to mark the .bss segment.
             la a0, {sbss}               // a0 = first address of .bss
             la a1, {ebss}               // a1 = first address after .bss
@@ -116,12 +136,17 @@ pub extern "C" fn _start() {
 
           201: // data_init_done
 
+            // Tell the kernel where its stack is.
+            call {assign_kernel_stack}
+
             // With that initial setup out of the way, we now branch to the main
             // code, likely defined in a board's main.rs.
             j main
         ",
+        assign_kernel_stack = sym assign_kernel_stack,
         gp = sym __global_pointer,
         estack = sym _estack,
+        sstack = sym _sstack,
         sbss = sym _szero,
         ebss = sym _ezero,
         sdata = sym _srelocate,
@@ -132,6 +157,17 @@ pub extern "C" fn _start() {
     }
 }
 
+/// Tell the kernel where its stack is. Called by `_start` before `main()`.
+#[cfg(all(target_arch = "riscv32", target_os = "none"))]
+extern "C" fn assign_kernel_stack() {
+    unsafe {
+        kernel::debug::assign_kernel_stack(
+            &_sstack as *const usize as *const u8,
+            &_estack as *const usize as *const u8,
+        );
+    }
+}
+
 /// The various privilege levels in RISC-V.
 pub enum PermissionMode {
     User = 0x0,
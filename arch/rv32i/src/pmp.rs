@@ -181,6 +181,14 @@ impl PMPRegion {
         }
     }
 
+    /// Create a region that denies all user mode accesses.
+    fn no_access(start: *const u8, size: usize) -> PMPRegion {
+        PMPRegion {
+            location: (start, size),
+            cfg: pmpcfg::r::CLEAR + pmpcfg::w::CLEAR + pmpcfg::x::CLEAR + pmpcfg::a::TOR,
+        }
+    }
+
     fn location(&self) -> (*const u8, usize) {
         self.location
     }
@@ -397,6 +405,42 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::platform::mpu::MPU
         Ok(())
     }
 
+    fn allocate_guard_region(
+        &self,
+        memory_start: *const u8,
+        min_region_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let mut region_num = config.unused_region_number(self.locked_region_mask.get())?;
+
+        // Region start and size always have to align to 4 bytes, and regions
+        // must be at least 8 bytes.
+        let mut start = memory_start as usize;
+        if start % 4 != 0 {
+            start += 4 - (start % 4);
+        }
+        let mut size = cmp::max(min_region_size, 8);
+        if size % 4 != 0 {
+            size += 4 - (size % 4);
+        }
+
+        // The lowest numbered matching PMP entry applies, so the guard region
+        // must come before the app memory region. If it does not, they swap
+        // places.
+        if let Some(app_region_num) = config.app_memory_region.extract() {
+            if app_region_num < region_num {
+                config.regions[region_num] = config.regions[app_region_num];
+                config.app_memory_region.set(region_num);
+                region_num = app_region_num;
+            }
+        }
+
+        config.regions[region_num] = Some(PMPRegion::no_access(start as *const u8, size));
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(start as *const u8, size))
+    }
+
     fn allocate_app_memory_region(
         &self,
         unallocated_memory_start: *const u8,
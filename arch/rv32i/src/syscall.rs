@@ -594,4 +594,13 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             Err(ErrorCode::SIZE)
         }
     }
+
+    fn restore_context(
+        &self,
+        state: &mut Riscv32iStoredState,
+        stored: &[u8],
+    ) -> Result<(), ErrorCode> {
+        *state = Riscv32iStoredState::try_from(stored)?;
+        Ok(())
+    }
 }
@@ -49,8 +49,8 @@ const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::Panic
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 4;
 
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
@@ -121,6 +121,8 @@ impl KernelResources<nrf52832::chip::NRF52<'static, Nrf52832DefaultPeripherals<'
     type ProcessFault = ();
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -139,6 +141,12 @@ impl KernelResources<nrf52832::chip::NRF52<'static, Nrf52832DefaultPeripherals<'
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.systick
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -595,7 +603,7 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_management_capability,
     )
@@ -30,8 +30,8 @@ const NUM_PROCS: usize = 4;
 const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
 
 // Actual memory for holding the active process structures.
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None, None, None, None];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 // Reference to the chip for panic dumps.
 static mut CHIP: Option<&'static arty_e21_chip::chip::ArtyExx<ArtyExxDefaultPeripherals>> = None;
@@ -89,6 +89,8 @@ impl KernelResources<arty_e21_chip::chip::ArtyExx<'static, ArtyExxDefaultPeriphe
     type ProcessFault = ();
     type Scheduler = PrioritySched;
     type SchedulerTimer = ();
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -107,6 +109,12 @@ impl KernelResources<arty_e21_chip::chip::ArtyExx<'static, ArtyExxDefaultPeriphe
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &()
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -287,7 +295,7 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_mgmt_cap,
     )
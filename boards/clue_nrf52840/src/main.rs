@@ -105,8 +105,8 @@ const FAULT_RESPONSE: kernel::process::StopWithDebugFaultPolicy =
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 8;
 
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 static mut CHIP: Option<&'static nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
@@ -205,6 +205,8 @@ impl KernelResources<nrf52::chip::NRF52<'static, Nrf52840DefaultPeripherals<'sta
     type ProcessFault = ();
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -223,6 +225,12 @@ impl KernelResources<nrf52::chip::NRF52<'static, Nrf52840DefaultPeripherals<'sta
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.systick
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -791,7 +799,7 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_management_capability,
     )
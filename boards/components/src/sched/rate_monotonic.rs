@@ -0,0 +1,81 @@
+//! Component for a rate-monotonic scheduler.
+//!
+//! This provides one Component, RateMonotonicComponent.
+
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::process::ProcessSlot;
+use kernel::scheduler::rate_monotonic::RateMonotonicSched;
+use kernel::scheduler::real_time::RealTimeProcessNode;
+use kernel::static_init_half;
+
+#[macro_export]
+macro_rules! rate_monotonic_component_helper {
+    ($A:ty, $N:expr $(,)?) => {{
+        use core::mem::MaybeUninit;
+        use kernel::scheduler::rate_monotonic::RateMonotonicSched;
+        use kernel::scheduler::real_time::RealTimeProcessNode;
+        use kernel::static_init;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<RateMonotonicSched<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        const UNINIT: MaybeUninit<RealTimeProcessNode<'static>> = MaybeUninit::uninit();
+        static mut BUF3: [MaybeUninit<RealTimeProcessNode<'static>>; $N] = [UNINIT; $N];
+        (&mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct RateMonotonicComponent<A: 'static + time::Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    processes: &'static [ProcessSlot],
+}
+
+impl<A: 'static + time::Alarm<'static>> RateMonotonicComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        processes: &'static [ProcessSlot],
+    ) -> RateMonotonicComponent<A> {
+        RateMonotonicComponent {
+            alarm_mux,
+            processes,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for RateMonotonicComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<RateMonotonicSched<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut [MaybeUninit<RealTimeProcessNode<'static>>],
+    );
+    type Output = &'static RateMonotonicSched<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let (alarm_buf, sched_buf, proc_nodes) = static_buffer;
+        let scheduler_alarm = static_init_half!(
+            alarm_buf,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        scheduler_alarm.setup();
+
+        let scheduler = static_init_half!(
+            sched_buf,
+            RateMonotonicSched<'static, VirtualMuxAlarm<'static, A>>,
+            RateMonotonicSched::new(scheduler_alarm)
+        );
+        scheduler_alarm.set_alarm_client(scheduler);
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                RealTimeProcessNode<'static>,
+                RealTimeProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
@@ -14,7 +14,7 @@
 
 use core::mem::MaybeUninit;
 use kernel::component::Component;
-use kernel::process::Process;
+use kernel::process::ProcessSlot;
 use kernel::scheduler::round_robin::{RoundRobinProcessNode, RoundRobinSched};
 use kernel::{static_init, static_init_half};
 
@@ -31,11 +31,11 @@ macro_rules! rr_component_helper {
 }
 
 pub struct RoundRobinComponent {
-    processes: &'static [Option<&'static dyn Process>],
+    processes: &'static [ProcessSlot],
 }
 
 impl RoundRobinComponent {
-    pub fn new(processes: &'static [Option<&'static dyn Process>]) -> RoundRobinComponent {
+    pub fn new(processes: &'static [ProcessSlot]) -> RoundRobinComponent {
         RoundRobinComponent { processes }
     }
 }
@@ -30,8 +30,8 @@ const NUM_PROCS: usize = 4;
 //
 // Actual memory for holding the active process structures. Need an empty list
 // at least.
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 // Reference to the chip for panic dumps.
 static mut CHIP: Option<&'static esp32_c3::chip::Esp32C3<Esp32C3DefaultPeripherals>> = None;
@@ -100,6 +100,8 @@ impl KernelResources<esp32_c3::chip::Esp32C3<'static, Esp32C3DefaultPeripherals<
     type ProcessFault = ();
     type Scheduler = PrioritySched;
     type SchedulerTimer = VirtualSchedulerTimer<esp32_c3::timg::TimG<'static>>;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -118,6 +120,12 @@ impl KernelResources<esp32_c3::chip::Esp32C3<'static, Esp32C3DefaultPeripherals<
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.scheduler_timer
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -307,7 +315,7 @@ unsafe fn setup() -> (
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_mgmt_cap,
     )
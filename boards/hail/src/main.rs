@@ -39,8 +39,8 @@ mod test_take_map_cell;
 const NUM_PROCS: usize = 20;
 
 // Actual memory for holding the active process structures.
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 static mut CHIP: Option<&'static sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
@@ -122,6 +122,8 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Hail {
     type ProcessFault = ();
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -140,6 +142,12 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Hail {
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.systick
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -579,7 +587,7 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         fault_policy,
         &process_management_capability,
     )
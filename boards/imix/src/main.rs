@@ -10,6 +10,8 @@
 #![deny(missing_docs)]
 
 mod imix_components;
+use core::mem::MaybeUninit;
+
 use capsules::alarm::AlarmDriver;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
@@ -89,11 +91,14 @@ const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compressio
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
 
+/// Persistent app ID of the app allowed to read and clear the crash log.
+const CRASH_LOG_APP_ID: u32 = 0x1000;
+
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
 
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 static mut CHIP: Option<&'static sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> = None;
 static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText> = None;
@@ -103,6 +108,10 @@ static mut PROCESS_PRINTER: Option<&'static kernel::process::ProcessPrinterText>
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
+/// Record of the last kernel panic, kept in RAM that survives resets.
+#[link_section = ".noinit"]
+static mut CRASH_RECORD: MaybeUninit<kernel::crash_record::CrashRecord> = MaybeUninit::uninit();
+
 struct Imix {
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
@@ -132,6 +141,10 @@ struct Imix {
         VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
     >,
     ipc: kernel::ipc::IPC<NUM_PROCS>,
+    ipc_mailbox: kernel::ipc_mailbox::IpcMailbox<32, 4>,
+    pubsub: &'static capsules::pubsub::PubSub<'static, 4>,
+    crash_log: &'static capsules::crash_log::CrashLog,
+    shared_memory: kernel::shared_memory::SharedMemory<4>,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
     crc: &'static capsules::crc::CrcDriver<'static, sam4l::crccu::Crccu<'static>>,
@@ -182,7 +195,11 @@ impl SyscallDriverLookup for Imix {
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules::pubsub::DRIVER_NUM => f(Some(self.pubsub)),
+            capsules::crash_log::DRIVER_NUM => f(Some(self.crash_log)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::ipc_mailbox::DRIVER_NUM => f(Some(&self.ipc_mailbox)),
+            kernel::shared_memory::DRIVER_NUM => f(Some(&self.shared_memory)),
             _ => f(None),
         }
     }
@@ -194,8 +211,10 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
     type ProcessFault = ();
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
-    type ContextSwitchCallback = ();
+    type ContextSwitchCallback = kernel::shared_memory::SharedMemory<4>;
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -212,11 +231,17 @@ impl KernelResources<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> for Imix {
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.systick
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
-        &()
+        &self.shared_memory
     }
 }
 
@@ -347,6 +372,10 @@ pub unsafe fn main() {
         },
     );
 
+    // Keep the record of a panic before the last reset, and record the next
+    // one.
+    kernel::crash_record::assign_crash_record(&mut CRASH_RECORD, sam4l::pm::reset_cause());
+
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
@@ -619,6 +648,22 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    let pubsub = static_init!(
+        capsules::pubsub::PubSub<'static, 4>,
+        capsules::pubsub::PubSub::new(
+            &mut capsules::pubsub::BUF,
+            board_kernel.create_grant(capsules::pubsub::DRIVER_NUM, &grant_cap),
+        )
+    );
+
+    let crash_log = static_init!(
+        capsules::crash_log::CrashLog,
+        capsules::crash_log::CrashLog::new(
+            board_kernel.create_grant(capsules::crash_log::DRIVER_NUM, &grant_cap),
+            core::num::NonZeroU32::new(CRASH_LOG_APP_ID).unwrap(),
+        )
+    );
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
 
@@ -638,6 +683,18 @@ pub unsafe fn main() {
         crc,
         spi: spi_syscalls,
         ipc: kernel::ipc::IPC::new(board_kernel, kernel::ipc::DRIVER_NUM, &grant_cap),
+        ipc_mailbox: kernel::ipc_mailbox::IpcMailbox::new(
+            board_kernel,
+            kernel::ipc_mailbox::DRIVER_NUM,
+            &grant_cap,
+        ),
+        pubsub,
+        crash_log,
+        shared_memory: kernel::shared_memory::SharedMemory::new(
+            board_kernel,
+            kernel::shared_memory::DRIVER_NUM,
+            &grant_cap,
+        ),
         ninedof,
         udp_driver,
         usb_driver,
@@ -731,7 +788,7 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_mgmt_cap,
     )
@@ -24,6 +24,7 @@ use kernel::hil::digest::Digest;
 use kernel::hil::entropy::Entropy32;
 use kernel::hil::hasher::Hasher;
 use kernel::hil::i2c::I2CMaster;
+use kernel::hil::kv_system::KVSystem;
 use kernel::hil::led::LedHigh;
 use kernel::hil::rng::Rng;
 use kernel::hil::symmetric_encryption::AES128;
@@ -45,10 +46,28 @@ pub mod usb;
 
 const NUM_PROCS: usize = 4;
 
+/// The number of bytes the key-value storage of an app can use, unless it is
+/// listed in `KV_QUOTAS`.
+const KV_DEFAULT_QUOTA: usize = 1024;
+/// Key-value storage quotas of specific apps, by storage identifier.
+static KV_QUOTAS: [capsules::kv_driver::StorageQuota; 0] = [];
+/// The maximum length of keys and values apps can store. TicKV uses 64 byte
+/// regions on this board, so values are kept small enough to fit in a single
+/// object.
+const KV_MAX_KEY_LEN: usize = 32;
+const KV_MAX_VALUE_LEN: usize = 32;
+
+type TicKVStore = capsules::tickv::TicKVStore<
+    'static,
+    capsules::virtual_flash::FlashUser<'static, lowrisc::flash_ctrl::FlashCtrl<'static>>,
+    capsules::sip_hash::SipHasher24<'static>,
+>;
+
 //
 // Actual memory for holding the active process structures. Need an empty list
 // at least.
-static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; 4] = [None; NUM_PROCS];
+static mut PROCESSES: [kernel::process::ProcessSlot; 4] =
+    [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
 
 // Test access to the peripherals
 #[cfg(test)]
@@ -65,13 +84,7 @@ static mut MAIN_CAP: Option<&dyn kernel::capabilities::MainLoopCapability> = Non
 // Test access to alarm
 static mut ALARM: Option<&'static MuxAlarm<'static, earlgrey::timer::RvTimer<'static>>> = None;
 // Test access to TicKV
-static mut TICKV: Option<
-    &capsules::tickv::TicKVStore<
-        'static,
-        capsules::virtual_flash::FlashUser<'static, lowrisc::flash_ctrl::FlashCtrl<'static>>,
-        capsules::sip_hash::SipHasher24<'static>,
-    >,
-> = None;
+static mut TICKV: Option<&TicKVStore> = None;
 // Test access to AES CCM
 static mut AES: Option<&virtual_aes_ccm::VirtualAES128CCM<'static, earlgrey::aes::Aes<'static>>> =
     None;
@@ -127,6 +140,11 @@ struct EarlGreyNexysVideo {
     >,
     i2c_master: &'static capsules::i2c_master::I2CMasterDriver<'static, lowrisc::i2c::I2c<'static>>,
     rng: &'static capsules::rng::RngDriver<'static>,
+    kv_driver: &'static capsules::kv_driver::KVStoreDriver<
+        'static,
+        TicKVStore,
+        capsules::tickv::TicKVKeyType,
+    >,
     aes: &'static capsules::symmetric_encryption::aes::AesDriver<
         'static,
         virtual_aes_ccm::VirtualAES128CCM<'static, earlgrey::aes::Aes<'static>>,
@@ -154,6 +172,7 @@ impl SyscallDriverLookup for EarlGreyNexysVideo {
             capsules::i2c_master::DRIVER_NUM => f(Some(self.i2c_master)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::symmetric_encryption::aes::DRIVER_NUM => f(Some(self.aes)),
+            capsules::kv_driver::DRIVER_NUM => f(Some(self.kv_driver)),
             _ => f(None),
         }
     }
@@ -168,6 +187,8 @@ impl KernelResources<earlgrey::chip::EarlGrey<'static, EarlGreyDefaultPeripheral
     type Scheduler = PrioritySched;
     type SchedulerTimer =
         VirtualSchedulerTimer<VirtualMuxAlarm<'static, earlgrey::timer::RvTimer<'static>>>;
+    type ProcessCpuBudget = ();
+    type Tracer = ();
     type WatchDog = ();
     type ContextSwitchCallback = ();
 
@@ -186,6 +207,12 @@ impl KernelResources<earlgrey::chip::EarlGrey<'static, EarlGreyDefaultPeripheral
     fn scheduler_timer(&self) -> &Self::SchedulerTimer {
         &self.scheduler_timer
     }
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget {
+        &()
+    }
+    fn tracer(&self) -> &Self::Tracer {
+        &()
+    }
     fn watchdog(&self) -> &Self::WatchDog {
         &()
     }
@@ -482,6 +509,32 @@ unsafe fn setup() -> (
     sip_hash.set_client(tickv);
     TICKV = Some(tickv);
 
+    // Key-value storage for apps, stored in TicKV
+    let kv_driver = static_init!(
+        capsules::kv_driver::KVStoreDriver<'static, TicKVStore, capsules::tickv::TicKVKeyType>,
+        capsules::kv_driver::KVStoreDriver::new(
+            tickv,
+            board_kernel.create_grant(capsules::kv_driver::DRIVER_NUM, &memory_allocation_cap),
+            static_init!(
+                [u8; capsules::kv_driver::KEY_PREFIX_LEN + KV_MAX_KEY_LEN],
+                [0; capsules::kv_driver::KEY_PREFIX_LEN + KV_MAX_KEY_LEN]
+            ),
+            static_init!(capsules::tickv::TicKVKeyType, [0; 8]),
+            static_init!(capsules::tickv::TicKVKeyType, [0; 8]),
+            static_init!(
+                [u8; capsules::kv_driver::HEADER_LEN + KV_MAX_VALUE_LEN],
+                [0; capsules::kv_driver::HEADER_LEN + KV_MAX_VALUE_LEN]
+            ),
+            static_init!(
+                [u8; capsules::kv_driver::USAGE_BUFFER_LEN],
+                [0; capsules::kv_driver::USAGE_BUFFER_LEN]
+            ),
+            KV_DEFAULT_QUOTA,
+            &KV_QUOTAS,
+        )
+    );
+    tickv.set_client(kv_driver);
+
     // Newer FPGA builds of OpenTitan don't include the OTBN, so any accesses
     // to the OTBN hardware will hang.
     // OTBN is still connected though as it works on simulation runs
@@ -590,6 +643,7 @@ unsafe fn setup() -> (
             hmac,
             sha,
             rng,
+            kv_driver,
             lldb: lldb,
             i2c_master,
             aes,
@@ -656,7 +710,7 @@ unsafe fn setup() -> (
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
+        &PROCESSES,
         &FAULT_RESPONSE,
         &process_mgmt_cap,
     )
@@ -0,0 +1,182 @@
+//! Gives processes access to the record of the last kernel panic.
+//!
+//! If the board assigned a crash record with
+//! `kernel::crash_record::assign_crash_record()`, the kernel keeps the panic
+//! message, the name of the faulting process and everything the panic handler
+//! printed across the reset that follows a panic. This driver lets a process
+//! read that record, for example to send it to a server from a deployed unit,
+//! and clear it once it has been handled.
+//!
+//! The record can hold data of other processes, so only the app the board
+//! designates by its persistent app ID can read and clear it. That app must
+//! be approved with credentials. Any process can check whether a crash is
+//! recorded.
+//!
+//! The record is copied into the buffer the process allowed with read-write
+//! allow 0. All commands complete synchronously.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use core::num::NonZeroU32;
+//! # use kernel::static_init;
+//!
+//! let crash_log = static_init!(
+//!     capsules::crash_log::CrashLog,
+//!     capsules::crash_log::CrashLog::new(
+//!         board_kernel.create_grant(capsules::crash_log::DRIVER_NUM, &grant_cap),
+//!         NonZeroU32::new(0x1000).unwrap(),
+//!     )
+//! );
+//! ```
+
+use core::num::NonZeroU32;
+
+use kernel::crash_record;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::CrashLog as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer parts of the crash record are copied into.
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Bits of the flags returned by command 1.
+const FLAG_CRASHED: u32 = 1 << 0;
+const FLAG_COMPLETE: u32 = 1 << 1;
+const FLAG_RESET_CAUSE: u32 = 1 << 2;
+const FLAG_LOG_TRUNCATED: u32 = 1 << 3;
+
+pub struct CrashLog {
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    /// Persistent app ID of the app allowed to read and clear the record.
+    reader: NonZeroU32,
+}
+
+impl CrashLog {
+    pub fn new(
+        grant: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+        reader: NonZeroU32,
+    ) -> CrashLog {
+        CrashLog {
+            apps: grant,
+            reader,
+        }
+    }
+
+    /// Whether `processid` is the app the board allowed to read and clear
+    /// the record.
+    fn is_reader(&self, processid: ProcessId) -> bool {
+        processid.credentialed_app_id() == Some(self.reader)
+    }
+
+    /// Copy `data` into the read-write allow buffer of `processid`, and
+    /// return the number of bytes copied.
+    fn copy_to_process(&self, processid: ProcessId, data: &[u8]) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::READ)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buffer| {
+                            let len = data.len().min(buffer.len());
+                            buffer[..len].copy_from_slice(&data[..len]);
+                            len
+                        })
+                    })
+                    .map_err(ErrorCode::from)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl SyscallDriver for CrashLog {
+    /// Read and clear the record of the last kernel panic.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Returns the flags of the crash record and the reset cause. Bit
+    ///        0 of the flags is set if a crash is recorded, bit 1 if the
+    ///        panic handler finished writing the record, bit 2 if the reset
+    ///        cause is known and bit 3 if the log was truncated.
+    /// - `2`: Copy the panic message into the buffer. Returns the number of
+    ///        bytes copied.
+    /// - `3`: Copy the name of the process that faulted into the buffer.
+    ///        Returns the number of bytes copied, which is 0 if the panic was
+    ///        not caused by a process fault.
+    /// - `4`: Copy the log of the panic handler, starting at offset `arg1`,
+    ///        into the buffer. Returns the number of bytes copied, which is 0
+    ///        once the end of the log is reached.
+    /// - `5`: Clear the crash record.
+    ///
+    /// Commands 2 to 4 return `FAIL` if no crash is recorded, and `RESERVE`
+    /// if the process has not allowed a buffer. Commands 2 to 5 return
+    /// `NOSUPPORT` to every process but the designated reader.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let record = crash_record::crash_record();
+        if (2..=5).contains(&command_num) && !self.is_reader(processid) {
+            return CommandReturn::failure(ErrorCode::NOSUPPORT);
+        }
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match record {
+                Some(record) => {
+                    let mut flags = FLAG_CRASHED;
+                    if record.is_complete() {
+                        flags |= FLAG_COMPLETE;
+                    }
+                    if record.reset_cause().is_some() {
+                        flags |= FLAG_RESET_CAUSE;
+                    }
+                    if record.log_dropped() > 0 {
+                        flags |= FLAG_LOG_TRUNCATED;
+                    }
+                    CommandReturn::success_u32_u32(flags, record.reset_cause().unwrap_or(0))
+                }
+                None => CommandReturn::success_u32_u32(0, 0),
+            },
+
+            2 | 3 | 4 => {
+                let data = match (command_num, record) {
+                    (2, Some(record)) => record.message(),
+                    (3, Some(record)) => record.process_name().unwrap_or(&[]),
+                    (4, Some(record)) => record.log().get(arg1..).unwrap_or(&[]),
+                    _ => return CommandReturn::failure(ErrorCode::FAIL),
+                };
+                match self.copy_to_process(processid, data) {
+                    Ok(len) => CommandReturn::success_u32(len as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            5 => {
+                crash_record::clear_crash_record();
+                CommandReturn::success()
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
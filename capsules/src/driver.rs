@@ -21,6 +21,9 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    IpcMailbox            = 0x10001,
+    SharedMemory          = 0x10002,
+    WatchdogSupervisor    = 0x10003,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -46,6 +49,8 @@ pub enum NUM {
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    KVStore               = 0x50003,
+    FatFs                 = 0x50004,
 
     // Sensors
     Temperature           = 0x60000,
@@ -76,5 +81,7 @@ pub enum NUM {
     Screen                = 0x90001,
     Touch                 = 0x90002,
     TextScreen            = 0x90003,
+    PubSub                = 0x90004,
+    CrashLog              = 0x90005,
 }
 }
@@ -0,0 +1,2445 @@
+//! FAT12, FAT16 and FAT32 file system on top of a block storage device.
+//!
+//! This capsule implements files and directories on any `hil::block_storage`
+//! device, such as an SD card, in the FAT format that PCs can read and write.
+//! It is used by `capsules::fat_fs_driver` to give processes access to
+//! files, and can be used by other capsules directly.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FAT syscall driver   |
+//! |                       |
+//! +-----------------------+
+//!
+//!    FatFsClient
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FAT (this file)      |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::block_storage
+//!
+//! +-----------------------+
+//! |                       |
+//! |  SD card              |
+//! |                       |
+//! +-----------------------+
+//!
+//! The volume is either the whole device or the first FAT partition of its
+//! partition table, and is mounted before the first operation. Blocks must
+//! be 512 bytes long.
+//!
+//! Only short (8.3) names are supported. Files created by this capsule get
+//! short names, and files with long names are found and listed under their
+//! short names. Removing a file also removes its long name. There is no
+//! clock, so new files are dated 1980-01-01.
+//!
+//! Operations work on `File`s returned by `open()`, which hold the position
+//! in the file. Reads and writes return the updated `File` to the client.
+//! All operations complete with a callback to the `FatFsClient`, and only one
+//! operation can be in progress at a time.
+//!
+//! The capsule keeps two blocks of the device in RAM. Modified blocks are
+//! written to the device before an operation that changed them completes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let fat_fs = static_init!(
+//!     capsules::fat_fs::FatFs<'static, capsules::sdcard::SDCard<'static, A>>,
+//!     capsules::fat_fs::FatFs::new(
+//!         sdcard,
+//!         static_init!([u8; 512], [0; 512]),
+//!         static_init!([u8; 512], [0; 512]),
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! fat_fs.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(fat_fs)
+//!         .expect("no deferred call slot available for fat_fs"),
+//! );
+//! kernel::hil::block_storage::BlockStorage::set_client(sdcard, fat_fs);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::block_storage::{BlockStorage, BlockStorageClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The size of the blocks of the device and of the sectors of the volume.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The maximum number of names in a path, including the name of the file.
+pub const MAX_PATH_DEPTH: usize = 8;
+
+/// The maximum length of a name returned by `read_dir()`: an 8.3 name, and a
+/// `/` for directories.
+pub const MAX_NAME_LEN: usize = 13;
+
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / ENTRY_SIZE) as u32;
+/// The maximum number of entries of a directory.
+const MAX_DIR_ENTRIES: u32 = 65536;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+
+/// The first byte of the entry that follows the last entry of a directory.
+const ENTRY_END: u8 = 0x00;
+/// The first byte of a deleted entry.
+const ENTRY_FREE: u8 = 0xE5;
+/// Stored as the first byte of names that start with 0xE5.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// 1980-01-01, the earliest date FAT can store.
+const DEFAULT_DATE: u16 = 0x0021;
+
+/// Partition types of FAT volumes in a partition table.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// The layout of a mounted volume.
+#[derive(Clone, Copy)]
+struct Volume {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    num_fats: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    /// The fixed root directory of FAT12 and FAT16 volumes.
+    root_dir_start: u32,
+    root_entries: u32,
+    /// The first cluster of the root directory of FAT32 volumes.
+    root_cluster: u32,
+    /// The FSInfo sector of FAT32 volumes, or 0.
+    fs_info: u32,
+    data_start: u32,
+    cluster_count: u32,
+}
+
+impl Volume {
+    /// Parse the boot sector at sector `start`, if it holds a FAT volume.
+    fn parse(boot: &[u8], start: u32) -> Option<Volume> {
+        let u16_at = |i: usize| u16::from_le_bytes([boot[i], boot[i + 1]]) as u32;
+        let u32_at =
+            |i: usize| u32::from_le_bytes([boot[i], boot[i + 1], boot[i + 2], boot[i + 3]]);
+
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = u16_at(14);
+        let num_fats = boot[16] as u32;
+        let root_entries = u16_at(17);
+        if (boot[0] != 0xEB && boot[0] != 0xE9)
+            || u16_at(11) != SECTOR_SIZE as u32
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || num_fats == 0
+        {
+            return None;
+        }
+
+        let fat_sectors = if u16_at(22) != 0 {
+            u16_at(22)
+        } else {
+            u32_at(36)
+        };
+        let total_sectors = if u16_at(19) != 0 {
+            u16_at(19)
+        } else {
+            u32_at(32)
+        };
+        let root_dir_sectors =
+            (root_entries * ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1) / SECTOR_SIZE as u32;
+        let data_sectors = total_sectors
+            .checked_sub(reserved)?
+            .checked_sub(num_fats.checked_mul(fat_sectors)?)?
+            .checked_sub(root_dir_sectors)?;
+        let cluster_count = data_sectors / sectors_per_cluster;
+
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let fat_start = start.checked_add(reserved)?;
+        let root_dir_start = fat_start + num_fats * fat_sectors;
+        let mut volume = Volume {
+            fat_type,
+            sectors_per_cluster,
+            num_fats,
+            fat_start,
+            fat_sectors,
+            root_dir_start,
+            root_entries,
+            root_cluster: 0,
+            fs_info: 0,
+            data_start: root_dir_start + root_dir_sectors,
+            cluster_count,
+        };
+        if fat_type == FatType::Fat32 {
+            volume.root_cluster = u32_at(44);
+            if root_entries != 0 || !volume.is_valid_cluster(volume.root_cluster) {
+                return None;
+            }
+            let fs_info = u16_at(48);
+            if fs_info != 0 && fs_info != 0xFFFF {
+                volume.fs_info = start + fs_info;
+            }
+        }
+        Some(volume)
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// The first cluster of the root directory, or 0 for the fixed root
+    /// directory of FAT12 and FAT16 volumes.
+    fn root_dir_cluster(&self) -> u32 {
+        self.root_cluster
+    }
+
+    /// The number of directory entries in `cluster`, or in the fixed root
+    /// directory if `cluster` is 0.
+    fn entries_in(&self, cluster: u32) -> u32 {
+        if cluster == 0 {
+            self.root_entries
+        } else {
+            self.sectors_per_cluster * ENTRIES_PER_SECTOR
+        }
+    }
+
+    /// The sector and the index in the sector of the entry at `cursor`.
+    fn entry_location(&self, cursor: Cursor) -> (u32, usize) {
+        let start = if cursor.cluster == 0 {
+            self.root_dir_start
+        } else {
+            self.cluster_sector(cursor.cluster)
+        };
+        (
+            start + cursor.index / ENTRIES_PER_SECTOR,
+            (cursor.index % ENTRIES_PER_SECTOR) as usize,
+        )
+    }
+}
+
+/// A position in a directory.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Cursor {
+    /// The cluster of the directory, or 0 for the fixed root directory.
+    cluster: u32,
+    /// The index of the entry in the cluster.
+    index: u32,
+    /// The index of the entry in the directory.
+    count: u32,
+}
+
+impl Cursor {
+    fn new(cluster: u32) -> Cursor {
+        Cursor {
+            cluster,
+            index: 0,
+            count: 0,
+        }
+    }
+}
+
+/// An open file or directory.
+#[derive(Clone, Copy, Default)]
+pub struct File {
+    /// The sector and index of the directory entry.
+    entry_sector: u32,
+    entry_index: usize,
+    /// The first long name entry in front of the directory entry.
+    long_name: Option<Cursor>,
+    first_cluster: u32,
+    size: u32,
+    is_dir: bool,
+    is_root: bool,
+    read_only: bool,
+    /// For files the position in bytes, and the cluster that holds it and
+    /// its index in the cluster chain, which is kept so that reading and
+    /// writing don't walk the chain from the start.
+    position: u32,
+    cluster: u32,
+    cluster_index: u32,
+    /// For directories the position of the next entry.
+    cursor: Cursor,
+}
+
+impl File {
+    fn root(volume: &Volume) -> File {
+        let cluster = volume.root_dir_cluster();
+        File {
+            first_cluster: cluster,
+            is_dir: true,
+            is_root: true,
+            cursor: Cursor::new(cluster),
+            ..File::default()
+        }
+    }
+
+    fn from_entry(
+        volume: &Volume,
+        entry: &[u8],
+        sector: u32,
+        index: usize,
+        long_name: Option<Cursor>,
+    ) -> File {
+        let high = if volume.fat_type == FatType::Fat32 {
+            u16::from_le_bytes([entry[20], entry[21]]) as u32
+        } else {
+            0
+        };
+        let first_cluster = (high << 16) | u16::from_le_bytes([entry[26], entry[27]]) as u32;
+        let is_dir = entry[11] & ATTR_DIRECTORY != 0;
+        File {
+            entry_sector: sector,
+            entry_index: index,
+            long_name,
+            first_cluster,
+            size: if is_dir {
+                0
+            } else {
+                u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]])
+            },
+            is_dir,
+            is_root: false,
+            read_only: entry[11] & ATTR_READ_ONLY != 0,
+            position: 0,
+            cluster: first_cluster,
+            cluster_index: 0,
+            cursor: Cursor::new(first_cluster),
+        }
+    }
+
+    /// The size of the file in bytes, 0 for directories.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The position in the file that is read or written next.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Whether `self` and `other` were opened from the same directory entry.
+    pub fn is_same(&self, other: &File) -> bool {
+        self.is_root == other.is_root
+            && self.entry_sector == other.entry_sector
+            && self.entry_index == other.entry_index
+    }
+
+    /// Move the position of a file, which can't be past the end of the file.
+    /// Directories can only be moved back to their first entry.
+    pub fn seek(&mut self, position: u32) -> Result<(), ErrorCode> {
+        if self.is_dir {
+            if position != 0 {
+                return Err(ErrorCode::INVAL);
+            }
+            self.cursor = Cursor::new(self.first_cluster);
+        } else {
+            if position > self.size {
+                return Err(ErrorCode::INVAL);
+            }
+            self.position = position;
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a directory returned by `read_dir()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirEntry {
+    /// The length of the name, which is copied into the buffer.
+    pub name_len: usize,
+    /// The size of the file, 0 for directories.
+    pub size: u32,
+    pub is_dir: bool,
+}
+
+pub trait FatFsClient {
+    /// Called when `open()` completes, with the opened file or directory.
+    fn open_done(&self, result: Result<File, ErrorCode>);
+
+    /// Called when `truncate()` completes, with the updated file.
+    fn truncate_done(&self, result: Result<File, ErrorCode>);
+
+    /// Called when `remove()` completes.
+    fn remove_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when `read()` completes, with the number of bytes read into
+    /// `buffer`, which is 0 at the end of the file.
+    fn read_done(&self, file: File, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+
+    /// Called when `write()` completes, with the number of bytes written,
+    /// which is less than requested if the volume is full.
+    fn write_done(&self, file: File, buffer: &'static mut [u8], result: Result<usize, ErrorCode>);
+
+    /// Called when `read_dir()` completes, with the next entry of the
+    /// directory, or `None` after the last entry.
+    fn read_dir_done(
+        &self,
+        file: File,
+        buffer: &'static mut [u8],
+        result: Result<Option<DirEntry>, ErrorCode>,
+    );
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Open,
+    Truncate,
+    Remove,
+    Read,
+    Write,
+    ReadDir,
+}
+
+/// The steps of the operations.
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    /// Start looking for a path at the root directory.
+    OpenRoot,
+    /// Find the file named by the path.
+    Resolve,
+    /// Add a cluster to a full directory, fill it with zeros and link it to
+    /// the end of the directory.
+    AllocDirCluster,
+    ZeroDirCluster,
+    FlushDirCluster,
+    LinkDirCluster,
+    /// Write a new directory entry.
+    CreateEntry,
+    /// Read or write data, or read directory entries.
+    Data,
+    /// Write the size and the first cluster of the file to its directory
+    /// entry.
+    UpdateEntry,
+    /// Mark the directory entry and the long name entries in front of it
+    /// deleted.
+    MarkDeleted,
+    /// Free the clusters of a chain.
+    FreeChain,
+    /// Write all modified sectors.
+    Flush,
+}
+
+/// Why an operation can't continue.
+enum Stop {
+    /// A sector is being read or written, the operation continues when that
+    /// completes.
+    Io,
+    Error(ErrorCode),
+}
+
+impl From<ErrorCode> for Stop {
+    fn from(error: ErrorCode) -> Stop {
+        Stop::Error(error)
+    }
+}
+
+type Step<T> = Result<T, Stop>;
+
+/// A sector kept in RAM.
+struct CacheEntry {
+    buffer: TakeCell<'static, [u8]>,
+    sector: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    /// When the sector was last used, to replace the least recently used.
+    used: Cell<u32>,
+}
+
+impl CacheEntry {
+    fn new(buffer: &'static mut [u8; SECTOR_SIZE]) -> CacheEntry {
+        CacheEntry {
+            buffer: TakeCell::new(buffer),
+            sector: Cell::new(None),
+            dirty: Cell::new(false),
+            used: Cell::new(0),
+        }
+    }
+}
+
+pub struct FatFs<'a, B: BlockStorage<'a>> {
+    block: &'a B,
+    client: OptionalCell<&'a dyn FatFsClient>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+
+    volume: OptionalCell<Volume>,
+    /// The sector the volume starts at, which is read first when mounting.
+    volume_start: Cell<u32>,
+    /// Whether the free cluster count of the FSInfo sector was cleared since
+    /// mounting.
+    fs_info_cleared: Cell<bool>,
+
+    cache: [CacheEntry; 2],
+    /// The cache entry that is being read or written.
+    io: OptionalCell<usize>,
+    io_sector: Cell<u32>,
+    io_write: Cell<bool>,
+    clock: Cell<u32>,
+
+    operation: Cell<Operation>,
+    phase: Cell<Phase>,
+    file: Cell<File>,
+    buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    done: Cell<usize>,
+    dir_entry: Cell<Option<DirEntry>>,
+
+    /// The names of the path being opened.
+    path: Cell<[[u8; 11]; MAX_PATH_DEPTH]>,
+    path_len: Cell<usize>,
+    component: Cell<usize>,
+    create: Cell<bool>,
+    /// The entry of the directory being searched.
+    cursor: Cell<Cursor>,
+    /// The first long name entry in front of `cursor`.
+    long_name: Cell<Option<Cursor>>,
+    /// The first free entry of the directory being searched.
+    free_slot: Cell<Option<Cursor>>,
+    /// Whether the entry at `cursor` was handled, and only the next entry
+    /// has to be found.
+    advancing: Cell<bool>,
+    /// Whether the file being written needs a new cluster.
+    extending: Cell<bool>,
+
+    /// The next cluster to check when looking for a free cluster.
+    next_free: Cell<u32>,
+    searched: Cell<u32>,
+    /// The free cluster that was found and is being allocated, and whether
+    /// it is marked as the end of a chain.
+    allocating: Cell<Option<u32>>,
+    allocated: Cell<bool>,
+    /// The number of FATs that were changed by `fat_set()`.
+    fats_set: Cell<u32>,
+    new_cluster: Cell<u32>,
+    zeroed: Cell<u32>,
+    /// The cluster of a chain that is freed next, and the cluster after it.
+    chain: Cell<u32>,
+    chain_next: Cell<Option<u32>>,
+}
+
+impl<'a, B: BlockStorage<'a>> FatFs<'a, B> {
+    pub fn new(
+        block: &'a B,
+        cache0: &'static mut [u8; SECTOR_SIZE],
+        cache1: &'static mut [u8; SECTOR_SIZE],
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> FatFs<'a, B> {
+        FatFs {
+            block,
+            client: OptionalCell::empty(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            volume: OptionalCell::empty(),
+            volume_start: Cell::new(0),
+            fs_info_cleared: Cell::new(false),
+            cache: [CacheEntry::new(cache0), CacheEntry::new(cache1)],
+            io: OptionalCell::empty(),
+            io_sector: Cell::new(0),
+            io_write: Cell::new(false),
+            clock: Cell::new(0),
+            operation: Cell::new(Operation::None),
+            phase: Cell::new(Phase::Flush),
+            file: Cell::new(File::default()),
+            buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            done: Cell::new(0),
+            dir_entry: Cell::new(None),
+            path: Cell::new([[b' '; 11]; MAX_PATH_DEPTH]),
+            path_len: Cell::new(0),
+            component: Cell::new(0),
+            create: Cell::new(false),
+            cursor: Cell::new(Cursor::default()),
+            long_name: Cell::new(None),
+            free_slot: Cell::new(None),
+            advancing: Cell::new(false),
+            extending: Cell::new(false),
+            next_free: Cell::new(2),
+            searched: Cell::new(0),
+            allocating: Cell::new(None),
+            allocated: Cell::new(false),
+            fats_set: Cell::new(0),
+            new_cluster: Cell::new(0),
+            zeroed: Cell::new(0),
+            chain: Cell::new(0),
+            chain_next: Cell::new(None),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    pub fn set_client(&self, client: &'a dyn FatFsClient) {
+        self.client.set(client);
+    }
+
+    /// Open the file or directory at `path`, which is a list of names
+    /// separated by `/`. The path can end with a 0 byte. If the file doesn't
+    /// exist and `create` is set, an empty file is created.
+    ///
+    /// `open_done()` is called with the opened file, or with `NOSUPPORT` if
+    /// it doesn't exist, and `INVAL` if a name in the path isn't a directory.
+    pub fn open(&self, path: &[u8], create: bool) -> Result<(), ErrorCode> {
+        let mut names = [[b' '; 11]; MAX_PATH_DEPTH];
+        let mut depth = 0;
+        let path = path.split(|c| *c == 0).next().unwrap_or(&[]);
+        for name in path.split(|c| *c == b'/').filter(|name| !name.is_empty()) {
+            if depth == MAX_PATH_DEPTH {
+                return Err(ErrorCode::SIZE);
+            }
+            names[depth] = short_name(name)?;
+            depth += 1;
+        }
+
+        self.start(Operation::Open)?;
+        self.path.set(names);
+        self.path_len.set(depth);
+        self.component.set(0);
+        self.create.set(create);
+        self.phase.set(Phase::OpenRoot);
+        Ok(())
+    }
+
+    /// Remove the contents of a file.
+    pub fn truncate(&self, file: File) -> Result<(), ErrorCode> {
+        if file.is_dir || file.read_only {
+            return Err(ErrorCode::INVAL);
+        }
+        self.start(Operation::Truncate)?;
+        self.chain.set(file.first_cluster);
+        self.chain_next.set(None);
+        self.file.set(File {
+            first_cluster: 0,
+            size: 0,
+            position: 0,
+            cluster: 0,
+            cluster_index: 0,
+            ..file
+        });
+        self.phase.set(Phase::UpdateEntry);
+        Ok(())
+    }
+
+    /// Remove a file. Directories can't be removed.
+    pub fn remove(&self, file: File) -> Result<(), ErrorCode> {
+        if file.is_dir || file.read_only {
+            return Err(ErrorCode::INVAL);
+        }
+        self.start(Operation::Remove)?;
+        self.chain.set(file.first_cluster);
+        self.chain_next.set(None);
+        self.file.set(file);
+        self.cursor.set(file.long_name.unwrap_or_default());
+        self.phase.set(Phase::MarkDeleted);
+        Ok(())
+    }
+
+    /// Read up to `len` bytes from the position of `file` into `buffer`.
+    pub fn read(
+        &self,
+        file: File,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if file.is_dir {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+        self.start_transfer(Operation::Read, file, buffer, len)
+    }
+
+    /// Write `len` bytes from `buffer` at the position of `file`.
+    pub fn write(
+        &self,
+        file: File,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if file.is_dir || file.read_only {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+        // Files can't be larger than 4 GiB
+        let len = cmp::min(len, (u32::MAX - file.position) as usize);
+        self.start_transfer(Operation::Write, file, buffer, len)
+    }
+
+    /// Read the next entry of the directory `file`. Its name is copied into
+    /// `buffer`, with a `/` at the end for directories.
+    pub fn read_dir(
+        &self,
+        file: File,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !file.is_dir {
+            return Err((ErrorCode::INVAL, buffer));
+        }
+        self.start_transfer(Operation::ReadDir, file, buffer, 0)
+    }
+
+    fn start_transfer(
+        &self,
+        operation: Operation,
+        file: File,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.start(operation) {
+            return Err((e, buffer));
+        }
+        self.len.set(cmp::min(len, buffer.len()));
+        self.done.set(0);
+        self.buffer.replace(buffer);
+        self.file.set(file);
+        self.dir_entry.set(None);
+        self.phase.set(Phase::Data);
+        Ok(())
+    }
+
+    fn start(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::None {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(operation);
+        self.cursor.set(Cursor::default());
+        self.advancing.set(false);
+        self.extending.set(false);
+        self.searched.set(0);
+        self.allocating.set(None);
+        self.allocated.set(false);
+        self.fats_set.set(0);
+        // Run the operation from a deferred call, so that the client isn't
+        // called back before this returns
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+
+    /// Run the current operation until it waits for the device or
+    /// completes.
+    fn run(&self) {
+        match self.step() {
+            Ok(()) => self.finish(Ok(())),
+            Err(Stop::Io) => {}
+            Err(Stop::Error(e)) => self.finish(Err(e)),
+        }
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        let operation = self.operation.replace(Operation::None);
+        let file = self.file.get();
+        let done = self.done.get();
+        let dir_entry = self.dir_entry.get();
+        let buffer = self.buffer.take();
+
+        self.client.map(move |client| match operation {
+            Operation::Open => client.open_done(result.map(|()| file)),
+            Operation::Truncate => client.truncate_done(result.map(|()| file)),
+            Operation::Remove => client.remove_done(result),
+            Operation::Read => {
+                if let Some(buffer) = buffer {
+                    client.read_done(file, buffer, result.map(|()| done));
+                }
+            }
+            Operation::Write => {
+                if let Some(buffer) = buffer {
+                    client.write_done(file, buffer, result.map(|()| done));
+                }
+            }
+            Operation::ReadDir => {
+                if let Some(buffer) = buffer {
+                    client.read_dir_done(file, buffer, result.map(|()| dir_entry));
+                }
+            }
+            Operation::None => {}
+        });
+    }
+
+    /// Forget the volume and the cached sectors, after the device failed.
+    fn unmount(&self) {
+        self.volume.clear();
+        self.volume_start.set(0);
+        self.fs_info_cleared.set(false);
+        for entry in self.cache.iter() {
+            entry.sector.set(None);
+            entry.dirty.set(false);
+        }
+    }
+
+    /// Run the current operation as far as possible. Every phase can be
+    /// run again after it stopped to wait for the device, and only records
+    /// its progress once it can't be stopped anymore.
+    fn step(&self) -> Step<()> {
+        let volume = match self.volume.extract() {
+            Some(volume) => volume,
+            None => {
+                let volume = self.mount()?;
+                self.volume.set(volume);
+                self.next_free.set(2);
+                volume
+            }
+        };
+        let volume = &volume;
+
+        loop {
+            match self.phase.get() {
+                Phase::OpenRoot => {
+                    let root = File::root(volume);
+                    self.file.set(root);
+                    self.start_search(root.first_cluster);
+                    self.phase.set(Phase::Resolve);
+                }
+
+                Phase::Resolve => {
+                    match self.resolve(volume)? {
+                        Some(_) => return Ok(()),
+                        None if !self.create.get() => return Err(ErrorCode::NOSUPPORT.into()),
+                        None if self.free_slot.get().is_some() => {
+                            self.phase.set(Phase::CreateEntry)
+                        }
+                        // The fixed root directory can't grow
+                        None if self.cursor.get().cluster == 0 => {
+                            return Err(ErrorCode::NOMEM.into())
+                        }
+                        None => self.phase.set(Phase::AllocDirCluster),
+                    }
+                }
+
+                Phase::AllocDirCluster => {
+                    let cluster = self.alloc_cluster(volume, 0)?;
+                    self.new_cluster.set(cluster);
+                    self.zeroed.set(0);
+                    self.phase.set(Phase::ZeroDirCluster);
+                }
+
+                Phase::ZeroDirCluster => {
+                    let first = volume.cluster_sector(self.new_cluster.get());
+                    while self.zeroed.get() < volume.sectors_per_cluster {
+                        self.new_sector(first + self.zeroed.get())?;
+                        self.zeroed.set(self.zeroed.get() + 1);
+                    }
+                    self.phase.set(Phase::FlushDirCluster);
+                }
+
+                Phase::FlushDirCluster => {
+                    // Only link the cluster once it is empty
+                    self.flush()?;
+                    self.phase.set(Phase::LinkDirCluster);
+                }
+
+                Phase::LinkDirCluster => {
+                    let cluster = self.new_cluster.get();
+                    self.fat_set(volume, self.cursor.get().cluster, cluster)?;
+                    self.free_slot.set(Some(Cursor::new(cluster)));
+                    self.phase.set(Phase::CreateEntry);
+                }
+
+                Phase::CreateEntry => {
+                    let (sector, index) =
+                        volume.entry_location(self.free_slot.get().unwrap_or_default());
+                    let name = self.path.get()[self.path_len.get() - 1];
+                    let mut entry = [0; ENTRY_SIZE];
+                    entry[..11].copy_from_slice(&name);
+                    entry[11] = ATTR_ARCHIVE;
+                    for date in [16, 18, 24] {
+                        entry[date..date + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+                    }
+                    self.update(sector, index * ENTRY_SIZE, &entry)?;
+                    self.file
+                        .set(File::from_entry(volume, &entry, sector, index, None));
+                    self.phase.set(Phase::Flush);
+                }
+
+                Phase::Data => match self.operation.get() {
+                    Operation::Read => {
+                        self.read_data(volume)?;
+                        return Ok(());
+                    }
+                    Operation::Write => {
+                        self.write_data(volume)?;
+                        self.phase.set(Phase::UpdateEntry);
+                    }
+                    _ => {
+                        self.read_dir_entry(volume)?;
+                        return Ok(());
+                    }
+                },
+
+                Phase::UpdateEntry => {
+                    let file = self.file.get();
+                    let offset = file.entry_index * ENTRY_SIZE;
+                    let mut entry = [0; ENTRY_SIZE];
+                    self.sector(file.entry_sector, |buf| {
+                        entry.copy_from_slice(&buf[offset..offset + ENTRY_SIZE]);
+                    })?;
+                    if volume.fat_type == FatType::Fat32 {
+                        entry[20..22]
+                            .copy_from_slice(&((file.first_cluster >> 16) as u16).to_le_bytes());
+                    }
+                    entry[26..28].copy_from_slice(&(file.first_cluster as u16).to_le_bytes());
+                    entry[28..32].copy_from_slice(&file.size.to_le_bytes());
+                    entry[11] |= ATTR_ARCHIVE;
+                    self.update(file.entry_sector, offset, &entry)?;
+                    self.phase
+                        .set(if self.operation.get() == Operation::Truncate {
+                            Phase::FreeChain
+                        } else {
+                            Phase::Flush
+                        });
+                }
+
+                Phase::MarkDeleted => {
+                    let file = self.file.get();
+                    loop {
+                        let cursor = self.cursor.get();
+                        if !self.advancing.get() {
+                            let (sector, index) = if file.long_name.is_some() {
+                                volume.entry_location(cursor)
+                            } else {
+                                (file.entry_sector, file.entry_index)
+                            };
+                            self.update(sector, index * ENTRY_SIZE, &[ENTRY_FREE])?;
+                            if sector == file.entry_sector && index == file.entry_index {
+                                break;
+                            }
+                            self.advancing.set(true);
+                        }
+                        match self.advance(volume, cursor)? {
+                            Some(next) => self.cursor.set(next),
+                            None => return Err(ErrorCode::FAIL.into()),
+                        }
+                        self.advancing.set(false);
+                    }
+                    self.phase.set(Phase::FreeChain);
+                }
+
+                Phase::FreeChain => {
+                    self.free_chain(volume)?;
+                    self.phase.set(Phase::Flush);
+                }
+
+                Phase::Flush => {
+                    self.flush()?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Read the boot sector, and the partition table if the device doesn't
+    /// start with a FAT volume.
+    fn mount(&self) -> Step<Volume> {
+        if self.block.block_size() != SECTOR_SIZE {
+            return Err(ErrorCode::NOSUPPORT.into());
+        }
+        loop {
+            let start = self.volume_start.get();
+            let (volume, partition) = self.sector(start, |boot| {
+                if boot[510] != 0x55 || boot[511] != 0xAA {
+                    return (None, None);
+                }
+                let partition = boot[446..510].chunks(16).find_map(|entry| {
+                    let lba = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+                    if FAT_PARTITION_TYPES.contains(&entry[4]) && lba != 0 {
+                        Some(lba)
+                    } else {
+                        None
+                    }
+                });
+                (Volume::parse(boot, start), partition)
+            })?;
+
+            match (volume, partition) {
+                (Some(volume), _) => return Ok(volume),
+                (None, Some(partition)) if start == 0 => self.volume_start.set(partition),
+                _ => {
+                    self.volume_start.set(0);
+                    return Err(ErrorCode::NODEVICE.into());
+                }
+            }
+        }
+    }
+
+    /// Start searching the directory at `cluster`.
+    fn start_search(&self, cluster: u32) {
+        self.cursor.set(Cursor::new(cluster));
+        self.long_name.set(None);
+        self.free_slot.set(None);
+        self.advancing.set(false);
+    }
+
+    /// Find the file named by the path. Returns `None` if the last name of
+    /// the path isn't in its directory, in which case `cursor` is the last
+    /// entry of the directory and `free_slot` is the first free entry, if
+    /// there is one.
+    fn resolve(&self, volume: &Volume) -> Step<Option<File>> {
+        loop {
+            let component = self.component.get();
+            if component == self.path_len.get() {
+                return Ok(Some(self.file.get()));
+            }
+            let name = self.path.get()[component];
+            let last = component + 1 == self.path_len.get();
+
+            let cursor = self.cursor.get();
+            if !self.advancing.get() {
+                let (entry, sector, index) = match self.read_entry(volume, cursor)? {
+                    Some(entry) => entry,
+                    None if last => return Ok(None),
+                    None => return Err(ErrorCode::NOSUPPORT.into()),
+                };
+
+                if entry[0] == ENTRY_END {
+                    if self.free_slot.get().is_none() {
+                        self.free_slot.set(Some(cursor));
+                    }
+                    if last {
+                        return Ok(None);
+                    }
+                    return Err(ErrorCode::NOSUPPORT.into());
+                } else if entry[0] == ENTRY_FREE {
+                    if self.free_slot.get().is_none() {
+                        self.free_slot.set(Some(cursor));
+                    }
+                    self.long_name.set(None);
+                } else if entry[11] & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                    if self.long_name.get().is_none() {
+                        self.long_name.set(Some(cursor));
+                    }
+                } else if entry[11] & ATTR_VOLUME_ID == 0 && entry_name(&entry) == name {
+                    let file =
+                        File::from_entry(volume, &entry, sector, index, self.long_name.get());
+                    if !last && !file.is_dir {
+                        return Err(ErrorCode::INVAL.into());
+                    }
+                    self.file.set(file);
+                    self.component.set(component + 1);
+                    if !last {
+                        self.start_search(file.first_cluster);
+                    }
+                    continue;
+                } else {
+                    self.long_name.set(None);
+                }
+                self.advancing.set(true);
+            }
+
+            match self.advance(volume, cursor)? {
+                Some(next) => self.cursor.set(next),
+                None if last => return Ok(None),
+                None => return Err(ErrorCode::NOSUPPORT.into()),
+            }
+            self.advancing.set(false);
+        }
+    }
+
+    /// Read the directory entry at `cursor`, with its sector and its index
+    /// in the sector. Returns `None` past the end of the directory.
+    fn read_entry(
+        &self,
+        volume: &Volume,
+        cursor: Cursor,
+    ) -> Step<Option<([u8; ENTRY_SIZE], u32, usize)>> {
+        if cursor.index >= volume.entries_in(cursor.cluster) {
+            return Ok(None);
+        }
+        let (sector, index) = volume.entry_location(cursor);
+        let mut entry = [0; ENTRY_SIZE];
+        self.sector(sector, |buf| {
+            entry.copy_from_slice(&buf[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE]);
+        })?;
+        Ok(Some((entry, sector, index)))
+    }
+
+    /// The entry after `cursor`, or `None` at the end of the directory.
+    fn advance(&self, volume: &Volume, cursor: Cursor) -> Step<Option<Cursor>> {
+        if cursor.count + 1 >= MAX_DIR_ENTRIES {
+            return Ok(None);
+        }
+        if cursor.index + 1 < volume.entries_in(cursor.cluster) {
+            return Ok(Some(Cursor {
+                index: cursor.index + 1,
+                count: cursor.count + 1,
+                ..cursor
+            }));
+        }
+        if cursor.cluster == 0 {
+            return Ok(None);
+        }
+        let next = self.fat_get(volume, cursor.cluster)?;
+        if volume.is_valid_cluster(next) {
+            Ok(Some(Cursor {
+                cluster: next,
+                index: 0,
+                count: cursor.count + 1,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the next visible entry of the directory being read.
+    fn read_dir_entry(&self, volume: &Volume) -> Step<()> {
+        loop {
+            let mut file = self.file.get();
+            let cursor = file.cursor;
+            if !self.advancing.get() {
+                let entry = match self.read_entry(volume, cursor)? {
+                    Some((entry, _, _)) if entry[0] != ENTRY_END => entry,
+                    _ => return Ok(()),
+                };
+                if entry[0] != ENTRY_FREE
+                    && entry[0] != b'.'
+                    && entry[11] & ATTR_LONG_NAME_MASK != ATTR_LONG_NAME
+                    && entry[11] & ATTR_VOLUME_ID == 0
+                {
+                    self.found_dir_entry(&entry);
+                }
+                self.advancing.set(true);
+            }
+
+            // Past the end of the directory, `read_entry()` returns `None`
+            file.cursor = self.advance(volume, cursor)?.unwrap_or(Cursor {
+                index: u32::MAX,
+                ..cursor
+            });
+            self.file.set(file);
+            self.advancing.set(false);
+            if self.dir_entry.get().is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copy the name of a directory entry into the buffer of `read_dir()`.
+    fn found_dir_entry(&self, entry: &[u8; ENTRY_SIZE]) {
+        let is_dir = entry[11] & ATTR_DIRECTORY != 0;
+        let mut name = [0; MAX_NAME_LEN];
+        let mut name_len = format_name(&entry_name(entry), &mut name);
+        if is_dir {
+            name[name_len] = b'/';
+            name_len += 1;
+        }
+        self.buffer.map(|buffer| {
+            let len = cmp::min(name_len, buffer.len());
+            buffer[..len].copy_from_slice(&name[..len]);
+        });
+        self.dir_entry.set(Some(DirEntry {
+            name_len,
+            size: if is_dir {
+                0
+            } else {
+                u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]])
+            },
+            is_dir,
+        }));
+    }
+
+    /// Walk the cluster chain of the file to the cluster that holds its
+    /// position. Returns `None` if the chain ends before.
+    fn seek_cluster(&self, volume: &Volume) -> Step<Option<u32>> {
+        loop {
+            let mut file = self.file.get();
+            let target = file.position / volume.cluster_bytes();
+            if !volume.is_valid_cluster(file.cluster) || file.cluster_index > target {
+                if !volume.is_valid_cluster(file.first_cluster) {
+                    return Ok(None);
+                }
+                file.cluster = file.first_cluster;
+                file.cluster_index = 0;
+                self.file.set(file);
+            }
+            if file.cluster_index == target {
+                return Ok(Some(file.cluster));
+            }
+
+            let next = self.fat_get(volume, file.cluster)?;
+            if !volume.is_valid_cluster(next) {
+                return Ok(None);
+            }
+            file.cluster = next;
+            file.cluster_index += 1;
+            self.file.set(file);
+        }
+    }
+
+    /// Like `seek_cluster()`, but once the end of the chain was found it
+    /// isn't walked again while a cluster is added to it.
+    fn seek_cluster_or_extend(&self, volume: &Volume) -> Step<Option<u32>> {
+        if self.extending.get() {
+            return Ok(None);
+        }
+        let cluster = self.seek_cluster(volume)?;
+        self.extending.set(cluster.is_none());
+        Ok(cluster)
+    }
+
+    /// The sector that holds the position of the file, and the offset of
+    /// the position in it.
+    fn data_sector(volume: &Volume, cluster: u32, position: u32) -> (u32, usize) {
+        let offset = position % volume.cluster_bytes();
+        (
+            volume.cluster_sector(cluster) + offset / SECTOR_SIZE as u32,
+            (offset % SECTOR_SIZE as u32) as usize,
+        )
+    }
+
+    fn read_data(&self, volume: &Volume) -> Step<()> {
+        loop {
+            let file = self.file.get();
+            let done = self.done.get();
+            let len = cmp::min(
+                self.len.get() - done,
+                (file.size - cmp::min(file.position, file.size)) as usize,
+            );
+            if len == 0 {
+                return Ok(());
+            }
+
+            let cluster = self
+                .seek_cluster(volume)?
+                .ok_or(Stop::Error(ErrorCode::FAIL))?;
+            let (sector, offset) = Self::data_sector(volume, cluster, file.position);
+            let len = cmp::min(len, SECTOR_SIZE - offset);
+            self.sector(sector, |buf| {
+                self.buffer.map(|buffer| {
+                    buffer[done..done + len].copy_from_slice(&buf[offset..offset + len]);
+                });
+            })?;
+
+            let mut file = self.file.get();
+            file.position += len as u32;
+            self.file.set(file);
+            self.done.set(done + len);
+        }
+    }
+
+    fn write_data(&self, volume: &Volume) -> Step<()> {
+        loop {
+            let file = self.file.get();
+            let done = self.done.get();
+            let len = self.len.get() - done;
+            if len == 0 {
+                return Ok(());
+            }
+
+            let cluster = match self.seek_cluster_or_extend(volume)? {
+                Some(cluster) => cluster,
+                None => {
+                    let previous = if volume.is_valid_cluster(file.first_cluster) {
+                        file.cluster
+                    } else {
+                        0
+                    };
+                    let cluster = match self.alloc_cluster(volume, previous) {
+                        Ok(cluster) => cluster,
+                        // Report the bytes written before the volume was full
+                        Err(Stop::Error(ErrorCode::NOMEM)) if done > 0 => {
+                            self.len.set(done);
+                            return Ok(());
+                        }
+                        Err(e) => return Err(e),
+                    };
+
+                    let mut file = self.file.get();
+                    if previous == 0 {
+                        file.first_cluster = cluster;
+                        file.cluster_index = 0;
+                    } else {
+                        file.cluster_index += 1;
+                    }
+                    file.cluster = cluster;
+                    self.file.set(file);
+                    self.extending.set(false);
+                    continue;
+                }
+            };
+
+            let (sector, offset) = Self::data_sector(volume, cluster, file.position);
+            let len = cmp::min(len, SECTOR_SIZE - offset);
+            // Sectors past the end of the file don't need to be read first
+            if file.position - offset as u32 >= file.size {
+                self.new_sector(sector)?;
+            }
+            self.buffer.map_or(Err(ErrorCode::FAIL.into()), |buffer| {
+                self.update(sector, offset, &buffer[done..done + len])
+            })?;
+
+            let mut file = self.file.get();
+            file.position += len as u32;
+            file.size = cmp::max(file.size, file.position);
+            self.file.set(file);
+            self.done.set(done + len);
+        }
+    }
+
+    /// Read the entry of `cluster` in the FAT.
+    fn fat_get(&self, volume: &Volume, cluster: u32) -> Step<u32> {
+        let start = volume.fat_start;
+        match volume.fat_type {
+            FatType::Fat12 => {
+                // Entries are 12 bits long, and can span two sectors
+                let offset = cluster + cluster / 2;
+                let low = self.fat_byte(start, offset)?;
+                let high = self.fat_byte(start, offset + 1)?;
+                let value = u16::from_le_bytes([low, high]) as u32;
+                Ok(if cluster & 1 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xFFF
+                })
+            }
+            FatType::Fat16 => {
+                let offset = cluster * 2;
+                let i = (offset % SECTOR_SIZE as u32) as usize;
+                self.sector(start + offset / SECTOR_SIZE as u32, |buf| {
+                    u16::from_le_bytes([buf[i], buf[i + 1]]) as u32
+                })
+            }
+            FatType::Fat32 => {
+                let offset = cluster * 4;
+                let i = (offset % SECTOR_SIZE as u32) as usize;
+                self.sector(start + offset / SECTOR_SIZE as u32, |buf| {
+                    u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]) & 0x0FFF_FFFF
+                })
+            }
+        }
+    }
+
+    fn fat_byte(&self, start: u32, offset: u32) -> Step<u8> {
+        let i = (offset % SECTOR_SIZE as u32) as usize;
+        self.sector(start + offset / SECTOR_SIZE as u32, |buf| buf[i])
+    }
+
+    /// Set the entry of `cluster` in all FATs.
+    fn fat_set(&self, volume: &Volume, cluster: u32, value: u32) -> Step<()> {
+        while self.fats_set.get() < volume.num_fats {
+            let start = volume.fat_start + self.fats_set.get() * volume.fat_sectors;
+            match volume.fat_type {
+                FatType::Fat12 => {
+                    // Entries are 12 bits long, and share a byte with the
+                    // entry next to them
+                    let offset = cluster + cluster / 2;
+                    let (low, high) = if cluster & 1 == 1 {
+                        let low = self.fat_byte(start, offset)?;
+                        ((low & 0x0F) | (value << 4) as u8, (value >> 4) as u8)
+                    } else {
+                        let high = self.fat_byte(start, offset + 1)?;
+                        (value as u8, (high & 0xF0) | ((value >> 8) as u8 & 0x0F))
+                    };
+                    self.set_fat_byte(start, offset, low)?;
+                    self.set_fat_byte(start, offset + 1, high)?;
+                }
+                FatType::Fat16 => {
+                    let offset = cluster * 2;
+                    self.update(
+                        start + offset / SECTOR_SIZE as u32,
+                        (offset % SECTOR_SIZE as u32) as usize,
+                        &(value as u16).to_le_bytes(),
+                    )?;
+                }
+                FatType::Fat32 => {
+                    let offset = cluster * 4;
+                    let sector = start + offset / SECTOR_SIZE as u32;
+                    let i = (offset % SECTOR_SIZE as u32) as usize;
+                    // The top 4 bits are reserved
+                    let reserved = self.sector(sector, |buf| buf[i + 3] & 0xF0)?;
+                    let value = ((reserved as u32) << 24) | (value & 0x0FFF_FFFF);
+                    self.update(sector, i, &value.to_le_bytes())?;
+                }
+            }
+            self.fats_set.set(self.fats_set.get() + 1);
+        }
+        self.fats_set.set(0);
+        Ok(())
+    }
+
+    fn set_fat_byte(&self, start: u32, offset: u32, value: u8) -> Step<()> {
+        self.update(
+            start + offset / SECTOR_SIZE as u32,
+            (offset % SECTOR_SIZE as u32) as usize,
+            &[value],
+        )
+    }
+
+    /// Mark the free cluster count of the FSInfo sector unknown before the
+    /// FAT is changed, so that it doesn't have to be kept up to date.
+    fn clear_fs_info(&self, volume: &Volume) -> Step<()> {
+        if volume.fs_info == 0 || self.fs_info_cleared.get() {
+            return Ok(());
+        }
+        let valid = self.sector(volume.fs_info, |buf| {
+            buf[0..4] == [0x52, 0x52, 0x61, 0x41] && buf[484..488] == [0x72, 0x72, 0x41, 0x61]
+        })?;
+        if valid {
+            self.update(volume.fs_info, 488, &[0xFF; 8])?;
+        }
+        self.fs_info_cleared.set(true);
+        Ok(())
+    }
+
+    /// Find a free cluster, mark it as the end of a chain, and link it to
+    /// `previous` unless that is 0.
+    fn alloc_cluster(&self, volume: &Volume, previous: u32) -> Step<u32> {
+        self.clear_fs_info(volume)?;
+        if self.allocating.get().is_none() {
+            loop {
+                if self.searched.get() >= volume.cluster_count {
+                    self.searched.set(0);
+                    return Err(ErrorCode::NOMEM.into());
+                }
+                let cluster = self.next_free.get();
+                let value = self.fat_get(volume, cluster)?;
+                self.next_free.set(if volume.is_valid_cluster(cluster + 1) {
+                    cluster + 1
+                } else {
+                    2
+                });
+                self.searched.set(self.searched.get() + 1);
+                if value == 0 {
+                    self.allocating.set(Some(cluster));
+                    break;
+                }
+            }
+        }
+
+        let cluster = self.allocating.get().unwrap_or_default();
+        if !self.allocated.get() {
+            self.fat_set(volume, cluster, volume.end_of_chain())?;
+            self.allocated.set(true);
+        }
+        if previous != 0 {
+            self.fat_set(volume, previous, cluster)?;
+        }
+        self.allocating.set(None);
+        self.allocated.set(false);
+        self.searched.set(0);
+        Ok(cluster)
+    }
+
+    /// Free the clusters of the chain that starts at `chain`.
+    fn free_chain(&self, volume: &Volume) -> Step<()> {
+        loop {
+            let cluster = self.chain.get();
+            if !volume.is_valid_cluster(cluster) {
+                return Ok(());
+            }
+            self.clear_fs_info(volume)?;
+            let next = match self.chain_next.get() {
+                Some(next) => next,
+                None => {
+                    let next = self.fat_get(volume, cluster)?;
+                    self.chain_next.set(Some(next));
+                    next
+                }
+            };
+            self.fat_set(volume, cluster, 0)?;
+            self.chain.set(next);
+            self.chain_next.set(None);
+        }
+    }
+
+    /// Run `f` on the contents of `sector`, after reading it if it isn't
+    /// cached.
+    fn sector<R, F: FnOnce(&[u8]) -> R>(&self, sector: u32, f: F) -> Step<R> {
+        let entry = &self.cache[self.load(sector, true)?];
+        entry
+            .buffer
+            .map(|buf| f(buf))
+            .ok_or(Stop::Error(ErrorCode::FAIL))
+    }
+
+    /// Copy `data` to `offset` in `sector`, after reading it if it isn't
+    /// cached. The sector is only written back if this changed it, so that
+    /// steps can be repeated.
+    fn update(&self, sector: u32, offset: usize, data: &[u8]) -> Step<()> {
+        let entry = &self.cache[self.load(sector, true)?];
+        entry
+            .buffer
+            .map(|buf| {
+                let dest = &mut buf[offset..offset + data.len()];
+                if dest != data {
+                    dest.copy_from_slice(data);
+                    entry.dirty.set(true);
+                }
+            })
+            .ok_or(Stop::Error(ErrorCode::FAIL))
+    }
+
+    /// Replace the contents of `sector` with zeros, without reading it.
+    fn new_sector(&self, sector: u32) -> Step<()> {
+        let entry = &self.cache[self.load(sector, false)?];
+        entry.dirty.set(true);
+        entry
+            .buffer
+            .map(|buf| buf.iter_mut().for_each(|b| *b = 0))
+            .ok_or(Stop::Error(ErrorCode::FAIL))
+    }
+
+    /// Find `sector` in the cache, or replace the least recently used
+    /// sector with it. Returns the index of the cache entry once it holds
+    /// the sector.
+    fn load(&self, sector: u32, read: bool) -> Step<usize> {
+        self.clock.set(self.clock.get().wrapping_add(1));
+        if let Some(i) = self
+            .cache
+            .iter()
+            .position(|entry| entry.sector.get() == Some(sector))
+        {
+            self.cache[i].used.set(self.clock.get());
+            return Ok(i);
+        }
+
+        let i = if self.cache[0].used.get() <= self.cache[1].used.get() {
+            0
+        } else {
+            1
+        };
+        let entry = &self.cache[i];
+        if entry.dirty.get() {
+            return self.start_io(i, entry.sector.get().unwrap_or_default(), true);
+        }
+        if read {
+            entry.sector.set(None);
+            return self.start_io(i, sector, false);
+        }
+        entry.sector.set(Some(sector));
+        entry.used.set(self.clock.get());
+        Ok(i)
+    }
+
+    /// Write all modified sectors.
+    fn flush(&self) -> Step<()> {
+        for (i, entry) in self.cache.iter().enumerate() {
+            if entry.dirty.get() {
+                self.start_io(i, entry.sector.get().unwrap_or_default(), true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start reading or writing cache entry `i`. Returns `Stop::Io` once
+    /// started.
+    fn start_io<T>(&self, i: usize, sector: u32, write: bool) -> Step<T> {
+        let buffer = self.cache[i]
+            .buffer
+            .take()
+            .ok_or(Stop::Error(ErrorCode::FAIL))?;
+        let result = if write {
+            self.block.write_block(sector, buffer)
+        } else {
+            self.block.read_block(sector, buffer)
+        };
+        match result {
+            Ok(()) => {
+                self.io.set(i);
+                self.io_sector.set(sector);
+                self.io_write.set(write);
+                Err(Stop::Io)
+            }
+            Err((e, buffer)) => {
+                self.cache[i].buffer.replace(buffer);
+                // The device was removed or reset, it might hold another
+                // volume once it is back
+                if e == ErrorCode::UNINSTALLED || e == ErrorCode::RESERVE {
+                    self.unmount();
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Handle the completion of a read or write of a cached sector.
+    fn io_done(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        let i = match self.io.take() {
+            Some(i) => i,
+            None => return,
+        };
+        let entry = &self.cache[i];
+        entry.buffer.replace(buffer);
+
+        if result.is_err() {
+            self.unmount();
+            self.finish(Err(ErrorCode::FAIL));
+            return;
+        }
+        if self.io_write.get() {
+            entry.dirty.set(false);
+        } else {
+            entry.sector.set(Some(self.io_sector.get()));
+            entry.used.set(self.clock.get());
+        }
+        self.run();
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> BlockStorageClient for FatFs<'a, B> {
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.io_done(buffer, result);
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.io_done(buffer, result);
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> DynamicDeferredCallClient for FatFs<'a, B> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.operation.get() != Operation::None && self.io.is_none() {
+            self.run();
+        }
+    }
+}
+
+/// Convert a name to the 11 byte form stored in directory entries.
+fn short_name(name: &[u8]) -> Result<[u8; 11], ErrorCode> {
+    let mut short = [b' '; 11];
+    let mut parts = name.splitn(2, |c| *c == b'.');
+    let base = parts.next().unwrap_or(&[]);
+    let extension = parts.next().unwrap_or(&[]);
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return Err(ErrorCode::INVAL);
+    }
+
+    let (short_base, short_extension) = short.split_at_mut(8);
+    for (dest, c) in short_base
+        .iter_mut()
+        .zip(base.iter())
+        .chain(short_extension.iter_mut().zip(extension.iter()))
+    {
+        let c = c.to_ascii_uppercase();
+        if !(c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c)) {
+            return Err(ErrorCode::INVAL);
+        }
+        *dest = c;
+    }
+    Ok(short)
+}
+
+/// The 11 byte name of a directory entry.
+fn entry_name(entry: &[u8]) -> [u8; 11] {
+    let mut name = [0; 11];
+    name.copy_from_slice(&entry[..11]);
+    if name[0] == ENTRY_KANJI_E5 {
+        name[0] = ENTRY_FREE;
+    }
+    name
+}
+
+/// Convert an 11 byte name to `NAME.EXT`, and return its length.
+fn format_name(name: &[u8; 11], out: &mut [u8]) -> usize {
+    let mut len = 0;
+    for &c in name[..8].iter().take_while(|c| **c != b' ') {
+        out[len] = c;
+        len += 1;
+    }
+    if name[8] != b' ' {
+        out[len] = b'.';
+        len += 1;
+        for &c in name[8..].iter().take_while(|c| **c != b' ') {
+            out[len] = c;
+            len += 1;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use std::boxed::Box;
+    use std::string::String;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A block device in RAM, which completes reads and writes when the test
+    /// calls `complete()`.
+    struct RamDisk {
+        image: RefCell<Vec<u8>>,
+        client: OptionalCell<&'static dyn BlockStorageClient>,
+        pending: Cell<Option<(u32, bool)>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl RamDisk {
+        fn complete(&self) -> bool {
+            let (block, write) = match self.pending.take() {
+                Some(pending) => pending,
+                None => return false,
+            };
+            let buffer = self.buffer.take().unwrap();
+            let range = block as usize * SECTOR_SIZE..(block as usize + 1) * SECTOR_SIZE;
+            if write {
+                self.image.borrow_mut()[range].copy_from_slice(&buffer[..SECTOR_SIZE]);
+                self.client
+                    .map(|client| client.write_complete(buffer, Ok(())));
+            } else {
+                buffer[..SECTOR_SIZE].copy_from_slice(&self.image.borrow()[range]);
+                self.client
+                    .map(|client| client.read_complete(buffer, Ok(())));
+            }
+            true
+        }
+
+        fn start(
+            &self,
+            block: u32,
+            buffer: &'static mut [u8],
+            write: bool,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            if self.pending.get().is_some() {
+                return Err((ErrorCode::BUSY, buffer));
+            }
+            if block >= self.block_count() {
+                return Err((ErrorCode::INVAL, buffer));
+            }
+            self.pending.set(Some((block, write)));
+            self.buffer.replace(buffer);
+            Ok(())
+        }
+    }
+
+    impl BlockStorage<'static> for RamDisk {
+        fn set_client(&self, client: &'static dyn BlockStorageClient) {
+            self.client.set(client);
+        }
+
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn block_count(&self) -> u32 {
+            (self.image.borrow().len() / SECTOR_SIZE) as u32
+        }
+
+        fn read_block(
+            &self,
+            block: u32,
+            buffer: &'static mut [u8],
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.start(block, buffer, false)
+        }
+
+        fn write_block(
+            &self,
+            block: u32,
+            buffer: &'static mut [u8],
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            self.start(block, buffer, true)
+        }
+    }
+
+    struct TestClient {
+        opened: Cell<Option<Result<File, ErrorCode>>>,
+        truncated: Cell<Option<Result<File, ErrorCode>>>,
+        removed: Cell<Option<Result<(), ErrorCode>>>,
+        transferred: Cell<Option<(File, Result<usize, ErrorCode>)>>,
+        listed: Cell<Option<(File, Result<Option<DirEntry>, ErrorCode>)>>,
+        buffer: TakeCell<'static, [u8]>,
+    }
+
+    impl TestClient {
+        fn new() -> TestClient {
+            TestClient {
+                opened: Cell::new(None),
+                truncated: Cell::new(None),
+                removed: Cell::new(None),
+                transferred: Cell::new(None),
+                listed: Cell::new(None),
+                buffer: TakeCell::empty(),
+            }
+        }
+    }
+
+    impl FatFsClient for TestClient {
+        fn open_done(&self, result: Result<File, ErrorCode>) {
+            self.opened.set(Some(result));
+        }
+
+        fn truncate_done(&self, result: Result<File, ErrorCode>) {
+            self.truncated.set(Some(result));
+        }
+
+        fn remove_done(&self, result: Result<(), ErrorCode>) {
+            self.removed.set(Some(result));
+        }
+
+        fn read_done(
+            &self,
+            file: File,
+            buffer: &'static mut [u8],
+            result: Result<usize, ErrorCode>,
+        ) {
+            self.buffer.replace(buffer);
+            self.transferred.set(Some((file, result)));
+        }
+
+        fn write_done(
+            &self,
+            file: File,
+            buffer: &'static mut [u8],
+            result: Result<usize, ErrorCode>,
+        ) {
+            self.buffer.replace(buffer);
+            self.transferred.set(Some((file, result)));
+        }
+
+        fn read_dir_done(
+            &self,
+            file: File,
+            buffer: &'static mut [u8],
+            result: Result<Option<DirEntry>, ErrorCode>,
+        ) {
+            self.buffer.replace(buffer);
+            self.listed.set(Some((file, result)));
+        }
+    }
+
+    struct Test {
+        disk: &'static RamDisk,
+        fs: &'static FatFs<'static, RamDisk>,
+        client: &'static TestClient,
+        handle: DeferredCallHandle,
+    }
+
+    impl Test {
+        fn new(image: Vec<u8>) -> Test {
+            let disk: &'static RamDisk = Box::leak(Box::new(RamDisk {
+                image: RefCell::new(image),
+                client: OptionalCell::empty(),
+                pending: Cell::new(None),
+                buffer: TakeCell::empty(),
+            }));
+            let states = Box::leak(Box::new([DynamicDeferredCallClientState::default()]));
+            let deferred_caller = Box::leak(Box::new(DynamicDeferredCall::new(states)));
+            let fs: &'static FatFs<'static, RamDisk> = Box::leak(Box::new(FatFs::new(
+                disk,
+                Box::leak(Box::new([0; SECTOR_SIZE])),
+                Box::leak(Box::new([0; SECTOR_SIZE])),
+                deferred_caller,
+            )));
+            let handle = deferred_caller.register(fs).unwrap();
+            fs.initialize_callback_handle(handle);
+            disk.set_client(fs);
+            let client: &'static TestClient = Box::leak(Box::new(TestClient::new()));
+            fs.set_client(client);
+            Test {
+                disk,
+                fs,
+                client,
+                handle,
+            }
+        }
+
+        fn image(&self) -> Vec<u8> {
+            self.disk.image.borrow().clone()
+        }
+
+        /// Run the deferred call that starts the operation, and complete
+        /// reads and writes until it is done.
+        fn run(&self) {
+            self.fs.call(self.handle);
+            while self.disk.complete() {}
+        }
+
+        fn open(&self, path: &str, create: bool) -> Result<File, ErrorCode> {
+            self.fs.open(path.as_bytes(), create)?;
+            self.run();
+            self.client.opened.take().unwrap()
+        }
+
+        fn remove(&self, file: File) -> Result<(), ErrorCode> {
+            self.fs.remove(file)?;
+            self.run();
+            self.client.removed.take().unwrap()
+        }
+
+        fn truncate(&self, file: File) -> Result<File, ErrorCode> {
+            self.fs.truncate(file)?;
+            self.run();
+            self.client.truncated.take().unwrap()
+        }
+
+        fn write(&self, file: File, data: &[u8]) -> (File, Result<usize, ErrorCode>) {
+            let buffer = Box::leak(data.to_vec().into_boxed_slice());
+            self.fs.write(file, buffer, data.len()).unwrap();
+            self.run();
+            self.client.buffer.take().unwrap();
+            self.client.transferred.take().unwrap()
+        }
+
+        fn write_all(&self, file: File, data: &[u8]) -> File {
+            let (file, result) = self.write(file, data);
+            assert_eq!(result, Ok(data.len()));
+            file
+        }
+
+        fn read(&self, file: File, len: usize) -> (File, Vec<u8>) {
+            let buffer = Box::leak(vec![0; len].into_boxed_slice());
+            self.fs.read(file, buffer, len).unwrap();
+            self.run();
+            let (file, result) = self.client.transferred.take().unwrap();
+            let buffer = self.client.buffer.take().unwrap();
+            (file, buffer[..result.unwrap()].to_vec())
+        }
+
+        fn list(&self, mut dir: File) -> Vec<(String, u32)> {
+            let mut entries = Vec::new();
+            loop {
+                let buffer = Box::leak(vec![0; MAX_NAME_LEN].into_boxed_slice());
+                self.fs.read_dir(dir, buffer).unwrap();
+                self.run();
+                let (file, result) = self.client.listed.take().unwrap();
+                let buffer = self.client.buffer.take().unwrap();
+                dir = file;
+                match result.unwrap() {
+                    Some(entry) => entries.push((
+                        String::from_utf8(buffer[..entry.name_len].to_vec()).unwrap(),
+                        entry.size,
+                    )),
+                    None => return entries,
+                }
+            }
+        }
+    }
+
+    /// The layout of a volume created by `format()`.
+    struct Geometry {
+        fat_type: FatType,
+        sectors_per_cluster: usize,
+        fat_start: usize,
+        fat_sectors: usize,
+        root_start: usize,
+        data_start: usize,
+    }
+
+    impl Geometry {
+        fn fat_offset(&self, fat: usize, cluster: u32) -> usize {
+            let start = (self.fat_start + fat * self.fat_sectors) * SECTOR_SIZE;
+            start
+                + match self.fat_type {
+                    FatType::Fat12 => cluster as usize * 3 / 2,
+                    FatType::Fat16 => cluster as usize * 2,
+                    FatType::Fat32 => cluster as usize * 4,
+                }
+        }
+
+        fn fat_entry(&self, image: &[u8], fat: usize, cluster: u32) -> u32 {
+            let i = self.fat_offset(fat, cluster);
+            match self.fat_type {
+                FatType::Fat12 => {
+                    let value = u16::from_le_bytes([image[i], image[i + 1]]) as u32;
+                    if cluster & 1 == 1 {
+                        value >> 4
+                    } else {
+                        value & 0xFFF
+                    }
+                }
+                FatType::Fat16 => u16::from_le_bytes([image[i], image[i + 1]]) as u32,
+                FatType::Fat32 => {
+                    u32::from_le_bytes([image[i], image[i + 1], image[i + 2], image[i + 3]])
+                        & 0x0FFF_FFFF
+                }
+            }
+        }
+
+        fn set_fat_entry(&self, image: &mut [u8], cluster: u32, value: u32) {
+            for fat in 0..2 {
+                let i = self.fat_offset(fat, cluster);
+                match self.fat_type {
+                    FatType::Fat12 if cluster & 1 == 1 => {
+                        image[i] = (image[i] & 0x0F) | (value << 4) as u8;
+                        image[i + 1] = (value >> 4) as u8;
+                    }
+                    FatType::Fat12 => {
+                        image[i] = value as u8;
+                        image[i + 1] = (image[i + 1] & 0xF0) | ((value >> 8) & 0x0F) as u8;
+                    }
+                    FatType::Fat16 => {
+                        image[i..i + 2].copy_from_slice(&(value as u16).to_le_bytes())
+                    }
+                    FatType::Fat32 => image[i..i + 4].copy_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+
+        /// The clusters of the chain that starts at `cluster`.
+        fn chain(&self, image: &[u8], mut cluster: u32) -> Vec<u32> {
+            let mut chain = Vec::new();
+            while cluster >= 2 && self.fat_entry(image, 0, cluster) != 0 && chain.len() < 100000 {
+                chain.push(cluster);
+                cluster = self.fat_entry(image, 0, cluster);
+                if cluster >= 0x0FF8 && self.fat_type == FatType::Fat12
+                    || cluster >= 0xFFF8 && self.fat_type == FatType::Fat16
+                    || cluster >= 0x0FFF_FFF8
+                {
+                    break;
+                }
+            }
+            chain
+        }
+
+        fn cluster(&self, cluster: u32) -> usize {
+            (self.data_start + (cluster as usize - 2) * self.sectors_per_cluster) * SECTOR_SIZE
+        }
+
+        /// The byte offset of the root directory.
+        fn root(&self) -> usize {
+            match self.fat_type {
+                FatType::Fat32 => self.cluster(2),
+                _ => self.root_start * SECTOR_SIZE,
+            }
+        }
+    }
+
+    /// Create an image with an empty volume of `sectors` sectors, like a PC
+    /// would. If `start` isn't 0, the volume is in the first partition of a
+    /// partition table, which starts at sector `start`.
+    fn format(
+        fat_type: FatType,
+        sectors: usize,
+        sectors_per_cluster: usize,
+        root_entries: usize,
+        start: usize,
+    ) -> (Vec<u8>, Geometry) {
+        let mut image = vec![0; (start + sectors) * SECTOR_SIZE];
+        let reserved = if fat_type == FatType::Fat32 { 32 } else { 1 };
+        let root_sectors = root_entries * ENTRY_SIZE / SECTOR_SIZE;
+        let bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        let mut fat_sectors = 1;
+        loop {
+            let clusters =
+                (sectors - reserved - root_sectors - 2 * fat_sectors) / sectors_per_cluster;
+            let needed = ((clusters + 2) * bits / 8 + SECTOR_SIZE) / SECTOR_SIZE;
+            if needed <= fat_sectors {
+                break;
+            }
+            fat_sectors = needed;
+        }
+
+        if start != 0 {
+            let entry = &mut image[446..462];
+            entry[4] = if fat_type == FatType::Fat32 {
+                0x0C
+            } else {
+                0x06
+            };
+            entry[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&(sectors as u32).to_le_bytes());
+            image[510] = 0x55;
+            image[511] = 0xAA;
+        }
+
+        let boot = &mut image[start * SECTOR_SIZE..(start + 1) * SECTOR_SIZE];
+        boot[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        boot[3..11].copy_from_slice(b"MSWIN4.1");
+        boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[13] = sectors_per_cluster as u8;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&(root_entries as u16).to_le_bytes());
+        if sectors < 0x10000 && fat_type != FatType::Fat32 {
+            boot[19..21].copy_from_slice(&(sectors as u16).to_le_bytes());
+        } else {
+            boot[32..36].copy_from_slice(&(sectors as u32).to_le_bytes());
+        }
+        boot[21] = 0xF8;
+        boot[28..32].copy_from_slice(&(start as u32).to_le_bytes());
+        if fat_type == FatType::Fat32 {
+            boot[36..40].copy_from_slice(&(fat_sectors as u32).to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+            boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+            boot[66] = 0x29;
+            boot[82..90].copy_from_slice(b"FAT32   ");
+        } else {
+            boot[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+            boot[38] = 0x29;
+            boot[54..62].copy_from_slice(if fat_type == FatType::Fat12 {
+                b"FAT12   "
+            } else {
+                b"FAT16   "
+            });
+        }
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        if fat_type == FatType::Fat32 {
+            let fs_info = &mut image[(start + 1) * SECTOR_SIZE..(start + 2) * SECTOR_SIZE];
+            fs_info[0..4].copy_from_slice(&[0x52, 0x52, 0x61, 0x41]);
+            fs_info[484..488].copy_from_slice(&[0x72, 0x72, 0x41, 0x61]);
+            fs_info[488..496].copy_from_slice(&[0x10, 0, 0, 0, 3, 0, 0, 0]);
+            fs_info[510] = 0x55;
+            fs_info[511] = 0xAA;
+        }
+
+        let fat_start = start + reserved;
+        let root_start = fat_start + 2 * fat_sectors;
+        let geometry = Geometry {
+            fat_type,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            root_start,
+            data_start: root_start + root_sectors,
+        };
+        let end_of_chain = match fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        };
+        // The first entry holds the media type 0xF8
+        geometry.set_fat_entry(&mut image, 0, end_of_chain & !0x07);
+        geometry.set_fat_entry(&mut image, 1, end_of_chain);
+        if fat_type == FatType::Fat32 {
+            geometry.set_fat_entry(&mut image, 2, end_of_chain);
+        }
+        (image, geometry)
+    }
+
+    fn dir_entry(name: &[u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+        let mut entry = [0; ENTRY_SIZE];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    fn pattern(len: usize, seed: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 7 + seed) % 251) as u8).collect()
+    }
+
+    fn names(entries: &[(String, u32)]) -> Vec<&str> {
+        entries.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn fat12_write_read() {
+        let (image, geometry) = format(FatType::Fat12, 2880, 1, 224, 0);
+        let test = Test::new(image);
+
+        let file = test.open("hello.txt", true).unwrap();
+        assert_eq!(file.size(), 0);
+        let file = test.write_all(file, b"Hello, world!");
+        assert_eq!((file.size(), file.position()), (13, 13));
+
+        let (file, data) = test.read(file, 64);
+        assert!(data.is_empty());
+        let mut file = file;
+        file.seek(7).unwrap();
+        let (_, data) = test.read(file, 64);
+        assert_eq!(data, b"world!");
+
+        // The file is stored the way a PC expects it
+        let image = test.image();
+        let root = geometry.root();
+        assert_eq!(&image[root..root + 11], b"HELLO   TXT");
+        assert_eq!(image[root + 11], ATTR_ARCHIVE);
+        assert_eq!(&image[root + 26..root + 32], &[2, 0, 13, 0, 0, 0]);
+        assert_eq!(
+            &image[geometry.cluster(2)..geometry.cluster(2) + 13],
+            b"Hello, world!"
+        );
+        assert_eq!(geometry.fat_entry(&image, 0, 2), 0xFFF);
+        assert_eq!(geometry.fat_entry(&image, 1, 2), 0xFFF);
+    }
+
+    #[test]
+    fn fat12_long_file() {
+        let (image, geometry) = format(FatType::Fat12, 2880, 1, 224, 0);
+        let test = Test::new(image);
+
+        // Long enough for FAT entries that span two sectors of the FAT
+        let data = pattern(400 * SECTOR_SIZE + 100, 1);
+        let mut file = test.open("big.bin", true).unwrap();
+        for chunk in data.chunks(4096) {
+            file = test.write_all(file, chunk);
+        }
+        assert_eq!(file.size() as usize, data.len());
+
+        let image = test.image();
+        let chain = geometry.chain(&image, 2);
+        assert_eq!(chain.len(), 401);
+        assert_eq!(chain, (2..403).collect::<Vec<u32>>());
+
+        file.seek(0).unwrap();
+        let mut read = Vec::new();
+        loop {
+            let (next, chunk) = test.read(file, 3000);
+            if chunk.is_empty() {
+                break;
+            }
+            file = next;
+            read.extend_from_slice(&chunk);
+        }
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn fat16_seek_overwrite() {
+        let (image, geometry) = format(FatType::Fat16, 8400, 2, 512, 0);
+        let test = Test::new(image);
+
+        let data = pattern(3000, 2);
+        let mut file = test.open("data.bin", true).unwrap();
+        for chunk in data.chunks(300) {
+            file = test.write_all(file, chunk);
+        }
+        assert_eq!(geometry.chain(&test.image(), 2), vec![2, 3, 4]);
+
+        file.seek(700).unwrap();
+        let (_, read) = test.read(file, 600);
+        assert_eq!(read, &data[700..1300]);
+
+        // Overwrite across a sector boundary without changing the size
+        file.seek(1020).unwrap();
+        let mut file = test.write_all(file, &[0xAA; 10]);
+        assert_eq!((file.size(), file.position()), (3000, 1030));
+        assert!(file.seek(3001).is_err());
+
+        let mut expected = data.clone();
+        expected[1020..1030].copy_from_slice(&[0xAA; 10]);
+        let test = Test::new(test.image());
+        let file = test.open("/DATA.BIN", false).unwrap();
+        assert_eq!(file.size(), 3000);
+        let (_, read) = test.read(file, 4000);
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn list_remove() {
+        let (image, geometry) = format(FatType::Fat12, 2880, 1, 224, 0);
+        let test = Test::new(image);
+
+        test.open("a.txt", true).unwrap();
+        let b = test.open("b.txt", true).unwrap();
+        test.write_all(b, &pattern(600, 3));
+        test.open("c", true).unwrap();
+
+        let root = test.open("", false).unwrap();
+        assert!(root.is_dir());
+        assert_eq!(
+            test.list(root),
+            vec![
+                (String::from("A.TXT"), 0),
+                (String::from("B.TXT"), 600),
+                (String::from("C"), 0)
+            ]
+        );
+
+        let b = test.open("b.txt", false).unwrap();
+        test.remove(b).unwrap();
+        assert_eq!(names(&test.list(root)), vec!["A.TXT", "C"]);
+        assert_eq!(test.open("b.txt", false).err(), Some(ErrorCode::NOSUPPORT));
+        let image = test.image();
+        assert_eq!(geometry.fat_entry(&image, 0, 2), 0);
+        assert_eq!(geometry.fat_entry(&image, 1, 3), 0);
+
+        // The entry of the removed file is reused
+        test.open("d.txt", true).unwrap();
+        assert_eq!(names(&test.list(root)), vec!["A.TXT", "D.TXT", "C"]);
+    }
+
+    #[test]
+    fn long_names_subdirectories() {
+        let (mut image, geometry) = format(FatType::Fat16, 8400, 1, 512, 0);
+        let root = geometry.root();
+        let mut long_name = [0xFF; ENTRY_SIZE];
+        long_name[0] = 0x41;
+        long_name[11] = ATTR_LONG_NAME;
+        image[root..root + 32].copy_from_slice(&long_name);
+        image[root + 32..root + 64].copy_from_slice(&dir_entry(b"LONGFI~1TXT", ATTR_ARCHIVE, 0, 0));
+        image[root + 64..root + 96].copy_from_slice(&dir_entry(
+            b"DOCS       ",
+            ATTR_DIRECTORY,
+            2,
+            0,
+        ));
+        geometry.set_fat_entry(&mut image, 2, 0xFFFF);
+        let docs = geometry.cluster(2);
+        image[docs..docs + 32].copy_from_slice(&dir_entry(b".          ", ATTR_DIRECTORY, 2, 0));
+        image[docs + 32..docs + 64].copy_from_slice(&dir_entry(
+            b"..         ",
+            ATTR_DIRECTORY,
+            0,
+            0,
+        ));
+        let test = Test::new(image);
+
+        let root_dir = test.open("/", false).unwrap();
+        assert_eq!(names(&test.list(root_dir)), vec!["LONGFI~1.TXT", "DOCS/"]);
+
+        let notes = test.open("docs/notes.txt", true).unwrap();
+        test.write_all(notes, b"notes");
+        let docs_dir = test.open("docs", false).unwrap();
+        assert_eq!(test.list(docs_dir), vec![(String::from("NOTES.TXT"), 5)]);
+        let notes = test.open("/docs/notes.txt", false).unwrap();
+        assert_eq!(test.read(notes, 10).1, b"notes");
+
+        assert_eq!(
+            test.open("longfi~1.txt/x", true).err(),
+            Some(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            test.open("docs/missing/x", true).err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+
+        // Removing a file also removes its long name
+        let file = test.open("longfi~1.txt", false).unwrap();
+        test.remove(file).unwrap();
+        let image = test.image();
+        assert_eq!(image[root], ENTRY_FREE);
+        assert_eq!(image[root + 32], ENTRY_FREE);
+        assert_eq!(names(&test.list(root_dir)), vec!["DOCS/"]);
+    }
+
+    #[test]
+    fn fat32_partition() {
+        let (image, geometry) = format(FatType::Fat32, 66800, 1, 0, 64);
+        let test = Test::new(image);
+
+        // The root directory holds 16 entries per cluster, so it grows
+        for i in 0..20 {
+            let file = test.open(&std::format!("file{}.txt", i), true).unwrap();
+            test.write_all(file, &pattern(10 + i, i));
+        }
+        let root = test.open("", false).unwrap();
+        let entries = test.list(root);
+        assert_eq!(entries.len(), 20);
+        for (i, (name, size)) in entries.iter().enumerate() {
+            assert_eq!(name, &std::format!("FILE{}.TXT", i));
+            assert_eq!(*size as usize, 10 + i);
+        }
+
+        let image = test.image();
+        assert_eq!(geometry.chain(&image, 2).len(), 2);
+        // The free cluster count isn't kept up to date
+        let fs_info = 65 * SECTOR_SIZE;
+        assert_eq!(&image[fs_info + 488..fs_info + 496], &[0xFF; 8]);
+
+        let test = Test::new(image);
+        let file = test.open("file19.txt", false).unwrap();
+        assert_eq!(test.read(file, 100).1, pattern(29, 19));
+    }
+
+    #[test]
+    fn truncate_full_volume() {
+        // 8 clusters of 512 bytes
+        let (image, geometry) = format(FatType::Fat12, 12, 1, 16, 0);
+        let test = Test::new(image);
+
+        let file = test.open("full", true).unwrap();
+        let (file, result) = test.write(file, &pattern(5000, 4));
+        assert_eq!(result, Ok(4096));
+        assert_eq!(file.size(), 4096);
+        let (file, result) = test.write(file, &[1]);
+        assert_eq!(result, Err(ErrorCode::NOMEM));
+
+        let file = test.truncate(file).unwrap();
+        assert_eq!((file.size(), file.position()), (0, 0));
+        let image = test.image();
+        assert!((2..10).all(|cluster| geometry.fat_entry(&image, 0, cluster) == 0));
+
+        let file = test.write_all(file, b"again");
+        let test = Test::new(test.image());
+        let file2 = test.open("full", false).unwrap();
+        assert_eq!(file2.size(), file.size());
+    }
+
+    #[test]
+    fn errors() {
+        let test = Test::new(vec![0; 64 * SECTOR_SIZE]);
+        assert_eq!(test.open("a", false).err(), Some(ErrorCode::NODEVICE));
+
+        let (image, _) = format(FatType::Fat12, 2880, 1, 224, 0);
+        let test = Test::new(image);
+        assert_eq!(
+            test.open("missing", false).err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            test.open("toolongname.txt", true).err(),
+            Some(ErrorCode::INVAL)
+        );
+        assert_eq!(test.open("a*b", true).err(), Some(ErrorCode::INVAL));
+        assert_eq!(
+            test.open("a/b/c/d/e/f/g/h/i", true).err(),
+            Some(ErrorCode::SIZE)
+        );
+
+        let file = test.open("file", true).unwrap();
+        let root = test.open("", false).unwrap();
+        let mut dir = root;
+        assert_eq!(dir.seek(1), Err(ErrorCode::INVAL));
+        assert_eq!(test.fs.truncate(root), Err(ErrorCode::INVAL));
+        assert_eq!(test.fs.remove(root), Err(ErrorCode::INVAL));
+        let buffer = Box::leak(vec![0; 16].into_boxed_slice());
+        let (error, buffer) = test.fs.write(root, buffer, 1).unwrap_err();
+        assert_eq!(error, ErrorCode::INVAL);
+        let (error, _) = test.fs.read_dir(file, buffer).unwrap_err();
+        assert_eq!(error, ErrorCode::INVAL);
+
+        // One operation at a time
+        test.fs.open(b"file", false).unwrap();
+        assert_eq!(test.fs.open(b"file", false), Err(ErrorCode::BUSY));
+        test.run();
+        assert!(test.client.opened.take().unwrap().is_ok());
+    }
+}
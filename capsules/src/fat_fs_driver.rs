@@ -0,0 +1,618 @@
+//! Gives processes access to the files of a FAT file system.
+//!
+//! This capsule exposes the files and directories of a `capsules::fat_fs`
+//! volume, such as one on an SD card, to userspace. Processes open files by
+//! path and get a handle, which they use to read, write, seek and list
+//! directories. Files can also be removed by path.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Userspace            |
+//! |                       |
+//! +-----------------------+
+//!
+//!    Syscalls
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FAT driver (this)    |
+//! |                       |
+//! +-----------------------+
+//!
+//!    FatFsClient
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FAT                  |
+//! |                       |
+//! +-----------------------+
+//!
+//! Handles
+//! -------
+//!
+//! The open files of a process are stored in its grant, so they are closed
+//! when the process stops. A file can only be open once at a time, across
+//! all processes, so that the size and the contents seen through a handle are
+//! always up to date. Directories can be open any number of times. Open files
+//! can't be removed.
+//!
+//! Data is copied between the process and the file system in chunks of the
+//! size of the buffer the capsule is given.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let fat_fs_driver = static_init!(
+//!     capsules::fat_fs_driver::FatFsDriver<
+//!         'static,
+//!         capsules::sdcard::SDCard<'static, A>,
+//!     >,
+//!     capsules::fat_fs_driver::FatFsDriver::new(
+//!         fat_fs,
+//!         board_kernel.create_grant(capsules::fat_fs_driver::DRIVER_NUM, &grant_cap),
+//!         static_init!([u8; 512], [0; 512]),
+//!     )
+//! );
+//! fat_fs.set_client(fat_fs_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::block_storage::BlockStorage;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::fat_fs::{DirEntry, FatFs, FatFsClient, File};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FatFs as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The path of the file to open or remove.
+    pub const PATH: usize = 0;
+    /// The data to write with command 4.
+    pub const DATA: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer data and directory entries are read into.
+    pub const DATA: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// Called when an operation completes.
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// The number of files a process can have open at the same time.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// The maximum length of a path.
+pub const MAX_PATH_LEN: usize = 128;
+
+/// Flags of the open command.
+const OPEN_CREATE: usize = 1 << 0;
+const OPEN_TRUNCATE: usize = 1 << 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Open { flags: usize },
+    Read { handle: usize },
+    Write { handle: usize },
+    ReadDir { handle: usize },
+    Remove,
+}
+
+#[derive(Default)]
+pub struct App {
+    files: [Option<File>; MAX_OPEN_FILES],
+    pending: Option<Operation>,
+}
+
+pub struct FatFsDriver<'a, B: BlockStorage<'a>> {
+    fs: &'a FatFs<'a, B>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    processid: OptionalCell<ProcessId>,
+    operation: Cell<Operation>,
+    /// The number of bytes read or written so far.
+    done: Cell<usize>,
+    /// The number of bytes of the read or write in progress.
+    chunk_len: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, B: BlockStorage<'a>> FatFsDriver<'a, B> {
+    pub fn new(
+        fs: &'a FatFs<'a, B>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        buffer: &'static mut [u8],
+    ) -> FatFsDriver<'a, B> {
+        FatFsDriver {
+            fs,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            operation: Cell::new(Operation::Remove),
+            done: Cell::new(0),
+            chunk_len: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    /// The open file or directory of `processid` with `handle`.
+    fn file(&self, processid: ProcessId, handle: usize) -> Result<File, ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                app.files
+                    .get(handle)
+                    .copied()
+                    .flatten()
+                    .ok_or(ErrorCode::INVAL)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Store the updated state of a file after an operation.
+    fn set_file(&self, processid: ProcessId, handle: usize, file: File) {
+        let _ = self.apps.enter(processid, |app, _| {
+            app.files[handle] = Some(file);
+        });
+    }
+
+    /// Whether `file` is open by any process.
+    fn is_open(&self, file: &File) -> bool {
+        self.apps.iter().any(|cntr| {
+            cntr.enter(|app, _| app.files.iter().flatten().any(|open| open.is_same(file)))
+        })
+    }
+
+    /// Whether `handle` of `processid` is used by the operation in progress.
+    fn in_use(&self, processid: ProcessId, handle: usize) -> bool {
+        self.processid.map_or(false, |current| {
+            *current == processid
+                && match self.operation.get() {
+                    Operation::Read { handle: h }
+                    | Operation::Write { handle: h }
+                    | Operation::ReadDir { handle: h } => h == handle,
+                    _ => false,
+                }
+        })
+    }
+
+    /// Start `operation` for `processid`.
+    fn start(&self, processid: ProcessId, operation: Operation) -> Result<(), ErrorCode> {
+        match operation {
+            Operation::Open { flags } => {
+                let has_free_handle = self
+                    .apps
+                    .enter(processid, |app, _| app.files.iter().any(|f| f.is_none()))
+                    .unwrap_or(false);
+                if !has_free_handle {
+                    return Err(ErrorCode::NOMEM);
+                }
+                self.open(processid, flags & OPEN_CREATE != 0)?;
+            }
+            Operation::Remove => self.open(processid, false)?,
+            Operation::Read { handle } | Operation::Write { handle } => {
+                self.file(processid, handle)?;
+                self.done.set(0);
+                self.processid.set(processid);
+                self.operation.set(operation);
+                return self.transfer(processid, handle).map_err(|e| {
+                    self.processid.clear();
+                    e
+                });
+            }
+            Operation::ReadDir { handle } => {
+                let file = self.file(processid, handle)?;
+                let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+                if let Err((e, buffer)) = self.fs.read_dir(file, buffer) {
+                    self.buffer.replace(buffer);
+                    return Err(e);
+                }
+            }
+        }
+        self.processid.set(processid);
+        self.operation.set(operation);
+        Ok(())
+    }
+
+    /// Open the path in the read-only allow buffer of `processid`.
+    fn open(&self, processid: ProcessId, create: bool) -> Result<(), ErrorCode> {
+        let mut path = [0; MAX_PATH_LEN];
+        let len = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PATH)
+                    .and_then(|buffer| {
+                        buffer.enter(|buffer| {
+                            if buffer.len() > MAX_PATH_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            buffer.copy_to_slice(&mut path[..buffer.len()]);
+                            Ok(buffer.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.fs.open(&path[..len], create)
+    }
+
+    /// Read or write the next chunk of the data of the process.
+    fn transfer(&self, processid: ProcessId, handle: usize) -> Result<(), ErrorCode> {
+        let file = self.file(processid, handle)?;
+        let buffer = self.buffer.take().ok_or(ErrorCode::RESERVE)?;
+        let done = self.done.get();
+        let write = matches!(self.operation.get(), Operation::Write { .. });
+
+        let len = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                if write {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::DATA)
+                        .and_then(|data| {
+                            data.enter(|data| {
+                                let len = cmp::min(data.len().saturating_sub(done), buffer.len());
+                                if len > 0 {
+                                    data[done..done + len].copy_to_slice(&mut buffer[..len]);
+                                }
+                                len
+                            })
+                        })
+                        .unwrap_or(0)
+                } else {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::DATA)
+                        .map_or(0, |data| {
+                            cmp::min(data.len().saturating_sub(done), buffer.len())
+                        })
+                }
+            })
+            .unwrap_or(0);
+        self.chunk_len.set(len);
+
+        let result = if write {
+            self.fs.write(file, buffer, len)
+        } else {
+            self.fs.read(file, buffer, len)
+        };
+        result.map_err(|(e, buffer)| {
+            self.buffer.replace(buffer);
+            e
+        })
+    }
+
+    /// Continue a read or write after a chunk was transferred.
+    fn transfer_done(&self, file: File, result: Result<usize, ErrorCode>) {
+        let processid = match self.processid.extract() {
+            Some(processid) => processid,
+            None => return,
+        };
+        let (handle, write) = match self.operation.get() {
+            Operation::Read { handle } => (handle, false),
+            Operation::Write { handle } => (handle, true),
+            _ => return,
+        };
+
+        let ret = result.and_then(|len| {
+            self.set_file(processid, handle, file);
+            if !write {
+                let done = self.done.get();
+                self.apps
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::DATA)
+                            .and_then(|data| {
+                                data.mut_enter(|data| {
+                                    self.buffer.map(|buffer| {
+                                        let len = cmp::min(len, data.len().saturating_sub(done));
+                                        if len > 0 {
+                                            data[done..done + len].copy_from_slice(&buffer[..len]);
+                                        }
+                                    });
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))?;
+            }
+            self.done.set(self.done.get() + len);
+
+            // Stop at the end of the file, or when the volume is full
+            if len == 0 || len < self.chunk_len.get() {
+                Ok(true)
+            } else {
+                self.transfer(processid, handle).map(|()| false)
+            }
+        });
+
+        match ret {
+            Ok(false) => {}
+            Ok(true) => self.finish(Ok(()), self.done.get(), 0),
+            Err(e) => self.finish(Err(e), self.done.get(), 0),
+        }
+    }
+
+    /// Store an opened file in a free handle of the process.
+    fn add_file(&self, processid: ProcessId, file: File) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                let handle = app
+                    .files
+                    .iter()
+                    .position(|f| f.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                app.files[handle] = Some(file);
+                Ok(handle)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Report the result of the operation to the process, and start the next
+    /// queued operation.
+    fn finish(&self, result: Result<(), ErrorCode>, arg1: usize, arg2: usize) {
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::DONE, (into_statuscode(result), arg1, arg2))
+                    .ok();
+            });
+        });
+
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            let operation = cntr.enter(|app, _| app.pending.take());
+            if let Some(operation) = operation {
+                match self.start(processid, operation) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        let _ = self.apps.enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .schedule_upcall(upcall::DONE, (into_statuscode(Err(e)), 0, 0))
+                                .ok();
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> FatFsClient for FatFsDriver<'a, B> {
+    fn open_done(&self, result: Result<File, ErrorCode>) {
+        let processid = match self.processid.extract() {
+            Some(processid) => processid,
+            None => return,
+        };
+        let ret = result.and_then(|file| {
+            if !file.is_dir() && self.is_open(&file) {
+                return Err(ErrorCode::BUSY);
+            }
+            match self.operation.get() {
+                Operation::Open { flags } if flags & OPEN_TRUNCATE != 0 && !file.is_dir() => {
+                    self.fs.truncate(file).map(|()| None)
+                }
+                Operation::Open { .. } => {
+                    let handle = self.add_file(processid, file)?;
+                    Ok(Some((handle, file.size() as usize)))
+                }
+                _ => self.fs.remove(file).map(|()| None),
+            }
+        });
+
+        match ret {
+            Ok(None) => {}
+            Ok(Some((handle, size))) => self.finish(Ok(()), handle, size),
+            Err(e) => self.finish(Err(e), 0, 0),
+        }
+    }
+
+    fn truncate_done(&self, result: Result<File, ErrorCode>) {
+        let processid = match self.processid.extract() {
+            Some(processid) => processid,
+            None => return,
+        };
+        match result.and_then(|file| self.add_file(processid, file)) {
+            Ok(handle) => self.finish(Ok(()), handle, 0),
+            Err(e) => self.finish(Err(e), 0, 0),
+        }
+    }
+
+    fn remove_done(&self, result: Result<(), ErrorCode>) {
+        self.finish(result, 0, 0);
+    }
+
+    fn read_done(&self, file: File, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        self.buffer.replace(buffer);
+        self.transfer_done(file, result);
+    }
+
+    fn write_done(&self, file: File, buffer: &'static mut [u8], result: Result<usize, ErrorCode>) {
+        self.buffer.replace(buffer);
+        self.transfer_done(file, result);
+    }
+
+    fn read_dir_done(
+        &self,
+        file: File,
+        buffer: &'static mut [u8],
+        result: Result<Option<DirEntry>, ErrorCode>,
+    ) {
+        let processid = match self.processid.extract() {
+            Some(processid) => processid,
+            None => {
+                self.buffer.replace(buffer);
+                return;
+            }
+        };
+        if let Operation::ReadDir { handle } = self.operation.get() {
+            self.set_file(processid, handle, file);
+        }
+
+        let ret = result.map(|entry| {
+            entry.map_or((0, 0), |entry| {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readwrite_processbuffer(rw_allow::DATA)
+                        .and_then(|data| {
+                            data.mut_enter(|data| {
+                                let len = cmp::min(entry.name_len, data.len());
+                                data[..len].copy_from_slice(&buffer[..len]);
+                            })
+                        })
+                });
+                (entry.name_len, entry.size as usize)
+            })
+        });
+        self.buffer.replace(buffer);
+
+        match ret {
+            Ok((name_len, size)) => self.finish(Ok(()), name_len, size),
+            Err(e) => self.finish(Err(e), 0, 0),
+        }
+    }
+}
+
+impl<'a, B: BlockStorage<'a>> SyscallDriver for FatFsDriver<'a, B> {
+    /// Open, read, write and remove files.
+    ///
+    /// Paths are read from read-only allow buffer 0, as names separated by
+    /// `/` that can end with a 0 byte. Names must be short (8.3) names.
+    /// Operations complete with upcall 0, with the status of the operation as
+    /// the first argument. Only one operation per process can be outstanding
+    /// at a time.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file or directory at the path. Bit 0 of `arg1` creates
+    ///        the file if it doesn't exist, and bit 1 removes the contents of
+    ///        an existing file. The upcall returns the handle of the file and
+    ///        its size.
+    /// - `2`: Close the handle `arg1`.
+    /// - `3`: Read from the file with handle `arg1` into read-write allow
+    ///        buffer 0. The upcall returns the number of bytes read, which is
+    ///        0 at the end of the file.
+    /// - `4`: Write the contents of read-only allow buffer 1 to the file with
+    ///        handle `arg1`. The upcall returns the number of bytes written,
+    ///        which is less than the length of the buffer if the volume is
+    ///        full.
+    /// - `5`: Move the position of the file with handle `arg1` to `arg2`,
+    ///        which can't be past the end of the file. Directories can only be
+    ///        moved back to their first entry.
+    /// - `6`: Read the name of the next entry of the directory with handle
+    ///        `arg1` into read-write allow buffer 0. The upcall returns the
+    ///        length of the name, which is 0 after the last entry, and the
+    ///        size of the file. The names of directories end with `/`.
+    /// - `7`: Remove the file at the path.
+    /// - `8`: Returns the size of the file with handle `arg1`.
+    ///
+    /// Opening or removing a file that doesn't exist fails with `NOSUPPORT`,
+    /// and opening or removing a file that is already open fails with
+    /// `BUSY`.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 | 3 | 4 | 6 | 7 => {
+                let operation = match command_num {
+                    1 => Operation::Open { flags: arg1 },
+                    3 => Operation::Read { handle: arg1 },
+                    4 => Operation::Write { handle: arg1 },
+                    6 => Operation::ReadDir { handle: arg1 },
+                    _ => Operation::Remove,
+                };
+
+                if self.processid.is_none() {
+                    return match self.start(processid, operation) {
+                        Ok(()) => CommandReturn::success(),
+                        Err(e) => CommandReturn::failure(e),
+                    };
+                }
+
+                // Queue the operation until the current one completes
+                self.apps
+                    .enter(processid, |app, _| {
+                        if app.pending.is_some() {
+                            CommandReturn::failure(ErrorCode::BUSY)
+                        } else {
+                            app.pending = Some(operation);
+                            CommandReturn::success()
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            2 | 5 | 8 => {
+                if self.in_use(processid, arg1) {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                self.apps
+                    .enter(processid, |app, _| {
+                        let file = match app.files.get_mut(arg1) {
+                            Some(Some(file)) => file,
+                            _ => return CommandReturn::failure(ErrorCode::INVAL),
+                        };
+                        match command_num {
+                            2 => {
+                                app.files[arg1] = None;
+                                CommandReturn::success()
+                            }
+                            5 => match file.seek(arg2 as u32) {
+                                Ok(()) => CommandReturn::success(),
+                                Err(e) => CommandReturn::failure(e),
+                            },
+                            _ => CommandReturn::success_u32(file.size()),
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,202 @@
+//! Drain binary kernel trace records to the host.
+//!
+//! The kernel records its events in a `kernel::trace::RingBufferTracer`.
+//! These capsules periodically move the records out of the ring buffer so
+//! that it does not fill up, and send them to the host to be decoded with
+//! `tools/trace_decode`:
+//!
+//! - `TraceTransmit` sends the raw binary records over anything that
+//!   implements `uart::Transmit`: a UART (usually a dedicated one, or a
+//!   virtual UART device that nothing else writes binary data to), or a
+//!   SEGGER RTT channel.
+//! - `TraceDebug` prints the records as hex lines prefixed with `TRACE` on
+//!   the kernel debug output, which works on any board with `debug!()`
+//!   without another channel. This is slower and shares the debug buffer, so
+//!   it suits short traces.
+//!
+//! Draining only happens every `interval_ms`, so that sending the records
+//! changes the timing of the traced system as little as possible. The ring
+//! buffer must be large enough to hold the events of one interval; events
+//! that do not fit are counted in a `Dropped` record.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let trace_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! trace_alarm.setup();
+//! let trace_transmit = static_init!(
+//!     capsules::kernel_trace::TraceTransmit<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         sam4l::ast::Ast,
+//!     >,
+//!     capsules::kernel_trace::TraceTransmit::new(
+//!         tracer,
+//!         rtt,
+//!         trace_alarm,
+//!         10,
+//!         &mut capsules::kernel_trace::TX_BUF,
+//!     )
+//! );
+//! trace_alarm.set_alarm_client(trace_transmit);
+//! rtt.set_transmit_client(trace_transmit);
+//! trace_transmit.start();
+//! ```
+
+use core::fmt;
+
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, ConvertTicks};
+use kernel::hil::uart;
+use kernel::trace::{RingBufferTracer, TRACE_RECORD_LEN};
+use kernel::utilities::cells::TakeCell;
+use kernel::ErrorCode;
+
+/// Buffer for `TraceTransmit` to send records from.
+pub static mut TX_BUF: [u8; 32 * TRACE_RECORD_LEN] = [0; 32 * TRACE_RECORD_LEN];
+
+/// How many records `TraceDebug` prints every interval, to leave room in the
+/// debug buffer for other output.
+const DEBUG_RECORDS_PER_INTERVAL: usize = 8;
+
+/// Sends binary trace records over a UART or SEGGER RTT.
+pub struct TraceTransmit<'a, A: Alarm<'a>, T: time::Time> {
+    tracer: &'a RingBufferTracer<'a, T>,
+    uart: &'a dyn uart::Transmit<'a>,
+    alarm: &'a A,
+    interval_ms: u32,
+    tx_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm<'a>, T: time::Time> TraceTransmit<'a, A, T> {
+    pub fn new(
+        tracer: &'a RingBufferTracer<'a, T>,
+        uart: &'a dyn uart::Transmit<'a>,
+        alarm: &'a A,
+        interval_ms: u32,
+        tx_buffer: &'static mut [u8],
+    ) -> TraceTransmit<'a, A, T> {
+        TraceTransmit {
+            tracer,
+            uart,
+            alarm,
+            interval_ms,
+            tx_buffer: TakeCell::new(tx_buffer),
+        }
+    }
+
+    /// Start draining records.
+    pub fn start(&self) {
+        self.wait();
+    }
+
+    fn wait(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.interval_ms));
+    }
+
+    /// Send as many records as fit in the TX buffer. Returns whether a
+    /// transmission started.
+    fn send(&self) -> bool {
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let len = self.tracer.drain(buffer);
+            if len == 0 {
+                self.tx_buffer.replace(buffer);
+                return false;
+            }
+            match self.uart.transmit_buffer(buffer, len) {
+                Ok(()) => true,
+                Err((_, buffer)) => {
+                    // The records are lost, but the next ones can still be
+                    // sent.
+                    self.tx_buffer.replace(buffer);
+                    false
+                }
+            }
+        })
+    }
+}
+
+impl<'a, A: Alarm<'a>, T: time::Time> time::AlarmClient for TraceTransmit<'a, A, T> {
+    fn alarm(&self) {
+        if !self.send() {
+            self.wait();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, T: time::Time> uart::TransmitClient for TraceTransmit<'a, A, T> {
+    fn transmitted_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        tx_len: usize,
+        _rcode: Result<(), ErrorCode>,
+    ) {
+        let full = tx_len == buffer.len() - buffer.len() % TRACE_RECORD_LEN;
+        self.tx_buffer.replace(buffer);
+        // If the buffer was full there are likely more records waiting, so
+        // keep going until the ring buffer is empty.
+        if !(full && self.send()) {
+            self.wait();
+        }
+    }
+}
+
+/// Formats bytes as lowercase hex digits.
+struct Hex<'b>(&'b [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints trace records as hex lines on the kernel debug output.
+pub struct TraceDebug<'a, A: Alarm<'a>, T: time::Time> {
+    tracer: &'a RingBufferTracer<'a, T>,
+    alarm: &'a A,
+    interval_ms: u32,
+}
+
+impl<'a, A: Alarm<'a>, T: time::Time> TraceDebug<'a, A, T> {
+    pub fn new(
+        tracer: &'a RingBufferTracer<'a, T>,
+        alarm: &'a A,
+        interval_ms: u32,
+    ) -> TraceDebug<'a, A, T> {
+        TraceDebug {
+            tracer,
+            alarm,
+            interval_ms,
+        }
+    }
+
+    /// Start draining records.
+    pub fn start(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.interval_ms));
+    }
+}
+
+impl<'a, A: Alarm<'a>, T: time::Time> time::AlarmClient for TraceDebug<'a, A, T> {
+    fn alarm(&self) {
+        let mut record = [0; TRACE_RECORD_LEN];
+        for _ in 0..DEBUG_RECORDS_PER_INTERVAL {
+            if self.tracer.drain(&mut record) == 0 {
+                break;
+            }
+            debug!("TRACE {}", Hex(&record));
+        }
+        self.alarm.set_alarm(
+            self.alarm.get_alarm(),
+            self.alarm.ticks_from_ms(self.interval_ms),
+        );
+    }
+}
@@ -0,0 +1,891 @@
+//! Gives processes access to persistent key-value storage.
+//!
+//! This capsule implements get, set and delete operations on unhashed keys on
+//! top of a `hil::kv_system` implementation, such as `capsules::tickv`, and
+//! exposes them to userspace.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Userspace            |
+//! |                       |
+//! +-----------------------+
+//!
+//!    Syscalls
+//!
+//! +-----------------------+
+//! |                       |
+//! |  K-V in Tock (this)   |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  TicKV                |
+//! |                       |
+//! +-----------------------+
+//!
+//! Namespaces
+//! ----------
+//!
+//! Keys are scoped to a storage identifier, which is the persistent `AppId` of
+//! the process. The identifier is part of the key that is hashed, so the same
+//! key used by two applications refers to two different values, and a process
+//! can't read, overwrite or delete the values of another application.
+//!
+//! Only processes that were approved with credentials have a storage
+//! identifier, since nothing authenticates the app ID or the TBF headers of
+//! other processes. Processes without a credential-approved `AppId::Fixed`
+//! can't use this driver.
+//!
+//! Every stored value starts with a header containing the storage identifier
+//! and the length of the value, which is checked when the value is read.
+//!
+//! Quotas
+//! ------
+//!
+//! The board sets the number of bytes the values of each storage identifier
+//! can use, including their headers, with a default for identifiers that
+//! aren't listed. The number of bytes used is kept in a usage record for every
+//! storage identifier, so quotas still hold after a reboot. The usage record
+//! is increased before a value grows and decreased after it shrinks or is
+//! deleted, so an interrupted operation can only overestimate the usage.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let kv_driver = static_init!(
+//!     capsules::kv_driver::KVStoreDriver<
+//!         'static,
+//!         capsules::tickv::TicKVStore<'static, F, H>,
+//!         capsules::tickv::TicKVKeyType,
+//!     >,
+//!     capsules::kv_driver::KVStoreDriver::new(
+//!         tickv,
+//!         board_kernel.create_grant(capsules::kv_driver::DRIVER_NUM, &grant_cap),
+//!         unhashed_key_buf, // [u8; capsules::kv_driver::KEY_PREFIX_LEN + 64]
+//!         key_buf,          // [u8; 8]
+//!         usage_key_buf,    // [u8; 8]
+//!         value_buf,        // [u8; capsules::kv_driver::HEADER_LEN + 256]
+//!         usage_buf,        // [u8; capsules::kv_driver::USAGE_BUFFER_LEN]
+//!         4096,             // Default quota in bytes
+//!         &QUOTAS,
+//!     )
+//! );
+//! tickv.set_client(kv_driver);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::kv_system::{self, KVSystem, KeyType};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The key of the operation.
+    pub const KEY: usize = 0;
+    /// The value to store with command 2.
+    pub const VALUE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer the value is copied into by command 1.
+    pub const VALUE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// Called when an operation completes.
+    pub const DONE: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// The version of the header stored in front of every value.
+const HEADER_VERSION: u8 = 0;
+
+/// The length of the header stored in front of every value: the version, the
+/// storage identifier and the length of the value.
+pub const HEADER_LEN: usize = 9;
+
+/// The length of the buffer used for usage records.
+pub const USAGE_BUFFER_LEN: usize = HEADER_LEN + 4;
+
+/// The length of the prefix of unhashed keys: the kind of key, the storage
+/// identifier and the length of the key. The unhashed key buffer must be this
+/// much longer than the longest key.
+pub const KEY_PREFIX_LEN: usize = 6;
+const KIND_VALUE: u8 = 0;
+const KIND_USAGE: u8 = 1;
+
+/// The number of bytes the values of an application can use.
+#[derive(Clone, Copy)]
+pub struct StorageQuota {
+    /// The storage identifier of the application, which is its persistent
+    /// `AppId`.
+    pub storage_id: u32,
+    /// The number of bytes the values of the application can use.
+    pub bytes: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    HashKey,
+    HashUsageKey,
+    ReadUsage,
+    ReadValue,
+    WriteUsage,
+    WriteValue,
+    DeleteValue,
+}
+
+/// What happens after a step of an operation.
+enum Next {
+    /// Wait for the KV system to call back.
+    Wait,
+    /// The operation is complete, with the length of the value.
+    Done(usize),
+}
+
+#[derive(Default)]
+pub struct App {
+    pending: Option<Operation>,
+}
+
+/// Fill in the header in front of a value.
+fn write_header(buf: &mut [u8], storage_id: u32, len: usize) {
+    buf[0] = HEADER_VERSION;
+    buf[1..5].copy_from_slice(&storage_id.to_be_bytes());
+    buf[5..HEADER_LEN].copy_from_slice(&(len as u32).to_be_bytes());
+}
+
+/// Check the header in front of a value read from the KV system, and return
+/// the length of the value.
+fn read_header(buf: &[u8], storage_id: u32) -> Result<usize, ErrorCode> {
+    if buf.len() < HEADER_LEN || buf[0] != HEADER_VERSION {
+        return Err(ErrorCode::FAIL);
+    }
+    let id = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
+    let len = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) as usize;
+    if id != storage_id || len > buf.len() - HEADER_LEN {
+        return Err(ErrorCode::FAIL);
+    }
+    Ok(len)
+}
+
+/// Fill `buf` with the prefix of an unhashed key and clear the rest.
+fn write_key_prefix(buf: &mut [u8], kind: u8, storage_id: u32, len: usize) {
+    buf.iter_mut().for_each(|b| *b = 0);
+    buf[0] = kind;
+    buf[1..5].copy_from_slice(&storage_id.to_be_bytes());
+    buf[5] = len as u8;
+}
+
+pub struct KVStoreDriver<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> {
+    kv: &'a K,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    processid: OptionalCell<ProcessId>,
+    operation: Cell<Operation>,
+    state: Cell<State>,
+
+    default_quota: usize,
+    quotas: &'static [StorageQuota],
+    max_key_len: usize,
+    max_value_len: usize,
+
+    storage_id: Cell<u32>,
+    /// The usage stored before the operation started
+    usage: Cell<usize>,
+    usage_exists: Cell<bool>,
+    /// The usage once the operation completes
+    new_usage: Cell<usize>,
+    usage_written: Cell<bool>,
+    value_exists: Cell<bool>,
+    value_len: Cell<usize>,
+    value_written: Cell<bool>,
+    /// The error to report once the usage has been restored
+    result: Cell<Result<(), ErrorCode>>,
+
+    unhashed_key: TakeCell<'static, [u8]>,
+    key: TakeCell<'static, T>,
+    usage_key: TakeCell<'static, T>,
+    value: TakeCell<'static, [u8]>,
+    usage_value: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> KVStoreDriver<'a, K, T> {
+    pub fn new(
+        kv: &'a K,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        unhashed_key: &'static mut [u8],
+        key: &'static mut T,
+        usage_key: &'static mut T,
+        value: &'static mut [u8],
+        usage_value: &'static mut [u8; USAGE_BUFFER_LEN],
+        default_quota: usize,
+        quotas: &'static [StorageQuota],
+    ) -> KVStoreDriver<'a, K, T> {
+        KVStoreDriver {
+            kv,
+            apps: grant,
+            processid: OptionalCell::empty(),
+            operation: Cell::new(Operation::Get),
+            state: Cell::new(State::Idle),
+            default_quota,
+            quotas,
+            max_key_len: unhashed_key
+                .len()
+                .saturating_sub(KEY_PREFIX_LEN)
+                .min(u8::MAX as usize),
+            max_value_len: value.len().saturating_sub(HEADER_LEN),
+            storage_id: Cell::new(0),
+            usage: Cell::new(0),
+            usage_exists: Cell::new(false),
+            new_usage: Cell::new(0),
+            usage_written: Cell::new(false),
+            value_exists: Cell::new(false),
+            value_len: Cell::new(0),
+            value_written: Cell::new(false),
+            result: Cell::new(Ok(())),
+            unhashed_key: TakeCell::new(unhashed_key),
+            key: TakeCell::new(key),
+            usage_key: TakeCell::new(usage_key),
+            value: TakeCell::new(value),
+            usage_value: TakeCell::new(usage_value),
+        }
+    }
+
+    /// The storage identifier the keys of `processid` are scoped to: its
+    /// persistent app ID, if the process was approved with credentials.
+    fn storage_id(processid: ProcessId) -> Result<u32, ErrorCode> {
+        processid
+            .credentialed_app_id()
+            .map(|app_id| app_id.get())
+            .ok_or(ErrorCode::NOSUPPORT)
+    }
+
+    /// The number of bytes the values of `storage_id` can use.
+    fn quota(&self, storage_id: u32) -> usize {
+        self.quotas
+            .iter()
+            .find(|quota| quota.storage_id == storage_id)
+            .map_or(self.default_quota, |quota| quota.bytes)
+    }
+
+    /// Start `operation` for `processid` by hashing its key.
+    fn start(&self, processid: ProcessId, operation: Operation) -> Result<(), ErrorCode> {
+        let storage_id = Self::storage_id(processid)?;
+
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::KEY)
+                    .and_then(|key| {
+                        key.enter(|key| {
+                            if key.len() == 0 || key.len() > self.max_key_len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            self.unhashed_key.map_or(Err(ErrorCode::RESERVE), |buf| {
+                                write_key_prefix(buf, KIND_VALUE, storage_id, key.len());
+                                key.copy_to_slice(
+                                    &mut buf[KEY_PREFIX_LEN..KEY_PREFIX_LEN + key.len()],
+                                );
+                                Ok(())
+                            })
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+                // Check the length of a new value now, it is copied once the
+                // current value has been read
+                if operation == Operation::Set
+                    && kernel_data
+                        .get_readonly_processbuffer(ro_allow::VALUE)
+                        .map_or(0, |value| value.len())
+                        > self.max_value_len
+                {
+                    return Err(ErrorCode::SIZE);
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        self.processid.set(processid);
+        self.operation.set(operation);
+        self.storage_id.set(storage_id);
+        self.usage_written.set(false);
+        self.value_written.set(false);
+        self.result.set(Ok(()));
+
+        self.generate_key(State::HashKey).map(|_| ()).map_err(|e| {
+            self.processid.clear();
+            self.state.set(State::Idle);
+            e
+        })
+    }
+
+    /// Hash the key in `unhashed_key`, into `key` for `State::HashKey` and
+    /// into `usage_key` for `State::HashUsageKey`.
+    fn generate_key(&self, state: State) -> Result<Next, ErrorCode> {
+        let key_buf = if state == State::HashKey {
+            &self.key
+        } else {
+            &self.usage_key
+        };
+        let unhashed_key = self.unhashed_key.take().ok_or(ErrorCode::RESERVE)?;
+        let key = key_buf.take().ok_or(ErrorCode::RESERVE)?;
+
+        self.state.set(state);
+        self.kv
+            .generate_key(unhashed_key, key)
+            .map(|()| Next::Wait)
+            .map_err(|(unhashed_key, key, e)| {
+                self.unhashed_key.replace(unhashed_key);
+                key_buf.replace(key);
+                e.err().unwrap_or(ErrorCode::FAIL)
+            })
+    }
+
+    /// Read the usage record for `State::ReadUsage` or the value for
+    /// `State::ReadValue`.
+    fn read(&self, state: State) -> Result<Next, ErrorCode> {
+        let (key_buf, value_buf) = if state == State::ReadUsage {
+            (&self.usage_key, &self.usage_value)
+        } else {
+            (&self.key, &self.value)
+        };
+        let key = key_buf.take().ok_or(ErrorCode::RESERVE)?;
+        let value = value_buf.take().ok_or(ErrorCode::RESERVE)?;
+
+        self.state.set(state);
+        self.kv
+            .get_value(key, value)
+            .map(|()| Next::Wait)
+            .map_err(|(key, value, e)| {
+                key_buf.replace(key);
+                value_buf.replace(value);
+                e.err().unwrap_or(ErrorCode::FAIL)
+            })
+    }
+
+    /// Store `usage` in the usage record.
+    fn write_usage(&self, usage: usize) -> Result<Next, ErrorCode> {
+        let key = self.usage_key.take().ok_or(ErrorCode::RESERVE)?;
+        let buf = self.usage_value.take().ok_or(ErrorCode::RESERVE)?;
+        write_header(buf, self.storage_id.get(), USAGE_BUFFER_LEN - HEADER_LEN);
+        buf[HEADER_LEN..USAGE_BUFFER_LEN].copy_from_slice(&(usage as u32).to_be_bytes());
+        let mut value = LeasableBuffer::new(buf);
+        value.slice(..USAGE_BUFFER_LEN);
+
+        self.state.set(State::WriteUsage);
+        let ret = if self.usage_exists.get() {
+            self.kv.update_key(key, value)
+        } else {
+            self.kv.append_key(key, value)
+        };
+        ret.map(|()| Next::Wait).map_err(|(key, value, e)| {
+            self.usage_key.replace(key);
+            self.usage_value.replace(value);
+            e.err().unwrap_or(ErrorCode::FAIL)
+        })
+    }
+
+    /// Store the value in `value`, replacing the current value if there is
+    /// one.
+    fn write_value(&self) -> Result<Next, ErrorCode> {
+        let key = self.key.take().ok_or(ErrorCode::RESERVE)?;
+        let mut value = LeasableBuffer::new(self.value.take().ok_or(ErrorCode::RESERVE)?);
+        value.slice(..HEADER_LEN + self.value_len.get());
+
+        self.state.set(State::WriteValue);
+        let ret = if self.value_exists.get() {
+            self.kv.update_key(key, value)
+        } else {
+            self.kv.append_key(key, value)
+        };
+        ret.map(|()| Next::Wait).map_err(|(key, value, e)| {
+            self.key.replace(key);
+            self.value.replace(value);
+            e.err().unwrap_or(ErrorCode::FAIL)
+        })
+    }
+
+    /// Copy the value that was read to the process.
+    fn get(&self, processid: ProcessId) -> Result<Next, ErrorCode> {
+        if !self.value_exists.get() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let len = self.value_len.get();
+
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::VALUE)
+                    .and_then(|dest| {
+                        dest.mut_enter(|dest| {
+                            self.value.map(|buf| {
+                                let copy_len = len.min(dest.len());
+                                dest[..copy_len]
+                                    .copy_from_slice(&buf[HEADER_LEN..HEADER_LEN + copy_len]);
+                            });
+                        })
+                    })
+                    .map_err(ErrorCode::from)
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        Ok(Next::Done(len))
+    }
+
+    /// Copy the new value from the process and store it, if it fits in the
+    /// quota.
+    fn set(&self, processid: ProcessId) -> Result<Next, ErrorCode> {
+        let storage_id = self.storage_id.get();
+        let len = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::VALUE)
+                    .and_then(|value| {
+                        value.enter(|value| {
+                            if value.len() > self.max_value_len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            self.value.map_or(Err(ErrorCode::RESERVE), |buf| {
+                                write_header(buf, storage_id, value.len());
+                                value.copy_to_slice(&mut buf[HEADER_LEN..HEADER_LEN + value.len()]);
+                                Ok(value.len())
+                            })
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        let old_size = if self.value_exists.get() {
+            HEADER_LEN + self.value_len.get()
+        } else {
+            0
+        };
+        let usage = self.usage.get();
+        let new_usage = usage.saturating_sub(old_size) + HEADER_LEN + len;
+        if new_usage > usage && new_usage > self.quota(storage_id) {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.value_len.set(len);
+        self.new_usage.set(new_usage);
+
+        if new_usage > usage {
+            self.write_usage(new_usage)
+        } else {
+            self.write_value()
+        }
+    }
+
+    /// Invalidate the value that was read.
+    fn delete(&self) -> Result<Next, ErrorCode> {
+        if !self.value_exists.get() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        self.new_usage.set(
+            self.usage
+                .get()
+                .saturating_sub(HEADER_LEN + self.value_len.get()),
+        );
+
+        let key = self.key.take().ok_or(ErrorCode::RESERVE)?;
+        self.state.set(State::DeleteValue);
+        self.kv
+            .invalidate_key(key)
+            .map(|()| Next::Wait)
+            .map_err(|(key, e)| {
+                self.key.replace(key);
+                e.err().unwrap_or(ErrorCode::FAIL)
+            })
+    }
+
+    /// Continue the operation after the usage record or the value has been
+    /// written or invalidated.
+    fn write_done(&self, result: Result<(), ErrorCode>) -> Result<Next, ErrorCode> {
+        match self.state.get() {
+            State::WriteUsage => {
+                if result.is_ok() {
+                    self.usage_exists.set(true);
+                }
+                // The usage has been restored after the value couldn't be
+                // written
+                self.result.get()?;
+                result?;
+
+                self.usage_written.set(true);
+                if self.operation.get() == Operation::Set && !self.value_written.get() {
+                    self.write_value()
+                } else {
+                    Ok(Next::Done(0))
+                }
+            }
+            _ => match result {
+                Ok(()) => {
+                    self.value_written.set(true);
+                    if self.usage_written.get() || self.new_usage.get() == self.usage.get() {
+                        Ok(Next::Done(0))
+                    } else {
+                        self.write_usage(self.new_usage.get())
+                    }
+                }
+                Err(e) if self.usage_written.get() => {
+                    self.result.set(Err(e));
+                    self.write_usage(self.usage.get())
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn advance(&self, ret: Result<Next, ErrorCode>) {
+        match ret {
+            Ok(Next::Wait) => {}
+            Ok(Next::Done(len)) => self.finish(Ok(()), len),
+            Err(e) => self.finish(Err(e), 0),
+        }
+    }
+
+    /// Report the result of the operation to the process, and start the next
+    /// queued operation.
+    fn finish(&self, result: Result<(), ErrorCode>, len: usize) {
+        self.state.set(State::Idle);
+        self.processid.take().map(|processid| {
+            let _ = self.apps.enter(processid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::DONE, (into_statuscode(result), len, 0))
+                    .ok();
+            });
+        });
+
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            let operation = cntr.enter(|app, _| app.pending.take());
+            if let Some(operation) = operation {
+                match self.start(processid, operation) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        let _ = self.apps.enter(processid, |_, kernel_data| {
+                            kernel_data
+                                .schedule_upcall(upcall::DONE, (into_statuscode(Err(e)), 0, 0))
+                                .ok();
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> kv_system::Client<T>
+    for KVStoreDriver<'a, K, T>
+{
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: &'static mut [u8],
+        key: &'static mut T,
+    ) {
+        self.unhashed_key.replace(unhashed_key);
+
+        let ret = match self.state.get() {
+            State::HashKey => {
+                self.key.replace(key);
+                result.and_then(|()| {
+                    if self.operation.get() == Operation::Get {
+                        self.read(State::ReadValue)
+                    } else {
+                        let storage_id = self.storage_id.get();
+                        self.unhashed_key
+                            .map(|buf| write_key_prefix(buf, KIND_USAGE, storage_id, 0));
+                        self.generate_key(State::HashUsageKey)
+                    }
+                })
+            }
+            State::HashUsageKey => {
+                self.usage_key.replace(key);
+                result.and_then(|()| self.read(State::ReadUsage))
+            }
+            _ => unreachable!(),
+        };
+        self.advance(ret);
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.update_key_complete(result, key, value);
+    }
+
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        if self.state.get() == State::WriteUsage {
+            self.usage_key.replace(key);
+            self.usage_value.replace(value);
+        } else {
+            self.key.replace(key);
+            self.value.replace(value);
+        }
+        self.advance(self.write_done(result));
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) {
+        let storage_id = self.storage_id.get();
+        // A key that isn't found is reported as `NOSUPPORT`
+        let len = match result {
+            Ok(()) => read_header(ret_buf, storage_id).map(Some),
+            Err(ErrorCode::NOSUPPORT) => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        let ret = match self.state.get() {
+            State::ReadUsage => {
+                self.usage_key.replace(key);
+                let usage = len.and_then(|len| match len {
+                    Some(len) if len == USAGE_BUFFER_LEN - HEADER_LEN => {
+                        let mut usage = [0; 4];
+                        usage.copy_from_slice(&ret_buf[HEADER_LEN..USAGE_BUFFER_LEN]);
+                        Ok(Some(u32::from_be_bytes(usage) as usize))
+                    }
+                    Some(_) => Err(ErrorCode::FAIL),
+                    None => Ok(None),
+                });
+                self.usage_value.replace(ret_buf);
+
+                usage.and_then(|usage| {
+                    self.usage_exists.set(usage.is_some());
+                    self.usage.set(usage.unwrap_or(0));
+                    self.read(State::ReadValue)
+                })
+            }
+            State::ReadValue => {
+                self.key.replace(key);
+                self.value.replace(ret_buf);
+
+                len.and_then(|len| {
+                    self.value_exists.set(len.is_some());
+                    self.value_len.set(len.unwrap_or(0));
+                    let processid = self.processid.extract().ok_or(ErrorCode::FAIL)?;
+                    match self.operation.get() {
+                        Operation::Get => self.get(processid),
+                        Operation::Set => self.set(processid),
+                        Operation::Delete => self.delete(),
+                    }
+                })
+            }
+            _ => unreachable!(),
+        };
+        self.advance(ret);
+    }
+
+    fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
+        self.key.replace(key);
+        self.advance(self.write_done(result));
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> SyscallDriver for KVStoreDriver<'a, K, T> {
+    /// Get, set and delete values of the process's key-value storage.
+    ///
+    /// The key is read from read-only allow buffer 0. Operations complete
+    /// with upcall 0, with the status of the operation as the first argument.
+    /// Only one operation per process can be outstanding at a time.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the value of the key. The value is copied into read-write
+    ///        allow buffer 0, and the length of the value is the second
+    ///        argument of the upcall. If the buffer is shorter than the value
+    ///        only the start of the value is copied.
+    /// - `2`: Set the value of the key to the contents of read-only allow
+    ///        buffer 1, replacing the current value if there is one. Fails
+    ///        with `NOMEM` if the value doesn't fit in the quota of the
+    ///        process.
+    /// - `3`: Delete the key.
+    /// - `4`: Returns the quota of the process in bytes. Every value uses
+    ///        `HEADER_LEN` bytes more than its length.
+    ///
+    /// Get and delete fail with `NOSUPPORT` if the key doesn't exist. All
+    /// commands fail with `NOSUPPORT` if the process has no storage
+    /// identifier.
+    fn command(
+        &self,
+        command_num: usize,
+        _: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 | 2 | 3 => {
+                let operation = match command_num {
+                    1 => Operation::Get,
+                    2 => Operation::Set,
+                    _ => Operation::Delete,
+                };
+
+                if self.processid.is_none() {
+                    return match self.start(processid, operation) {
+                        Ok(()) => CommandReturn::success(),
+                        Err(e) => CommandReturn::failure(e),
+                    };
+                }
+
+                if let Err(e) = Self::storage_id(processid) {
+                    return CommandReturn::failure(e);
+                }
+                // Queue the operation until the current one completes
+                self.apps
+                    .enter(processid, |app, _| {
+                        if app.pending.is_some() {
+                            CommandReturn::failure(ErrorCode::BUSY)
+                        } else {
+                            app.pending = Some(operation);
+                            CommandReturn::success()
+                        }
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            4 => match Self::storage_id(processid) {
+                Ok(storage_id) => CommandReturn::success_u32(self.quota(storage_id) as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A KV store that keeps values in a map indexed by their unhashed keys,
+    /// written and read the way the driver does for a process with the
+    /// storage identifier `storage_id`.
+    #[derive(Default)]
+    struct Store {
+        values: BTreeMap<Vec<u8>, Vec<u8>>,
+    }
+
+    impl Store {
+        fn unhashed_key(storage_id: u32, key: &[u8]) -> Vec<u8> {
+            let mut buf = vec![0; KEY_PREFIX_LEN + 16];
+            write_key_prefix(&mut buf, KIND_VALUE, storage_id, key.len());
+            buf[KEY_PREFIX_LEN..KEY_PREFIX_LEN + key.len()].copy_from_slice(key);
+            buf
+        }
+
+        fn set(&mut self, storage_id: u32, key: &[u8], value: &[u8]) {
+            let mut buf = vec![0; HEADER_LEN + value.len()];
+            write_header(&mut buf, storage_id, value.len());
+            buf[HEADER_LEN..].copy_from_slice(value);
+            self.values.insert(Self::unhashed_key(storage_id, key), buf);
+        }
+
+        fn get(&self, storage_id: u32, key: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+            let buf = self
+                .values
+                .get(&Self::unhashed_key(storage_id, key))
+                .ok_or(ErrorCode::NOSUPPORT)?;
+            let len = read_header(buf, storage_id)?;
+            Ok(buf[HEADER_LEN..HEADER_LEN + len].to_vec())
+        }
+    }
+
+    #[test]
+    fn same_key_in_two_apps() {
+        let mut store = Store::default();
+        store.set(1, b"key", b"first");
+        assert_eq!(store.get(2, b"key"), Err(ErrorCode::NOSUPPORT));
+
+        store.set(2, b"key", b"second");
+        assert_eq!(store.get(1, b"key"), Ok(b"first".to_vec()));
+        assert_eq!(store.get(2, b"key"), Ok(b"second".to_vec()));
+    }
+
+    #[test]
+    fn value_of_other_app_is_rejected() {
+        // Even if the keys of two apps hash to the same value, the header of
+        // the value read doesn't match the app reading it.
+        let mut store = Store::default();
+        store.set(1, b"key", b"first");
+        let value = store
+            .values
+            .remove(&Store::unhashed_key(1, b"key"))
+            .unwrap();
+        store.values.insert(Store::unhashed_key(2, b"key"), value);
+        assert_eq!(store.get(2, b"key"), Err(ErrorCode::FAIL));
+    }
+}
@@ -0,0 +1,1411 @@
+//! Encrypts and authenticates the values of a key-value system.
+//!
+//! This capsule sits between users of `hil::kv_system` and an implementation
+//! of it, such as `capsules::tickv`, and implements `hil::kv_system` itself.
+//! Values are encrypted with AES-128-CCM before they are stored and decrypted
+//! and verified when they are read, so they can't be read or changed through
+//! the flash.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  K-V in Tock          |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Encryption (this)    |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  TicKV                |
+//! |                       |
+//! +-----------------------+
+//!
+//! Records
+//! -------
+//!
+//! Every value is stored as a record:
+//!
+//! ```text
+//! [ version (1) | epoch (8) | counter (4) | length (2) | encrypted value | MIC (16) ]
+//! ```
+//!
+//! The hashed key and the header of the record are authenticated as
+//! associated data, so a record can't be moved to another key and its header
+//! can't be changed. The nonce is built from the epoch and the counter of the
+//! record.
+//!
+//! Nonces
+//! ------
+//!
+//! The epoch is a random number read from the RNG before the first record is
+//! written after boot. Every record written gets the next value of a
+//! monotonic counter, which is never used twice within a boot. The counter
+//! record can be missing or rolled back, so counters can repeat across boots,
+//! but the epoch makes the nonces of two boots differ. The RNG must therefore
+//! return different numbers on every boot.
+//!
+//! Counters
+//! --------
+//!
+//! The counter is kept in a counter record of its own, which is authenticated
+//! like the other records. To avoid writing the counter record for every
+//! value, counters are leased in blocks of `COUNTER_LEASE`: the counter record
+//! holds the end of the current block, and is written before the first
+//! counter of the next block is used.
+//!
+//! A record with a counter that is not below the one in the counter record
+//! was not written by this capsule since the counter record was written, so
+//! it is rejected. This catches records written after the counter record was
+//! rolled back. If the counter record is missing, for example on the first
+//! boot, counters start at 0 and existing records are rejected until the
+//! first block of counters is leased.
+//!
+//! Limitations
+//! -----------
+//!
+//! Records don't carry a version per key, so an older record of a key whose
+//! counter is below the one in the counter record can be written back to the
+//! flash and is read as the current value of the key. Rolling back the whole
+//! flash, including the counter record, can't be detected either. Both need
+//! storage that can't be rolled back.
+//!
+//! The AES key must not be stored in the flash the records are stored in,
+//! for example it can be derived by a key manager.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let kv_encryption = static_init!(
+//!     capsules::kv_encryption::KVEncryption<
+//!         'static,
+//!         capsules::tickv::TicKVStore<'static, F, H>,
+//!         capsules::tickv::TicKVKeyType,
+//!         VirtualAES128CCM<'static, A>,
+//!         R,
+//!     >,
+//!     capsules::kv_encryption::KVEncryption::new(
+//!         tickv,
+//!         ccm_client,
+//!         rng,
+//!         aes_key,        // [u8; AES128_KEY_SIZE]
+//!         counter_key,    // [u8; 8]
+//!         crypt_buf,      // [u8; capsules::kv_encryption::RECORD_OVERHEAD + 8 + 256]
+//!     )
+//! );
+//! tickv.set_client(kv_encryption);
+//! ccm_client.set_client(kv_encryption);
+//! rng.set_client(kv_encryption);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::kv_system::{self, KVSystem, KeyType};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+
+/// The version of the records.
+const RECORD_VERSION: u8 = 0;
+
+/// The length of the random epoch of a boot.
+const EPOCH_LEN: usize = 8;
+
+/// The length of the header of a record: the version, the epoch, the counter
+/// and the length of the value.
+const HEADER_LEN: usize = 15;
+
+/// The length of the message integrity code at the end of a record.
+const MIC_LEN: usize = 16;
+
+/// The number of bytes a record adds to a value, in addition to the length
+/// of the hashed key, which is used in the crypt buffer while a record is
+/// encrypted or decrypted.
+pub const RECORD_OVERHEAD: usize = HEADER_LEN + MIC_LEN;
+
+/// The number of counters leased every time the counter record is written.
+pub const COUNTER_LEASE: u32 = 64;
+
+/// The length of the value of the counter record.
+const COUNTER_LEN: usize = 4;
+
+/// The kind of record, which is part of the nonce so values and the counter
+/// record never share nonces.
+const KIND_VALUE: u8 = 0;
+const KIND_COUNTER: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    GenerateKey,
+    AppendKey,
+    UpdateKey,
+    GetKey,
+    InvalidateKey,
+    GarbageCollect,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    GetEpoch,
+    ReadCounter,
+    DecryptCounter,
+    EncryptCounter,
+    WriteCounter,
+    EncryptValue,
+    WriteValue,
+    ReadValue,
+    DecryptValue,
+}
+
+pub struct KVEncryption<
+    'a,
+    K: KVSystem<'a, K = T>,
+    T: 'static + KeyType,
+    A: AES128CCM<'a>,
+    R: Rng<'a>,
+> {
+    kv: &'a K,
+    aes: &'a A,
+    rng: &'a R,
+    aes_key: [u8; AES128_KEY_SIZE],
+    client: OptionalCell<&'a dyn kv_system::Client<T>>,
+    operation: Cell<Operation>,
+    state: Cell<State>,
+
+    key_len: usize,
+    max_value_len: usize,
+
+    /// The epoch of this boot, once it has been read from the RNG
+    epoch: OptionalCell<[u8; EPOCH_LEN]>,
+    /// Whether the counter record has been read since boot
+    counter_loaded: Cell<bool>,
+    /// Whether the counter record exists
+    counter_stored: Cell<bool>,
+    /// The next counter to use
+    counter: Cell<u32>,
+    /// The counter stored in the counter record, all counters below it are
+    /// leased
+    counter_limit: Cell<u32>,
+    /// The length of the value of the current record
+    value_len: Cell<usize>,
+    /// The length of the value the caller is writing
+    write_len: Cell<usize>,
+
+    counter_key: TakeCell<'static, T>,
+    crypt_buf: TakeCell<'static, [u8]>,
+    user_key: TakeCell<'static, T>,
+    user_value: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType, A: AES128CCM<'a>, R: Rng<'a>>
+    KVEncryption<'a, K, T, A, R>
+{
+    pub fn new(
+        kv: &'a K,
+        aes: &'a A,
+        rng: &'a R,
+        aes_key: [u8; AES128_KEY_SIZE],
+        counter_key: &'static mut T,
+        crypt_buf: &'static mut [u8],
+    ) -> KVEncryption<'a, K, T, A, R> {
+        // The counter record is stored under a fixed hashed key
+        counter_key.as_mut().iter_mut().for_each(|b| *b = 0xFF);
+        let key_len = counter_key.as_ref().len();
+
+        KVEncryption {
+            kv,
+            aes,
+            rng,
+            aes_key,
+            client: OptionalCell::empty(),
+            operation: Cell::new(Operation::None),
+            state: Cell::new(State::Idle),
+            key_len,
+            max_value_len: crypt_buf
+                .len()
+                .saturating_sub(key_len + RECORD_OVERHEAD)
+                .min(u16::MAX as usize),
+            epoch: OptionalCell::empty(),
+            counter_loaded: Cell::new(false),
+            counter_stored: Cell::new(false),
+            counter: Cell::new(0),
+            counter_limit: Cell::new(0),
+            value_len: Cell::new(0),
+            write_len: Cell::new(0),
+            counter_key: TakeCell::new(counter_key),
+            crypt_buf: TakeCell::new(crypt_buf),
+            user_key: TakeCell::empty(),
+            user_value: TakeCell::empty(),
+        }
+    }
+
+    /// Check that no operation is in progress, that the crypt buffer can hold
+    /// the counter record, and that `key` isn't the key of the counter record.
+    fn check_request(&self, key: &T) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::None {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.crypt_buf.map_or(0, |buf| buf.len()) < self.key_len + RECORD_OVERHEAD + COUNTER_LEN
+        {
+            return Err(ErrorCode::SIZE);
+        }
+        if self
+            .counter_key
+            .map_or(false, |counter_key| counter_key.as_ref() == key.as_ref())
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+
+    /// Start the operation, and return the buffers of the caller if it
+    /// couldn't be started.
+    fn start(
+        &self,
+        operation: Operation,
+    ) -> Result<(), (&'static mut T, &'static mut [u8], Result<(), ErrorCode>)> {
+        self.operation.set(operation);
+        self.next_step().map_err(|e| {
+            self.operation.set(Operation::None);
+            self.state.set(State::Idle);
+            (
+                self.user_key.take().unwrap(),
+                self.user_value.take().unwrap(),
+                Err(e),
+            )
+        })
+    }
+
+    /// Run the next step of the current operation. The counter record is
+    /// read first after boot, the epoch is read before the first write, and
+    /// the counter record is written first when all leased counters have been
+    /// used.
+    fn next_step(&self) -> Result<(), ErrorCode> {
+        if !self.counter_loaded.get() {
+            return self.read_counter();
+        }
+
+        match self.operation.get() {
+            Operation::AppendKey | Operation::UpdateKey => {
+                if self.epoch.is_none() {
+                    self.state.set(State::GetEpoch);
+                    self.rng.get()
+                } else if self.counter.get() >= self.counter_limit.get() {
+                    self.encrypt_counter()
+                } else {
+                    self.encrypt_value()
+                }
+            }
+            Operation::GetKey => self.read_value(),
+            _ => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// Report the result of the current operation to the client.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::None);
+        self.state.set(State::Idle);
+
+        if let (Some(key), Some(value)) = (self.user_key.take(), self.user_value.take()) {
+            self.client.map(move |client| match operation {
+                Operation::AppendKey => client.append_key_complete(result, key, value),
+                Operation::UpdateKey => client.update_key_complete(result, key, value),
+                Operation::GetKey => client.get_value_complete(result, key, value),
+                _ => {}
+            });
+        }
+    }
+
+    /// Continue with the next step of the current operation, or report the
+    /// error if it fails.
+    fn continue_operation(&self) {
+        if let Err(e) = self.next_step() {
+            self.finish(Err(e));
+        }
+    }
+
+    /// Fill in the header and the hashed key in front of the value in `buf`,
+    /// and start encrypting or decrypting the record.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        key: &T,
+        kind: u8,
+        epoch: [u8; EPOCH_LEN],
+        counter: u32,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        let len = self.value_len.get();
+        buf[..self.key_len].copy_from_slice(key.as_ref());
+        let header = &mut buf[self.key_len..self.key_len + HEADER_LEN];
+        header[0] = RECORD_VERSION;
+        header[1..9].copy_from_slice(&epoch);
+        header[9..13].copy_from_slice(&counter.to_be_bytes());
+        header[13..15].copy_from_slice(&(len as u16).to_be_bytes());
+
+        let mut nonce = [0; CCM_NONCE_LENGTH];
+        nonce[..EPOCH_LEN].copy_from_slice(&epoch);
+        nonce[EPOCH_LEN..EPOCH_LEN + 4].copy_from_slice(&counter.to_be_bytes());
+        nonce[CCM_NONCE_LENGTH - 1] = kind;
+
+        if let Err(e) = self
+            .aes
+            .set_key(&self.aes_key)
+            .and_then(|()| self.aes.set_nonce(&nonce))
+        {
+            self.crypt_buf.replace(buf);
+            return Err(e);
+        }
+
+        self.aes
+            .crypt(
+                buf,
+                0,
+                self.key_len + HEADER_LEN,
+                len,
+                MIC_LEN,
+                true,
+                encrypting,
+            )
+            .map_err(|(e, buf)| {
+                self.crypt_buf.replace(buf);
+                e
+            })
+    }
+
+    /// Check the header of a record that was read into `buf`, move the
+    /// record behind the hashed key, and start decrypting it.
+    fn decrypt(&self, buf: &'static mut [u8], key: &T, kind: u8) -> Result<(), ErrorCode> {
+        let version = buf[0];
+        let mut epoch = [0; EPOCH_LEN];
+        epoch.copy_from_slice(&buf[1..9]);
+        let counter = u32::from_be_bytes([buf[9], buf[10], buf[11], buf[12]]);
+        let len = u16::from_be_bytes([buf[13], buf[14]]) as usize;
+
+        if version != RECORD_VERSION
+            || len > self.max_value_len
+            || (kind == KIND_VALUE && counter >= self.counter_limit.get())
+        {
+            self.crypt_buf.replace(buf);
+            return Err(ErrorCode::FAIL);
+        }
+
+        buf.copy_within(..HEADER_LEN + len + MIC_LEN, self.key_len);
+        self.value_len.set(len);
+        self.crypt(buf, key, kind, epoch, counter, false)
+    }
+
+    /// Move an encrypted record to the start of `buf`, and return the part
+    /// of `buf` to store.
+    fn record(&self, buf: &'static mut [u8]) -> LeasableBuffer<'static, u8> {
+        let record_len = HEADER_LEN + self.value_len.get() + MIC_LEN;
+        buf.copy_within(self.key_len..self.key_len + record_len, 0);
+        let mut record = LeasableBuffer::new(buf);
+        record.slice(..record_len);
+        record
+    }
+
+    fn read_counter(&self) -> Result<(), ErrorCode> {
+        let key = self.counter_key.take().ok_or(ErrorCode::RESERVE)?;
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::RESERVE)?;
+        self.state.set(State::ReadCounter);
+        self.kv.get_value(key, buf).map_err(|(key, buf, e)| {
+            self.counter_key.replace(key);
+            self.crypt_buf.replace(buf);
+            e.err().unwrap_or(ErrorCode::FAIL)
+        })
+    }
+
+    /// Lease the next block of counters.
+    fn encrypt_counter(&self) -> Result<(), ErrorCode> {
+        let limit = self
+            .counter_limit
+            .get()
+            .checked_add(COUNTER_LEASE)
+            .ok_or(ErrorCode::FAIL)?;
+        let epoch = self.epoch.extract().ok_or(ErrorCode::FAIL)?;
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::RESERVE)?;
+
+        let m_off = self.key_len + HEADER_LEN;
+        buf[m_off..m_off + COUNTER_LEN].copy_from_slice(&limit.to_be_bytes());
+        self.value_len.set(COUNTER_LEN);
+        self.state.set(State::EncryptCounter);
+        self.counter_key.map_or(Err(ErrorCode::RESERVE), |key| {
+            self.crypt(buf, key, KIND_COUNTER, epoch, limit, true)
+        })
+    }
+
+    fn encrypt_value(&self) -> Result<(), ErrorCode> {
+        let counter = self.counter.get();
+        let epoch = self.epoch.extract().ok_or(ErrorCode::FAIL)?;
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::RESERVE)?;
+
+        let m_off = self.key_len + HEADER_LEN;
+        let len = self.write_len.get();
+        self.value_len.set(len);
+        self.user_value
+            .map(|value| buf[m_off..m_off + len].copy_from_slice(&value[..len]));
+        // Never use a counter twice, even if this record isn't stored
+        self.counter.set(counter + 1);
+        self.state.set(State::EncryptValue);
+        self.user_key.map_or(Err(ErrorCode::RESERVE), |key| {
+            self.crypt(buf, key, KIND_VALUE, epoch, counter, true)
+        })
+    }
+
+    fn read_value(&self) -> Result<(), ErrorCode> {
+        let key = self.user_key.take().ok_or(ErrorCode::RESERVE)?;
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::RESERVE)?;
+        self.state.set(State::ReadValue);
+        self.kv.get_value(key, buf).map_err(|(key, buf, e)| {
+            self.user_key.replace(key);
+            self.crypt_buf.replace(buf);
+            e.err().unwrap_or(ErrorCode::FAIL)
+        })
+    }
+
+    /// Copy the decrypted value to the buffer of the caller, and clear it
+    /// from the crypt buffer.
+    fn copy_value(&self, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let m_off = self.key_len + HEADER_LEN;
+        let len = self.value_len.get();
+        let plaintext = &mut buf[m_off..m_off + len];
+
+        let result = self.user_value.map_or(Err(ErrorCode::RESERVE), |value| {
+            let copy_len = len.min(value.len());
+            value[..copy_len].copy_from_slice(&plaintext[..copy_len]);
+            if copy_len < len {
+                Err(ErrorCode::SIZE)
+            } else {
+                Ok(())
+            }
+        });
+        plaintext.iter_mut().for_each(|b| *b = 0);
+        result
+    }
+
+    /// Handle the completion of a write of the counter record or a value.
+    fn write_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.crypt_buf.replace(value);
+
+        match self.state.get() {
+            State::WriteCounter => {
+                self.counter_key.replace(key);
+                match result {
+                    Ok(()) => {
+                        self.counter_stored.set(true);
+                        self.counter_limit
+                            .set(self.counter_limit.get() + COUNTER_LEASE);
+                        self.continue_operation();
+                    }
+                    Err(e) => self.finish(Err(e)),
+                }
+            }
+            State::WriteValue => {
+                self.user_key.replace(key);
+                self.finish(result);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType, A: AES128CCM<'a>, R: Rng<'a>> KVSystem<'a>
+    for KVEncryption<'a, K, T, A, R>
+{
+    type K = T;
+
+    fn set_client(&self, client: &'a dyn kv_system::Client<T>) {
+        self.client.set(client);
+    }
+
+    fn generate_key(
+        &self,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut T,
+    ) -> Result<(), (&'static mut [u8], &'static mut T, Result<(), ErrorCode>)> {
+        if self.operation.get() != Operation::None {
+            return Err((unhashed_key, key_buf, Err(ErrorCode::BUSY)));
+        }
+        self.operation.set(Operation::GenerateKey);
+        self.kv.generate_key(unhashed_key, key_buf).map_err(|e| {
+            self.operation.set(Operation::None);
+            e
+        })
+    }
+
+    fn append_key(
+        &self,
+        key: &'static mut T,
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<(), (&'static mut T, &'static mut [u8], Result<(), ErrorCode>)> {
+        let length = value.len();
+        let start = value.as_ptr();
+        let value = value.take();
+        if start != value.as_ptr() {
+            return Err((key, value, Err(ErrorCode::INVAL)));
+        }
+        if length > self.max_value_len {
+            return Err((key, value, Err(ErrorCode::SIZE)));
+        }
+        if let Err(e) = self.check_request(key) {
+            return Err((key, value, Err(e)));
+        }
+
+        self.write_len.set(length);
+        self.user_key.replace(key);
+        self.user_value.replace(value);
+        self.start(Operation::AppendKey)
+    }
+
+    fn update_key(
+        &self,
+        key: &'static mut T,
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<(), (&'static mut T, &'static mut [u8], Result<(), ErrorCode>)> {
+        let length = value.len();
+        let start = value.as_ptr();
+        let value = value.take();
+        if start != value.as_ptr() {
+            return Err((key, value, Err(ErrorCode::INVAL)));
+        }
+        if length > self.max_value_len {
+            return Err((key, value, Err(ErrorCode::SIZE)));
+        }
+        if let Err(e) = self.check_request(key) {
+            return Err((key, value, Err(e)));
+        }
+
+        self.write_len.set(length);
+        self.user_key.replace(key);
+        self.user_value.replace(value);
+        self.start(Operation::UpdateKey)
+    }
+
+    fn get_value(
+        &self,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) -> Result<(), (&'static mut T, &'static mut [u8], Result<(), ErrorCode>)> {
+        if let Err(e) = self.check_request(key) {
+            return Err((key, ret_buf, Err(e)));
+        }
+
+        self.user_key.replace(key);
+        self.user_value.replace(ret_buf);
+        self.start(Operation::GetKey)
+    }
+
+    fn invalidate_key(
+        &self,
+        key: &'static mut T,
+    ) -> Result<(), (&'static mut T, Result<(), ErrorCode>)> {
+        if let Err(e) = self.check_request(key) {
+            return Err((key, Err(e)));
+        }
+        self.operation.set(Operation::InvalidateKey);
+        self.kv.invalidate_key(key).map_err(|e| {
+            self.operation.set(Operation::None);
+            e
+        })
+    }
+
+    fn garbage_collect(&self) -> Result<usize, Result<(), ErrorCode>> {
+        if self.operation.get() != Operation::None {
+            return Err(Err(ErrorCode::BUSY));
+        }
+        self.operation.set(Operation::GarbageCollect);
+        self.kv.garbage_collect().map_err(|e| {
+            self.operation.set(Operation::None);
+            e
+        })
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType, A: AES128CCM<'a>, R: Rng<'a>>
+    kv_system::Client<T> for KVEncryption<'a, K, T, A, R>
+{
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut T,
+    ) {
+        self.operation.set(Operation::None);
+        self.client.map(move |client| {
+            client.generate_key_complete(result, unhashed_key, key_buf);
+        });
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.write_complete(result, key, value);
+    }
+
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.write_complete(result, key, value);
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) {
+        match self.state.get() {
+            State::ReadCounter => {
+                self.counter_key.replace(key);
+                match result {
+                    Ok(()) => {
+                        self.state.set(State::DecryptCounter);
+                        let ret = self.counter_key.map_or(Err(ErrorCode::RESERVE), |key| {
+                            self.decrypt(ret_buf, key, KIND_COUNTER)
+                        });
+                        if let Err(e) = ret {
+                            self.finish(Err(e));
+                        }
+                    }
+                    Err(ErrorCode::NOSUPPORT) => {
+                        // There is no counter record yet, so start counting
+                        // from 0
+                        self.crypt_buf.replace(ret_buf);
+                        self.counter_loaded.set(true);
+                        self.continue_operation();
+                    }
+                    Err(e) => {
+                        self.crypt_buf.replace(ret_buf);
+                        self.finish(Err(e));
+                    }
+                }
+            }
+            State::ReadValue => {
+                self.user_key.replace(key);
+                match result {
+                    Ok(()) => {
+                        self.state.set(State::DecryptValue);
+                        let ret = self.user_key.map_or(Err(ErrorCode::RESERVE), |key| {
+                            self.decrypt(ret_buf, key, KIND_VALUE)
+                        });
+                        if let Err(e) = ret {
+                            self.finish(Err(e));
+                        }
+                    }
+                    Err(e) => {
+                        self.crypt_buf.replace(ret_buf);
+                        // The record doesn't fit in the crypt buffer, so it
+                        // wasn't written by this capsule
+                        self.finish(Err(if e == ErrorCode::SIZE {
+                            ErrorCode::FAIL
+                        } else {
+                            e
+                        }));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
+        self.operation.set(Operation::None);
+        self.client.map(move |client| {
+            client.invalidate_key_complete(result, key);
+        });
+    }
+
+    fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.set(Operation::None);
+        self.client.map(move |client| {
+            client.garbage_collect_complete(result);
+        });
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType, A: AES128CCM<'a>, R: Rng<'a>> CCMClient
+    for KVEncryption<'a, K, T, A, R>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        if let Err(e) = res {
+            self.crypt_buf.replace(buf);
+            self.finish(Err(e));
+            return;
+        }
+
+        match self.state.get() {
+            State::EncryptCounter => {
+                self.state.set(State::WriteCounter);
+                let record = self.record(buf);
+                let key = self.counter_key.take().unwrap();
+                let ret = if self.counter_stored.get() {
+                    self.kv.update_key(key, record)
+                } else {
+                    self.kv.append_key(key, record)
+                };
+                if let Err((key, buf, e)) = ret {
+                    self.counter_key.replace(key);
+                    self.crypt_buf.replace(buf);
+                    self.finish(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+                }
+            }
+            State::EncryptValue => {
+                self.state.set(State::WriteValue);
+                let record = self.record(buf);
+                let key = self.user_key.take().unwrap();
+                let ret = if self.operation.get() == Operation::UpdateKey {
+                    self.kv.update_key(key, record)
+                } else {
+                    self.kv.append_key(key, record)
+                };
+                if let Err((key, buf, e)) = ret {
+                    self.user_key.replace(key);
+                    self.crypt_buf.replace(buf);
+                    self.finish(Err(e.err().unwrap_or(ErrorCode::FAIL)));
+                }
+            }
+            State::DecryptCounter => {
+                let m_off = self.key_len + HEADER_LEN;
+                let limit = u32::from_be_bytes([
+                    buf[m_off],
+                    buf[m_off + 1],
+                    buf[m_off + 2],
+                    buf[m_off + 3],
+                ]);
+                // The counter record is written with its limit as counter
+                let counter = u32::from_be_bytes([
+                    buf[self.key_len + 9],
+                    buf[self.key_len + 10],
+                    buf[self.key_len + 11],
+                    buf[self.key_len + 12],
+                ]);
+                let valid = tag_is_valid && self.value_len.get() == COUNTER_LEN && counter == limit;
+                self.crypt_buf.replace(buf);
+
+                if valid {
+                    // Counters below the limit might have been used before
+                    // the reboot
+                    self.counter_loaded.set(true);
+                    self.counter_stored.set(true);
+                    self.counter.set(limit);
+                    self.counter_limit.set(limit);
+                    self.continue_operation();
+                } else {
+                    self.finish(Err(ErrorCode::FAIL));
+                }
+            }
+            State::DecryptValue => {
+                let result = if tag_is_valid {
+                    self.copy_value(buf)
+                } else {
+                    // Don't leave the unverified plaintext in the buffer
+                    let m_off = self.key_len + HEADER_LEN;
+                    buf[m_off..m_off + self.value_len.get()]
+                        .iter_mut()
+                        .for_each(|b| *b = 0);
+                    Err(ErrorCode::FAIL)
+                };
+                self.crypt_buf.replace(buf);
+                self.finish(result);
+            }
+            _ => {
+                self.crypt_buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType, A: AES128CCM<'a>, R: Rng<'a>> rng::Client
+    for KVEncryption<'a, K, T, A, R>
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        if self.state.get() != State::GetEpoch {
+            return rng::Continue::Done;
+        }
+        if let Err(e) = error {
+            self.finish(Err(e));
+            return rng::Continue::Done;
+        }
+
+        let mut epoch = [0; EPOCH_LEN];
+        for word in epoch.chunks_mut(4) {
+            match randomness.next() {
+                Some(random) => word.copy_from_slice(&random.to_be_bytes()),
+                None => return rng::Continue::More,
+            }
+        }
+        self.epoch.set(epoch);
+        self.continue_operation();
+        rng::Continue::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::hil::kv_system::Client;
+    use std::boxed::Box;
+    use std::collections::BTreeMap;
+    use std::vec;
+    use std::vec::Vec;
+
+    type Key = [u8; 8];
+
+    const COUNTER_KEY: Key = [0xFF; 8];
+
+    enum KVRequest {
+        Write(&'static mut Key, &'static mut [u8], usize, bool),
+        Read(&'static mut Key, &'static mut [u8]),
+    }
+
+    /// A KV system in RAM, which completes requests when the test calls
+    /// `complete()`.
+    struct RamKV {
+        records: RefCell<BTreeMap<Key, Vec<u8>>>,
+        client: OptionalCell<&'static dyn Client<Key>>,
+        request: RefCell<Option<KVRequest>>,
+    }
+
+    impl RamKV {
+        fn new() -> RamKV {
+            RamKV {
+                records: RefCell::new(BTreeMap::new()),
+                client: OptionalCell::empty(),
+                request: RefCell::new(None),
+            }
+        }
+
+        fn complete(&self) -> bool {
+            let request = match self.request.borrow_mut().take() {
+                Some(request) => request,
+                None => return false,
+            };
+            let client = self.client.extract().unwrap();
+            match request {
+                KVRequest::Write(key, buf, len, update) => {
+                    let mut records = self.records.borrow_mut();
+                    if update {
+                        records.insert(*key, buf[..len].to_vec());
+                        drop(records);
+                        client.update_key_complete(Ok(()), key, buf);
+                    } else if records.contains_key(key) {
+                        drop(records);
+                        client.append_key_complete(Err(ErrorCode::NOSUPPORT), key, buf);
+                    } else {
+                        records.insert(*key, buf[..len].to_vec());
+                        drop(records);
+                        client.append_key_complete(Ok(()), key, buf);
+                    }
+                }
+                KVRequest::Read(key, buf) => {
+                    let record = self.records.borrow().get(key).cloned();
+                    let result = match record {
+                        None => Err(ErrorCode::NOSUPPORT),
+                        Some(record) if record.len() > buf.len() => Err(ErrorCode::SIZE),
+                        Some(record) => {
+                            buf[..record.len()].copy_from_slice(&record);
+                            Ok(())
+                        }
+                    };
+                    client.get_value_complete(result, key, buf);
+                }
+            }
+            true
+        }
+
+        fn write(
+            &self,
+            key: &'static mut Key,
+            value: LeasableBuffer<'static, u8>,
+            update: bool,
+        ) -> Result<(), (&'static mut Key, &'static mut [u8], Result<(), ErrorCode>)> {
+            let len = value.len();
+            self.request
+                .replace(Some(KVRequest::Write(key, value.take(), len, update)));
+            Ok(())
+        }
+    }
+
+    impl KVSystem<'static> for RamKV {
+        type K = Key;
+
+        fn set_client(&self, client: &'static dyn Client<Key>) {
+            self.client.set(client);
+        }
+
+        fn generate_key(
+            &self,
+            unhashed_key: &'static mut [u8],
+            key_buf: &'static mut Key,
+        ) -> Result<(), (&'static mut [u8], &'static mut Key, Result<(), ErrorCode>)> {
+            Err((unhashed_key, key_buf, Err(ErrorCode::NOSUPPORT)))
+        }
+
+        fn append_key(
+            &self,
+            key: &'static mut Key,
+            value: LeasableBuffer<'static, u8>,
+        ) -> Result<(), (&'static mut Key, &'static mut [u8], Result<(), ErrorCode>)> {
+            self.write(key, value, false)
+        }
+
+        fn update_key(
+            &self,
+            key: &'static mut Key,
+            value: LeasableBuffer<'static, u8>,
+        ) -> Result<(), (&'static mut Key, &'static mut [u8], Result<(), ErrorCode>)> {
+            self.write(key, value, true)
+        }
+
+        fn get_value(
+            &self,
+            key: &'static mut Key,
+            ret_buf: &'static mut [u8],
+        ) -> Result<(), (&'static mut Key, &'static mut [u8], Result<(), ErrorCode>)> {
+            self.request.replace(Some(KVRequest::Read(key, ret_buf)));
+            Ok(())
+        }
+
+        fn invalidate_key(
+            &self,
+            key: &'static mut Key,
+        ) -> Result<(), (&'static mut Key, Result<(), ErrorCode>)> {
+            Err((key, Err(ErrorCode::NOSUPPORT)))
+        }
+
+        fn garbage_collect(&self) -> Result<usize, Result<(), ErrorCode>> {
+            Err(Err(ErrorCode::NOSUPPORT))
+        }
+    }
+
+    /// Hash `parts` with FNV-1a.
+    fn hash(parts: &[&[u8]]) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        for part in parts {
+            for b in part.iter().chain(&[0xA5]) {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// A stand-in for AES-CCM, which encrypts with a keystream and computes a
+    /// MIC from hashes of the key, the nonce, the associated data and the
+    /// message. It records the nonces it is used with.
+    struct FakeCCM {
+        key: RefCell<Vec<u8>>,
+        nonce: RefCell<Vec<u8>>,
+        nonces: RefCell<Vec<Vec<u8>>>,
+        client: OptionalCell<&'static dyn CCMClient>,
+        request: RefCell<Option<(&'static mut [u8], usize, usize, usize, bool)>>,
+    }
+
+    impl FakeCCM {
+        fn new() -> FakeCCM {
+            FakeCCM {
+                key: RefCell::new(Vec::new()),
+                nonce: RefCell::new(Vec::new()),
+                nonces: RefCell::new(Vec::new()),
+                client: OptionalCell::empty(),
+                request: RefCell::new(None),
+            }
+        }
+
+        fn complete(&self) -> bool {
+            let (buf, a_off, m_off, m_len, encrypting) = match self.request.borrow_mut().take() {
+                Some(request) => request,
+                None => return false,
+            };
+            let key = self.key.borrow().clone();
+            let nonce = self.nonce.borrow().clone();
+            self.nonces.borrow_mut().push(nonce.clone());
+
+            let (a_data, rest) = buf[a_off..].split_at_mut(m_off - a_off);
+            let (message, rest) = rest.split_at_mut(m_len);
+            let mic = &mut rest[..MIC_LEN];
+            let keystream = |message: &mut [u8]| {
+                for (i, b) in message.iter_mut().enumerate() {
+                    *b ^= hash(&[&key, &nonce, &i.to_be_bytes()]) as u8;
+                }
+            };
+            // The MIC covers the plaintext
+            let expected = |plaintext: &[u8]| {
+                let mut expected = [0; MIC_LEN];
+                expected[..8]
+                    .copy_from_slice(&hash(&[&key, &nonce, a_data, plaintext]).to_be_bytes());
+                expected[8..].copy_from_slice(&hash(&[&nonce, plaintext, &key]).to_be_bytes());
+                expected
+            };
+
+            let tag_is_valid = if encrypting {
+                mic.copy_from_slice(&expected(message));
+                keystream(message);
+                true
+            } else {
+                keystream(message);
+                mic == &expected(message)[..]
+            };
+            self.client
+                .map(move |client| client.crypt_done(buf, Ok(()), tag_is_valid));
+            true
+        }
+    }
+
+    impl AES128CCM<'static> for FakeCCM {
+        fn set_client(&'static self, client: &'static dyn CCMClient) {
+            self.client.set(client);
+        }
+
+        fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+            self.key.replace(key.to_vec());
+            Ok(())
+        }
+
+        fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+            self.nonce.replace(nonce.to_vec());
+            Ok(())
+        }
+
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            a_off: usize,
+            m_off: usize,
+            m_len: usize,
+            mic_len: usize,
+            _confidential: bool,
+            encrypting: bool,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            assert_eq!(mic_len, MIC_LEN);
+            self.request
+                .replace(Some((buf, a_off, m_off, m_len, encrypting)));
+            Ok(())
+        }
+    }
+
+    /// An RNG that returns consecutive numbers from `next`.
+    struct FakeRng {
+        next: Cell<u32>,
+        pending: Cell<bool>,
+        client: OptionalCell<&'static dyn rng::Client>,
+    }
+
+    impl FakeRng {
+        fn complete(&self) -> bool {
+            if !self.pending.replace(false) {
+                return false;
+            }
+            let mut randomness = core::iter::from_fn(|| {
+                let next = self.next.get();
+                self.next.set(next + 1);
+                Some(next)
+            });
+            self.client.map(|client| {
+                client.randomness_available(&mut randomness, Ok(()));
+            });
+            true
+        }
+    }
+
+    impl Rng<'static> for FakeRng {
+        fn get(&self) -> Result<(), ErrorCode> {
+            self.pending.set(true);
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            self.pending.set(false);
+            Ok(())
+        }
+
+        fn set_client(&'static self, client: &'static dyn rng::Client) {
+            self.client.set(client);
+        }
+    }
+
+    struct TestClient {
+        result: Cell<Option<Result<(), ErrorCode>>>,
+        value: TakeCell<'static, [u8]>,
+    }
+
+    impl TestClient {
+        fn new() -> TestClient {
+            TestClient {
+                result: Cell::new(None),
+                value: TakeCell::empty(),
+            }
+        }
+    }
+
+    impl Client<Key> for TestClient {
+        fn generate_key_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _unhashed_key: &'static mut [u8],
+            _key_buf: &'static mut Key,
+        ) {
+        }
+
+        fn append_key_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: &'static mut Key,
+            value: &'static mut [u8],
+        ) {
+            self.result.set(Some(result));
+            self.value.replace(value);
+        }
+
+        fn update_key_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: &'static mut Key,
+            value: &'static mut [u8],
+        ) {
+            self.result.set(Some(result));
+            self.value.replace(value);
+        }
+
+        fn get_value_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: &'static mut Key,
+            ret_buf: &'static mut [u8],
+        ) {
+            self.result.set(Some(result));
+            self.value.replace(ret_buf);
+        }
+
+        fn invalidate_key_complete(&self, _result: Result<(), ErrorCode>, _key: &'static mut Key) {}
+
+        fn garbage_collect_complete(&self, _result: Result<(), ErrorCode>) {}
+    }
+
+    type Encryption = KVEncryption<'static, RamKV, Key, FakeCCM, FakeRng>;
+
+    struct Test {
+        kv: &'static RamKV,
+        ccm: &'static FakeCCM,
+        rng: &'static FakeRng,
+        client: &'static TestClient,
+        encryption: &'static Encryption,
+    }
+
+    impl Test {
+        fn new() -> Test {
+            Test::boot(Box::leak(Box::new(RamKV::new())), 1, 64)
+        }
+
+        /// Set up the capsule on `kv`, as after a reboot, with an RNG that
+        /// starts at `random`.
+        fn boot(kv: &'static RamKV, random: u32, buf_len: usize) -> Test {
+            let ccm = Box::leak(Box::new(FakeCCM::new()));
+            let rng = Box::leak(Box::new(FakeRng {
+                next: Cell::new(random),
+                pending: Cell::new(false),
+                client: OptionalCell::empty(),
+            }));
+            let client = Box::leak(Box::new(TestClient::new()));
+            let encryption = Box::leak(Box::new(KVEncryption::new(
+                kv,
+                ccm,
+                rng,
+                [0x42; AES128_KEY_SIZE],
+                Box::leak(Box::new([0; 8])),
+                Box::leak(vec![0; buf_len].into_boxed_slice()),
+            )));
+            kv.set_client(encryption);
+            ccm.set_client(encryption);
+            rng.set_client(encryption);
+            encryption.set_client(client);
+            Test {
+                kv,
+                ccm,
+                rng,
+                client,
+                encryption,
+            }
+        }
+
+        /// Complete requests until the current operation is done, and
+        /// return its result.
+        fn run(&self) -> Result<(), ErrorCode> {
+            while self.kv.complete() || self.ccm.complete() || self.rng.complete() {}
+            self.client
+                .result
+                .take()
+                .expect("operation didn't complete")
+        }
+
+        fn set(&self, key: Key, value: &[u8]) -> Result<(), ErrorCode> {
+            let buf = Box::leak(value.to_vec().into_boxed_slice());
+            self.encryption
+                .update_key(Box::leak(Box::new(key)), LeasableBuffer::new(buf))
+                .map_err(|(_, _, e)| e.unwrap_err())?;
+            self.run()
+        }
+
+        fn get(&self, key: Key) -> Result<Vec<u8>, ErrorCode> {
+            self.encryption
+                .get_value(
+                    Box::leak(Box::new(key)),
+                    Box::leak(vec![0; 16].into_boxed_slice()),
+                )
+                .map_err(|(_, _, e)| e.unwrap_err())?;
+            self.run()?;
+            Ok(self.client.value.take().unwrap().to_vec())
+        }
+
+        fn record(&self, key: Key) -> Vec<u8> {
+            self.kv.records.borrow()[&key].clone()
+        }
+    }
+
+    fn padded(value: &[u8]) -> Vec<u8> {
+        let mut padded = value.to_vec();
+        padded.resize(16, 0);
+        padded
+    }
+
+    #[test]
+    fn round_trip() {
+        let test = Test::new();
+        test.set([1; 8], b"secret value").unwrap();
+        test.set([2; 8], b"other").unwrap();
+
+        let record = test.record([1; 8]);
+        assert_eq!(record.len(), RECORD_OVERHEAD + 12);
+        assert!(!record.windows(6).any(|w| w == b"secret"));
+        assert_eq!(test.get([1; 8]), Ok(padded(b"secret value")));
+        assert_eq!(test.get([2; 8]), Ok(padded(b"other")));
+        assert_eq!(test.get([3; 8]), Err(ErrorCode::NOSUPPORT));
+    }
+
+    #[test]
+    fn tampered_record() {
+        let test = Test::new();
+        test.set([1; 8], b"secret value").unwrap();
+        let record = test.record([1; 8]);
+
+        // Flip a byte of the ciphertext, of the MIC and of the counter and
+        // the epoch in the header
+        for i in &[HEADER_LEN + 3, record.len() - 1, 10, 2] {
+            let mut tampered = record.clone();
+            tampered[*i] ^= 1;
+            test.kv.records.borrow_mut().insert([1; 8], tampered);
+            assert_eq!(test.get([1; 8]), Err(ErrorCode::FAIL));
+            assert_eq!(test.client.value.take().unwrap(), &[0; 16]);
+            // The unverified plaintext isn't left in the crypt buffer
+            test.encryption
+                .crypt_buf
+                .map(|buf| assert!(!buf.windows(6).any(|w| w == b"secret")));
+        }
+
+        test.kv.records.borrow_mut().insert([1; 8], record);
+        assert_eq!(test.get([1; 8]), Ok(padded(b"secret value")));
+    }
+
+    #[test]
+    fn moved_record() {
+        let test = Test::new();
+        test.set([1; 8], b"secret value").unwrap();
+        let record = test.record([1; 8]);
+        test.kv.records.borrow_mut().insert([2; 8], record);
+        assert_eq!(test.get([2; 8]), Err(ErrorCode::FAIL));
+    }
+
+    #[test]
+    fn counter_lease() {
+        let kv = Box::leak(Box::new(RamKV::new()));
+        let test = Test::boot(kv, 1, 64);
+        test.set([1; 8], b"first").unwrap();
+        let lease = test.record(COUNTER_KEY);
+        assert_eq!(test.encryption.counter_limit.get(), COUNTER_LEASE);
+
+        // The counter record isn't written until the lease is used up
+        for i in 1..COUNTER_LEASE {
+            test.set([2; 8], &i.to_be_bytes()).unwrap();
+        }
+        assert_eq!(test.record(COUNTER_KEY), lease);
+        test.set([2; 8], b"next lease").unwrap();
+        assert_ne!(test.record(COUNTER_KEY), lease);
+        assert_eq!(test.encryption.counter_limit.get(), 2 * COUNTER_LEASE);
+
+        // After a reboot counting continues after the lease
+        let test = Test::boot(kv, 100, 64);
+        assert_eq!(test.get([1; 8]), Ok(padded(b"first")));
+        assert_eq!(test.get([2; 8]), Ok(padded(b"next lease")));
+        test.set([3; 8], b"third").unwrap();
+        assert_eq!(test.encryption.counter.get(), 2 * COUNTER_LEASE + 1);
+        assert_eq!(test.encryption.counter_limit.get(), 3 * COUNTER_LEASE);
+    }
+
+    #[test]
+    fn rolled_back_counter() {
+        let kv = Box::leak(Box::new(RamKV::new()));
+        let test = Test::boot(kv, 1, 64);
+        test.set([1; 8], b"first").unwrap();
+        let first_nonces = test.ccm.nonces.take();
+
+        // A record written after the counter record is rejected
+        test.set([2; 8], b"second").unwrap();
+        test.kv.records.borrow_mut().remove(&COUNTER_KEY);
+        let test = Test::boot(kv, 1, 64);
+        assert_eq!(test.get([2; 8]), Err(ErrorCode::FAIL));
+
+        // Counters start again from 0, but nonces aren't reused since the
+        // epoch of this boot is different
+        let test = Test::boot(kv, 1000, 64);
+        test.set([3; 8], b"third").unwrap();
+        assert_eq!(test.encryption.counter.get(), 1);
+        assert!(test
+            .ccm
+            .nonces
+            .borrow()
+            .iter()
+            .all(|nonce| !first_nonces.contains(nonce)));
+        assert_eq!(test.get([3; 8]), Ok(padded(b"third")));
+    }
+
+    #[test]
+    fn crypt_buffer_too_small() {
+        let kv = Box::leak(Box::new(RamKV::new()));
+        let test = Test::boot(kv, 1, 8 + RECORD_OVERHEAD + COUNTER_LEN - 1);
+        assert_eq!(test.set([1; 8], b""), Err(ErrorCode::SIZE));
+        assert_eq!(test.get([1; 8]), Err(ErrorCode::SIZE));
+    }
+}
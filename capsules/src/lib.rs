@@ -20,11 +20,14 @@ pub mod bus;
 pub mod button;
 pub mod buzzer_driver;
 pub mod console;
+pub mod crash_log;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod fat_fs;
+pub mod fat_fs_driver;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -38,6 +41,9 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kernel_trace;
+pub mod kv_driver;
+pub mod kv_encryption;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
@@ -59,9 +65,11 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod process_checker;
 pub mod process_console;
 pub mod proximity;
 pub mod public_key_crypto;
+pub mod pubsub;
 pub mod read_only_state;
 pub mod rf233;
 pub mod rf233_const;
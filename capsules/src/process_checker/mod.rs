@@ -0,0 +1,4 @@
+//! Credentials checking policies for `kernel::process_checker`.
+
+pub mod sha256;
+pub mod signature;
@@ -0,0 +1,202 @@
+//! Credentials checking policy that accepts processes whose SHA-256 hash
+//! credentials match the hash of their integrity region.
+//!
+//! A SHA-256 footer only proves the image was not corrupted, not who built
+//! it. Boards that need to restrict which apps run should use a signature
+//! policy instead.
+//!
+//! The digest engine reads data from RAM, while the integrity region of a
+//! process is in flash, so the region is copied through `data_buffer` one
+//! chunk at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let checker = static_init!(
+//!     capsules::process_checker::sha256::AppCheckerSha256<'static, Sha256Software>,
+//!     capsules::process_checker::sha256::AppCheckerSha256::new(
+//!         sha,
+//!         &mut CHECKER_DATA_BUFFER,
+//!         &mut CHECKER_HASH_BUFFER,
+//!         true,
+//!     )
+//! );
+//! sha.set_client(checker);
+//! checker.set_client(checking_machine);
+//! checking_machine.set_policy(checker);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::digest::{self, DigestDataVerify, Sha256};
+use kernel::process_checker::{AppCredentialsChecker, CheckResult, Client};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+pub struct AppCheckerSha256<'a, H: DigestDataVerify<'a, 32> + Sha256> {
+    hasher: &'a H,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    require_credentials: bool,
+    /// RAM buffer the integrity region is copied into for hashing.
+    data_buffer: TakeCell<'static, [u8]>,
+    /// Holds the expected hash while the digest engine verifies it.
+    hash: TakeCell<'static, [u8; 32]>,
+    /// The credentials and integrity region being checked.
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+    binary: OptionalCell<&'a [u8]>,
+    /// How many bytes of `binary` have been given to the digest engine.
+    position: Cell<usize>,
+}
+
+impl<'a, H: DigestDataVerify<'a, 32> + Sha256> AppCheckerSha256<'a, H> {
+    pub fn new(
+        hasher: &'a H,
+        data_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; 32],
+        require_credentials: bool,
+    ) -> AppCheckerSha256<'a, H> {
+        AppCheckerSha256 {
+            hasher,
+            client: OptionalCell::empty(),
+            require_credentials,
+            data_buffer: TakeCell::new(data_buffer),
+            hash: TakeCell::new(hash_buffer),
+            credentials: OptionalCell::empty(),
+            binary: OptionalCell::empty(),
+            position: Cell::new(0),
+        }
+    }
+
+    /// Copy the next chunk of the integrity region into the data buffer and
+    /// give it to the digest engine.
+    fn add_next_chunk(&self, binary: &'a [u8]) -> Result<(), ErrorCode> {
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let position = self.position.get();
+        let len = core::cmp::min(buffer.len(), binary.len() - position);
+        buffer[..len].copy_from_slice(&binary[position..position + len]);
+
+        let mut lease = LeasableBuffer::new(buffer);
+        lease.slice(0..len);
+        match self.hasher.add_data(lease) {
+            Ok(_) => {
+                self.position.set(position + len);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.data_buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+
+    /// Report the result of the current check and become idle.
+    fn check_finished(&self, result: Result<CheckResult, ErrorCode>) {
+        self.binary.take().map(|binary| {
+            self.credentials.take().map(|credentials| {
+                self.client.map(|client| {
+                    client.check_done(result, credentials, binary);
+                });
+            });
+        });
+    }
+}
+
+impl<'a, H: DigestDataVerify<'a, 32> + Sha256> AppCredentialsChecker<'a>
+    for AppCheckerSha256<'a, H>
+{
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.replace(client);
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])> {
+        if credentials.format() != TbfFooterV2CredentialsType::SHA256 {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        if self.binary.is_some() || self.hash.is_none() || self.data_buffer.is_none() {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+
+        self.hasher.clear_data();
+        if let Err(e) = self.hasher.set_mode_sha256() {
+            return Err((e, credentials, binary));
+        }
+        self.hash.map(|hash| {
+            hash.copy_from_slice(credentials.data());
+        });
+        self.position.set(0);
+
+        match self.add_next_chunk(binary) {
+            Ok(()) => {
+                self.credentials.set(credentials);
+                self.binary.set(binary);
+                Ok(())
+            }
+            Err(e) => Err((e, credentials, binary)),
+        }
+    }
+}
+
+impl<'a, H: DigestDataVerify<'a, 32> + Sha256> digest::ClientData<'a, 32>
+    for AppCheckerSha256<'a, H>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+        if let Err(e) = result {
+            self.check_finished(Err(e));
+            return;
+        }
+
+        let binary = match self.binary.extract() {
+            Some(binary) => binary,
+            None => return,
+        };
+        let next = if self.position.get() < binary.len() {
+            self.add_next_chunk(binary)
+        } else {
+            // The whole integrity region has been hashed; compare the result
+            // to the hash in the credentials.
+            self.hash.take().map_or(Err(ErrorCode::FAIL), |hash| {
+                match self.hasher.verify(hash) {
+                    Ok(()) => Ok(()),
+                    Err((e, hash)) => {
+                        self.hash.replace(hash);
+                        Err(e)
+                    }
+                }
+            })
+        };
+        if let Err(e) = next {
+            self.check_finished(Err(e));
+        }
+    }
+}
+
+impl<'a, H: DigestDataVerify<'a, 32> + Sha256> digest::ClientVerify<'a, 32>
+    for AppCheckerSha256<'a, H>
+{
+    fn verification_done(
+        &'a self,
+        result: Result<bool, ErrorCode>,
+        compare: &'static mut [u8; 32],
+    ) {
+        self.hash.replace(compare);
+        self.check_finished(result.map(|matches| {
+            if matches {
+                CheckResult::Accept
+            } else {
+                CheckResult::Reject
+            }
+        }));
+    }
+}
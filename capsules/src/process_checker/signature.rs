@@ -0,0 +1,298 @@
+//! Credentials checking policy that accepts processes signed with a trusted
+//! key.
+//!
+//! The policy hashes the integrity region of the process with SHA-256 and
+//! then asks a `SignatureVerify` implementation whether the signature in the
+//! credentials is a valid signature of that hash. Which key the signature is
+//! checked against is up to the verifier, typically a public key imported
+//! with the `PubKey` trait when the board starts. The policy only handles one
+//! credentials format, chosen by the board, and passes on all others.
+//!
+//! A valid signature accepts the process. An invalid signature passes, so
+//! that another footer signed with a different key can still be checked.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let checker = static_init!(
+//!     capsules::process_checker::signature::AppCheckerSignature<
+//!         'static,
+//!         EcdsaP256Verifier,
+//!         Sha256Software,
+//!         32,
+//!         64,
+//!     >,
+//!     capsules::process_checker::signature::AppCheckerSignature::new(
+//!         sha,
+//!         verifier,
+//!         &mut CHECKER_DATA_BUFFER,
+//!         &mut CHECKER_HASH_BUFFER,
+//!         &mut CHECKER_SIGNATURE_BUFFER,
+//!         tock_tbf::types::TbfFooterV2CredentialsType::EcdsaNistP256,
+//!         true,
+//!     )
+//! );
+//! sha.set_client(checker);
+//! verifier.set_verify_client(checker);
+//! checker.set_client(checking_machine);
+//! checking_machine.set_policy(checker);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::hil::digest::{self, DigestDataHash, Sha256};
+use kernel::hil::public_key_crypto::signature::{ClientVerify, SignatureVerify};
+use kernel::process_checker::{AppCredentialsChecker, CheckResult, Client};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableBuffer;
+use kernel::ErrorCode;
+use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+pub struct AppCheckerSignature<
+    'a,
+    S: SignatureVerify<'a, HL, SL>,
+    H: DigestDataHash<'a, HL> + Sha256,
+    const HL: usize,
+    const SL: usize,
+> {
+    hasher: &'a H,
+    verifier: &'a S,
+    client: OptionalCell<&'a dyn Client<'a>>,
+    credentials_type: TbfFooterV2CredentialsType,
+    require_credentials: bool,
+    /// RAM buffer the integrity region is copied into for hashing.
+    data_buffer: TakeCell<'static, [u8]>,
+    hash: TakeCell<'static, [u8; HL]>,
+    signature: TakeCell<'static, [u8; SL]>,
+    /// The credentials and integrity region being checked.
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+    binary: OptionalCell<&'a [u8]>,
+    /// How many bytes of `binary` have been given to the digest engine.
+    position: Cell<usize>,
+}
+
+impl<
+        'a,
+        S: SignatureVerify<'a, HL, SL>,
+        H: DigestDataHash<'a, HL> + Sha256,
+        const HL: usize,
+        const SL: usize,
+    > AppCheckerSignature<'a, S, H, HL, SL>
+{
+    pub fn new(
+        hasher: &'a H,
+        verifier: &'a S,
+        data_buffer: &'static mut [u8],
+        hash_buffer: &'static mut [u8; HL],
+        signature_buffer: &'static mut [u8; SL],
+        credentials_type: TbfFooterV2CredentialsType,
+        require_credentials: bool,
+    ) -> AppCheckerSignature<'a, S, H, HL, SL> {
+        AppCheckerSignature {
+            hasher,
+            verifier,
+            client: OptionalCell::empty(),
+            credentials_type,
+            require_credentials,
+            data_buffer: TakeCell::new(data_buffer),
+            hash: TakeCell::new(hash_buffer),
+            signature: TakeCell::new(signature_buffer),
+            credentials: OptionalCell::empty(),
+            binary: OptionalCell::empty(),
+            position: Cell::new(0),
+        }
+    }
+
+    /// Copy the next chunk of the integrity region into the data buffer and
+    /// give it to the digest engine.
+    fn add_next_chunk(&self, binary: &'a [u8]) -> Result<(), ErrorCode> {
+        let buffer = self.data_buffer.take().ok_or(ErrorCode::BUSY)?;
+        let position = self.position.get();
+        let len = core::cmp::min(buffer.len(), binary.len() - position);
+        buffer[..len].copy_from_slice(&binary[position..position + len]);
+
+        let mut lease = LeasableBuffer::new(buffer);
+        lease.slice(0..len);
+        match self.hasher.add_data(lease) {
+            Ok(_) => {
+                self.position.set(position + len);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.data_buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+
+    /// Report the result of the current check and become idle.
+    fn check_finished(&self, result: Result<CheckResult, ErrorCode>) {
+        self.binary.take().map(|binary| {
+            self.credentials.take().map(|credentials| {
+                self.client.map(|client| {
+                    client.check_done(result, credentials, binary);
+                });
+            });
+        });
+    }
+}
+
+impl<
+        'a,
+        S: SignatureVerify<'a, HL, SL>,
+        H: DigestDataHash<'a, HL> + Sha256,
+        const HL: usize,
+        const SL: usize,
+    > AppCredentialsChecker<'a> for AppCheckerSignature<'a, S, H, HL, SL>
+{
+    fn set_client(&self, client: &'a dyn Client<'a>) {
+        self.client.replace(client);
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])> {
+        if credentials.format() != self.credentials_type {
+            return Err((ErrorCode::NOSUPPORT, credentials, binary));
+        }
+        // For formats that carry a public key the signature is at the end of
+        // the credentials.
+        let data = credentials.data();
+        let signature = match data.len().checked_sub(SL) {
+            Some(start) => &data[start..],
+            None => return Err((ErrorCode::SIZE, credentials, binary)),
+        };
+        if self.binary.is_some()
+            || self.hash.is_none()
+            || self.signature.is_none()
+            || self.data_buffer.is_none()
+        {
+            return Err((ErrorCode::BUSY, credentials, binary));
+        }
+
+        self.hasher.clear_data();
+        if let Err(e) = self.hasher.set_mode_sha256() {
+            return Err((e, credentials, binary));
+        }
+        self.signature.map(|buf| {
+            buf.copy_from_slice(signature);
+        });
+        self.position.set(0);
+
+        match self.add_next_chunk(binary) {
+            Ok(()) => {
+                self.credentials.set(credentials);
+                self.binary.set(binary);
+                Ok(())
+            }
+            Err(e) => Err((e, credentials, binary)),
+        }
+    }
+}
+
+impl<
+        'a,
+        S: SignatureVerify<'a, HL, SL>,
+        H: DigestDataHash<'a, HL> + Sha256,
+        const HL: usize,
+        const SL: usize,
+    > digest::ClientData<'a, HL> for AppCheckerSignature<'a, S, H, HL, SL>
+{
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+        if let Err(e) = result {
+            self.check_finished(Err(e));
+            return;
+        }
+
+        let binary = match self.binary.extract() {
+            Some(binary) => binary,
+            None => return,
+        };
+        let next = if self.position.get() < binary.len() {
+            self.add_next_chunk(binary)
+        } else {
+            // The whole integrity region has been added; compute the hash so
+            // the signature can be checked against it.
+            self.hash
+                .take()
+                .map_or(Err(ErrorCode::FAIL), |hash| match self.hasher.run(hash) {
+                    Ok(()) => Ok(()),
+                    Err((e, hash)) => {
+                        self.hash.replace(hash);
+                        Err(e)
+                    }
+                })
+        };
+        if let Err(e) = next {
+            self.check_finished(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        S: SignatureVerify<'a, HL, SL>,
+        H: DigestDataHash<'a, HL> + Sha256,
+        const HL: usize,
+        const SL: usize,
+    > digest::ClientHash<'a, HL> for AppCheckerSignature<'a, S, H, HL, SL>
+{
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; HL]) {
+        if let Err(e) = result {
+            self.hash.replace(digest);
+            self.check_finished(Err(e));
+            return;
+        }
+
+        let started = self
+            .signature
+            .take()
+            .map_or(Err(ErrorCode::FAIL), |signature| {
+                match self.verifier.verify(digest, signature) {
+                    Ok(()) => Ok(()),
+                    Err((e, digest, signature)) => {
+                        self.hash.replace(digest);
+                        self.signature.replace(signature);
+                        Err(e)
+                    }
+                }
+            });
+        if let Err(e) = started {
+            self.check_finished(Err(e));
+        }
+    }
+}
+
+impl<
+        'a,
+        S: SignatureVerify<'a, HL, SL>,
+        H: DigestDataHash<'a, HL> + Sha256,
+        const HL: usize,
+        const SL: usize,
+    > ClientVerify<HL, SL> for AppCheckerSignature<'a, S, H, HL, SL>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HL],
+        signature: &'static mut [u8; SL],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.check_finished(result.map(|valid| {
+            if valid {
+                CheckResult::Accept
+            } else {
+                CheckResult::Pass
+            }
+        }));
+    }
+}
@@ -5,16 +5,32 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'terminate n' terminates the process with name n
+//!  - 'boot n' starts the terminated or faulted process with name n again
+//!  - 'restart n' terminates the process with name n and starts it again
 //!  - 'panic' causes the kernel to run the panic handler
 //!  - 'process n' prints the memory map of process with name n
+//!  - 'memmap n [region]' prints the addresses of the memory regions of
+//!    process with name n, or only of `region`, and how much of its stack the
+//!    process has used if the kernel tracks it
+//!  - 'grants n' lists the grants the process with name n has allocated, with
+//!    the number of grant bytes used for each driver
+//!  - 'hexdump n [address] [length]' prints `length` bytes (default 64) of the
+//!    memory of process with name n, starting at `address` (default: the start
+//!    of its RAM). Only memory the process can access is printed.
 //!  - 'kernel' prints the kernel memory map
+//!  - 'crash' prints the record of the last kernel panic, if the board keeps
+//!    crash records, and 'crash clear' clears it
+//!
+//! The up and down arrow keys step through previously entered commands, and
+//! tab completes command names and process names.
 //!
 //! ### `list` Command Fields:
 //!
@@ -28,7 +44,10 @@
 //!   because the queue was full.
 //! - `Restarts`: How many times this process has crashed and been restarted by
 //!   the kernel.
-//! - `State`: The state the process is in.
+//! - `State`: The state the process is in. Processes that are loaded but
+//!   whose credentials have not been checked yet are `CredentialsUnchecked`,
+//!   and processes whose credentials were rejected are `CredentialsFailed`.
+//!   Neither will run.
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
 //!
@@ -97,6 +116,9 @@
 //! Total processes: 2
 //! Active processes: 2
 //! Timeslice expirations: 0
+//! Deadline misses: 0
+//! CPU time: 1042 ms
+//! Credentials unchecked: 0, failed: 0
 //! ```
 //!
 //! and you can control processes with the `start` and `stop` commands:
@@ -105,6 +127,17 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! To look at the memory of a process, use `memmap` and `hexdump`:
+//!
+//! ```text
+//! memmap blink heap
+//!  Region     Start       End            Size
+//!  heap       0x20006C00  0x20007000     1024
+//! hexdump blink 0x20006C00 32
+//!  0x20006C00: 00 00 00 00 28 6C 00 20 00 00 00 00 00 00 00 00  |....(l. ........|
+//!  0x20006C10: 01 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |................|
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
@@ -113,14 +146,14 @@ use core::fmt::write;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
 use kernel::hil::time::ConvertTicks;
-use kernel::utilities::cells::TakeCell;
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::ProcessId;
 
 use kernel::debug;
 use kernel::hil::time::{Alarm, AlarmClient};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
-use kernel::process::{ProcessPrinter, ProcessPrinterContext};
+use kernel::process::{Process, ProcessPrinter, ProcessPrinterContext, State};
 use kernel::utilities::binary_write::BinaryWrite;
 use kernel::ErrorCode;
 use kernel::Kernel;
@@ -133,9 +166,43 @@ pub static mut QUEUE_BUF: [u8; 300] = [0; 300];
 /// Since reads are byte-by-byte, to properly echo what's typed,
 /// we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-/// Commands can be up to 32 bytes long: since commands themselves are 4-5
-/// characters, limiting arguments to 25 bytes or so seems fine for now.
-pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+/// Commands can be up to 64 bytes long, enough for a command, a process name
+/// and two numeric arguments.
+pub static mut COMMAND_BUF: [u8; COMMAND_BUF_LEN] = [0; COMMAND_BUF_LEN];
+/// Length of `COMMAND_BUF`, and of the commands kept in the command history.
+pub const COMMAND_BUF_LEN: usize = 64;
+
+/// How many previous commands the console remembers.
+const COMMAND_HISTORY_LEN: usize = 8;
+
+/// The commands the console understands, used for tab completion.
+const COMMANDS: [&str; 15] = [
+    "help",
+    "status",
+    "list",
+    "stop",
+    "start",
+    "fault",
+    "terminate",
+    "boot",
+    "restart",
+    "process",
+    "memmap",
+    "grants",
+    "hexdump",
+    "kernel",
+    "crash",
+];
+
+/// The list of commands printed by `help`.
+const VALID_COMMANDS_STR: &[u8] =
+    b"help status list stop start fault terminate boot restart process memmap grants hexdump kernel crash\n";
+
+/// Number of bytes `hexdump` prints per line.
+const HEXDUMP_LINE_LEN: usize = 16;
+
+/// Number of bytes of the crash log `crash` prints at a time.
+const CRASH_LOG_CHUNK_LEN: usize = 128;
 
 /// States used for state machine to allow printing large strings asynchronously
 /// across multiple calls. This reduces the size of the buffer needed to print
@@ -157,6 +224,75 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    Grants {
+        process_id: ProcessId,
+        index: isize,
+        total: isize,
+    },
+    HexDump {
+        process_id: ProcessId,
+        address: usize,
+        length: usize,
+        line: isize,
+    },
+    CrashLog {
+        chunk: isize,
+    },
+}
+
+/// Progress through an escape sequence sent by the terminal, such as the
+/// ones for the arrow keys.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum EscapeState {
+    None,
+    /// Received the escape byte.
+    Escape,
+    /// Received the escape byte followed by `[`.
+    Bracket,
+}
+
+/// Previously entered commands.
+struct CommandHistory {
+    commands: [[u8; COMMAND_BUF_LEN]; COMMAND_HISTORY_LEN],
+    /// Length of each command in `commands`.
+    lengths: [usize; COMMAND_HISTORY_LEN],
+    /// Index in `commands` where the next command is stored.
+    next: usize,
+    /// How many commands are stored.
+    len: usize,
+}
+
+impl CommandHistory {
+    fn new() -> CommandHistory {
+        CommandHistory {
+            commands: [[0; COMMAND_BUF_LEN]; COMMAND_HISTORY_LEN],
+            lengths: [0; COMMAND_HISTORY_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Save `command`, unless it repeats the most recent command.
+    fn push(&mut self, command: &[u8]) {
+        if self.get(0) == Some(command) {
+            return;
+        }
+        let len = cmp::min(command.len(), COMMAND_BUF_LEN);
+        self.commands[self.next][..len].copy_from_slice(&command[..len]);
+        self.lengths[self.next] = len;
+        self.next = (self.next + 1) % COMMAND_HISTORY_LEN;
+        self.len = cmp::min(self.len + 1, COMMAND_HISTORY_LEN);
+    }
+
+    /// Returns the command entered `back` commands before the most recent
+    /// one, so `get(0)` is the most recent command.
+    fn get(&self, back: usize) -> Option<&[u8]> {
+        if back >= self.len {
+            return None;
+        }
+        let index = (self.next + COMMAND_HISTORY_LEN - 1 - back) % COMMAND_HISTORY_LEN;
+        Some(&self.commands[index][..self.lengths[index]])
+    }
 }
 
 impl Default for WriterState {
@@ -208,6 +344,14 @@ pub struct ProcessConsole<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
     /// received after finishing echoing the last newline character.
     execute: Cell<bool>,
 
+    /// Previously entered commands, and how far back the user has gone with
+    /// the up arrow key (if at all).
+    history: MapCell<CommandHistory>,
+    history_position: OptionalCell<usize>,
+
+    /// Whether we are in the middle of an escape sequence.
+    escape_state: Cell<EscapeState>,
+
     /// Reference to the kernel object so we can access process state.
     kernel: &'static Kernel,
 
@@ -285,6 +429,9 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
 
             running: Cell::new(false),
             execute: Cell::new(false),
+            history: MapCell::new(CommandHistory::new()),
+            history_position: OptionalCell::empty(),
+            escape_state: Cell::new(EscapeState::None),
             kernel: kernel,
             kernel_addresses: kernel_addresses,
             capability: capability,
@@ -327,8 +474,8 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
 
         let _ = self.write_bytes(b"Welcome to the process console.\n");
-        let _ = self
-            .write_bytes(b"Valid commands are: help status list stop start fault process kernel\n");
+        let _ = self.write_bytes(b"Valid commands are: ");
+        let _ = self.write_bytes(VALID_COMMANDS_STR);
         self.prompt();
     }
 
@@ -361,6 +508,48 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                     }
                 }
             }
+            WriterState::Grants {
+                process_id,
+                index,
+                total,
+            } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Grants {
+                        process_id,
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::HexDump {
+                process_id,
+                address,
+                length,
+                line,
+            } => {
+                let lines = (length + HEXDUMP_LINE_LEN - 1) / HEXDUMP_LINE_LEN;
+                if (line + 1) as usize == lines {
+                    WriterState::Empty
+                } else {
+                    WriterState::HexDump {
+                        process_id,
+                        address,
+                        length,
+                        line: line + 1,
+                    }
+                }
+            }
+            WriterState::CrashLog { chunk } => {
+                let length = kernel::crash_record::crash_record().map_or(0, |r| r.log().len());
+                let chunks = (length + CRASH_LOG_CHUNK_LEN - 1) / CRASH_LOG_CHUNK_LEN;
+                if (chunk + 1) as usize >= chunks {
+                    WriterState::Empty
+                } else {
+                    WriterState::CrashLog { chunk: chunk + 1 }
+                }
+            }
             WriterState::Empty => WriterState::Empty,
         }
     }
@@ -418,9 +607,28 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                     format_args!(
                         "\
                     \r\n  {:#010X} ┼─────────────────────────────── A\
-                    \r\n             │ ▼ Stack      {:6}            M\
+                    \r\n             │ ▼ Stack      {:6}            M",
+                        stack_end, stack_size
+                    ),
+                );
+                // Boards that tell the kernel where its stack is also get
+                // how much of it has been used.
+                if let Some((used, _)) = kernel::debug::kernel_stack_usage() {
+                    let _ = write(
+                        &mut console_writer,
+                        format_args!(
+                            "\
+                        \r\n             │   Used       {:6}",
+                            used
+                        ),
+                    );
+                }
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        "\
                     \r\n  {:#010X} ┼───────────────────────────────",
-                        stack_end, stack_size, stack_start
+                        stack_start
                     ),
                 );
                 let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
@@ -528,6 +736,107 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                         }
                     });
             }
+            WriterState::Grants {
+                process_id,
+                index,
+                total,
+            } => {
+                // Only allocated grants are listed, so skip ahead to the next
+                // one.
+                let mut next = None;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        if process_id == process.processid() {
+                            next = (index..total).find_map(|grant_num| {
+                                process
+                                    .get_grant_allocation(grant_num as usize)
+                                    .map(|allocation| (grant_num, allocation))
+                            });
+                        }
+                    });
+
+                match next {
+                    Some((grant_num, (driver_num, address, size))) => {
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:5}  {:#07x}     {:#010X}  {:5}\n",
+                                grant_num, driver_num, address, size
+                            ),
+                        );
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                        self.writer_state.replace(WriterState::Grants {
+                            process_id,
+                            index: grant_num,
+                            total,
+                        });
+                    }
+                    None => {
+                        self.writer_state.replace(WriterState::Empty);
+                        self.prompt();
+                    }
+                }
+            }
+            WriterState::HexDump {
+                process_id,
+                address,
+                length,
+                line,
+            } => {
+                let offset = line as usize * HEXDUMP_LINE_LEN;
+                let line_address = address + offset;
+                let mut bytes = [0; HEXDUMP_LINE_LEN];
+                let wanted = cmp::min(HEXDUMP_LINE_LEN, length - offset);
+                let mut read = Err(ErrorCode::FAIL);
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        if process_id == process.processid() {
+                            read = process.debug_read_memory(line_address, &mut bytes[..wanted]);
+                        }
+                    });
+
+                match read {
+                    Ok(len) => {
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(" {:#010X}:", line_address),
+                        );
+                        for byte in bytes[..len].iter() {
+                            let _ = write(&mut console_writer, format_args!(" {:02X}", byte));
+                        }
+                        for _ in len..HEXDUMP_LINE_LEN {
+                            let _ = write(&mut console_writer, format_args!("   "));
+                        }
+                        let _ = write(&mut console_writer, format_args!("  |"));
+                        for byte in bytes[..len].iter() {
+                            let c = if byte.is_ascii_graphic() || *byte == b' ' {
+                                *byte as char
+                            } else {
+                                '.'
+                            };
+                            let _ = write(&mut console_writer, format_args!("{}", c));
+                        }
+                        let _ = write(&mut console_writer, format_args!("|\n"));
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                    Err(_) => {
+                        // We reached the end of the memory the process can
+                        // access, or the process is gone.
+                        self.writer_state.replace(WriterState::Empty);
+                        self.prompt();
+                    }
+                }
+            }
+            WriterState::CrashLog { chunk } => {
+                kernel::crash_record::crash_record().map(|record| {
+                    let log = record.log();
+                    let start = chunk as usize * CRASH_LOG_CHUNK_LEN;
+                    let end = cmp::min(start + CRASH_LOG_CHUNK_LEN, log.len());
+                    let _ = self.write_bytes(&log[start..end]);
+                });
+            }
             WriterState::Empty => {
                 self.prompt();
             }
@@ -535,6 +844,93 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         }
     }
 
+    /// Print the start and end addresses of the memory regions of `process`,
+    /// or only of the region named `region`.
+    fn print_memory_map(&self, process: &dyn Process, region: Option<&str>) {
+        let addresses = process.get_addresses();
+        let regions = [
+            ("flash", Some((addresses.flash_start, addresses.flash_end))),
+            (
+                "protected",
+                Some((addresses.flash_start, addresses.flash_non_protected_start)),
+            ),
+            (
+                "appflash",
+                Some((addresses.flash_non_protected_start, addresses.flash_end)),
+            ),
+            ("ram", Some((addresses.sram_start, addresses.sram_end))),
+            (
+                "stack",
+                addresses
+                    .sram_stack_top
+                    .map(|stack_top| (addresses.sram_start, stack_top)),
+            ),
+            (
+                "data",
+                addresses.sram_stack_top.zip(addresses.sram_heap_start),
+            ),
+            (
+                "heap",
+                addresses
+                    .sram_heap_start
+                    .map(|heap_start| (heap_start, addresses.sram_app_brk)),
+            ),
+            (
+                "unused",
+                Some((addresses.sram_app_brk, addresses.sram_grant_start)),
+            ),
+            (
+                "grant",
+                Some((addresses.sram_grant_start, addresses.sram_end)),
+            ),
+        ];
+
+        if let Some(region) = region {
+            if !regions.iter().any(|(name, _)| *name == region) {
+                let _ = self.write_bytes(
+                    b"Regions are: flash protected appflash ram stack data heap unused grant\n",
+                );
+                return;
+            }
+        }
+
+        let mut console_writer = ConsoleWriter::new();
+        let _ = write(
+            &mut console_writer,
+            format_args!(" Region     Start       End            Size\n"),
+        );
+        for (name, bounds) in regions
+            .iter()
+            .filter(|(name, _)| region.map_or(true, |region| *name == region))
+        {
+            let _ = match bounds {
+                Some((start, end)) => write(
+                    &mut console_writer,
+                    format_args!(
+                        " {:<10} {:#010X}  {:#010X}  {:7}\n",
+                        name,
+                        start,
+                        end,
+                        end.saturating_sub(*start)
+                    ),
+                ),
+                None => write(
+                    &mut console_writer,
+                    format_args!(" {:<10} ?           ?                 ?\n", name),
+                ),
+            };
+        }
+        if region.map_or(true, |region| region == "stack") {
+            if let Some(used) = process.get_sizes().stack_high_water_mark {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(" Stack high-water mark: {} bytes\n", used),
+                );
+            }
+        }
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+    }
+
     // Process the command in the command buffer and clear the buffer.
     fn read_command(&self) {
         self.command_buffer.map(|command| {
@@ -556,12 +952,15 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                 match cmd_str {
                     Ok(s) => {
                         let clean_str = s.trim();
+                        if !clean_str.is_empty() {
+                            self.history
+                                .map(|history| history.push(clean_str.as_bytes()));
+                        }
 
                         if clean_str.starts_with("help") {
                             let _ = self.write_bytes(b"Welcome to the process console.\n");
                             let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self
-                                .write_bytes(b"help status list stop start fault process kernel\n");
+                            let _ = self.write_bytes(VALID_COMMANDS_STR);
                         } else if clean_str.starts_with("start") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -622,6 +1021,191 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                         }
                                     });
                             });
+                        } else if clean_str.starts_with("terminate") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        let proc_name = proc.get_process_name();
+                                        if proc_name == name {
+                                            proc.terminate(None);
+                                            let mut console_writer = ConsoleWriter::new();
+                                            let _ = write(
+                                                &mut console_writer,
+                                                format_args!("Process {} terminated\n", proc_name),
+                                            );
+
+                                            let _ = self.write_bytes(
+                                                &(console_writer.buf)[..console_writer.size],
+                                            );
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("boot") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        let proc_name = proc.get_process_name();
+                                        if proc_name == name {
+                                            let mut console_writer = ConsoleWriter::new();
+                                            if matches!(
+                                                proc.get_state(),
+                                                State::Terminated | State::Faulted
+                                            ) {
+                                                proc.try_restart(None);
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!("Process {} booted\n", proc_name),
+                                                );
+                                            } else {
+                                                let _ = write(
+                                                    &mut console_writer,
+                                                    format_args!(
+                                                        "Process {} is not terminated or faulted\n",
+                                                        proc_name
+                                                    ),
+                                                );
+                                            }
+
+                                            let _ = self.write_bytes(
+                                                &(console_writer.buf)[..console_writer.size],
+                                            );
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("restart") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        let proc_name = proc.get_process_name();
+                                        if proc_name == name {
+                                            proc.try_restart(None);
+                                            // A restarted process waits to be
+                                            // started again. Processes that
+                                            // were not approved to run are not
+                                            // restarted, and a failed restart
+                                            // leaves the process terminated.
+                                            let result = if proc.get_state() == State::Unstarted {
+                                                "restarted"
+                                            } else {
+                                                "could not be restarted"
+                                            };
+                                            let mut console_writer = ConsoleWriter::new();
+                                            let _ = write(
+                                                &mut console_writer,
+                                                format_args!("Process {} {}\n", proc_name, result),
+                                            );
+
+                                            let _ = self.write_bytes(
+                                                &(console_writer.buf)[..console_writer.size],
+                                            );
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("memmap") {
+                            let mut arguments = clean_str.split_whitespace().skip(1);
+                            let name = arguments.next();
+                            let region = arguments.next();
+                            name.map(|name| {
+                                let mut found = false;
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        if !found && proc.get_process_name() == name {
+                                            self.print_memory_map(proc, region);
+                                            found = true;
+                                        }
+                                    });
+                            });
+                        } else if clean_str.starts_with("grants") {
+                            let argument = clean_str.split_whitespace().nth(1);
+                            let mut grants = None;
+                            argument.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        if grants.is_none() && proc.get_process_name() == name {
+                                            grants = Some((
+                                                proc.processid(),
+                                                proc.grant_allocated_count().unwrap_or(0),
+                                            ));
+                                        }
+                                    });
+                            });
+                            match grants {
+                                Some((process_id, allocated)) if allocated > 0 => {
+                                    let info: KernelInfo = KernelInfo::new(self.kernel);
+                                    let (_, total) =
+                                        info.number_app_grant_uses(process_id, &self.capability);
+                                    let _ = self
+                                        .write_bytes(b" Grant  Driver      Address      Size\n");
+                                    // Start the state machine to print each
+                                    // allocated grant separately.
+                                    self.write_state(WriterState::Grants {
+                                        process_id,
+                                        index: -1,
+                                        total: total as isize,
+                                    });
+                                }
+                                Some(_) => {
+                                    let _ = self.write_bytes(b"No grants allocated\n");
+                                }
+                                None => {}
+                            }
+                        } else if clean_str.starts_with("hexdump") {
+                            let mut arguments = clean_str.split_whitespace().skip(1);
+                            let name = arguments.next();
+                            let address = arguments.next().map(parse_number);
+                            let length = arguments.next().map(parse_number);
+                            let mut dump = None;
+                            name.map(|name| {
+                                self.kernel
+                                    .process_each_capability(&self.capability, |proc| {
+                                        if dump.is_some() || proc.get_process_name() != name {
+                                            return;
+                                        }
+                                        let address = address
+                                            .unwrap_or(Some(proc.get_addresses().sram_start));
+                                        let length = length.unwrap_or(Some(64));
+                                        dump = match (address, length) {
+                                            (Some(address), Some(length))
+                                                if length > 0
+                                                    && proc
+                                                        .debug_read_memory(address, &mut [0])
+                                                        .is_ok() =>
+                                            {
+                                                // Only dump memory up to the
+                                                // end of the memory the
+                                                // process can access.
+                                                let accessible = proc
+                                                    .get_addresses()
+                                                    .sram_app_brk
+                                                    .saturating_sub(address);
+                                                let length = cmp::min(length, accessible);
+                                                Some(Ok((proc.processid(), address, length)))
+                                            }
+                                            _ => Some(Err(())),
+                                        };
+                                    });
+                            });
+                            match dump {
+                                Some(Ok((process_id, address, length))) => {
+                                    // Start the state machine to print each
+                                    // line separately.
+                                    self.write_state(WriterState::HexDump {
+                                        process_id,
+                                        address,
+                                        length,
+                                        line: -1,
+                                    });
+                                }
+                                Some(Err(())) => {
+                                    let _ = self.write_bytes(
+                                        b"Address is not in process accessible memory\n",
+                                    );
+                                }
+                                None => {}
+                            }
                         } else if clean_str.starts_with("list") {
                             let _ = self.write_bytes(b" PID    Name                Quanta  ");
                             let _ = self.write_bytes(b"Syscalls  Dropped Upcalls  ");
@@ -669,6 +1253,46 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                                 ),
                             );
                             let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            console_writer.clear();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "Deadline misses: {}\n",
+                                    info.deadline_misses(&self.capability)
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            console_writer.clear();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "CPU time: {} ms\n",
+                                    info.cpu_time_us(&self.capability) / 1000
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            console_writer.clear();
+
+                            // Processes that are loaded but not allowed to
+                            // run because of their credentials.
+                            let mut unchecked = 0;
+                            let mut failed = 0;
+                            self.kernel
+                                .process_each_capability(&self.capability, |process| match process
+                                    .get_state()
+                                {
+                                    State::CredentialsUnchecked => unchecked += 1,
+                                    State::CredentialsFailed => failed += 1,
+                                    _ => {}
+                                });
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "Credentials unchecked: {}, failed: {}\n",
+                                    unchecked, failed
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
                         } else if clean_str.starts_with("process") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -724,10 +1348,65 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
                             // Prints kernel memory by moving the writer to the
                             // start state.
                             self.writer_state.replace(WriterState::KernelStart);
+                        } else if clean_str.starts_with("crash") {
+                            let clear = clean_str.split_whitespace().nth(1) == Some("clear");
+                            match kernel::crash_record::crash_record() {
+                                Some(_) if clear => {
+                                    kernel::crash_record::clear_crash_record();
+                                    let _ = self.write_bytes(b"Crash record cleared\n");
+                                }
+                                Some(record) => {
+                                    let mut console_writer = ConsoleWriter::new();
+                                    let _ = write(
+                                        &mut console_writer,
+                                        format_args!("Last kernel panic:\r\n"),
+                                    );
+                                    if let Some(cause) = record.reset_cause() {
+                                        let _ = write(
+                                            &mut console_writer,
+                                            format_args!(" Reset cause: {:#010X}\r\n", cause),
+                                        );
+                                    }
+                                    if let Some(name) = record.process_name() {
+                                        let _ = write(
+                                            &mut console_writer,
+                                            format_args!(
+                                                " Faulting process: {}\r\n",
+                                                str::from_utf8(name).unwrap_or("<invalid name>")
+                                            ),
+                                        );
+                                    }
+                                    if !record.is_complete() {
+                                        let _ = write(
+                                            &mut console_writer,
+                                            format_args!(" The panic handler did not finish\r\n"),
+                                        );
+                                    }
+                                    if record.log_dropped() > 0 {
+                                        let _ = write(
+                                            &mut console_writer,
+                                            format_args!(
+                                                " The last {} bytes of the log were dropped\r\n",
+                                                record.log_dropped()
+                                            ),
+                                        );
+                                    }
+                                    let _ = self
+                                        .write_bytes(&(console_writer.buf)[..console_writer.size]);
+
+                                    // Print the log in parts, as it is larger
+                                    // than the transmit buffer.
+                                    if !record.log().is_empty() {
+                                        self.write_state(WriterState::CrashLog { chunk: -1 });
+                                    }
+                                }
+                                None => {
+                                    let _ = self.write_bytes(b"No crash recorded\n");
+                                }
+                            }
                         } else {
                             let _ = self.write_bytes(b"Valid commands are: ");
-                            let _ = self
-                                .write_bytes(b"help status list stop start fault process kernel\n");
+                            let _ = self.write_bytes(VALID_COMMANDS_STR);
                         }
                     }
                     Err(_e) => {
@@ -745,6 +1424,7 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
             command[0] = 0;
         });
         self.command_index.set(0);
+        self.history_position.clear();
         if self.writer_state.get() == WriterState::Empty {
             self.prompt();
         }
@@ -754,6 +1434,125 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> ProcessConsole<'a, A, C>
         let _ = self.write_bytes(b"tock$ ");
     }
 
+    /// Replace the command being typed with the command `back` entries back
+    /// in the history, or with an empty command if `back` is `None`.
+    fn show_history(&self, command: &mut [u8], back: Option<usize>) {
+        let mut len = 0;
+        if let Some(back) = back {
+            self.history.map(|history| {
+                if let Some(entry) = history.get(back) {
+                    len = cmp::min(entry.len(), command.len() - 1);
+                    command[..len].copy_from_slice(&entry[..len]);
+                }
+            });
+        }
+        command[len] = 0;
+        self.command_index.set(len);
+
+        // Erase the line and print it again with the new command.
+        let _ = self.write_bytes(b"\x1b[2K\r");
+        self.prompt();
+        let _ = self.write_bytes(&command[..len]);
+    }
+
+    /// Go one command back in the history.
+    fn history_up(&self, command: &mut [u8]) {
+        let back = self.history_position.extract().map_or(0, |back| back + 1);
+        if self.history.map_or(0, |history| history.len) > back {
+            self.history_position.set(back);
+            self.show_history(command, Some(back));
+        }
+    }
+
+    /// Go one command forward in the history, back to an empty command after
+    /// the most recent one.
+    fn history_down(&self, command: &mut [u8]) {
+        match self.history_position.extract() {
+            None => {}
+            Some(0) => {
+                self.history_position.clear();
+                self.show_history(command, None);
+            }
+            Some(back) => {
+                self.history_position.set(back - 1);
+                self.show_history(command, Some(back - 1));
+            }
+        }
+    }
+
+    /// Complete the word being typed. The first word is completed from the
+    /// command names and the other words from the process names. The word is
+    /// extended as far as all names that start with it agree.
+    fn complete(&self, command: &mut [u8]) {
+        let index = self.command_index.get();
+        let (completion, unique) = {
+            let typed = match str::from_utf8(&command[..index]) {
+                Ok(typed) => typed,
+                Err(_) => return,
+            };
+            let word_start = typed.rfind(' ').map_or(0, |space| space + 1);
+            let word = &typed[word_start..];
+
+            let mut first_match: Option<&'static str> = None;
+            let mut common_len = 0;
+            let mut matches = 0;
+            let mut consider = |name: &'static str| {
+                if !name.starts_with(word) {
+                    return;
+                }
+                matches += 1;
+                match first_match {
+                    None => {
+                        first_match = Some(name);
+                        common_len = name.len();
+                    }
+                    Some(first) => {
+                        common_len = first
+                            .bytes()
+                            .zip(name.bytes())
+                            .take(common_len)
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                    }
+                }
+            };
+            if word_start == 0 {
+                COMMANDS.iter().for_each(|name| consider(name));
+            } else {
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        consider(process.get_process_name())
+                    });
+            }
+
+            match first_match {
+                Some(first) => {
+                    // Names that differ inside of a multi-byte character are
+                    // only completed up to the start of that character.
+                    while !first.is_char_boundary(common_len) {
+                        common_len -= 1;
+                    }
+                    (&first[word.len()..common_len], matches == 1)
+                }
+                None => return,
+            }
+        };
+
+        // Add the completion, and a space after a complete name.
+        let mut end = index;
+        let space: &[u8] = if unique { b" " } else { b"" };
+        for byte in completion.bytes().chain(space.iter().copied()) {
+            if end >= command.len() - 1 {
+                break;
+            }
+            command[end] = byte;
+            end += 1;
+        }
+        command[end] = 0;
+        self.command_index.set(end);
+        let _ = self.write_bytes(&command[index..end]);
+    }
+
     /// Start or iterate the state machine for an asynchronous write operation
     /// spread across multiple callback cycles.
     fn write_state(&self, state: WriterState) {
@@ -909,7 +1708,25 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::ReceiveClient
                         let previous_byte = self.previous_byte.get();
                         self.previous_byte.set(read_buf[0]);
                         let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
+                        let escape_state = self.escape_state.get();
+                        if escape_state != EscapeState::None {
+                            // Arrow keys are sent as the escape byte, `[`, and
+                            // a letter for the direction.
+                            self.escape_state.set(EscapeState::None);
+                            if escape_state == EscapeState::Escape && read_buf[0] == b'[' {
+                                self.escape_state.set(EscapeState::Bracket);
+                            } else if escape_state == EscapeState::Bracket {
+                                match read_buf[0] {
+                                    b'A' => self.history_up(command),
+                                    b'B' => self.history_down(command),
+                                    _ => {}
+                                }
+                            }
+                        } else if read_buf[0] == 0x1b {
+                            self.escape_state.set(EscapeState::Escape);
+                        } else if read_buf[0] == b'\t' {
+                            self.complete(command);
+                        } else if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
                             if (previous_byte == ('\n' as u8) || previous_byte == ('\r' as u8))
                                 && previous_byte != read_buf[0]
                             {
@@ -950,3 +1767,11 @@ impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> uart::ReceiveClient
         let _ = self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+/// Parse a decimal number, or a hexadecimal number starting with `0x`.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
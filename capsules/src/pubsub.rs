@@ -0,0 +1,269 @@
+//! Topic-based publish/subscribe event bus for userspace applications.
+//!
+//! Processes subscribe to topics, and a message published to a topic is
+//! copied to every process subscribed to it. This decouples producers from
+//! consumers: a sensor app publishes its readings without knowing which apps
+//! (if any) use them, and apps can come and go without the others changing.
+//!
+//! Topics are 32-bit numbers. Numeric topics agreed on by the apps should be
+//! below 0x80000000. Apps can also turn a topic name into a topic number with
+//! command 4; those topic numbers have the top bit set and are a hash of the
+//! name, so names should be chosen to not collide.
+//!
+//! Each subscriber receives messages in its read-write allow buffer, which
+//! holds one message: a new message overwrites the previous one, even if the
+//! subscriber has not handled it yet. A message longer than the buffer is
+//! truncated. Along with the copy, the subscriber gets an upcall with the
+//! topic, the full length of the message and the process identifier of the
+//! publisher.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let pubsub = static_init!(
+//!     capsules::pubsub::PubSub<'static, 4>,
+//!     capsules::pubsub::PubSub::new(
+//!         &mut capsules::pubsub::BUF,
+//!         board_kernel.create_grant(capsules::pubsub::DRIVER_NUM, &grant_cap),
+//!     )
+//! );
+//! ```
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::TakeCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::PubSub as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Message to publish.
+    pub const PUBLISH: usize = 0;
+    /// Topic name to convert to a topic number.
+    pub const TOPIC_NAME: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer that published messages are copied into.
+    pub const RECEIVE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: usize = 1;
+}
+
+/// Bit that is set in topic numbers created from topic names.
+const NAMED_TOPIC: u32 = 0x80000000;
+
+/// Buffer for published messages, which also sets the maximum message length.
+pub static mut BUF: [u8; 64] = [0; 64];
+
+pub struct App<const MAX_TOPICS: usize> {
+    /// Topics the process is subscribed to.
+    topics: [Option<u32>; MAX_TOPICS],
+}
+
+impl<const MAX_TOPICS: usize> Default for App<MAX_TOPICS> {
+    fn default() -> Self {
+        App {
+            topics: [None; MAX_TOPICS],
+        }
+    }
+}
+
+impl<const MAX_TOPICS: usize> App<MAX_TOPICS> {
+    fn is_subscribed(&self, topic: u32) -> bool {
+        self.topics.contains(&Some(topic))
+    }
+}
+
+/// Returns the topic number for the topic name `name`, using the 32-bit
+/// FNV-1a hash.
+fn named_topic(name: impl Iterator<Item = u8>) -> u32 {
+    let hash = name.fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    hash | NAMED_TOPIC
+}
+
+pub struct PubSub<'a, const MAX_TOPICS: usize> {
+    apps: Grant<
+        App<MAX_TOPICS>,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    buffer: TakeCell<'a, [u8]>,
+}
+
+impl<'a, const MAX_TOPICS: usize> PubSub<'a, MAX_TOPICS> {
+    pub fn new(
+        buffer: &'a mut [u8],
+        grant: Grant<
+            App<MAX_TOPICS>,
+            UpcallCount<1>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> PubSub<'a, MAX_TOPICS> {
+        PubSub {
+            apps: grant,
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    fn subscribe(&self, processid: ProcessId, topic: u32) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                if app.is_subscribed(topic) {
+                    return Err(ErrorCode::ALREADY);
+                }
+                app.topics.iter_mut().find(|slot| slot.is_none()).map_or(
+                    Err(ErrorCode::NOMEM),
+                    |slot| {
+                        *slot = Some(topic);
+                        Ok(())
+                    },
+                )
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn unsubscribe(&self, processid: ProcessId, topic: u32) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                app.topics
+                    .iter_mut()
+                    .find(|slot| **slot == Some(topic))
+                    .map_or(Err(ErrorCode::INVAL), |slot| {
+                        *slot = None;
+                        Ok(())
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Copy the message of `publisher` to every subscriber of `topic`.
+    /// Returns the number of subscribers.
+    fn publish(&self, publisher: ProcessId, topic: u32) -> Result<usize, ErrorCode> {
+        self.buffer.take().map_or(Err(ErrorCode::BUSY), |buffer| {
+            let result = self.publish_with(publisher, topic, buffer);
+            self.buffer.replace(buffer);
+            result
+        })
+    }
+
+    fn publish_with(
+        &self,
+        publisher: ProcessId,
+        topic: u32,
+        buffer: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        // Copy the message out of the publisher's grant first, as no grant
+        // may be entered while iterating over all of them.
+        let len = self
+            .apps
+            .enter(publisher, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PUBLISH)
+                    .map_err(ErrorCode::from)
+                    .and_then(|message| {
+                        message
+                            .enter(|data| {
+                                if data.len() > buffer.len() {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                data.copy_to_slice(&mut buffer[..data.len()]);
+                                Ok(data.len())
+                            })
+                            .unwrap_or(Err(ErrorCode::INVAL))
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        let mut subscribers = 0;
+        self.apps.each(|_, app, kernel_data| {
+            if !app.is_subscribed(topic) {
+                return;
+            }
+            subscribers += 1;
+            let _ = kernel_data
+                .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                .and_then(|receive| {
+                    receive.mut_enter(|data| {
+                        let copy_len = len.min(data.len());
+                        data[..copy_len].copy_from_slice(&buffer[..copy_len]);
+                    })
+                });
+            kernel_data
+                .schedule_upcall(0, (topic as usize, len, publisher.id()))
+                .ok();
+        });
+        Ok(subscribers)
+    }
+
+    /// Returns the topic number for the topic name shared by `processid`.
+    fn topic_from_name(&self, processid: ProcessId) -> Result<u32, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::TOPIC_NAME)
+                    .and_then(|name| name.enter(|name| named_topic(name.iter().map(|c| c.get()))))
+                    .map_err(ErrorCode::from)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, const MAX_TOPICS: usize> SyscallDriver for PubSub<'a, MAX_TOPICS> {
+    /// Subscribe to topics and publish messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Subscribe to topic `topic`. Returns `ALREADY` if the process is
+    ///        already subscribed and `NOMEM` if it is subscribed to the
+    ///        maximum number of topics.
+    /// - `2`: Unsubscribe from topic `topic`. Returns `INVAL` if the process
+    ///        is not subscribed to it.
+    /// - `3`: Publish the message shared with read-only allow 0 to topic
+    ///        `topic`. Returns the number of subscribers the message was
+    ///        delivered to, or `SIZE` if the message is too long.
+    /// - `4`: Returns the topic number for the topic name shared with
+    ///        read-only allow 1.
+    fn command(
+        &self,
+        command_num: usize,
+        topic: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let topic = topic as u32;
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.subscribe(processid, topic).into(),
+            2 => self.unsubscribe(processid, topic).into(),
+            3 => match self.publish(processid, topic) {
+                Ok(subscribers) => CommandReturn::success_u32(subscribers as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+            4 => match self.topic_from_name(processid) {
+                Ok(topic) => CommandReturn::success_u32(topic),
+                Err(e) => CommandReturn::failure(e),
+            },
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -55,6 +55,13 @@
 //!             &memory_allocation_capability)));
 //! sdcard.set_client(sdcard_driver);
 //! ```
+//!
+//! The card also implements `hil::block_storage`, so that file systems such as
+//! `capsules::fat_fs` can be used on it. The card must be initialized with
+//! `initialize()` before blocks can be read or written, either by the board or
+//! by a process through the syscall driver. Each transfer completes to the
+//! client that started it, so the syscall driver and a file system can share
+//! the card, one transfer at a time.
 
 // Resources for SD Card API:
 //  * elm-chan.org/docs/mmc/mmc_e.html
@@ -120,6 +127,13 @@ pub struct SDCard<'a, A: hil::time::Alarm<'a>> {
     client: OptionalCell<&'static dyn SDCardClient>,
     client_buffer: TakeCell<'static, [u8]>,
     client_offset: Cell<usize>,
+    client_write: Cell<bool>,
+
+    block_client: OptionalCell<&'a dyn hil::block_storage::BlockStorageClient>,
+    /// Whether the transfer in progress was started through the block storage
+    /// interface, so it completes to the block storage client
+    block_transfer: Cell<bool>,
+    total_size: Cell<u64>,
 }
 
 /// SD card command codes
@@ -281,6 +295,68 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             client: OptionalCell::empty(),
             client_buffer: TakeCell::empty(),
             client_offset: Cell::new(0),
+            client_write: Cell::new(false),
+            block_client: OptionalCell::empty(),
+            block_transfer: Cell::new(false),
+            total_size: Cell::new(0),
+        }
+    }
+
+    /// Pass the blocks that were read to the client that started the transfer.
+    fn read_done(&self, buffer: &'static mut [u8], len: usize) {
+        match self.block_transfer_client() {
+            Some(client) => client.read_complete(buffer, Ok(())),
+            None => {
+                self.client.map(move |client| {
+                    client.read_done(buffer, len);
+                });
+            }
+        }
+    }
+
+    /// Return the written blocks to the client that started the transfer.
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        match self.block_transfer_client() {
+            Some(client) => client.write_complete(buffer, Ok(())),
+            None => {
+                self.client.map(move |client| {
+                    client.write_done(buffer);
+                });
+            }
+        }
+    }
+
+    /// Returns the block storage client if the transfer in progress was
+    /// started through the block storage interface, and marks the transfer as
+    /// complete.
+    fn block_transfer_client(&self) -> Option<&'a dyn hil::block_storage::BlockStorageClient> {
+        if self.block_transfer.replace(false) {
+            self.block_client.extract()
+        } else {
+            None
+        }
+    }
+
+    /// Report a failed transfer to the client that started it. A block storage
+    /// request always completes with an error and its buffer, which is held
+    /// in `client_buffer` until the transfer completes. The SD card client
+    /// gets the error code.
+    fn transfer_failed(&self, error: SdCardError) {
+        match self.block_transfer_client() {
+            Some(client) => {
+                self.client_buffer.take().map(|buffer| {
+                    if self.client_write.get() {
+                        client.write_complete(buffer, Err(ErrorCode::FAIL));
+                    } else {
+                        client.read_complete(buffer, Err(ErrorCode::FAIL));
+                    }
+                });
+            }
+            None => {
+                self.client.map(move |client| {
+                    client.error(error as u32);
+                });
+            }
         }
     }
 
@@ -773,6 +849,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // initialization complete
                     self.state.set(SpiState::Idle);
                     self.is_initialized.set(true);
+                    self.total_size.set(total_size);
 
                     // perform callback
                     self.client.map(move |client| {
@@ -812,9 +889,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::ReadFailure);
                 }
             }
 
@@ -842,9 +917,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::ReadFailure);
                 }
             }
 
@@ -868,9 +941,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                         // callback
                         let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                        self.read_done(buffer, read_len);
                     });
                 });
             }
@@ -900,9 +971,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::ReadFailure);
                 }
             }
 
@@ -951,9 +1020,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
 
                     // read finished, perform callback
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.read_done(buffer, self.client_offset.get());
-                        });
+                        self.read_done(buffer, self.client_offset.get());
                     });
                 } else {
                     // error, send callback and quit
@@ -962,9 +1029,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::ReadFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::ReadFailure);
                 }
             }
 
@@ -1015,9 +1080,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                         self.state.set(SpiState::Idle);
                         self.alarm_state.set(AlarmState::Idle);
                         self.alarm_count.set(0);
-                        self.client.map(move |client| {
-                            client.error(SdCardError::WriteFailure as u32);
-                        });
+                        self.transfer_failed(SdCardError::WriteFailure);
                     }
                 } else {
                     // error, send callback and quit
@@ -1026,9 +1089,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::WriteFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::WriteFailure);
                 }
             }
 
@@ -1050,9 +1111,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(SdCardError::WriteFailure as u32);
-                    });
+                    self.transfer_failed(SdCardError::WriteFailure);
                 }
             }
 
@@ -1067,9 +1126,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_count.set(0);
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.write_done(buffer);
-                        });
+                        self.write_done(buffer);
                     });
                 } else {
                     // replace buffers
@@ -1102,9 +1159,7 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
             self.alarm_count.set(0);
-            self.client.map(move |client| {
-                client.error(SdCardError::TimeoutFailure as u32);
-            });
+            self.transfer_failed(SdCardError::TimeoutFailure);
         } else {
             self.alarm_count.set(repeats + 1);
         }
@@ -1276,6 +1331,23 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         }
     }
 
+    /// Check that a single block can be read or written now.
+    fn check_block_access(&self, block: u32, buffer: &[u8]) -> Result<(), ErrorCode> {
+        if !self.is_installed() {
+            Err(ErrorCode::UNINSTALLED)
+        } else if !self.is_initialized() {
+            Err(ErrorCode::RESERVE)
+        } else if self.txbuffer.is_none() || self.rxbuffer.is_none() {
+            Err(ErrorCode::BUSY)
+        } else if buffer.len() < 512 {
+            Err(ErrorCode::SIZE)
+        } else if block >= (self.total_size.get() / 512) as u32 {
+            Err(ErrorCode::INVAL)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn read_blocks(
         &self,
         buffer: &'static mut [u8],
@@ -1294,6 +1366,8 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                                 // save the user buffer for later
                                 self.client_buffer.replace(buffer);
                                 self.client_offset.set(0);
+                                self.client_write.set(false);
+                                self.block_transfer.set(false);
 
                                 // convert block address to byte address for non-block
                                 //  access cards
@@ -1353,6 +1427,8 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
                                 // save the user buffer for later
                                 self.client_buffer.replace(buffer);
                                 self.client_offset.set(0);
+                                self.client_write.set(true);
+                                self.block_transfer.set(false);
 
                                 // convert block address to byte address for non-block
                                 //  access cards
@@ -1390,6 +1466,53 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
     }
 }
 
+/// Single block access for file systems
+impl<'a, A: hil::time::Alarm<'a>> hil::block_storage::BlockStorage<'a> for SDCard<'a, A> {
+    fn set_client(&self, client: &'a dyn hil::block_storage::BlockStorageClient) {
+        self.block_client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn block_count(&self) -> u32 {
+        if self.is_initialized() {
+            (self.total_size.get() / 512) as u32
+        } else {
+            0
+        }
+    }
+
+    fn read_block(
+        &self,
+        block: u32,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check_block_access(block, buffer) {
+            return Err((e, buffer));
+        }
+        // The checks above cover every error of `read_blocks()`
+        let _ = self.read_blocks(buffer, block, 1);
+        self.block_transfer.set(true);
+        Ok(())
+    }
+
+    fn write_block(
+        &self,
+        block: u32,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check_block_access(block, buffer) {
+            return Err((e, buffer));
+        }
+        // The checks above cover every error of `write_blocks()`
+        let _ = self.write_blocks(buffer, block, 1);
+        self.block_transfer.set(true);
+        Ok(())
+    }
+}
+
 /// Handle callbacks from the SPI peripheral
 impl<'a, A: hil::time::Alarm<'a>> hil::spi::SpiMasterClient for SDCard<'a, A> {
     fn read_write_done(
@@ -40,6 +40,7 @@
 //! Key: [18, 52, 86, 120, 154, 188, 222, 240] with value [16, 32, 48] was added
 //! Now retriving the key
 //! Key: [18, 52, 86, 120, 154, 188, 222, 240] with value [16, 32, 48, 0] was retrived
+//! Key: [18, 52, 86, 120, 154, 188, 222, 240] was updated
 //! Removed Key: [18, 52, 86, 120, 154, 188, 222, 240]
 //! Try to read removed key: [18, 52, 86, 120, 154, 188, 222, 240]
 //! Unable to find key: [18, 52, 86, 120, 154, 188, 222, 240]
@@ -53,6 +54,7 @@ use core::marker::PhantomData;
 use kernel::debug;
 use kernel::hil::kv_system::{self, KVSystem, KeyType};
 use kernel::utilities::cells::TakeCell;
+use kernel::utilities::leasable_buffer::LeasableBuffer;
 use kernel::ErrorCode;
 
 #[derive(Clone, Copy, PartialEq)]
@@ -101,7 +103,7 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
                 debug!("Generated key: {:?}", key_buf);
                 debug!("Now appending the key");
                 self.kv_system
-                    .append_key(key_buf, self.value.take().unwrap())
+                    .append_key(key_buf, LeasableBuffer::new(self.value.take().unwrap()))
                     .unwrap();
             }
             Err(e) => {
@@ -114,11 +116,12 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         &self,
         result: Result<(), ErrorCode>,
         key: &'static mut T,
-        value: &'static [u8],
+        value: &'static mut [u8],
     ) {
         match result {
             Ok(()) => {
                 debug!("Key: {:?} with value {:?} was added", key, value);
+                self.value.replace(value);
                 debug!("Now retriving the key");
                 self.kv_system
                     .get_value(key, self.ret_buffer.take().unwrap())
@@ -140,7 +143,9 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
             Ok(()) => {
                 debug!("Key: {:?} with value {:?} was retrived", key, ret_buf);
                 self.ret_buffer.replace(ret_buf);
-                self.kv_system.invalidate_key(key).unwrap();
+                self.kv_system
+                    .update_key(key, LeasableBuffer::new(self.value.take().unwrap()))
+                    .unwrap();
             }
             Err(e) => {
                 if self.state.get() == CurrentState::ExpectGetValueFail {
@@ -157,6 +162,24 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         }
     }
 
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        match result {
+            Ok(()) => {
+                debug!("Key: {:?} was updated", key);
+                self.value.replace(value);
+                self.kv_system.invalidate_key(key).unwrap();
+            }
+            Err(e) => {
+                panic!("Error updating key: {:?}", e);
+            }
+        }
+    }
+
     fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
         match result {
             Ok(()) => {
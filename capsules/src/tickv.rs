@@ -45,6 +45,7 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    UpdateKey,
     InvalidateKey,
     GarbageCollect,
 }
@@ -117,13 +118,28 @@ impl<'a, F: Flash> tickv::flash_controller::FlashController<64> for TickFSFlastC
 
 pub type TicKVKeyType = [u8; 8];
 
+/// Convert an error from TicKV to the `ErrorCode` described by
+/// `hil::kv_system`.
+fn convert_error(error: tickv::error_codes::ErrorCode) -> ErrorCode {
+    match error {
+        tickv::error_codes::ErrorCode::KeyNotFound
+        | tickv::error_codes::ErrorCode::KeyAlreadyExists => ErrorCode::NOSUPPORT,
+        tickv::error_codes::ErrorCode::RegionFull
+        | tickv::error_codes::ErrorCode::FlashFull
+        | tickv::error_codes::ErrorCode::ObjectTooLarge => ErrorCode::NOMEM,
+        tickv::error_codes::ErrorCode::BufferTooSmall(_) => ErrorCode::SIZE,
+        _ => ErrorCode::FAIL,
+    }
+}
+
 pub struct TicKVStore<'a, F: Flash + 'static, H: Hasher<'a, 8>> {
     tickv: AsyncTicKV<'a, TickFSFlastCtrl<'a, F>, 64>,
     hasher: &'a H,
     operation: Cell<Operation>,
     next_operation: Cell<Operation>,
 
-    value_buffer: Cell<Option<&'static [u8]>>,
+    value_buffer: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
     key_buffer: TakeCell<'static, [u8; 8]>,
     ret_buffer: TakeCell<'static, [u8]>,
     unhashed_key_buf: TakeCell<'static, [u8]>,
@@ -152,7 +168,8 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
             hasher,
             operation: Cell::new(Operation::None),
             next_operation: Cell::new(Operation::None),
-            value_buffer: Cell::new(None),
+            value_buffer: TakeCell::empty(),
+            value_length: Cell::new(0),
             key_buffer: TakeCell::empty(),
             ret_buffer: TakeCell::empty(),
             unhashed_key_buf: TakeCell::empty(),
@@ -166,15 +183,46 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
         self.operation.set(Operation::Init);
     }
 
+    fn complete_write_operation(&self, ret: Result<(), ErrorCode>) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::None);
+
+        match operation {
+            Operation::AppendKey => {
+                self.client.map(|cb| {
+                    cb.append_key_complete(
+                        ret,
+                        self.key_buffer.take().unwrap(),
+                        self.tickv.get_stored_value_buffer().unwrap(),
+                    );
+                });
+            }
+            Operation::UpdateKey => {
+                self.client.map(|cb| {
+                    cb.update_key_complete(
+                        ret,
+                        self.key_buffer.take().unwrap(),
+                        self.tickv.get_stored_value_buffer().unwrap(),
+                    );
+                });
+            }
+            Operation::InvalidateKey => {
+                self.client.map(|cb| {
+                    cb.invalidate_key_complete(ret, self.key_buffer.take().unwrap());
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+
     fn complete_init(&self) {
         self.operation.set(Operation::None);
         match self.next_operation.get() {
             Operation::None | Operation::Init => {}
             Operation::AppendKey => {
-                match self.append_key(
-                    self.key_buffer.take().unwrap(),
-                    self.value_buffer.take().unwrap(),
-                ) {
+                let mut value = LeasableBuffer::new(self.value_buffer.take().unwrap());
+                value.slice(..self.value_length.get());
+                match self.append_key(self.key_buffer.take().unwrap(), value) {
                     Err((key, value, error)) => {
                         self.client.map(move |cb| {
                             cb.append_key_complete(error, key, value);
@@ -183,6 +231,18 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> TicKVStore<'a, F, H> {
                     _ => {}
                 }
             }
+            Operation::UpdateKey => {
+                let mut value = LeasableBuffer::new(self.value_buffer.take().unwrap());
+                value.slice(..self.value_length.get());
+                match self.update_key(self.key_buffer.take().unwrap(), value) {
+                    Err((key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.update_key_complete(error, key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::GetKey => {
                 match self.get_value(
                     self.key_buffer.take().unwrap(),
@@ -269,31 +329,29 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
                         );
                     });
                 }
-                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
-                _ => {
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                Err(e) => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
                         cb.get_value_complete(
-                            Err(ErrorCode::FAIL),
+                            Err(convert_error(e)),
                             self.key_buffer.take().unwrap(),
                             self.ret_buffer.take().unwrap(),
                         );
                     });
                 }
             },
-            Operation::AppendKey => match ret {
-                Ok(tickv::success_codes::SuccessCode::Complete)
-                | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.operation.set(Operation::None);
-                }
-                _ => {}
-            },
-            Operation::InvalidateKey => match ret {
+            Operation::AppendKey | Operation::UpdateKey | Operation::InvalidateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
-                    self.operation.set(Operation::None);
+                    self.complete_write_operation(Ok(()));
                 }
-                _ => {}
+                Ok(tickv::success_codes::SuccessCode::Queued)
+                | Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::WriteNotReady(_)) => {}
+                Err(e) => self.complete_write_operation(Err(convert_error(e))),
             },
             Operation::GarbageCollect => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
@@ -320,21 +378,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> flash::Client<F> for TicKVStore<'a, F, H> {
             Operation::Init => {
                 self.complete_init();
             }
-            Operation::AppendKey => {
-                self.operation.set(Operation::None);
-                self.client.map(|cb| {
-                    cb.append_key_complete(
-                        Ok(()),
-                        self.key_buffer.take().unwrap(),
-                        self.tickv.get_stored_value_buffer().unwrap(),
-                    );
-                });
-            }
-            Operation::InvalidateKey => {
-                self.operation.set(Operation::None);
-                self.client.map(|cb| {
-                    cb.invalidate_key_complete(Ok(()), self.key_buffer.take().unwrap());
-                });
+            Operation::AppendKey | Operation::UpdateKey | Operation::InvalidateKey => {
+                // Updates and values stored in chunks take multiple writes
+                if self.tickv.is_operation_pending() {
+                    let (ret, _) = self.tickv.continue_operation();
+                    match ret {
+                        Ok(tickv::success_codes::SuccessCode::Queued)
+                        | Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                        | Err(tickv::error_codes::ErrorCode::WriteNotReady(_)) => {}
+                        Ok(_) => self.complete_write_operation(Ok(())),
+                        Err(e) => self.complete_write_operation(Err(convert_error(e))),
+                    }
+                } else {
+                    self.complete_write_operation(Ok(()));
+                }
             }
             _ => unreachable!(),
         }
@@ -401,13 +458,30 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], Result<(), ErrorCode>)> {
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
+        let length = value.len();
+        let start = value.as_ptr();
+        let value = value.take();
+        if start != value.as_ptr() {
+            return Err((key, value, Err(ErrorCode::INVAL)));
+        }
+
         match self.operation.get() {
             Operation::None => {
                 self.operation.set(Operation::AppendKey);
 
-                match self.tickv.append_key(u64::from_le_bytes(*key), value) {
+                match self
+                    .tickv
+                    .append_key(u64::from_le_bytes(*key), value, length)
+                {
                     Ok(_ret) => {
                         self.key_buffer.replace(key);
                         Ok(())
@@ -418,7 +492,14 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, value, Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((
+                                key,
+                                self.tickv.get_stored_value_buffer().unwrap(),
+                                Err(convert_error(e)),
+                            ))
+                        }
                     },
                 }
             }
@@ -427,7 +508,72 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                 // We can save this request and start it after init
                 self.next_operation.set(Operation::AppendKey);
                 self.key_buffer.replace(key);
-                self.value_buffer.replace(Some(value));
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, value, Err(ErrorCode::BUSY)))
+            }
+        }
+    }
+
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    > {
+        let length = value.len();
+        let start = value.as_ptr();
+        let value = value.take();
+        if start != value.as_ptr() {
+            return Err((key, value, Err(ErrorCode::INVAL)));
+        }
+
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::UpdateKey);
+
+                match self
+                    .tickv
+                    .update_key(u64::from_le_bytes(*key), value, length)
+                {
+                    Ok(_ret) => {
+                        self.key_buffer.replace(key);
+                        Ok(())
+                    }
+                    Err(e) => match e {
+                        tickv::error_codes::ErrorCode::ReadNotReady(_)
+                        | tickv::error_codes::ErrorCode::WriteNotReady(_) => {
+                            self.key_buffer.replace(key);
+                            Ok(())
+                        }
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((
+                                key,
+                                self.tickv.get_stored_value_buffer().unwrap(),
+                                Err(convert_error(e)),
+                            ))
+                        }
+                    },
+                }
+            }
+            Operation::Init => {
+                // The init process is still occuring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::UpdateKey);
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
                 Ok(())
             }
             _ => {
@@ -464,7 +610,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, buf.unwrap(), Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, buf.unwrap(), Err(convert_error(e))))
+                        }
                     },
                 }
             }
@@ -502,7 +651,10 @@ impl<'a, F: Flash, H: Hasher<'a, 8>> KVSystem<'a> for TicKVStore<'a, F, H> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, Err(ErrorCode::FAIL))),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, Err(convert_error(e))))
+                        }
                     },
                 }
             }
@@ -4,6 +4,8 @@ use core::fmt::Write;
 use cortexm4;
 use kernel::platform::chip::Chip;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 pub struct Apollo3<I: InterruptService<()> + 'static> {
     mpu: cortexm4::mpu::MPU,
@@ -85,10 +87,11 @@ impl<I: InterruptService<()> + 'static> Chip for Apollo3<I> {
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt, {}", interrupt);
                     }
@@ -2,6 +2,8 @@ use core::fmt::Write;
 use kernel;
 use kernel::debug;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 use kernel::utilities::registers::interfaces::Readable;
 use rv32i;
 
@@ -154,9 +156,10 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for ArtyExx<
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             while let Some(interrupt) = self.clic.next_pending() {
+                tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                 if !self.interrupt_service.service_interrupt(interrupt) {
                     debug!("unhandled interrupt: {:?}", interrupt);
                 }
@@ -13,6 +13,8 @@ use crate::interrupts;
 use crate::plic::Plic;
 use crate::plic::PLIC;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::{TraceEvent, LOCAL_INTERRUPT};
 
 pub struct E310x<'a, I: InterruptService<()> + 'a> {
     userspace_kernel_boundary: rv32i::syscall::SysCall,
@@ -84,8 +86,9 @@ impl<'a, I: InterruptService<()> + 'a> E310x<'a, I> {
         self.plic.enable_all();
     }
 
-    unsafe fn handle_plic_interrupts(&self) {
+    unsafe fn handle_plic_interrupts(&self, tracer: &dyn Tracer) {
         while let Some(interrupt) = self.plic.get_saved_interrupts() {
+            tracer.trace(TraceEvent::Interrupt { irq: interrupt });
             if !self.plic_interrupt_service.service_interrupt(interrupt) {
                 debug!("Pidx {}", interrupt);
             }
@@ -108,16 +111,19 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for E310x<'a
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         loop {
             let mip = CSR.mip.extract();
 
             if mip.is_set(mip::mtimer) {
+                tracer.trace(TraceEvent::Interrupt {
+                    irq: LOCAL_INTERRUPT | mip::mtimer.shift as u32,
+                });
                 self.timer.handle_interrupt();
             }
             if self.plic.get_saved_interrupts().is_some() {
                 unsafe {
-                    self.handle_plic_interrupts();
+                    self.handle_plic_interrupts(tracer);
                 }
             }
 
@@ -4,6 +4,8 @@ use core::fmt::Write;
 use kernel;
 use kernel::dynamic_deferred_call::DynamicDeferredCall;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use rv32i::csr::{mcause, mie::mie, mtvec::mtvec, CSR};
 use rv32i::epmp::PMP;
@@ -113,8 +115,9 @@ impl<'a, I: InterruptService<()> + 'a> EarlGrey<'a, I> {
         self.plic.enable_all();
     }
 
-    unsafe fn handle_plic_interrupts(&self) {
+    unsafe fn handle_plic_interrupts(&self, tracer: &dyn Tracer) {
         while let Some(interrupt) = self.plic.get_saved_interrupts() {
+            tracer.trace(TraceEvent::Interrupt { irq: interrupt });
             match interrupt {
                 interrupts::PWRMGRAONWAKEUP => {
                     self.pwrmgr.handle_interrupt();
@@ -210,11 +213,11 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for EarlGrey
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         loop {
             if self.plic.get_saved_interrupts().is_some() {
                 unsafe {
-                    self.handle_plic_interrupts();
+                    self.handle_plic_interrupts(tracer);
                 }
             }
 
@@ -4,6 +4,8 @@ use core::fmt::Write;
 
 use kernel;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable, Writeable};
 use kernel::utilities::StaticRef;
 
@@ -93,8 +95,9 @@ impl<'a, I: InterruptService<()> + 'a> Esp32C3<'a, I> {
         self.intc.enable_all();
     }
 
-    unsafe fn handle_pic_interrupts(&self) {
+    unsafe fn handle_pic_interrupts(&self, tracer: &dyn Tracer) {
         while let Some(interrupt) = self.intc.get_saved_interrupts() {
+            tracer.trace(TraceEvent::Interrupt { irq: interrupt });
             if !self.pic_interrupt_service.service_interrupt(interrupt) {
                 panic!("Unhandled interrupt {}", interrupt);
             }
@@ -118,11 +121,11 @@ impl<'a, I: InterruptService<()> + 'a> Chip for Esp32C3<'a, I> {
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         loop {
             if self.intc.get_saved_interrupts().is_some() {
                 unsafe {
-                    self.handle_pic_interrupts();
+                    self.handle_pic_interrupts(tracer);
                 }
             }
 
@@ -4,6 +4,8 @@ use core::fmt::Write;
 use cortexm7;
 use kernel::debug;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 use crate::nvic;
 
@@ -106,10 +108,11 @@ impl<I: InterruptService<()> + 'static> Chip for Imxrt10xx<I> {
     type MPU = cortexm7::mpu::MPU;
     type UserspaceKernelBoundary = cortexm7::syscall::SysCall;
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm7::nvic::next_pending() {
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     let handled = self.interrupt_service.service_interrupt(interrupt);
                     assert!(handled, "Unhandled interrupt number {}", interrupt);
                     let n = cortexm7::nvic::Nvic::new(interrupt);
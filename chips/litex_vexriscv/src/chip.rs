@@ -4,6 +4,8 @@ use core::fmt::Write;
 use kernel;
 use kernel::debug;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable};
 use rv32i::csr::{mcause, mie::mie, CSR};
 use rv32i::pmp::PMP;
@@ -41,8 +43,11 @@ impl<I: 'static + InterruptService<()>> LiteXVexRiscv<I> {
         VexRiscvInterruptController::unmask_all_interrupts();
     }
 
-    unsafe fn handle_interrupts(&self) {
+    unsafe fn handle_interrupts(&self, tracer: &dyn Tracer) {
         while let Some(interrupt) = self.interrupt_controller.next_saved() {
+            tracer.trace(TraceEvent::Interrupt {
+                irq: interrupt as u32,
+            });
             if !self.interrupt_service.service_interrupt(interrupt as u32) {
                 debug!("Unknown interrupt: {}", interrupt);
             }
@@ -63,10 +68,10 @@ impl<I: 'static + InterruptService<()>> kernel::platform::chip::Chip for LiteXVe
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         while self.interrupt_controller.next_saved().is_some() {
             unsafe {
-                self.handle_interrupts();
+                self.handle_interrupts(tracer);
             }
         }
 
@@ -5,6 +5,8 @@ use kernel::platform::chip::Chip;
 use crate::nvic;
 use crate::wdt;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 pub struct Msp432<'a, I: InterruptService<()> + 'a> {
     mpu: cortexm4::mpu::MPU,
@@ -107,10 +109,11 @@ impl<'a, I: InterruptService<()> + 'a> Chip for Msp432<'a, I> {
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             loop {
                 if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
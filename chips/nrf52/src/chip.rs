@@ -4,6 +4,8 @@ use cortexm4::{self, nvic};
 use kernel::deferred_call;
 use kernel::hil::time::Alarm;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 pub struct NRF52<'a, I: InterruptService<DeferredCallTask> + 'a> {
     mpu: cortexm4::mpu::MPU,
@@ -163,7 +165,7 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::platform::chip::Chi
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             loop {
                 if let Some(task) = deferred_call::DeferredCall::next_pending() {
@@ -171,6 +173,7 @@ impl<'a, I: InterruptService<DeferredCallTask> + 'a> kernel::platform::chip::Chi
                         panic!("unhandled deferred call task");
                     }
                 } else if let Some(interrupt) = nvic::next_pending() {
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
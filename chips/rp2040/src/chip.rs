@@ -4,6 +4,8 @@ use core::fmt::Write;
 use kernel::deferred_call;
 use kernel::platform::chip::Chip;
 use kernel::platform::chip::InterruptService;
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 use crate::adc;
 use crate::clocks::Clocks;
@@ -51,7 +53,7 @@ impl<'a, I: InterruptService<()>> Chip for Rp2040<'a, I> {
     type MPU = cortexm0p::mpu::MPU;
     type UserspaceKernelBoundary = cortexm0p::syscall::SysCall;
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             let mask = match self.sio.get_processor() {
                 Processor::Processor0 => self.processor0_interrupt_mask,
@@ -62,6 +64,7 @@ impl<'a, I: InterruptService<()>> Chip for Rp2040<'a, I> {
                     // ignore SIO_IRQ_PROC1 as it is intended for processor 1
                     // not able to unset its pending status
                     // probably only processor 1 can unset the pending by reading the fifo
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt {}", interrupt);
                     }
@@ -7,6 +7,8 @@ use core::fmt::Write;
 use cortexm4;
 use kernel::deferred_call;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::Tracer;
+use kernel::trace::TraceEvent;
 
 pub struct Sam4l<I: InterruptService<Task> + 'static> {
     mpu: cortexm4::mpu::MPU,
@@ -238,7 +240,7 @@ impl<I: InterruptService<Task> + 'static> Chip for Sam4l<I> {
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         unsafe {
             loop {
                 if let Some(task) = deferred_call::DeferredCall::next_pending() {
@@ -247,6 +249,7 @@ impl<I: InterruptService<Task> + 'static> Chip for Sam4l<I> {
                         false => panic!("unhandled deferred call task"),
                     }
                 } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                    tracer.trace(TraceEvent::Interrupt { irq: interrupt });
                     match self.interrupt_service.service_interrupt(interrupt) {
                         true => {}
                         false => panic!("unhandled interrupt"),
@@ -1091,6 +1091,12 @@ pub fn deep_sleep_ready() -> bool {
     hsb && pba && pbb && gpio
 }
 
+/// Returns the reset cause register, which has the bit of the source of the
+/// last reset set (see `ResetCause`).
+pub fn reset_cause() -> u32 {
+    PM_REGS.rcause.get()
+}
+
 impl ClockInterface for Clock {
     fn is_enabled(&self) -> bool {
         match self {
@@ -3,6 +3,8 @@
 use core::fmt::Write;
 use kernel;
 use kernel::platform::chip::{Chip, InterruptService};
+use kernel::platform::Tracer;
+use kernel::trace::{TraceEvent, LOCAL_INTERRUPT};
 use kernel::utilities::cells::VolatileCell;
 use kernel::utilities::registers::interfaces::{ReadWriteable, Readable};
 use kernel::utilities::StaticRef;
@@ -88,8 +90,9 @@ impl<'a, I: InterruptService<()> + 'a> SweRVolf<'a, I> {
         self.pic.enable_all();
     }
 
-    unsafe fn handle_pic_interrupts(&self) {
+    unsafe fn handle_pic_interrupts(&self, tracer: &dyn Tracer) {
         while let Some(interrupt) = self.pic.get_saved_interrupts() {
+            tracer.trace(TraceEvent::Interrupt { irq: interrupt });
             if !self.pic_interrupt_service.service_interrupt(interrupt) {
                 panic!("Unhandled interrupt {}", interrupt);
             }
@@ -113,12 +116,15 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for SweRVolf
         &self.userspace_kernel_boundary
     }
 
-    fn service_pending_interrupts(&self) {
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer) {
         loop {
             let mip = CSR.mip.extract();
 
             // Check if the timer interrupt is pending
             if mip.is_set(mip::mtimer) {
+                tracer.trace(TraceEvent::Interrupt {
+                    irq: LOCAL_INTERRUPT | mip::mtimer.shift as u32,
+                });
                 self.mtimer.handle_interrupt();
             }
             // timer0/timer1 pending bits in MIP are NOT sticky
@@ -126,6 +132,9 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for SweRVolf
             // Instead we have mutable statics that tell us.
             if unsafe { TIMER0_IRQ.get() } {
                 // timer0
+                tracer.trace(TraceEvent::Interrupt {
+                    irq: LOCAL_INTERRUPT | mie::BIT29.shift as u32,
+                });
                 self.scheduler_timer.handle_interrupt();
                 unsafe {
                     TIMER0_IRQ.set(false);
@@ -133,6 +142,9 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for SweRVolf
             }
             if unsafe { TIMER1_IRQ.get() } {
                 // timer1
+                tracer.trace(TraceEvent::Interrupt {
+                    irq: LOCAL_INTERRUPT | mie::BIT28.shift as u32,
+                });
                 unsafe {
                     self.pic_interrupt_service.service_interrupt(IRQ_TIMER1);
                     TIMER1_IRQ.set(false);
@@ -141,7 +153,7 @@ impl<'a, I: InterruptService<()> + 'a> kernel::platform::chip::Chip for SweRVolf
 
             if self.pic.get_saved_interrupts().is_some() {
                 unsafe {
-                    self.handle_pic_interrupts();
+                    self.handle_pic_interrupts(tracer);
                 }
             }
 
@@ -27,4 +27,10 @@ pub trait Queue<T> {
     fn retain<F>(&mut self, f: F)
     where
         F: FnMut(&T) -> bool;
+
+    /// Replace the first element that satisfies the predicate with `val`,
+    /// keeping its place in the queue. Returns the replaced element.
+    fn replace_first<F>(&mut self, val: T, f: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool;
 }
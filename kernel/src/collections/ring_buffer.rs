@@ -136,6 +136,23 @@ impl<T: Copy> queue::Queue<T> for RingBuffer<'_, T> {
 
         self.tail = dst;
     }
+
+    fn replace_first<F>(&mut self, val: T, f: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let len = self.ring.len();
+        let mut index = self.head;
+        while index != self.tail {
+            if f(&self.ring[index]) {
+                let old = self.ring[index];
+                self.ring[index] = val;
+                return Some(old);
+            }
+            index = (index + 1) % len;
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -280,4 +297,26 @@ mod test {
         assert_eq!(buf.dequeue(), Some(9));
         assert_eq!(buf.dequeue(), None);
     }
+
+    #[test]
+    fn test_replace_first() {
+        const LEN: usize = 10;
+        let mut ring = [0; LEN];
+        let mut buf = RingBuffer::new(&mut ring);
+
+        move_head(&mut buf, LEN - 2);
+        enqueue_iota(&mut buf, LEN);
+
+        // The first match is replaced in place, and the others are kept.
+        assert_eq!(buf.replace_first(42, |x| x % 3 == 2), Some(2));
+        assert_eq!(buf.replace_first(43, |x| *x == 100), None);
+        assert_eq!(buf.len(), LEN - 1);
+
+        assert_eq!(buf.dequeue(), Some(1));
+        assert_eq!(buf.dequeue(), Some(42));
+        for i in 3..LEN {
+            assert_eq!(buf.dequeue(), Some(i));
+        }
+        assert_eq!(buf.dequeue(), None);
+    }
 }
@@ -61,6 +61,29 @@ pub(crate) struct Config {
     // is identified, using configuration constants is the most effective
     // option.
     pub(crate) debug_panics: bool,
+
+    /// Whether the kernel should protect the bottom of each process's stack
+    /// with an MPU guard region.
+    ///
+    /// If enabled, once a process tells the kernel where its stack starts, the kernel adds an MPU
+    /// region the process cannot access at the start of its RAM, where the stack ends. A process
+    /// that grows its stack into it faults, and the fault is reported as a stack overflow. This
+    /// uses one MPU region per process and makes the usable stack slightly smaller.
+    pub(crate) process_stack_guard: bool,
+
+    /// Whether the kernel should track how much stack each process has used.
+    ///
+    /// If enabled, the kernel fills process memory with a known value when it loads or restarts
+    /// a process, so that it can later find the deepest point the stack has reached. This makes
+    /// loading processes slower.
+    pub(crate) process_stack_watermark: bool,
+
+    /// Whether `AclSyscallFilter` should report the system calls it denies to the debug output.
+    ///
+    /// If enabled, the filter prints a message for each system call it denies, or would deny in
+    /// `FilterMode::Report`. A process that keeps retrying a denied call can fill the debug
+    /// output, so this is meant for writing and checking access lists.
+    pub(crate) debug_syscall_filter: bool,
 }
 
 /// A unique instance of `Config` where compile-time configuration options are defined. These
@@ -71,4 +94,7 @@ pub(crate) const CONFIG: Config = Config {
     trace_syscalls: cfg!(feature = "trace_syscalls"),
     debug_load_processes: cfg!(feature = "debug_load_processes"),
     debug_panics: !cfg!(feature = "no_debug_panics"),
+    process_stack_guard: cfg!(feature = "process_stack_guard"),
+    process_stack_watermark: cfg!(feature = "process_stack_watermark"),
+    debug_syscall_filter: cfg!(feature = "debug_syscall_filter"),
 };
@@ -0,0 +1,316 @@
+//! Limits on how much CPU time processes may use.
+//!
+//! The kernel measures the CPU time each process uses with the
+//! `SchedulerTimer` around every switch to the process, and reports it to the
+//! board's `ProcessCpuBudget` (see `KernelResources`). This module provides
+//! `WindowedCpuBudget`, which gives every process the same budget of CPU time
+//! per accounting window. A process that uses more than its budget within one
+//! window is either throttled, which stops it until the window ends, or
+//! faulted, which hands it to its `ProcessFaultPolicy`. This catches runaway
+//! apps that spin without ever faulting.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let budget_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! budget_alarm.setup();
+//! let cpu_budget = static_init!(
+//!     kernel::cpu_budget::WindowedCpuBudget<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         NUM_PROCS,
+//!     >,
+//!     kernel::cpu_budget::WindowedCpuBudget::new(
+//!         board_kernel,
+//!         budget_alarm,
+//!         1000,   // 1 second windows
+//!         200000, // 200 ms of CPU time per window
+//!         kernel::cpu_budget::CpuBudgetAction::Throttle,
+//!         &process_management_capability,
+//!     )
+//! );
+//! budget_alarm.set_alarm_client(cpu_budget);
+//! cpu_budget.start();
+//! ```
+
+use core::cell::Cell;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::hil::time::{self, ConvertTicks};
+use crate::kernel::Kernel;
+use crate::platform::platform::ProcessCpuBudget;
+use crate::process::{Process, ProcessId, State};
+use crate::utilities::cells::OptionalCell;
+
+/// What to do with a process that exceeds its CPU time budget.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CpuBudgetAction {
+    /// Stop the process until the current accounting window ends.
+    Throttle,
+    /// Put the process in the fault state, so that its fault policy decides
+    /// whether it is restarted, stopped, or the board panics.
+    Fault,
+}
+
+/// CPU time budget that is the same for every process and renews at the
+/// start of every accounting window.
+pub struct WindowedCpuBudget<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    window_ms: u32,
+    budget_us: u32,
+    action: CpuBudgetAction,
+    /// CPU time used by the process in each slot of the processes array
+    /// during the current window.
+    used_us: [Cell<u32>; NUM_PROCS],
+    /// Processes this budget stopped, which are resumed when the window ends.
+    throttled: [OptionalCell<ProcessId>; NUM_PROCS],
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> WindowedCpuBudget<'a, A, NUM_PROCS> {
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        window_ms: u32,
+        budget_us: u32,
+        action: CpuBudgetAction,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> WindowedCpuBudget<'a, A, NUM_PROCS> {
+        const UNUSED: Cell<u32> = Cell::new(0);
+        const NOT_THROTTLED: OptionalCell<ProcessId> = OptionalCell::empty();
+        WindowedCpuBudget {
+            kernel,
+            alarm,
+            window_ms,
+            budget_us,
+            action,
+            used_us: [UNUSED; NUM_PROCS],
+            throttled: [NOT_THROTTLED; NUM_PROCS],
+        }
+    }
+
+    /// Start the first accounting window.
+    pub fn start(&self) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(self.window_ms));
+    }
+
+    /// Returns the CPU time `processid` has used in the current window.
+    pub fn window_usage_us(&self, processid: ProcessId) -> u32 {
+        self.used_us
+            .get(processid.index)
+            .map_or(0, |used_us| used_us.get())
+    }
+
+    /// Returns whether `processid` is currently throttled.
+    pub fn is_throttled(&self, processid: ProcessId) -> bool {
+        self.throttled
+            .get(processid.index)
+            .map_or(false, |throttled| throttled.contains(&processid))
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> ProcessCpuBudget
+    for WindowedCpuBudget<'a, A, NUM_PROCS>
+{
+    fn cpu_time_used(&self, process: &dyn Process, time_us: u32) {
+        let index = process.processid().index;
+        let used_us = match self.used_us.get(index) {
+            Some(used_us) => used_us,
+            None => return,
+        };
+        used_us.set(used_us.get().saturating_add(time_us));
+        if used_us.get() <= self.budget_us {
+            return;
+        }
+
+        match self.action {
+            CpuBudgetAction::Throttle => {
+                // Something else may have resumed the process since we
+                // stopped it, so stop it again every time it runs while over
+                // its budget.
+                process.stop();
+                self.throttled[index].set(process.processid());
+            }
+            CpuBudgetAction::Fault => {
+                // If the process is restarted it starts with a fresh budget.
+                used_us.set(0);
+                // A process that already faulted or exited has nothing left
+                // to stop, and faulting it again could restart it.
+                if !matches!(process.get_state(), State::Faulted | State::Terminated) {
+                    process.set_fault_state();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> time::AlarmClient
+    for WindowedCpuBudget<'a, A, NUM_PROCS>
+{
+    fn alarm(&self) {
+        // The window is over: everyone gets a fresh budget.
+        self.alarm.set_alarm(
+            self.alarm.get_alarm(),
+            self.alarm.ticks_from_ms(self.window_ms),
+        );
+        for used_us in self.used_us.iter() {
+            used_us.set(0);
+        }
+        for throttled in self.throttled.iter() {
+            throttled.take().map(|processid| {
+                // Only resume the process if it is still the one we stopped
+                // and nothing else has changed its state since.
+                self.kernel.process_map_or((), processid, |process| {
+                    if matches!(
+                        process.get_state(),
+                        State::StoppedRunning | State::StoppedYielded
+                    ) {
+                        process.resume();
+                    }
+                });
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dynamic_process_loader::{DynamicProcessLoader, DynamicProcessLoading};
+    use crate::hil::time::AlarmClient;
+    use crate::process::{FunctionCall, FunctionCallSource};
+    use crate::process_policies::StopFaultPolicy;
+    use crate::test_support::{self, ManagementCap, TestAlarm, FLAG_ENABLED, IMAGE_LEN};
+    use std::boxed::Box;
+
+    type TestBudget = WindowedCpuBudget<'static, TestAlarm, 2>;
+
+    const WINDOW_MS: u32 = 1000;
+    const BUDGET_US: u32 = 200;
+
+    /// A budget with `action` for the running processes `app0` and `app1`,
+    /// which are stopped rather than restarted when they fault.
+    fn budget(action: CpuBudgetAction) -> (&'static TestBudget, [&'static dyn Process; 2]) {
+        let kernel = test_support::kernel(2);
+        let flash = test_support::app_flash(&[FLAG_ENABLED; 2]);
+        let loader = DynamicProcessLoader::new(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(8192),
+            &StopFaultPolicy {},
+            false,
+            &ManagementCap,
+        );
+        let processes = [0, 1].map(|i| {
+            let processid = loader
+                .load_process(flash.as_ptr() as usize + i * IMAGE_LEN)
+                .unwrap();
+            let process = kernel.get_process(processid).unwrap();
+            process.set_process_function(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                argument0: 0,
+                argument1: 0,
+                argument2: 0,
+                argument3: 0,
+                pc: 0,
+            });
+            assert_eq!(process.get_state(), State::Running);
+            process
+        });
+
+        let budget: &'static TestBudget = Box::leak(Box::new(WindowedCpuBudget::new(
+            kernel,
+            Box::leak(Box::new(TestAlarm::default())),
+            WINDOW_MS,
+            BUDGET_US,
+            action,
+            &ManagementCap,
+        )));
+        budget.start();
+        (budget, processes)
+    }
+
+    /// End the current accounting window.
+    fn end_window(budget: &TestBudget) {
+        budget.alarm.advance(WINDOW_MS);
+        budget.alarm();
+    }
+
+    #[test]
+    fn window_rollover() {
+        let (budget, [app0, app1]) = budget(CpuBudgetAction::Throttle);
+        assert_eq!(budget.alarm.alarm.get(), WINDOW_MS);
+
+        budget.cpu_time_used(app0, 150);
+        budget.cpu_time_used(app1, 50);
+        assert_eq!(budget.window_usage_us(app0.processid()), 150);
+        assert_eq!(budget.window_usage_us(app1.processid()), 50);
+
+        // Usage from the previous window does not count against the next.
+        end_window(budget);
+        assert_eq!(budget.alarm.alarm.get(), 2 * WINDOW_MS);
+        assert_eq!(budget.window_usage_us(app0.processid()), 0);
+        assert_eq!(budget.window_usage_us(app1.processid()), 0);
+        budget.cpu_time_used(app0, 150);
+        assert_eq!(app0.get_state(), State::Running);
+        assert!(!budget.is_throttled(app0.processid()));
+
+        // Using exactly the budget is allowed.
+        budget.cpu_time_used(app0, BUDGET_US - 150);
+        assert_eq!(app0.get_state(), State::Running);
+        budget.cpu_time_used(app0, 1);
+        assert_eq!(app0.get_state(), State::StoppedRunning);
+    }
+
+    #[test]
+    fn throttle() {
+        let (budget, [app0, app1]) = budget(CpuBudgetAction::Throttle);
+
+        budget.cpu_time_used(app0, BUDGET_US + 1);
+        assert_eq!(app0.get_state(), State::StoppedRunning);
+        assert!(budget.is_throttled(app0.processid()));
+        assert_eq!(app1.get_state(), State::Running);
+        assert!(!budget.is_throttled(app1.processid()));
+
+        // If something else resumes the process within the window, it is
+        // stopped again as soon as it runs.
+        app0.resume();
+        budget.cpu_time_used(app0, 10);
+        assert_eq!(app0.get_state(), State::StoppedRunning);
+        assert!(budget.is_throttled(app0.processid()));
+
+        // The process is resumed when the window ends.
+        end_window(budget);
+        assert_eq!(app0.get_state(), State::Running);
+        assert!(!budget.is_throttled(app0.processid()));
+    }
+
+    #[test]
+    fn fault() {
+        let (budget, [app0, app1]) = budget(CpuBudgetAction::Fault);
+
+        budget.cpu_time_used(app0, BUDGET_US);
+        assert_eq!(app0.get_state(), State::Running);
+        budget.cpu_time_used(app0, 1);
+        assert_eq!(app0.get_state(), State::Faulted);
+        assert_eq!(budget.window_usage_us(app0.processid()), 0);
+        assert!(!budget.is_throttled(app0.processid()));
+        assert_eq!(app1.get_state(), State::Running);
+
+        // A process that already faulted is not faulted again.
+        budget.cpu_time_used(app0, BUDGET_US + 1);
+        assert_eq!(app0.get_state(), State::Faulted);
+
+        // Faulted processes are not resumed when the window ends.
+        end_window(budget);
+        assert_eq!(app0.get_state(), State::Faulted);
+    }
+}
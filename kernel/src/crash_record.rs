@@ -0,0 +1,269 @@
+//! Crash records that survive a reset.
+//!
+//! When the kernel panics, `debug::panic_print()` prints the panic message,
+//! the CPU state and the state of every process to the console, and that
+//! output is lost if nobody is watching. If the board assigns a crash record,
+//! everything the panic handler prints is also kept in RAM that the startup
+//! code does not initialize, together with the panic message and the name of
+//! the process that faulted, if a process fault caused the panic. After the
+//! reset the board assigns the same record again, along with the chip's reset
+//! cause, and the record of the crash can be read with `crash_record()`, for
+//! example by the `crash_log` capsule or the `crash` command of the process
+//! console.
+//!
+//! Usage
+//! -----
+//!
+//! The record must be in the `.noinit` section, which the Tock linker script
+//! places in RAM that is neither zeroed nor loaded at boot:
+//!
+//! ```rust,ignore
+//! #[link_section = ".noinit"]
+//! static mut CRASH_RECORD: MaybeUninit<kernel::crash_record::CrashRecord> =
+//!     MaybeUninit::uninit();
+//!
+//! kernel::crash_record::assign_crash_record(&mut CRASH_RECORD, sam4l::pm::reset_cause());
+//! ```
+
+use core::fmt::{write, Result, Write};
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::{cmp, ptr};
+
+use crate::debug::IoWrite;
+use crate::process::{ProcessSlot, State};
+
+/// Number of bytes of the panic message that are kept.
+pub const MESSAGE_LEN: usize = 128;
+/// Number of bytes of the name of the faulting process that are kept.
+pub const PROCESS_NAME_LEN: usize = 32;
+/// Number of bytes of the panic handler's output that are kept.
+pub const LOG_LEN: usize = 2048;
+
+/// Marks RAM that holds a crash record, so that RAM that was never written
+/// (for example after power-on) is not mistaken for one.
+const CRASH_RECORD_MAGIC: u32 = 0x4352_5348;
+
+/// The record holds no crash.
+const STATE_EMPTY: u32 = 0;
+/// The panic handler started writing the record but did not finish, because
+/// it faulted or the chip was reset.
+const STATE_PANICKING: u32 = 1;
+/// The panic handler finished writing the record.
+const STATE_PANICKED: u32 = 2;
+
+/// What the panic handler printed the last time the kernel panicked.
+#[repr(C)]
+pub struct CrashRecord {
+    magic: u32,
+    state: u32,
+    /// Whether `reset_cause` was set by the boot after the crash.
+    has_reset_cause: u32,
+    reset_cause: u32,
+    message_len: u32,
+    process_name_len: u32,
+    log_len: u32,
+    /// Number of bytes of the panic handler's output that did not fit.
+    log_dropped: u32,
+    message: [u8; MESSAGE_LEN],
+    process_name: [u8; PROCESS_NAME_LEN],
+    log: [u8; LOG_LEN],
+}
+
+impl CrashRecord {
+    /// Whether this looks like a record written by this module. The lengths
+    /// are checked too, since they are used to index the buffers.
+    fn is_valid(&self) -> bool {
+        self.magic == CRASH_RECORD_MAGIC
+            && self.state <= STATE_PANICKED
+            && self.has_reset_cause <= 1
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.process_name_len as usize <= PROCESS_NAME_LEN
+            && self.log_len as usize <= LOG_LEN
+    }
+
+    fn clear(&mut self) {
+        self.state = STATE_EMPTY;
+        self.has_reset_cause = 0;
+        self.reset_cause = 0;
+        self.message_len = 0;
+        self.process_name_len = 0;
+        self.log_len = 0;
+        self.log_dropped = 0;
+        // Written last, so the record is only valid once it is consistent.
+        self.magic = CRASH_RECORD_MAGIC;
+    }
+
+    /// Whether the panic handler finished writing the record. If it did not,
+    /// the log ends where the panic handler stopped.
+    pub fn is_complete(&self) -> bool {
+        self.state == STATE_PANICKED
+    }
+
+    /// The chip-specific reset cause the board reported on the boot after the
+    /// crash, if the board has booted since.
+    pub fn reset_cause(&self) -> Option<u32> {
+        if self.has_reset_cause == 1 {
+            Some(self.reset_cause)
+        } else {
+            None
+        }
+    }
+
+    /// The panic message, including where the panic happened, truncated to
+    /// `MESSAGE_LEN` bytes.
+    pub fn message(&self) -> &[u8] {
+        &self.message[..self.message_len as usize]
+    }
+
+    /// The name of the process in the faulted state when the kernel
+    /// panicked, if there was one.
+    pub fn process_name(&self) -> Option<&[u8]> {
+        if self.process_name_len > 0 {
+            Some(&self.process_name[..self.process_name_len as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Everything the panic handler printed, up to `LOG_LEN` bytes.
+    pub fn log(&self) -> &[u8] {
+        &self.log[..self.log_len as usize]
+    }
+
+    /// Number of bytes the panic handler printed after the log was full.
+    pub fn log_dropped(&self) -> usize {
+        self.log_dropped as usize
+    }
+
+    fn append_log(&mut self, bytes: &[u8]) {
+        let start = self.log_len as usize;
+        let len = cmp::min(bytes.len(), LOG_LEN - start);
+        self.log[start..start + len].copy_from_slice(&bytes[..len]);
+        self.log_len += len as u32;
+        self.log_dropped = self.log_dropped.saturating_add((bytes.len() - len) as u32);
+    }
+}
+
+/// Collects a formatted message into the message buffer of a record.
+struct MessageWriter<'a> {
+    record: &'a mut CrashRecord,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> Result {
+        let start = self.record.message_len as usize;
+        let len = cmp::min(s.len(), MESSAGE_LEN - start);
+        self.record.message[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.record.message_len += len as u32;
+        Ok(())
+    }
+}
+
+static mut CRASH_RECORD: Option<&'static mut CrashRecord> = None;
+
+/// Assign the memory crash records are kept in, and report the cause of the
+/// last reset. Call this early at boot, so crashes during the rest of the
+/// boot are recorded as well.
+///
+/// If the memory holds the record of a crash from before the reset, it is
+/// kept until the kernel panics again or it is cleared with
+/// `clear_crash_record()`, and `reset_cause` is stored with it. Otherwise the
+/// memory is initialized as an empty record.
+pub unsafe fn assign_crash_record(record: &'static mut MaybeUninit<CrashRecord>, reset_cause: u32) {
+    // The memory is not initialized by the startup code, so it either holds
+    // a record from before the reset or whatever was in RAM at power-on.
+    let record = &mut *record.as_mut_ptr();
+    if !record.is_valid() {
+        record.clear();
+    } else if record.state != STATE_EMPTY && record.has_reset_cause == 0 {
+        record.reset_cause = reset_cause;
+        record.has_reset_cause = 1;
+    }
+    CRASH_RECORD = Some(record);
+}
+
+/// Returns the record of the last crash, if the board assigned a crash
+/// record and it holds one.
+pub fn crash_record() -> Option<&'static CrashRecord> {
+    unsafe {
+        CRASH_RECORD
+            .as_deref()
+            .filter(|record| record.state != STATE_EMPTY)
+    }
+}
+
+/// Forget the last crash, so that `crash_record()` returns `None` until the
+/// kernel panics again.
+pub fn clear_crash_record() {
+    unsafe {
+        CRASH_RECORD.as_deref_mut().map(|record| record.clear());
+    }
+}
+
+/// Start recording a panic, overwriting any earlier crash.
+pub(crate) unsafe fn record_panic_start(panic_info: &PanicInfo, processes: &'static [ProcessSlot]) {
+    CRASH_RECORD.as_deref_mut().map(|record| {
+        record.clear();
+        record.state = STATE_PANICKING;
+        let _ = write(
+            &mut MessageWriter { record },
+            format_args!("{}", panic_info),
+        );
+
+        // Processes that fault with the panic fault policy are put in the
+        // faulted state just before they panic.
+        let faulted = processes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|process| process.get_state() == State::Faulted);
+        if let Some(process) = faulted {
+            let name = process.get_process_name().as_bytes();
+            let len = cmp::min(name.len(), PROCESS_NAME_LEN);
+            record.process_name[..len].copy_from_slice(&name[..len]);
+            record.process_name_len = len as u32;
+        }
+    });
+}
+
+/// Mark the panic record as complete.
+pub(crate) unsafe fn record_panic_end() {
+    CRASH_RECORD.as_deref_mut().map(|record| {
+        // Make sure the log is in memory before the record says it is
+        // complete.
+        ptr::write_volatile(&mut record.state, STATE_PANICKED);
+    });
+}
+
+/// A writer that also appends everything written to it to the crash record.
+pub(crate) struct RecordingWriter<'a, W> {
+    writer: &'a mut W,
+}
+
+impl<'a, W> RecordingWriter<'a, W> {
+    pub(crate) fn new(writer: &'a mut W) -> Self {
+        RecordingWriter { writer }
+    }
+
+    fn record(&self, bytes: &[u8]) {
+        unsafe {
+            CRASH_RECORD
+                .as_deref_mut()
+                .map(|record| record.append_log(bytes));
+        }
+    }
+}
+
+impl<W: Write> Write for RecordingWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        self.record(s.as_bytes());
+        self.writer.write_str(s)
+    }
+}
+
+impl<W: IoWrite> IoWrite for RecordingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) {
+        self.record(buf);
+        self.writer.write(buf);
+    }
+}
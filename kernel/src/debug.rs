@@ -56,10 +56,11 @@ use core::str;
 
 use crate::collections::queue::Queue;
 use crate::collections::ring_buffer::RingBuffer;
+use crate::crash_record;
 use crate::hil;
 use crate::platform::chip::Chip;
-use crate::process::Process;
 use crate::process::ProcessPrinter;
+use crate::process::ProcessSlot;
 use crate::utilities::binary_write::BinaryToWriteWrapper;
 use crate::utilities::cells::NumericCellExt;
 use crate::utilities::cells::{MapCell, TakeCell};
@@ -105,16 +106,22 @@ pub unsafe fn panic_print<W: Write + IoWrite, C: Chip, PP: ProcessPrinter>(
     writer: &mut W,
     panic_info: &PanicInfo,
     nop: &dyn Fn(),
-    processes: &'static [Option<&'static dyn Process>],
+    processes: &'static [ProcessSlot],
     chip: &'static Option<&'static C>,
     process_printer: &'static Option<&'static PP>,
 ) {
     panic_begin(nop);
+    // Keep a copy of everything printed below in the crash record, if the
+    // board assigned one.
+    crash_record::record_panic_start(panic_info, processes);
+    let writer = &mut crash_record::RecordingWriter::new(writer);
     panic_banner(writer, panic_info);
     // Flush debug buffer if needed
     flush(writer);
+    panic_kernel_stack(writer);
     panic_cpu_state(chip, writer);
     panic_process_info(processes, process_printer, writer);
+    crash_record::record_panic_end();
 }
 
 /// Tock default panic routine.
@@ -125,7 +132,7 @@ pub unsafe fn panic<L: hil::led::Led, W: Write + IoWrite, C: Chip, PP: ProcessPr
     writer: &mut W,
     panic_info: &PanicInfo,
     nop: &dyn Fn(),
-    processes: &'static [Option<&'static dyn Process>],
+    processes: &'static [ProcessSlot],
     chip: &'static Option<&'static C>,
     process_printer: &'static Option<&'static PP>,
 ) -> ! {
@@ -165,6 +172,28 @@ pub unsafe fn panic_banner<W: Write>(writer: &mut W, panic_info: &PanicInfo) {
     ));
 }
 
+/// Print how much of the kernel stack was used, and whether the kernel ran
+/// out of stack.
+///
+/// **NOTE:** The supplied `writer` must be synchronous.
+pub unsafe fn panic_kernel_stack<W: Write>(writer: &mut W) {
+    if let Some((used, size)) = kernel_stack_usage() {
+        if used == size {
+            // The last word of the stack was written, so the stack most likely
+            // grew past its end.
+            let _ = writer.write_fmt(format_args!(
+                "\tKernel stack overrun: all {} bytes were used, increase the size of STACK_MEMORY\r\n",
+                size
+            ));
+        } else {
+            let _ = writer.write_fmt(format_args!(
+                "\tKernel stack: {} of {} bytes used\r\n",
+                used, size
+            ));
+        }
+    }
+}
+
 /// Print current machine (CPU) state.
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
@@ -181,7 +210,7 @@ pub unsafe fn panic_cpu_state<W: Write, C: Chip>(
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
 pub unsafe fn panic_process_info<PP: ProcessPrinter, W: Write>(
-    procs: &'static [Option<&'static dyn Process>],
+    procs: &'static [ProcessSlot],
     process_printer: &'static Option<&'static PP>,
     writer: &mut W,
 ) {
@@ -189,7 +218,7 @@ pub unsafe fn panic_process_info<PP: ProcessPrinter, W: Write>(
         // print data about each process
         let _ = writer.write_fmt(format_args!("\r\n---| App Status |---\r\n"));
         for idx in 0..procs.len() {
-            procs[idx].map(|process| {
+            procs[idx].get().map(|process| {
                 // Print the memory map and basic process info.
                 //
                 // Because we are using a synchronous printer we do not need to
@@ -233,6 +262,50 @@ pub fn panic_blink_forever<L: hil::led::Led>(leds: &mut [&L]) -> ! {
 // panic! support routines
 ///////////////////////////////////////////////////////////////////
 
+///////////////////////////////////////////////////////////////////
+// kernel stack usage
+
+/// Value the reset handlers in the architecture crates fill the kernel stack
+/// with before the kernel starts, to measure how much of it gets used.
+pub const KERNEL_STACK_CANARY: u32 = 0xDEAD_C0DE;
+
+static mut KERNEL_STACK: Option<(*const u32, *const u32)> = None;
+
+/// Tell the kernel where its stack is, which is from `_sstack` to `_estack`
+/// in the Tock linker script, so it can report how much of it is used. The
+/// startup code in the architecture crates calls this before `main()`.
+///
+/// Interrupt handlers run on the kernel stack as well, so this includes the
+/// stack they use.
+pub unsafe fn assign_kernel_stack(stack_start: *const u8, stack_end: *const u8) {
+    KERNEL_STACK = Some((stack_start as *const u32, stack_end as *const u32));
+}
+
+/// Returns the largest number of bytes of kernel stack used since the kernel
+/// started, and the size of the kernel stack, if the kernel stack was
+/// assigned with `assign_kernel_stack()`.
+///
+/// The stack grows down, so this finds the lowest word that no longer holds
+/// the value the stack was filled with at startup. If all of the stack was
+/// used, the kernel may also have run out of stack.
+pub fn kernel_stack_usage() -> Option<(usize, usize)> {
+    // Safety: the kernel stack is only assigned while the board sets up the
+    // kernel, and the memory of the stack can always be read. The part of it
+    // below the current stack pointer is not in use.
+    unsafe {
+        KERNEL_STACK.map(|(stack_start, stack_end)| {
+            let size = stack_end as usize - stack_start as usize;
+            let mut lowest_used = stack_start;
+            while lowest_used < stack_end
+                && core::ptr::read_volatile(lowest_used) == KERNEL_STACK_CANARY
+            {
+                lowest_used = lowest_used.offset(1);
+            }
+            (stack_end as usize - lowest_used as usize, size)
+        })
+    }
+}
+
 ///////////////////////////////////////////////////////////////////
 // debug_gpio! support
 
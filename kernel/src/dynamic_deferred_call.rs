@@ -67,6 +67,8 @@
 
 use core::cell::Cell;
 
+use crate::platform::platform::Tracer;
+use crate::trace::TraceEvent;
 use crate::utilities::cells::OptionalCell;
 
 /// Kernel-global dynamic deferred call instance
@@ -139,12 +141,12 @@ impl DynamicDeferredCall {
     }
 
     /// Call the globally registered instance while the supplied predicate
-    /// returns `true`.
+    /// returns `true`, reporting each deferred call to `tracer`.
     ///
     /// Returns `true` if a global instance was registered and has been called.
-    pub unsafe fn call_global_instance_while<F: Fn() -> bool>(f: F) -> bool {
+    pub unsafe fn call_global_instance_while<F: Fn() -> bool>(f: F, tracer: &dyn Tracer) -> bool {
         DYNAMIC_DEFERRED_CALL
-            .map(move |ddc| ddc.call_while(f))
+            .map(move |ddc| ddc.call_while(f, tracer))
             .is_some()
     }
 
@@ -217,15 +219,16 @@ impl DynamicDeferredCall {
     /// It may be called without holding the `DynamicDeferredCall` reference through
     /// `call_global_instance`.
     pub(self) fn call(&self) {
-        self.call_while(|| true)
+        self.call_while(|| true, &())
     }
 
     /// Call all registered and to-be-scheduled deferred calls while the supplied
-    /// predicate returns `true`.
+    /// predicate returns `true`. Each call is reported to `tracer` before it
+    /// runs.
     ///
     /// It may be called without holding the `DynamicDeferredCall` reference through
     /// `call_global_instance_while`.
-    pub(self) fn call_while<F: Fn() -> bool>(&self, f: F) {
+    pub(self) fn call_while<F: Fn() -> bool>(&self, f: F, tracer: &dyn Tracer) {
         if self.call_pending.get() {
             for (i, client_state) in self.client_states.iter().enumerate() {
                 if !f() {
@@ -234,6 +237,9 @@ impl DynamicDeferredCall {
                 if client_state.scheduled.get() {
                     client_state.client.map(|client| {
                         client_state.scheduled.set(false);
+                        tracer.trace(TraceEvent::DeferredCall {
+                            handle: DeferredCallHandle(i),
+                        });
                         client.call(DeferredCallHandle(i));
                     });
                 }
@@ -263,3 +269,78 @@ pub trait DynamicDeferredCallClient {
 /// [DynamicDeferredCall](crate::dynamic_deferred_call::DynamicDeferredCall)
 #[derive(Copy, Clone, Debug)]
 pub struct DeferredCallHandle(usize);
+
+impl DeferredCallHandle {
+    /// Returns the number of the deferred call slot this handle addresses.
+    pub(crate) fn id(&self) -> usize {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct CountingClient {
+        calls: Cell<usize>,
+    }
+
+    impl DynamicDeferredCallClient for CountingClient {
+        fn call(&self, _handle: DeferredCallHandle) {
+            self.calls.set(self.calls.get() + 1);
+        }
+    }
+
+    struct RecordingTracer {
+        handles: Cell<Vec<usize>>,
+    }
+
+    impl Tracer for RecordingTracer {
+        fn trace(&self, event: TraceEvent) {
+            if let TraceEvent::DeferredCall { handle } = event {
+                let mut handles = self.handles.take();
+                handles.push(handle.id());
+                self.handles.set(handles);
+            }
+        }
+    }
+
+    #[test]
+    fn traces_each_call() {
+        let states: &'static [DynamicDeferredCallClientState] =
+            Box::leak(Box::new(<[DynamicDeferredCallClientState; 3]>::default()));
+        let ddc = DynamicDeferredCall::new(states);
+        let clients: &'static [CountingClient; 3] = Box::leak(Box::new([
+            CountingClient {
+                calls: Cell::new(0),
+            },
+            CountingClient {
+                calls: Cell::new(0),
+            },
+            CountingClient {
+                calls: Cell::new(0),
+            },
+        ]));
+        let handles: Vec<DeferredCallHandle> = clients
+            .iter()
+            .map(|client| ddc.register(client).unwrap())
+            .collect();
+
+        ddc.set(handles[0]);
+        ddc.set(handles[2]);
+        let tracer = RecordingTracer {
+            handles: Cell::new(Vec::new()),
+        };
+        ddc.call_while(|| true, &tracer);
+
+        assert_eq!(tracer.handles.take(), [0, 2]);
+        assert_eq!(clients[0].calls.get(), 1);
+        assert_eq!(clients[1].calls.get(), 0);
+        assert_eq!(clients[2].calls.get(), 1);
+        assert!(!ddc.has_pending());
+    }
+}
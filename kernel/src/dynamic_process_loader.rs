@@ -0,0 +1,303 @@
+//! Load new processes at runtime without rebooting the kernel.
+//!
+//! At boot, `load_processes()` walks the linked list of TBF images in the app
+//! flash region and fills in the processes array. Any process slots that are
+//! left empty, as well as any app flash and app memory that was not used, can
+//! be used later to load additional applications.
+//!
+//! `DynamicProcessLoader` implements this. Something else (for example a
+//! capsule receiving an app image over a serial link) writes a new TBF image
+//! into unused app flash, and then asks the loader to load the process at that
+//! address. The loader validates the TBF header, allocates RAM for the process
+//! from the app memory not used by any existing process, and creates the
+//! process. The kernel then places it in an empty slot of the processes array,
+//! and it is scheduled like any process that was loaded at boot.
+//!
+//! If the board checks process credentials (see `process_checker`), it should
+//! give the loader its `ProcessCheckerMachine` with `set_credentials_checker()`.
+//! Dynamically loaded processes are then checked the same way as processes
+//! loaded at boot, and do not run until they are approved.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let loader = static_init!(
+//!     kernel::process::DynamicProcessLoader<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>>,
+//!     kernel::process::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         app_flash,
+//!         app_memory,
+//!         &FAULT_RESPONSE,
+//!         true,
+//!         &process_mgmt_cap,
+//!     )
+//! );
+//! ```
+
+use core::convert::TryInto;
+use core::slice;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::config;
+use crate::debug;
+use crate::kernel::Kernel;
+use crate::platform::chip::Chip;
+use crate::process::ProcessId;
+use crate::process_checker::ProcessCheckerMachine;
+use crate::process_policies::ProcessFaultPolicy;
+use crate::process_standard::ProcessStandard;
+use crate::process_utilities::ProcessLoadError;
+use crate::utilities::cells::OptionalCell;
+
+/// Interface for loading a new process while the kernel is running.
+///
+/// This allows capsules that receive new application images to trigger
+/// loading without depending on the specific chip type of the board.
+pub trait DynamicProcessLoading {
+    /// Load the TBF image that starts at `app_address` in app flash as a new
+    /// process.
+    ///
+    /// The image must lie entirely inside the app flash region and must not
+    /// overlap the flash of any existing process. On success the new process
+    /// has been placed in an empty process slot and its `ProcessId` is
+    /// returned. The process is ready to be scheduled, or, if credentials are
+    /// checked, it will be once it is approved.
+    fn load_process(&self, app_address: usize) -> Result<ProcessId, ProcessLoadError>;
+}
+
+/// Kernel-side loader for processes added to app flash after boot.
+pub struct DynamicProcessLoader<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    /// The entire region of flash reserved for applications.
+    app_flash: &'static [u8],
+    /// Start of the entire region of RAM reserved for applications. We store
+    /// this as a pointer and length and not a slice for the same reason
+    /// `ProcessStandard` does: processes create `ProcessBuffer`s inside of
+    /// this memory, and holding a slice to it would be undefined behavior.
+    app_memory_start: *mut u8,
+    /// Length of the entire region of RAM reserved for applications.
+    app_memory_len: usize,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    require_kernel_version: bool,
+    /// If set, new processes must have their credentials approved by this
+    /// checker before they run.
+    checker: OptionalCell<&'static ProcessCheckerMachine>,
+}
+
+impl<C: 'static + Chip> DynamicProcessLoader<C> {
+    /// Create a new loader.
+    ///
+    /// `app_flash` and `app_memory` must be the same regions that were passed
+    /// to `load_processes()` at boot. New processes are added to the
+    /// processes array of `kernel`.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        app_memory: &mut [u8],
+        fault_policy: &'static dyn ProcessFaultPolicy,
+        require_kernel_version: bool,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> DynamicProcessLoader<C> {
+        // Processes with growable memory can grow into the unused part of
+        // `app_memory`.
+        kernel.set_app_memory_end(app_memory.as_ptr() as usize + app_memory.len());
+        DynamicProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            app_memory_start: app_memory.as_mut_ptr(),
+            app_memory_len: app_memory.len(),
+            fault_policy,
+            require_kernel_version,
+            checker: OptionalCell::empty(),
+        }
+    }
+
+    /// Require that processes loaded from now on have their credentials
+    /// approved by `checker` before they run.
+    pub fn set_credentials_checker(&self, checker: &'static ProcessCheckerMachine) {
+        self.checker.set(checker);
+    }
+
+    /// Returns the address of the first byte of app memory that is not used
+    /// by any process currently in the processes array.
+    ///
+    /// Processes are allocated back-to-back in app memory, so the free pool is
+    /// everything after the end of the highest process memory region,
+    /// including any memory the process grew into.
+    fn first_free_memory_address(&self) -> usize {
+        let app_memory_start = self.app_memory_start as usize;
+        let app_memory_end = app_memory_start + self.app_memory_len;
+        self.kernel
+            .get_process_iter()
+            .map(|process| process.get_addresses().sram_end)
+            .filter(|&end| end > app_memory_start && end <= app_memory_end)
+            .fold(app_memory_start, core::cmp::max)
+    }
+
+    /// Check that the TBF entry `[start, end)` does not overlap the flash of
+    /// any existing process.
+    fn flash_is_unused(&self, start: usize, end: usize) -> bool {
+        self.kernel.get_process_iter().all(|process| {
+            let addresses = process.get_addresses();
+            end <= addresses.flash_start || start >= addresses.flash_end
+        })
+    }
+}
+
+impl<C: 'static + Chip> DynamicProcessLoading for DynamicProcessLoader<C> {
+    fn load_process(&self, app_address: usize) -> Result<ProcessId, ProcessLoadError> {
+        // The new image must start inside of app flash.
+        let flash_start = self.app_flash.as_ptr() as usize;
+        let offset = app_address
+            .checked_sub(flash_start)
+            .ok_or(ProcessLoadError::InvalidFlashLocation)?;
+        let remaining_flash = self
+            .app_flash
+            .get(offset..)
+            .ok_or(ProcessLoadError::InvalidFlashLocation)?;
+
+        // Parse the lengths at the start of the TBF header so we know
+        // how much flash the image covers.
+        let test_header_slice = remaining_flash
+            .get(0..8)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let (version, header_length, entry_length) = match tock_tbf::parse::parse_tbf_header_lengths(
+            test_header_slice
+                .try_into()
+                .or(Err(ProcessLoadError::InternalError))?,
+        ) {
+            Ok((v, hl, el)) => (v, hl, el),
+            Err(_) => {
+                // Unlike at boot we are not walking a linked list
+                // we can skip ahead in. Any error here means there
+                // is no valid app image at this address.
+                return Err(ProcessLoadError::InvalidFlashLocation);
+            }
+        };
+
+        let entry_flash = remaining_flash
+            .get(0..entry_length as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+        // Make sure we are not about to load a second copy of a
+        // process that is already running out of this flash.
+        if !self.flash_is_unused(app_address, app_address + entry_flash.len()) {
+            return Err(ProcessLoadError::InvalidFlashLocation);
+        }
+
+        // Find an empty slot in the processes array.
+        let index = self
+            .kernel
+            .empty_process_slot()
+            .ok_or(ProcessLoadError::NoProcessSlotAvailable)?;
+
+        // Allocate process memory from what is left of app memory.
+        let free_start = self.first_free_memory_address();
+        let free_len = self.app_memory_len - (free_start - self.app_memory_start as usize);
+        // ### Safety
+        //
+        // The free region is inside the app memory region given to us
+        // at creation, and no existing process has been given memory
+        // from it. `ProcessStandard::create()` does not keep the slice,
+        // only a pointer and length.
+        let remaining_memory =
+            unsafe { slice::from_raw_parts_mut(free_start as *mut u8, free_len) };
+
+        let (process_option, _unused_memory) = unsafe {
+            ProcessStandard::create(
+                self.kernel,
+                self.chip,
+                entry_flash,
+                header_length as usize,
+                version,
+                remaining_memory,
+                self.fault_policy,
+                self.require_kernel_version,
+                self.checker.is_some(),
+                index,
+            )?
+        };
+
+        // Padding and disabled apps do not create a process.
+        let process = process_option.ok_or(ProcessLoadError::ProcessNotEnabled)?;
+
+        if config::CONFIG.debug_load_processes {
+            let addresses = process.get_addresses();
+            debug!(
+                "Dynamically loaded process[{}] from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X} = {:?}",
+                index,
+                entry_flash.as_ptr() as usize,
+                entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                addresses.sram_start,
+                addresses.sram_end - 1,
+                process.get_process_name()
+            );
+        }
+
+        self.kernel
+            .add_process(index, process)
+            .or(Err(ProcessLoadError::InternalError))?;
+        self.checker.map(|checker| checker.check_all());
+        Ok(process.processid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FLAG_ENABLED, IMAGE_LEN};
+
+    #[test]
+    fn load_processes_at_runtime() {
+        let kernel = test_support::kernel(2);
+        let chip = test_support::chip();
+        let flash = test_support::app_flash(&[FLAG_ENABLED; 3]);
+        let flash_start = flash.as_ptr() as usize;
+        // Room for two processes
+        let memory = test_support::app_memory(8192);
+        let memory_start = memory.as_ptr() as usize;
+        let loader = test_support::loader(kernel, chip, flash, memory);
+
+        let first = loader.load_process(flash_start).unwrap();
+        assert_eq!(first.index, 0);
+        assert!(kernel.get_process(first).is_some());
+        let first_addresses = kernel.get_process(first).unwrap().get_addresses();
+        assert_eq!(first_addresses.sram_start, memory_start);
+        assert_eq!(first_addresses.flash_start, flash_start);
+
+        // The same image can't be loaded twice
+        assert!(matches!(
+            loader.load_process(flash_start),
+            Err(ProcessLoadError::InvalidFlashLocation)
+        ));
+
+        // The next process goes into the next slot, after the memory of the
+        // first one
+        let second = loader.load_process(flash_start + IMAGE_LEN).unwrap();
+        assert_eq!(second.index, 1);
+        let second_addresses = kernel.get_process(second).unwrap().get_addresses();
+        assert!(second_addresses.sram_start >= first_addresses.sram_end);
+        assert_eq!(kernel.get_process_iter().count(), 2);
+
+        // There are no slots left
+        assert!(matches!(
+            loader.load_process(flash_start + 2 * IMAGE_LEN),
+            Err(ProcessLoadError::NoProcessSlotAvailable)
+        ));
+
+        // There is no image here, or it is outside of app flash
+        assert!(matches!(
+            loader.load_process(flash_start + 3 * IMAGE_LEN),
+            Err(ProcessLoadError::InvalidFlashLocation)
+        ));
+        assert!(matches!(
+            loader.load_process(flash_start - IMAGE_LEN),
+            Err(ProcessLoadError::InvalidFlashLocation)
+        ));
+    }
+}
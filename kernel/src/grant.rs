@@ -132,7 +132,7 @@ use core::ptr::{write, NonNull};
 use core::slice;
 
 use crate::kernel::Kernel;
-use crate::process::{Error, Process, ProcessCustomGrantIdentifer, ProcessId};
+use crate::process::{Error, Process, ProcessCustomGrantIdentifer, ProcessId, ProcessSlot};
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
 use crate::processbuffer::{ReadOnlyProcessBufferRef, ReadWriteProcessBufferRef};
 use crate::upcall::{Upcall, UpcallError, UpcallId};
@@ -1315,6 +1315,7 @@ impl<'a, T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: Allow
         // grant space.
         let mut allocator = GrantRegionAllocator {
             processid: self.process.processid(),
+            grant_num: self.grant_num,
         };
 
         // Call functor and pass back value.
@@ -1391,7 +1392,10 @@ impl<T> CustomGrant<T> {
                 // once (because of the `&mut self` requirement).
                 let custom_grant = unsafe { &mut *(grant_ptr as *mut T) };
                 let borrowed = GrantData::new(custom_grant);
-                Ok(fun(borrowed))
+                let res = fun(borrowed);
+
+                process.leave_custom_grant(self.identifier);
+                Ok(res)
             })
     }
 }
@@ -1403,6 +1407,9 @@ impl<T> CustomGrant<T> {
 pub struct GrantRegionAllocator {
     /// The process the allocator will allocate memory from.
     processid: ProcessId,
+    /// The grant that is entered, which the allocated memory is counted
+    /// towards.
+    grant_num: usize,
 }
 
 impl GrantRegionAllocator {
@@ -1509,7 +1516,7 @@ impl GrantRegionAllocator {
             .kernel
             .process_map_or(Err(Error::NoSuchApp), self.processid, |process| {
                 process
-                    .allocate_custom_grant(alloc_size, alloc_align)
+                    .allocate_custom_grant(self.grant_num, alloc_size, alloc_align)
                     .map_or(
                         Err(Error::OutOfMemory),
                         |(custom_grant_identifier, raw_ptr)| Ok((custom_grant_identifier, raw_ptr)),
@@ -1518,6 +1525,19 @@ impl GrantRegionAllocator {
     }
 }
 
+/// Limit on the grant memory a single process can use for one driver.
+///
+/// This counts the memory of the driver's grant and of all custom grants the
+/// driver allocates for the process. Boards set quotas with
+/// `Kernel::set_grant_quotas()`.
+#[derive(Clone, Copy)]
+pub struct GrantQuota {
+    /// The driver number the quota applies to.
+    pub driver_num: usize,
+    /// The maximum number of grant bytes a process can use for the driver.
+    pub max_bytes: usize,
+}
+
 /// Type for storing an object of type T in process memory that is only
 /// accessible by the kernel.
 ///
@@ -1651,8 +1671,8 @@ pub struct Iter<
 
     /// Iterator over valid processes.
     subiter: core::iter::FilterMap<
-        core::slice::Iter<'a, Option<&'static dyn Process>>,
-        fn(&Option<&'static dyn Process>) -> Option<&'static dyn Process>,
+        core::slice::Iter<'a, ProcessSlot>,
+        fn(&ProcessSlot) -> Option<&'static dyn Process>,
     >,
 }
 
@@ -1670,3 +1690,51 @@ impl<'a, T: Default, Upcalls: UpcallSize, AllowROs: AllowRoSize, AllowRWs: Allow
             .find_map(|process| ProcessGrant::new_if_allocated(grant, process))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic_process_loader::DynamicProcessLoading;
+    use crate::test_support::{self, MemoryCap, FLAG_ENABLED};
+
+    type TestGrant = Grant<[u64; 32], UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>;
+
+    #[test]
+    fn grant_quotas() {
+        let kernel = test_support::kernel(1);
+        kernel.set_grant_quotas(
+            &[
+                GrantQuota {
+                    driver_num: 0x1,
+                    max_bytes: 64,
+                },
+                GrantQuota {
+                    driver_num: 0x2,
+                    max_bytes: 1024,
+                },
+            ],
+            &MemoryCap,
+        );
+        let limited: TestGrant = kernel.create_grant(0x1, &MemoryCap);
+        let within_quota: TestGrant = kernel.create_grant(0x2, &MemoryCap);
+        let unlimited: TestGrant = kernel.create_grant(0x3, &MemoryCap);
+
+        let flash = test_support::app_flash(&[FLAG_ENABLED]);
+        let loader = test_support::loader(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(8192),
+        );
+        let processid = loader.load_process(flash.as_ptr() as usize).unwrap();
+
+        // The grant of the first driver is larger than its quota, so syscalls
+        // to it fail with NOMEM.
+        let err = limited.enter(processid, |_, _| ()).unwrap_err();
+        assert_eq!(ErrorCode::from(err), ErrorCode::NOMEM);
+
+        // The other drivers are not affected.
+        assert!(within_quota.enter(processid, |_, _| ()).is_ok());
+        assert!(unlimited.enter(processid, |_, _| ()).is_ok());
+    }
+}
@@ -0,0 +1,54 @@
+//! Interface for storage devices that are read and written in blocks, such as
+//! SD cards.
+
+use crate::errorcode::ErrorCode;
+
+/// Simple interface for reading and writing single blocks of a block storage
+/// device. It is expected that drivers for block devices would implement this
+/// trait, so that file systems can be used on top of any of them.
+pub trait BlockStorage<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// The size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks of the device, or 0 if it is not known yet, for
+    /// example because the device is not initialized.
+    fn block_count(&self) -> u32;
+
+    /// Read block number `block` into the start of `buffer`, which must be at
+    /// least `block_size()` bytes long.
+    ///
+    /// On error the buffer is returned with:
+    ///    `RESERVE`: The device is not initialized
+    ///    `UNINSTALLED`: The device is not present
+    ///    `BUSY`: A read or write is already in progress
+    ///    `SIZE`: The buffer is too short
+    ///    `INVAL`: The block is past the end of the device
+    fn read_block(
+        &self,
+        block: u32,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Write the start of `buffer`, which must be at least `block_size()`
+    /// bytes long, to block number `block`.
+    ///
+    /// On error the buffer is returned, with the same errors as
+    /// `read_block()`.
+    fn write_block(
+        &self,
+        block: u32,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
+
+/// Client interface for block storage devices.
+pub trait BlockStorageClient {
+    /// Called when a read started with `read_block()` completes. If the
+    /// result is `Ok(())`, the block is at the start of `buffer`.
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// Called when a write started with `write_block()` completes.
+    fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+}
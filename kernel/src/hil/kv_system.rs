@@ -30,7 +30,8 @@
 //! This level is also in charge of generating the key hash by calling into
 //! level 2.
 //!
-//! There is currently no implementation of this HIL in Tock.
+//! `capsules::kv_driver` implements this level for userspace, there is no
+//! HIL for it yet.
 //!
 //! The expected setup inside Tock will look like this:
 //! +-----------------------+
@@ -57,6 +58,7 @@
 //!
 //!    hil::flash
 
+use crate::utilities::leasable_buffer::LeasableBuffer;
 use crate::ErrorCode;
 
 /// The type of keys, this should define the output size of the digest
@@ -88,7 +90,19 @@ pub trait Client<K: KeyType> {
         &self,
         result: Result<(), ErrorCode>,
         key: &'static mut K,
-        value: &'static [u8],
+        value: &'static mut [u8],
+    );
+
+    /// This callback is called when the update_key operation completes
+    ///
+    /// `result`: Nothing on success, 'ErrorCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn update_key_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: &'static mut K,
+        value: &'static mut [u8],
     );
 
     /// This callback is called when the get_value operation completes
@@ -146,7 +160,9 @@ pub trait KVSystem<'a> {
     ///
     /// `key`: A hashed key. This key will be used in future to retrieve
     ///        or remove the `value`.
-    /// `value`: A buffer containing the data to be stored to flash.
+    /// `value`: A buffer containing the data to be stored to flash. Only the
+    ///          active part of the buffer is stored, which must start at
+    ///          the beginning of the buffer.
     ///
     /// On success nothing will be returned.
     /// On error the key, value and a `Result<(), ErrorCode>` will be returned.
@@ -160,8 +176,47 @@ pub trait KVSystem<'a> {
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], Result<(), ErrorCode>)>;
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    >;
+
+    /// Replaces the value of an existing key.
+    ///
+    /// `key`: A hashed key that has previously been appended.
+    /// `value`: A buffer containing the new data to be stored to flash. Only
+    ///          the active part of the buffer is stored, which must start at
+    ///          the beginning of the buffer.
+    ///
+    /// If the update is interrupted, for example by a power loss, the key
+    /// will either have the old or the new value afterwards.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    ///    `BUSY`: An operation is already in progress
+    ///    `INVAL`: An invalid parameter was passed
+    ///    `NODEVICE`: No KV store was setup
+    ///    `ENOSUPPORT`: The key could not be found.
+    ///    `NOMEM`: The key could not be updated due to no more space.
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: LeasableBuffer<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            &'static mut Self::K,
+            &'static mut [u8],
+            Result<(), ErrorCode>,
+        ),
+    >;
 
     /// Retrieves the value from a specified key.
     ///
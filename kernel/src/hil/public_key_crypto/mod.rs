@@ -1,3 +1,4 @@
 //! Provides public/private key encryption
 
 pub mod keys;
+pub mod signature;
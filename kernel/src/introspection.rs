@@ -123,6 +123,26 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns how much CPU time, in microseconds, this app has used.
+    pub fn app_cpu_time_us(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_cpu_time_us())
+    }
+
+    /// Returns the number of times this app has missed a deadline.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_deadline_miss_count())
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
@@ -143,6 +163,43 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Calls `closure` with the driver number and the number of grant bytes
+    /// for each driver the app has allocated a grant for. The number of bytes
+    /// includes the custom grants the driver allocated for the app.
+    pub fn app_grant_usage<F: FnMut(usize, usize)>(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+        mut closure: F,
+    ) {
+        let number_of_grants = self.kernel.get_grant_count_and_finalize();
+        self.kernel.process_map_or((), app, |process| {
+            for grant_num in 0..number_of_grants {
+                if let Some((driver_num, _, size)) = process.get_grant_allocation(grant_num) {
+                    closure(driver_num, size);
+                }
+            }
+        });
+    }
+
+    /// Returns the number of grant bytes all processes use for the driver
+    /// `driver_num`.
+    pub fn driver_grant_usage(
+        &self,
+        driver_num: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        let total: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            if let Ok(grant_num) = proc.lookup_grant_from_driver_num(driver_num) {
+                if let Some((_, _, size)) = proc.get_grant_allocation(grant_num) {
+                    total.add(size);
+                }
+            }
+        });
+        total.get()
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -152,4 +209,22 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the total number of deadlines missed by all processes.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
+
+    /// Returns how much CPU time, in microseconds, all processes have used.
+    pub fn cpu_time_us(&self, _capability: &dyn ProcessManagementCapability) -> u64 {
+        let total: Cell<u64> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            total.set(total.get() + proc.debug_cpu_time_us());
+        });
+        total.get()
+    }
 }
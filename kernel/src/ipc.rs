@@ -7,7 +7,7 @@ use crate::capabilities::MemoryAllocationCapability;
 use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use crate::kernel::Kernel;
 use crate::process;
-use crate::process::ProcessId;
+use crate::process::{AppId, ProcessId};
 use crate::processbuffer::ReadableProcessBuffer;
 use crate::syscall_driver::{CommandReturn, SyscallDriver};
 use crate::ErrorCode;
@@ -120,6 +120,10 @@ impl<const NUM_PROCS: usize> SyscallDriver for IPC<NUM_PROCS> {
     /// - `3`: Notify a client with descriptor `target_id`, typically in response to a previous
     ///        notify from the client. Returns an error if `target_id` refers to an invalid client
     ///        or the notify fails to enqueue.
+    /// - `4`: Perform discovery on the persistent app ID in `target_id`. Returns the service
+    ///        descriptor if a process with that app ID is found, otherwise returns an error.
+    ///        Unlike discovery by package name, this finds the same application across
+    ///        restarts and reboots.
     fn command(
         &self,
         command_number: usize,
@@ -230,6 +234,26 @@ impl<const NUM_PROCS: usize> SyscallDriver for IPC<NUM_PROCS> {
                     )
                 })
             }
+            4 =>
+            /* Discover by app ID */
+            {
+                let app_id = match core::num::NonZeroU32::new(target_id as u32) {
+                    Some(id) => AppId::Fixed(id),
+                    None => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                self.data
+                    .kernel
+                    .process_until(|p| {
+                        if p.get_app_id() == app_id {
+                            p.processid()
+                                .index()
+                                .map(|i| CommandReturn::success_u32(i as u32))
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or(CommandReturn::failure(ErrorCode::NODEVICE))
+            }
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,316 @@
+//! Message-passing inter-process communication with bounded mailboxes.
+//!
+//! This is a syscall driver that lets processes send each other small
+//! messages by copy, as an alternative to sharing whole buffers with the IPC
+//! driver in `ipc`. Every process gets a mailbox in its grant region that
+//! queues up to `QUEUE_LEN` messages of at most `MSG_LEN` bytes each.
+//!
+//! A process first discovers the receiver by package name, which returns the
+//! receiver's process identifier. To send, the sender shares the message
+//! with a read-only allow and calls the send command with the identifier of
+//! the receiver. The kernel copies the message into the receiver's mailbox,
+//! or fails with `BUSY` if the mailbox is full, so the sender can retry
+//! later. The receiver gets an upcall for every message with the identifier
+//! of the sender, and copies the oldest queued message into its read-write
+//! allow buffer with the receive command. The sender identifier can be used
+//! directly to reply.
+//!
+//! Process identifiers change when a process restarts, so messages can never
+//! reach a new instance of an application by mistake, and a restarted
+//! process starts with an empty mailbox.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ipc_mailbox = static_init!(
+//!     kernel::ipc_mailbox::IpcMailbox<32, 4>,
+//!     kernel::ipc_mailbox::IpcMailbox::new(
+//!         board_kernel,
+//!         kernel::ipc_mailbox::DRIVER_NUM,
+//!         &memory_allocation_capability,
+//!     )
+//! );
+//! ```
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use crate::kernel::Kernel;
+use crate::process::ProcessId;
+use crate::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use crate::syscall_driver::{CommandReturn, SyscallDriver};
+use crate::ErrorCode;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10001;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Package name of the process to discover.
+    pub(super) const SEARCH: usize = 0;
+    /// Message to send.
+    pub(super) const SEND: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub(super) const COUNT: usize = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer that received messages are copied into.
+    pub(super) const RECEIVE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub(super) const COUNT: usize = 1;
+}
+
+/// Upcall for received messages.
+const UPCALL_RECEIVED: usize = 0;
+
+/// A message waiting in a mailbox.
+#[derive(Clone, Copy)]
+struct Message<const MSG_LEN: usize> {
+    /// Identifier of the process that sent the message.
+    sender: usize,
+    len: usize,
+    data: [u8; MSG_LEN],
+}
+
+/// Queue of received messages that is stored in each process's grant region.
+struct Mailbox<const MSG_LEN: usize, const QUEUE_LEN: usize> {
+    messages: [Message<MSG_LEN>; QUEUE_LEN],
+    /// Index of the oldest message.
+    head: usize,
+    /// Number of queued messages.
+    count: usize,
+}
+
+impl<const MSG_LEN: usize, const QUEUE_LEN: usize> Default for Mailbox<MSG_LEN, QUEUE_LEN> {
+    fn default() -> Self {
+        Mailbox {
+            messages: [Message {
+                sender: 0,
+                len: 0,
+                data: [0; MSG_LEN],
+            }; QUEUE_LEN],
+            head: 0,
+            count: 0,
+        }
+    }
+}
+
+impl<const MSG_LEN: usize, const QUEUE_LEN: usize> Mailbox<MSG_LEN, QUEUE_LEN> {
+    fn push(&mut self, message: Message<MSG_LEN>) -> Result<(), ErrorCode> {
+        if self.count == QUEUE_LEN {
+            return Err(ErrorCode::BUSY);
+        }
+        self.messages[(self.head + self.count) % QUEUE_LEN] = message;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Message<MSG_LEN>> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(&self.messages[self.head])
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.count > 0 {
+            self.head = (self.head + 1) % QUEUE_LEN;
+            self.count -= 1;
+        }
+    }
+}
+
+/// The message IPC mechanism struct.
+pub struct IpcMailbox<const MSG_LEN: usize, const QUEUE_LEN: usize> {
+    /// The grant regions for each process that hold the mailboxes.
+    data: Grant<
+        Mailbox<MSG_LEN, QUEUE_LEN>,
+        UpcallCount<1>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+}
+
+impl<const MSG_LEN: usize, const QUEUE_LEN: usize> IpcMailbox<MSG_LEN, QUEUE_LEN> {
+    pub fn new(
+        kernel: &'static Kernel,
+        driver_num: usize,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> Self {
+        Self {
+            data: kernel.create_grant(driver_num, capability),
+        }
+    }
+
+    /// Find the process with the package name shared by `processid`.
+    fn discover(&self, processid: ProcessId) -> CommandReturn {
+        self.data
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEARCH)
+                    .and_then(|search| {
+                        search.enter(|name| {
+                            self.data
+                                .kernel
+                                .process_until(|p| {
+                                    let s = p.get_process_name().as_bytes();
+                                    if s.len() == name.len()
+                                        && s.iter().zip(name.iter()).all(|(c1, c2)| *c1 == c2.get())
+                                    {
+                                        Some(CommandReturn::success_u32(p.processid().id() as u32))
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .unwrap_or(CommandReturn::failure(ErrorCode::NODEVICE))
+                        })
+                    })
+                    .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
+            })
+            .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM))
+    }
+
+    /// Copy the message shared by `sender` into the mailbox of the process
+    /// with identifier `target_id`.
+    fn send(&self, sender: ProcessId, target_id: usize) -> Result<(), ErrorCode> {
+        let receiver = self
+            .data
+            .kernel
+            .process_until(|p| {
+                if p.processid().id() == target_id {
+                    Some(p.processid())
+                } else {
+                    None
+                }
+            })
+            .ok_or(ErrorCode::INVAL)?;
+
+        // Copy the message out of the sender's grant first, so that only one
+        // grant is entered at a time. This also makes sending to yourself
+        // work.
+        let mut message = Message {
+            sender: sender.id(),
+            len: 0,
+            data: [0; MSG_LEN],
+        };
+        self.data
+            .enter(sender, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEND)
+                    .map_err(ErrorCode::from)
+                    .and_then(|buffer| {
+                        buffer
+                            .enter(|data| {
+                                if data.len() > MSG_LEN {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                data.copy_to_slice(&mut message.data[..data.len()]);
+                                message.len = data.len();
+                                Ok(())
+                            })
+                            .unwrap_or(Err(ErrorCode::INVAL))
+                    })
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))?;
+
+        self.data
+            .enter(receiver, |mailbox, kernel_data| {
+                mailbox.push(message)?;
+                // The message stays queued even if the upcall cannot be
+                // scheduled, the receiver can still find it with the pending
+                // command.
+                let _ = kernel_data.schedule_upcall(
+                    UPCALL_RECEIVED,
+                    (message.sender, message.len, mailbox.count),
+                );
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))
+    }
+
+    /// Copy the oldest message in the mailbox of `processid` into its receive
+    /// buffer and remove it from the mailbox.
+    fn receive(&self, processid: ProcessId) -> CommandReturn {
+        self.data
+            .enter(processid, |mailbox, kernel_data| {
+                let message = match mailbox.peek() {
+                    Some(message) => *message,
+                    None => return CommandReturn::failure(ErrorCode::FAIL),
+                };
+                let copied = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .map_err(ErrorCode::from)
+                    .and_then(|buffer| {
+                        buffer
+                            .mut_enter(|data| {
+                                if data.len() < message.len {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                data[..message.len].copy_from_slice(&message.data[..message.len]);
+                                Ok(())
+                            })
+                            .unwrap_or(Err(ErrorCode::INVAL))
+                    });
+                match copied {
+                    Ok(()) => {
+                        mailbox.pop();
+                        CommandReturn::success_u32_u32(message.sender as u32, message.len as u32)
+                    }
+                    // The message stays queued so it can be received with a
+                    // larger buffer.
+                    Err(e) => CommandReturn::failure(e),
+                }
+            })
+            .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM))
+    }
+}
+
+impl<const MSG_LEN: usize, const QUEUE_LEN: usize> SyscallDriver
+    for IpcMailbox<MSG_LEN, QUEUE_LEN>
+{
+    /// Discover processes and send and receive messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Perform discovery on the package name passed to `allow_readonly`
+    ///        0. Returns the process identifier of the process if it is found,
+    ///        otherwise returns `NODEVICE`.
+    /// - `2`: Send the message passed to `allow_readonly` 1 to the process
+    ///        with identifier `target_id`. Returns `INVAL` if that process
+    ///        does not exist, `SIZE` if the message is longer than `MSG_LEN`
+    ///        and `BUSY` if the mailbox of the receiver is full.
+    /// - `3`: Receive the oldest message in the mailbox into the buffer
+    ///        passed to `allow_readwrite` 0. Returns the identifier of the
+    ///        sender and the length of the message, `FAIL` if the mailbox is
+    ///        empty, or `SIZE` if the buffer is too small for the message.
+    /// - `4`: Returns the number of messages in the mailbox.
+    fn command(
+        &self,
+        command_number: usize,
+        target_id: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => self.discover(processid),
+            2 => self.send(processid, target_id).into(),
+            3 => self.receive(processid),
+            4 => self
+                .data
+                .enter(processid, |mailbox, _| {
+                    CommandReturn::success_u32(mailbox.count as u32)
+                })
+                .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM)),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), crate::process::Error> {
+        self.data.enter(processid, |_, _| {})
+    }
+}
@@ -13,24 +13,27 @@ use crate::config;
 use crate::debug;
 use crate::dynamic_deferred_call::DynamicDeferredCall;
 use crate::errorcode::ErrorCode;
-use crate::grant::{AllowRoSize, AllowRwSize, Grant, UpcallSize};
+use crate::grant::{AllowRoSize, AllowRwSize, Grant, GrantQuota, UpcallSize};
 use crate::ipc;
 use crate::memop;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::MPU;
 use crate::platform::platform::ContextSwitchCallback;
 use crate::platform::platform::KernelResources;
-use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter};
+use crate::platform::platform::{
+    ProcessCpuBudget, ProcessFault, SyscallDriverLookup, SyscallFilter, Tracer,
+};
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::process::ProcessId;
 use crate::process::{self, Task};
 use crate::scheduler::{Scheduler, SchedulingDecision};
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
-use crate::syscall::{Syscall, YieldCall};
+use crate::syscall::{Syscall, SyscallRateLimit, SyscallRateLimitAction, YieldCall};
 use crate::syscall_driver::CommandReturn;
+use crate::trace::TraceEvent;
 use crate::upcall::{Upcall, UpcallId};
-use crate::utilities::cells::NumericCellExt;
+use crate::utilities::cells::{NumericCellExt, OptionalCell};
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -43,8 +46,9 @@ pub struct Kernel {
     /// outstanding upcalls and processes in the Running state.
     work: Cell<usize>,
 
-    /// This holds a pointer to the static array of Process pointers.
-    processes: &'static [Option<&'static dyn process::Process>],
+    /// This holds a pointer to the static array of Process pointers. The
+    /// slots are cells so that processes can be added while the kernel runs.
+    processes: &'static [process::ProcessSlot],
 
     /// A counter which keeps track of how many process identifiers have been
     /// created. This is used to create new unique identifiers for processes.
@@ -60,6 +64,23 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// The address immediately after the end of the memory set aside for
+    /// processes. Processes with growable memory can grow into unused memory
+    /// up to this address.
+    app_memory_end: Cell<usize>,
+
+    /// Limits on how much grant memory a single process can use for a
+    /// driver. Drivers without an entry are only limited by the size of the
+    /// grant region.
+    grant_quotas: Cell<&'static [GrantQuota]>,
+
+    /// Drivers whose upcalls replace a pending upcall with the same upcall ID
+    /// instead of being queued behind it.
+    coalesced_upcall_drivers: Cell<&'static [usize]>,
+
+    /// Limit on the number of system calls a process can make per timeslice.
+    syscall_rate_limit: OptionalCell<SyscallRateLimit>,
 }
 
 /// Enum used to inform scheduler why a process stopped executing (aka why
@@ -117,13 +138,17 @@ fn try_allocate_grant<KR: KernelResources<C>, C: Chip>(
 }
 
 impl Kernel {
-    pub fn new(processes: &'static [Option<&'static dyn process::Process>]) -> Kernel {
+    pub fn new(processes: &'static [process::ProcessSlot]) -> Kernel {
         Kernel {
             work: Cell::new(0),
             processes,
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            app_memory_end: Cell::new(0),
+            grant_quotas: Cell::new(&[]),
+            coalesced_upcall_drivers: Cell::new(&[]),
+            syscall_rate_limit: OptionalCell::empty(),
         }
     }
 
@@ -180,17 +205,21 @@ impl Kernel {
         // However, we are not guaranteed that the app still exists at that
         // index in the processes array. To avoid additional overhead, we do the
         // lookup and check here, rather than calling `.index()`.
-        match self.processes.get(processid.index) {
-            Some(Some(process)) => {
+        match self
+            .processes
+            .get(processid.index)
+            .and_then(|slot| slot.get())
+        {
+            Some(process) => {
                 // Check that the process stored here matches the identifier
                 // in the `appid`.
                 if process.processid() == processid {
-                    Some(*process)
+                    Some(process)
                 } else {
                     None
                 }
             }
-            _ => None,
+            None => None,
         }
     }
 
@@ -251,9 +280,9 @@ impl Kernel {
         F: FnMut(&dyn process::Process),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -264,17 +293,38 @@ impl Kernel {
     pub(crate) fn get_process_iter(
         &self,
     ) -> core::iter::FilterMap<
-        core::slice::Iter<Option<&dyn process::Process>>,
-        fn(&Option<&'static dyn process::Process>) -> Option<&'static dyn process::Process>,
+        core::slice::Iter<process::ProcessSlot>,
+        fn(&process::ProcessSlot) -> Option<&'static dyn process::Process>,
     > {
-        fn keep_some(
-            &x: &Option<&'static dyn process::Process>,
-        ) -> Option<&'static dyn process::Process> {
-            x
+        fn keep_some(x: &process::ProcessSlot) -> Option<&'static dyn process::Process> {
+            x.get()
         }
         self.processes.iter().filter_map(keep_some)
     }
 
+    /// Returns the index of the first empty slot in the processes array, or
+    /// `None` if every slot holds a process.
+    pub(crate) fn empty_process_slot(&self) -> Option<usize> {
+        self.processes.iter().position(|slot| slot.get().is_none())
+    }
+
+    /// Place `process` in the slot at `index` of the processes array, which
+    /// must be empty, so that it is scheduled from now on.
+    pub(crate) fn add_process(
+        &self,
+        index: usize,
+        process: &'static dyn process::Process,
+    ) -> Result<(), ErrorCode> {
+        match self.processes.get(index) {
+            Some(slot) if slot.get().is_none() => {
+                slot.set(Some(process));
+                Ok(())
+            }
+            Some(_) => Err(ErrorCode::BUSY),
+            None => Err(ErrorCode::INVAL),
+        }
+    }
+
     /// Run a closure on every valid process. This will iterate the array of
     /// processes and call the closure on every process that exists.
     ///
@@ -289,9 +339,9 @@ impl Kernel {
         F: FnMut(&dyn process::Process),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(p);
                 }
                 None => {}
             }
@@ -306,9 +356,9 @@ impl Kernel {
         F: Fn(&dyn process::Process) -> Option<T>,
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    let ret = closure(*p);
+                    let ret = closure(p);
                     if ret.is_some() {
                         return ret;
                     }
@@ -327,7 +377,8 @@ impl Kernel {
     /// verify that the referenced app is still at the correct index.
     pub(crate) fn processid_is_valid(&self, appid: &ProcessId) -> bool {
         self.processes.get(appid.index).map_or(false, |p| {
-            p.map_or(false, |process| process.processid().id() == appid.id())
+            p.get()
+                .map_or(false, |process| process.processid().id() == appid.id())
         })
     }
 
@@ -400,6 +451,86 @@ impl Kernel {
         self.process_identifier_max.get_and_increment()
     }
 
+    /// Record that the memory set aside for processes ends at
+    /// `app_memory_end`.
+    pub(crate) fn set_app_memory_end(&self, app_memory_end: usize) {
+        if app_memory_end > self.app_memory_end.get() {
+            self.app_memory_end.set(app_memory_end);
+        }
+    }
+
+    /// Returns the end of the unused app memory that starts at `address`,
+    /// which is where the memory of the next process starts, or the end of
+    /// app memory if there is no process after `address`.
+    ///
+    /// The result is at most `address` if there is no unused memory there.
+    pub(crate) fn unused_app_memory_end(&self, address: usize) -> usize {
+        self.get_process_iter()
+            .map(|process| process.get_addresses().sram_block_start)
+            .filter(|&start| start >= address)
+            .fold(self.app_memory_end.get(), core::cmp::min)
+    }
+
+    /// Limit how much grant memory each process can use for a driver.
+    ///
+    /// Once a process has allocated the number of bytes of a quota for a
+    /// driver, through its grant or custom grants allocated while it is
+    /// entered, further allocations for that driver fail as if the process
+    /// was out of grant memory, and syscalls to the driver return `NOMEM`.
+    /// This keeps one driver from using up the whole grant region of a
+    /// process. The quotas apply to allocations made after this call.
+    pub fn set_grant_quotas(
+        &self,
+        quotas: &'static [GrantQuota],
+        _capability: &dyn capabilities::MemoryAllocationCapability,
+    ) {
+        self.grant_quotas.set(quotas);
+    }
+
+    /// Returns the maximum number of grant bytes a process can use for the
+    /// driver `driver_num`, if there is a quota for it.
+    pub(crate) fn grant_quota(&self, driver_num: usize) -> Option<usize> {
+        self.grant_quotas
+            .get()
+            .iter()
+            .find(|quota| quota.driver_num == driver_num)
+            .map(|quota| quota.max_bytes)
+    }
+
+    /// Coalesce the upcalls of the drivers `driver_nums`.
+    ///
+    /// An upcall of one of these drivers that is scheduled while the process
+    /// still has an upcall with the same upcall ID pending replaces the
+    /// pending upcall, keeping its place in the queue, instead of being queued
+    /// behind it. This suits drivers whose upcalls report the latest state,
+    /// such as a sensor reading, where only the newest one matters, and keeps
+    /// a busy driver from filling the upcall queue of a process.
+    pub fn set_coalesced_upcall_drivers(
+        &self,
+        driver_nums: &'static [usize],
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.coalesced_upcall_drivers.set(driver_nums);
+    }
+
+    /// Returns whether upcalls of the driver `driver_num` are coalesced.
+    pub(crate) fn coalesces_upcalls(&self, driver_num: usize) -> bool {
+        self.coalesced_upcall_drivers.get().contains(&driver_num)
+    }
+
+    /// Limit how many system calls a process can make in one timeslice, or
+    /// remove the limit with `None`.
+    ///
+    /// Processes that run without a timeslice are limited per run, which
+    /// lasts until they yield with no upcalls pending.
+    pub fn set_syscall_rate_limit(
+        &self,
+        limit: Option<SyscallRateLimit>,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.syscall_rate_limit.insert(limit);
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -412,7 +543,7 @@ impl Kernel {
     /// apps.
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
-            p.map(|process| {
+            p.get().map(|process| {
                 process.set_fault_state();
             });
         }
@@ -455,7 +586,8 @@ impl Kernel {
                     // Execute kernel work. This includes handling
                     // interrupts and is how code in the chips/ and capsules
                     // crates is able to execute.
-                    scheduler.execute_kernel_work(chip);
+                    scheduler.execute_kernel_work(chip, resources.tracer());
+                    resources.tracer().trace(TraceEvent::KernelWorkDone);
                 }
                 false => {
                     // No kernel work ready, so ask scheduler for a process.
@@ -576,6 +708,10 @@ impl Kernel {
         // inform the scheduler.
         let mut return_reason = StoppedExecutingReason::NoWorkLeft;
 
+        // Number of system calls the process made in this timeslice, for the
+        // syscall rate limit.
+        let mut syscall_count = 0;
+
         // Since the timeslice counts both the process's execution time and the
         // time spent in the kernel on behalf of the process (setting it up and
         // handling its syscalls), we intend to keep running the process until
@@ -590,6 +726,9 @@ impl Kernel {
             if stop_running {
                 // Process ran out of time while the kernel was executing.
                 process.debug_timeslice_expired();
+                resources.tracer().trace(TraceEvent::TimesliceExpired {
+                    processid: process.processid(),
+                });
                 return_reason = StoppedExecutingReason::TimesliceExpired;
                 break;
             }
@@ -620,20 +759,42 @@ impl Kernel {
                     // process. Arming the scheduler timer instructs it to
                     // generate an interrupt when the timeslice has expired. The
                     // underlying timer is not affected.
+                    let mut rate_limited = false;
                     resources
                         .context_switch_callback()
                         .context_switch_hook(process);
                     process.setup_mpu();
                     chip.mpu().enable_app_mpu();
+                    let remaining_before = scheduler_timer.get_remaining_us();
+                    resources.tracer().trace(TraceEvent::ProcessRun {
+                        processid: process.processid(),
+                    });
                     scheduler_timer.arm();
                     let context_switch_reason = process.switch_to();
                     scheduler_timer.disarm();
+                    // This may only be read once: if it returns `None` the
+                    // timeslice has expired.
+                    let remaining_after = scheduler_timer.get_remaining_us();
                     chip.mpu().disable_app_mpu();
 
+                    // Charge the process for the time it spent executing. With
+                    // the dummy scheduler timer the process runs cooperatively
+                    // and no time can be measured.
+                    let time_used_us = match (timeslice_us, remaining_before) {
+                        (Some(_), Some(before)) => {
+                            Some(before.saturating_sub(remaining_after.unwrap_or(0)))
+                        }
+                        _ => None,
+                    };
+                    time_used_us.map(|us| process.debug_cpu_time_used(us));
+
                     // Now the process has returned back to the kernel. Check
                     // why and handle the process as appropriate.
                     match context_switch_reason {
                         Some(ContextSwitchReason::Fault) => {
+                            resources.tracer().trace(TraceEvent::ProcessFaulted {
+                                processid: process.processid(),
+                            });
                             // The app faulted, check if the chip wants to
                             // handle the fault.
                             if resources
@@ -646,20 +807,32 @@ impl Kernel {
                             }
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
-                            self.handle_syscall(resources, process, syscall);
+                            resources.tracer().trace(TraceEvent::Syscall {
+                                processid: process.processid(),
+                                syscall,
+                            });
+                            syscall_count += 1;
+                            let limit_action = self
+                                .syscall_rate_limit
+                                .extract()
+                                .filter(|limit| syscall_count > limit.max_syscalls)
+                                .map(|limit| limit.action);
+                            self.handle_syscall(
+                                resources,
+                                process,
+                                syscall,
+                                limit_action == Some(SyscallRateLimitAction::Throttle),
+                            );
+                            rate_limited = limit_action == Some(SyscallRateLimitAction::Yield);
                         }
                         Some(ContextSwitchReason::Interrupted) => {
-                            if scheduler_timer.get_remaining_us().is_none() {
-                                // This interrupt was a timeslice expiration.
-                                process.debug_timeslice_expired();
-                                return_reason = StoppedExecutingReason::TimesliceExpired;
-                                break;
-                            }
+                            resources.tracer().trace(TraceEvent::ProcessInterrupted {
+                                processid: process.processid(),
+                            });
                             // Go to the beginning of loop to determine whether
                             // to break to handle the interrupt, continue
                             // executing this process, or switch to another
                             // process.
-                            continue;
                         }
                         None => {
                             // Something went wrong when switching to this
@@ -668,6 +841,30 @@ impl Kernel {
                             process.set_fault_state();
                         }
                     }
+
+                    // Let the board enforce its CPU time budget now that the
+                    // process is back in a consistent state.
+                    time_used_us
+                        .map(|us| resources.process_cpu_budget().cpu_time_used(process, us));
+
+                    if remaining_after.is_none() {
+                        // The timeslice expired while the process was
+                        // executing.
+                        process.debug_timeslice_expired();
+                        resources.tracer().trace(TraceEvent::TimesliceExpired {
+                            processid: process.processid(),
+                        });
+                        return_reason = StoppedExecutingReason::TimesliceExpired;
+                        break;
+                    }
+
+                    if rate_limited {
+                        // The process made more system calls than its limit
+                        // allows, so let the scheduler move on as if its
+                        // timeslice had expired.
+                        return_reason = StoppedExecutingReason::TimesliceExpired;
+                        break;
+                    }
                 }
                 process::State::Yielded | process::State::Unstarted => {
                     // If the process is yielded or hasn't been started it is
@@ -688,6 +885,10 @@ impl Kernel {
                                         ccb.argument3,
                                     );
                                 }
+                                resources.tracer().trace(TraceEvent::Upcall {
+                                    processid: process.processid(),
+                                    source: ccb.source,
+                                });
                                 process.set_process_function(ccb);
                             }
                             Task::IPC((otherapp, ipc_type)) => {
@@ -727,6 +928,11 @@ impl Kernel {
                     return_reason = StoppedExecutingReason::Stopped;
                     break;
                 }
+                process::State::CredentialsUnchecked | process::State::CredentialsFailed => {
+                    // Processes whose credentials have not been approved are
+                    // never ready, so they should never be scheduled.
+                    panic!("Attempted to schedule an unapproved process");
+                }
             }
         }
 
@@ -765,6 +971,7 @@ impl Kernel {
         resources: &KR,
         process: &dyn process::Process,
         syscall: Syscall,
+        throttled: bool,
     ) {
         // Hook for process debugging.
         process.debug_syscall_called(syscall);
@@ -789,14 +996,16 @@ impl Kernel {
                 which: _,
                 completion_code: _,
             } => {} // Exit is not filterable.
-            Syscall::Memop {
-                operand: _,
-                arg0: _,
-            } => {} // Memop is not filterable.
             _ => {
-                // Check all other syscalls for filtering.
-                if let Err(response) = resources.syscall_filter().filter_syscall(process, &syscall)
-                {
+                // Check all other syscalls, including memop, for filtering.
+                // A process over its syscall rate limit has them rejected as
+                // if they were filtered.
+                let filtered = if throttled {
+                    Err(ErrorCode::BUSY)
+                } else {
+                    resources.syscall_filter().filter_syscall(process, &syscall)
+                };
+                if let Err(response) = filtered {
                     process.set_syscall_return_value(SyscallReturn::Failure(response));
 
                     if config::CONFIG.trace_syscalls {
@@ -96,6 +96,8 @@ pub const MINOR: u16 = 0;
 pub mod capabilities;
 pub mod collections;
 pub mod component;
+pub mod cpu_budget;
+pub mod crash_record;
 pub mod debug;
 pub mod deferred_call;
 pub mod dynamic_deferred_call;
@@ -104,15 +106,23 @@ pub mod grant;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod ipc_mailbox;
 pub mod platform;
 pub mod process;
+pub mod process_checker;
 pub mod processbuffer;
 pub mod scheduler;
+pub mod shared_memory;
+pub mod storage_permissions;
 pub mod syscall;
+pub mod syscall_filter;
+pub mod trace;
 pub mod upcall;
 pub mod utilities;
+pub mod watchdog_supervisor;
 
 mod config;
+mod dynamic_process_loader;
 mod kernel;
 mod memop;
 mod process_policies;
@@ -120,6 +130,8 @@ mod process_printer;
 mod process_standard;
 mod process_utilities;
 mod syscall_driver;
+#[cfg(test)]
+mod test_support;
 
 // Core resources exposed as `kernel::Type`.
 pub use crate::errorcode::ErrorCode;
@@ -1,6 +1,7 @@
 //! Interfaces for implementing microcontrollers in Tock.
 
 use crate::platform::mpu;
+use crate::platform::platform::Tracer;
 use crate::syscall;
 use core::fmt::Write;
 
@@ -28,7 +29,10 @@ pub trait Chip {
     /// This function should loop internally until all interrupts have been
     /// handled. It is ok, however, if an interrupt occurs after the last check
     /// but before this function returns. The kernel will handle this edge case.
-    fn service_pending_interrupts(&self);
+    ///
+    /// Before dispatching each interrupt the chip should report it to `tracer`
+    /// as a `TraceEvent::Interrupt` with its interrupt number.
+    fn service_pending_interrupts(&self, tracer: &dyn Tracer);
 
     /// Ask the chip to check if there are any pending interrupts.
     fn has_pending_interrupts(&self) -> bool;
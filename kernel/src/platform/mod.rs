@@ -11,7 +11,9 @@ pub(crate) mod platform;
 
 pub use self::platform::ContextSwitchCallback;
 pub use self::platform::KernelResources;
+pub use self::platform::ProcessCpuBudget;
 pub use self::platform::ProcessFault;
 pub use self::platform::SyscallDriverLookup;
 pub use self::platform::SyscallFilter;
 pub use self::platform::TbfHeaderFilterDefaultAllow;
+pub use self::platform::Tracer;
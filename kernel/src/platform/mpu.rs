@@ -175,6 +175,35 @@ pub trait MPU {
         Ok(())
     }
 
+    /// Allocates a guard region that user mode cannot access at all.
+    ///
+    /// An implementation must allocate an MPU region at least `min_region_size`
+    /// bytes in size that starts at or after `memory_start`, store it in
+    /// `config`, and deny all user mode accesses to it. Unlike other regions,
+    /// the guard region lies inside of the app memory region, and must take
+    /// precedence over it.
+    ///
+    /// # Arguments
+    ///
+    /// - `memory_start`:    lowest address the guard region may start at
+    /// - `min_region_size`: minimum size of the region
+    /// - `config`:          MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the start and size of the allocated guard region. If the MPU
+    /// does not support guard regions or it is infeasible to allocate one,
+    /// returns None.
+    #[allow(unused_variables)]
+    fn allocate_guard_region(
+        &self,
+        memory_start: *const u8,
+        min_region_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
     /// Chooses the location for a process's memory, and allocates an MPU region
     /// covering the app-owned part.
     ///
@@ -8,6 +8,7 @@ use crate::process;
 use crate::scheduler::Scheduler;
 use crate::syscall;
 use crate::syscall_driver::SyscallDriver;
+use crate::trace::TraceEvent;
 use tock_tbf::types::CommandPermissions;
 
 /// Combination trait that boards provide to the kernel that includes all of
@@ -38,6 +39,13 @@ pub trait KernelResources<C: Chip> {
     /// to applications.
     type SchedulerTimer: scheduler_timer::SchedulerTimer;
 
+    /// The implementation of the CPU time budget the kernel enforces on
+    /// processes.
+    type ProcessCpuBudget: ProcessCpuBudget;
+
+    /// The implementation of the tracer that records kernel events.
+    type Tracer: Tracer;
+
     /// The implementation of the WatchDog timer used to monitor the running
     /// of the kernel.
     type WatchDog: watchdog::WatchDog;
@@ -62,6 +70,14 @@ pub trait KernelResources<C: Chip> {
     /// for this platform.
     fn scheduler_timer(&self) -> &Self::SchedulerTimer;
 
+    /// Returns a reference to the implementation of the ProcessCpuBudget on
+    /// this platform.
+    fn process_cpu_budget(&self) -> &Self::ProcessCpuBudget;
+
+    /// Returns a reference to the implementation of the Tracer on this
+    /// platform.
+    fn tracer(&self) -> &Self::Tracer;
+
     /// Returns a reference to the implementation of the WatchDog on this
     /// platform.
     fn watchdog(&self) -> &Self::WatchDog;
@@ -115,11 +131,16 @@ pub trait SyscallDriverLookup {
 /// Trait for implementing system call filters that the kernel uses to decide
 /// whether to handle a specific system call or not.
 pub trait SyscallFilter {
-    /// Check the platform-provided system call filter for all non-yield system
-    /// calls. If the system call is allowed for the provided process then
-    /// return `Ok(())`. Otherwise, return `Err()` with an `ErrorCode` that will
-    /// be returned to the calling application. The default implementation
-    /// allows all system calls.
+    /// Check the platform-provided system call filter for all system calls
+    /// other than yield and exit. If the system call is allowed for the
+    /// provided process then return `Ok(())`. Otherwise, return `Err()` with an
+    /// `ErrorCode` that will be returned to the calling application. The
+    /// default implementation allows all system calls.
+    ///
+    /// Memop system calls are filtered too, so filters that deny system calls
+    /// they do not recognize also deny memop. Processes need memop to, for
+    /// example, move their heap break, so such filters should allow the memop
+    /// operations processes use.
     ///
     /// This API should be considered unstable, and is likely to change in the
     /// future.
@@ -281,3 +302,35 @@ pub trait ContextSwitchCallback {
 impl ContextSwitchCallback for () {
     fn context_switch_hook(&self, _process: &dyn process::Process) {}
 }
+
+/// Trait for implementing limits on how much CPU time processes may use.
+pub trait ProcessCpuBudget {
+    /// This function is called every time a process returns to the kernel,
+    /// with the time in microseconds the process just spent executing.
+    ///
+    /// The implementation can keep track of how much CPU time each process
+    /// uses, and stop or fault processes that use more than they are allowed.
+    /// Time is only measured when the scheduler runs processes with a
+    /// timeslice.
+    fn cpu_time_used(&self, process: &dyn process::Process, time_us: u32);
+}
+
+/// Implement default ProcessCpuBudget trait for unit, which does not limit
+/// processes.
+impl ProcessCpuBudget for () {
+    fn cpu_time_used(&self, _process: &dyn process::Process, _time_us: u32) {}
+}
+
+/// Trait for recording what the kernel does, such as switching to processes,
+/// system calls and handling interrupts, for example to analyze latency.
+pub trait Tracer {
+    /// This function is called when `event` happens. It is called from the
+    /// hot paths of the kernel, so implementations must be fast and must not
+    /// block or print.
+    fn trace(&self, event: TraceEvent);
+}
+
+/// Implement default Tracer trait for unit, which does not record anything.
+impl Tracer for () {
+    fn trace(&self, _event: TraceEvent) {}
+}
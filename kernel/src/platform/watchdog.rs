@@ -4,6 +4,9 @@
 /// This trait is called from the `kernel_loop()` code to setup
 /// and maintain the watchdog timer.
 /// It is up to the specific `Chip` how it will handle watchdog interrupts.
+/// `kernel::watchdog_supervisor::WatchdogSupervisor` implements this trait
+/// on top of a hardware watchdog to also check that the kernel makes
+/// progress.
 pub trait WatchDog {
     /// This function must enable the watchdog timer and configure it to
     /// trigger regulary. The period of the timer is left to the implementation
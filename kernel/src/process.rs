@@ -3,6 +3,7 @@
 use core::cell::Cell;
 use core::fmt;
 use core::fmt::Write;
+use core::num::NonZeroU32;
 use core::ptr::NonNull;
 use core::str;
 
@@ -12,18 +13,69 @@ use crate::ipc;
 use crate::kernel::Kernel;
 use crate::platform::mpu::{self};
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn};
 use crate::upcall::UpcallId;
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials, TbfHeaderV2RealTime};
 
 // Export all process related types via `kernel::process::`.
+pub use crate::dynamic_process_loader::{DynamicProcessLoader, DynamicProcessLoading};
 pub use crate::process_policies::{
-    PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy, StopFaultPolicy,
-    StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy, ThresholdRestartThenPanicFaultPolicy,
+    BackoffRestartFaultPolicy, PanicFaultPolicy, ProcessFaultPolicy, RestartFaultPolicy,
+    RestartGroup, StopFaultPolicy, StopWithDebugFaultPolicy, ThresholdRestartFaultPolicy,
+    ThresholdRestartThenPanicFaultPolicy,
 };
 pub use crate::process_printer::{ProcessPrinter, ProcessPrinterContext, ProcessPrinterText};
 pub use crate::process_standard::ProcessStandard;
-pub use crate::process_utilities::{load_processes, load_processes_advanced, ProcessLoadError};
+pub use crate::process_utilities::{
+    load_and_check_processes, load_processes, load_processes_advanced, ProcessLoadError,
+};
+
+/// A slot of the processes array that a board gives to the kernel.
+///
+/// The slots are cells so that the kernel can add processes to empty slots
+/// while other parts of the kernel and the schedulers hold references to the
+/// array.
+pub type ProcessSlot = Cell<Option<&'static dyn Process>>;
+
+/// An empty process slot, to initialize the processes array of a board:
+///
+/// ```rust,ignore
+/// static mut PROCESSES: [kernel::process::ProcessSlot; NUM_PROCS] =
+///     [kernel::process::EMPTY_PROCESS_SLOT; NUM_PROCS];
+/// ```
+pub const EMPTY_PROCESS_SLOT: ProcessSlot = Cell::new(None);
+
+/// Persistent identifier of the application a process runs.
+///
+/// Unlike `ProcessId`, which changes every time a process is created or
+/// restarted, an `AppId` identifies the application itself. It is the same
+/// across restarts and reboots, so capsules can use it to recognize an
+/// application that comes back, for example to give it access to data it
+/// stored earlier.
+///
+/// The kernel assigns app IDs with the board's `AppIdPolicy` (see
+/// `kernel::process_checker`) when a process's credentials are approved, and
+/// does not run two processes with the same persistent app ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppId {
+    /// The process has no persistent identity. It is only distinguishable
+    /// from other processes by its `ProcessId`.
+    LocallyUnique,
+    /// The process has this persistent 32-bit identifier.
+    Fixed(NonZeroU32),
+}
+
+impl AppId {
+    /// Returns the persistent identifier, or `None` if this app ID is only
+    /// locally unique.
+    pub fn persistent_id(&self) -> Option<NonZeroU32> {
+        match self {
+            AppId::LocallyUnique => None,
+            AppId::Fixed(id) => Some(*id),
+        }
+    }
+}
 
 /// Userspace process identifier.
 ///
@@ -157,6 +209,35 @@ impl ProcessId {
         self.identifier
     }
 
+    /// Returns the persistent `AppId` of the application this process runs.
+    ///
+    /// Returns `AppId::LocallyUnique` if the application has no persistent
+    /// identity or the process no longer exists.
+    pub fn app_id(&self) -> AppId {
+        self.kernel
+            .process_map_or(AppId::LocallyUnique, *self, |process| process.get_app_id())
+    }
+
+    /// Returns the persistent app ID of this process if it was approved with
+    /// credentials, so that the app ID is authenticated.
+    ///
+    /// Returns `None` if the process has no persistent `AppId`, was approved
+    /// without checking credentials, or no longer exists.
+    pub fn credentialed_app_id(&self) -> Option<NonZeroU32> {
+        self.kernel.process_map_or(None, *self, |process| {
+            process
+                .get_credentials()
+                .and_then(|_| process.get_app_id().persistent_id())
+        })
+    }
+
+    /// Returns the permissions of this process to access persistent storage,
+    /// or `None` if it has no access or no longer exists.
+    pub fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_storage_permissions())
+    }
+
     /// Returns the full address of the start and end of the flash region that
     /// the app owns and can write to. This includes the app's code and data and
     /// any padding at the end of the app. It does not include the TBF header,
@@ -236,9 +317,72 @@ pub trait Process {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Returns why this process last faulted, or `None` if it has never
+    /// faulted. The reason is known by the time the fault policy is asked
+    /// which `FaultAction` to take.
+    fn get_fault_reason(&self) -> Option<FaultReason>;
+
     /// Returns how many times this process has been restarted.
     fn get_restart_count(&self) -> usize;
 
+    /// Mark the credentials of this process as approved, moving it from the
+    /// `CredentialsUnchecked` state to the `Unstarted` state so that it will be
+    /// scheduled. `credentials` are the credentials that were accepted, or
+    /// `None` if the process was approved without credentials.
+    ///
+    /// Returns `Err(ErrorCode::INVAL)` and does nothing if the process is not
+    /// in the `CredentialsUnchecked` state.
+    fn mark_credentials_pass(
+        &self,
+        credentials: Option<TbfFooterV2Credentials>,
+    ) -> Result<(), ErrorCode>;
+
+    /// Mark the credentials of this process as failed, moving it from the
+    /// `CredentialsUnchecked` state to the `CredentialsFailed` state. The
+    /// process remains loaded but will never be scheduled.
+    ///
+    /// Returns `Err(ErrorCode::INVAL)` and does nothing if the process is not
+    /// in the `CredentialsUnchecked` state.
+    fn mark_credentials_fail(&self) -> Result<(), ErrorCode>;
+
+    /// Returns the credentials that were accepted when the process was
+    /// checked. Returns `None` if the process has not been checked or was
+    /// approved without credentials.
+    fn get_credentials(&self) -> Option<TbfFooterV2Credentials>;
+
+    /// Returns the persistent identifier of the application this process
+    /// runs. This is `AppId::LocallyUnique` until the kernel assigns one.
+    fn get_app_id(&self) -> AppId;
+
+    /// Set the persistent identifier of the application. This is called by the
+    /// kernel when the process is approved to run, after checking that no
+    /// other process has the same identifier.
+    fn set_app_id(&self, app_id: AppId);
+
+    /// Returns the app ID specified in the TBF `AppId` header, if any.
+    fn get_tbf_app_id(&self) -> Option<NonZeroU32>;
+
+    /// Returns the period, deadline and budget from the TBF `RealTime`
+    /// header, if the process has one. Real-time schedulers use these to
+    /// decide when the process runs.
+    fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime>;
+
+    /// Returns the permissions of the process to access persistent storage.
+    ///
+    /// These come from the TBF persistent ACL header if present, otherwise
+    /// from a persistent `AppId`. Returns `None` if the process has neither,
+    /// or if its credentials were not checked and approved, since neither the
+    /// header nor the app ID of such a process is authenticated.
+    fn get_storage_permissions(&self) -> Option<StoragePermissions>;
+
+    /// Returns the region of flash that credentials for this process cover:
+    /// the TBF header and the application binary, but not the footers.
+    fn get_integrity_region(&self) -> &'static [u8];
+
+    /// Returns the footers that follow the application binary in flash. This
+    /// slice is empty if the process has no footers.
+    fn get_footers(&self) -> &'static [u8];
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
@@ -421,8 +565,25 @@ pub trait Process {
         min_region_size: usize,
     ) -> Option<mpu::Region>;
 
+    /// Allocate an MPU region for the process that covers exactly `size`
+    /// bytes starting at `start` with user mode access `permissions`, such as
+    /// memory shared by another process.
+    ///
+    /// Returns `None` if the MPU cannot protect exactly that region, for
+    /// example because it does not meet the MPU's alignment rules, or if it
+    /// overlaps a region the process already has.
+    ///
+    /// It is not valid to call this function when the process is inactive (i.e.
+    /// the process will not run again).
+    fn add_exact_mpu_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        permissions: mpu::Permissions,
+    ) -> Option<mpu::Region>;
+
     /// Removes an MPU region from the process that has been previouly added with
-    /// `add_mpu_region`.
+    /// `add_mpu_region` or `add_exact_mpu_region`.
     ///
     /// It is not valid to call this function when the process is inactive (i.e.
     /// the process will not run again).
@@ -443,7 +604,8 @@ pub trait Process {
     /// - The process is inactive, or
     /// - There is not enough available memory to do the allocation, or
     /// - The grant_num is invalid, or
-    /// - The grant_num already has an allocated grant.
+    /// - The grant_num already has an allocated grant, or
+    /// - The allocation would exceed the board's grant quota for `driver_num`.
     fn allocate_grant(
         &self,
         grant_num: usize,
@@ -462,12 +624,16 @@ pub trait Process {
     /// aligned to `align` bytes. This is used for creating custom grants which
     /// are not recorded in the grant pointer array, but are useful for capsules
     /// which need additional process-specific dynamically allocated memory.
+    /// The memory is counted towards the grant memory of the driver that
+    /// allocated grant `grant_num`, and fails like `allocate_grant()` if that
+    /// would exceed its quota.
     ///
     /// If successful, return a Some() with an identifier that can be used with
     /// `enter_custom_grant()` to get access to the memory and the pointer to
     /// the memory which must be used to initialize the memory.
     fn allocate_custom_grant(
         &self,
+        grant_num: usize,
         size: usize,
         align: usize,
     ) -> Option<(ProcessCustomGrantIdentifer, NonNull<u8>)>;
@@ -493,6 +659,10 @@ pub trait Process {
     fn enter_custom_grant(&self, identifier: ProcessCustomGrantIdentifer)
         -> Result<*mut u8, Error>;
 
+    /// Opposite of `enter_custom_grant()`. Used to signal that the custom
+    /// grant is no longer entered, after `enter_custom_grant()` succeeded.
+    fn leave_custom_grant(&self, identifier: ProcessCustomGrantIdentifer);
+
     /// Opposite of `enter_grant()`. Used to signal that the grant is no longer
     /// entered.
     ///
@@ -514,6 +684,13 @@ pub trait Process {
     /// if there is a grant associated with that driver_num.
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error>;
 
+    /// Get the driver number, the address and the number of bytes of grant
+    /// `grant_num` if the process is active and has allocated that grant. The
+    /// number of bytes includes the custom grants allocated for the driver.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn get_grant_allocation(&self, grant_num: usize) -> Option<(usize, usize, usize)>;
+
     // subscribe
 
     /// Verify that an Upcall function pointer is within process-accessible
@@ -571,6 +748,58 @@ pub trait Process {
     /// representation. Returns `ErrorCode::FAIL` on an internal error.
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
 
+    /// Restore the stored state of the process from a binary blob written by
+    /// `get_stored_state()`, and discard any tasks queued for the process.
+    /// The process is left in the `StoppedRunning` state, and continues from
+    /// the restored state once it is resumed.
+    ///
+    /// The process must be `Unstarted` or stopped. The process RAM should be
+    /// restored with `restore_ram_snapshot()` first, since the stored state
+    /// refers to it (for example, the stack pointer).
+    ///
+    /// Returns `ErrorCode::INVAL` if the process is in any other state or
+    /// `state` is not a valid stored state for this architecture. Returns
+    /// `ErrorCode::FAIL` on an internal error.
+    fn restore_stored_state(&self, state: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Write a snapshot of the process accessible RAM (from the start of the
+    /// process memory up to the app break) into `out`. Grant memory is owned
+    /// by the kernel and is not included. Returns the number of bytes written
+    /// to `out` on success.
+    ///
+    /// Together with `get_stored_state()` this checkpoints the process, so
+    /// that it can be resumed later, for example after a reboot. Grants,
+    /// including upcalls the process subscribed to and buffers it allowed, are
+    /// not part of the checkpoint, so a restored process has to set them up
+    /// again.
+    ///
+    /// Returns `ErrorCode::SIZE` if `out` is too short to hold the snapshot.
+    /// Returns `ErrorCode::FAIL` if the process is not active.
+    fn get_ram_snapshot(&self, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Restore the process accessible RAM from a snapshot written by
+    /// `get_ram_snapshot()`, moving the app break to where it was when the
+    /// snapshot was taken.
+    ///
+    /// Process memory contains absolute addresses, so a snapshot can only be
+    /// restored into a process with the same binary loaded at the same flash
+    /// and RAM addresses. The process must be `Unstarted` or stopped.
+    ///
+    /// Returns `ErrorCode::INVAL` if the process is in any other state or the
+    /// snapshot was taken from a different process. Returns `ErrorCode::SIZE`
+    /// if the snapshot is truncated, and `ErrorCode::NOMEM` if the process
+    /// memory cannot hold it.
+    fn restore_ram_snapshot(&self, snapshot: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Copy the process accessible memory starting at `address` into `buf`,
+    /// stopping at the app break. Returns the number of bytes copied.
+    ///
+    /// Returns `ErrorCode::INVAL` if `address` is not in the memory the
+    /// process can access, and `ErrorCode::FAIL` if the process is not active.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn debug_read_memory(&self, address: usize, buf: &mut [u8]) -> Result<usize, ErrorCode>;
+
     /// Print out the full state of the process: its memory map, its
     /// context, and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
@@ -589,6 +818,20 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how much CPU time, in microseconds, this process has spent
+    /// executing. Time is only measured when the scheduler runs the process
+    /// with a timeslice.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add `time_us` microseconds to the CPU time used by this process.
+    fn debug_cpu_time_used(&self, time_us: u32);
+
+    /// Returns how many times this process has missed a deadline.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of times the process has missed a deadline.
+    fn debug_deadline_missed(&self);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -703,6 +946,15 @@ pub enum State {
     /// processes yet. It can also happen if an process is terminated and all of
     /// its state is reset as if it has not been executed yet.
     Unstarted,
+
+    /// The process has been loaded but its credentials have not been checked
+    /// yet. The process will not run until its credentials are approved, at
+    /// which point it moves to `Unstarted`.
+    CredentialsUnchecked,
+
+    /// The credentials of the process were rejected by the kernel's checking
+    /// policy. The process remains loaded but cannot be run.
+    CredentialsFailed,
 }
 
 /// A wrapper around `Cell<State>` is used by `Process` to prevent bugs arising
@@ -762,6 +1014,18 @@ pub enum FaultAction {
     Stop,
 }
 
+/// Why a process faulted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultReason {
+    /// The process's stack grew past its end, into the stack guard region or
+    /// below the start of process memory.
+    StackOverflow,
+
+    /// Any other fault, for example an access outside of the process's memory
+    /// regions or an invalid syscall.
+    Other,
+}
+
 /// Tasks that can be enqueued for a process.
 ///
 /// This is public for external implementations of `Process`.
@@ -820,6 +1084,11 @@ pub struct ProcessAddresses {
     /// process in nonvolatile memory.
     pub flash_end: usize,
 
+    /// The address of the beginning of the memory used for this process. This
+    /// is `sram_start`, unless the process has growable memory, in which case
+    /// the kernel keeps its own state for the process in front of
+    /// `sram_start`.
+    pub sram_block_start: usize,
     /// The address of the beginning of the process's allocated region in
     /// memory.
     pub sram_start: usize,
@@ -864,4 +1133,9 @@ pub struct ProcessSizes {
     /// The number of bytes used for the process control block (i.e. the
     /// `ProcessX` struct).
     pub process_control_block: usize,
+    /// The largest number of bytes of stack the process has used, if the
+    /// kernel tracks stack usage and knows where the stack starts. Unlike
+    /// `ProcessAddresses::sram_stack_bottom`, this includes stack used
+    /// between syscalls.
+    pub stack_high_water_mark: Option<usize>,
 }
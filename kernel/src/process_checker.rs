@@ -0,0 +1,627 @@
+//! Checking the credentials of processes before they are allowed to run.
+//!
+//! A TBF object can contain footers after the application binary that hold
+//! credentials for the application, such as a hash or a signature of the TBF
+//! header and binary (the integrity region). When processes are loaded with
+//! `load_and_check_processes()` they start in the `CredentialsUnchecked`
+//! state and do not run. The `ProcessCheckerMachine` then walks the footers of
+//! each unchecked process and asks an `AppCredentialsChecker` policy whether
+//! each set of credentials is acceptable.
+//!
+//! For each credentials footer the policy can:
+//!
+//! - `Accept` the credentials, which approves the process to run.
+//! - `Reject` the credentials, which prevents the process from running.
+//! - `Pass`, which makes no decision and moves on to the next footer.
+//!
+//! If every footer passes, the policy's `require_credentials()` decides
+//! whether the process runs. Processes that are rejected stay loaded in the
+//! `CredentialsFailed` state so they are visible (for example in the process
+//! console), but they are never scheduled.
+//!
+//! Application identifiers
+//! -----------------------
+//!
+//! When a process is approved, the machine also gives it a persistent `AppId`
+//! using the board's `AppIdPolicy`, if one is set. The app ID can come from
+//! the TBF `AppId` header, the package name, or the accepted credentials. A
+//! process whose persistent app ID is already used by another loaded process
+//! is rejected, so capsules can rely on an app ID naming a single application.
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::kernel::Kernel;
+use crate::process::{AppId, Process, ProcessId, State};
+use crate::utilities::cells::OptionalCell;
+use tock_tbf::types::{TbfFooterV2Credentials, TbfFooterV2CredentialsType};
+
+/// The decision of a credentials checking policy for one set of credentials.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckResult {
+    /// The credentials are valid and the process may run.
+    Accept,
+    /// The policy makes no decision based on these credentials. The next
+    /// credentials footer, if any, should be checked.
+    Pass,
+    /// The credentials are invalid and the process must not run.
+    Reject,
+}
+
+/// Client of an `AppCredentialsChecker`, called when a check completes.
+pub trait Client<'a> {
+    /// The check of `credentials` over the integrity region `binary` has
+    /// finished. An `Err` means the check could not be completed; the
+    /// `ProcessCheckerMachine` treats this the same as `CheckResult::Pass`.
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    );
+}
+
+/// A policy that decides whether the credentials of a process are acceptable.
+///
+/// Checks are asynchronous: `check_credentials()` starts a check and the
+/// result is reported through `Client::check_done()`. Implementations must
+/// not call `check_done()` from within `check_credentials()`.
+pub trait AppCredentialsChecker<'a> {
+    /// Set the client that is notified when a check finishes.
+    fn set_client(&self, client: &'a dyn Client<'a>);
+
+    /// Whether a process without any accepted credentials is allowed to run.
+    /// If this returns `true`, processes whose footers all pass (or that have
+    /// no footers) are not run.
+    fn require_credentials(&self) -> bool;
+
+    /// Start checking `credentials` against `binary`, which is the integrity
+    /// region of the process (its TBF header and application binary).
+    ///
+    /// Returns `Ok(())` if the check started and `check_done()` will be
+    /// called. Otherwise returns the error and the arguments. In particular,
+    /// `ErrorCode::NOSUPPORT` means this policy does not handle this format
+    /// of credentials.
+    fn check_credentials(
+        &self,
+        credentials: TbfFooterV2Credentials,
+        binary: &'a [u8],
+    ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'a [u8])>;
+}
+
+/// A policy that decides the persistent `AppId` of an approved process.
+pub trait AppIdPolicy {
+    /// Returns the app ID of `process`, whose credentials were approved.
+    /// `credentials` are the accepted credentials, or `None` if the process
+    /// was approved without credentials.
+    fn app_id(&self, process: &dyn Process, credentials: Option<&TbfFooterV2Credentials>) -> AppId;
+}
+
+/// Use the app ID from the TBF `AppId` header. Processes without the header
+/// have no persistent identity.
+pub struct AppIdFromTbfHeader;
+
+impl AppIdPolicy for AppIdFromTbfHeader {
+    fn app_id(&self, process: &dyn Process, _: Option<&TbfFooterV2Credentials>) -> AppId {
+        process
+            .get_tbf_app_id()
+            .map_or(AppId::LocallyUnique, AppId::Fixed)
+    }
+}
+
+/// Derive the app ID from the package name in the TBF header, so all versions
+/// of an application with the same name have the same app ID. Processes
+/// without a package name have no persistent identity.
+///
+/// Package names are not authenticated, so boards should only use this with
+/// a credentials checking policy that restricts which apps can run.
+pub struct AppIdFromPackageName;
+
+impl AppIdPolicy for AppIdFromPackageName {
+    fn app_id(&self, process: &dyn Process, _: Option<&TbfFooterV2Credentials>) -> AppId {
+        let name = process.get_process_name();
+        if name.is_empty() {
+            return AppId::LocallyUnique;
+        }
+        // 32-bit FNV-1a hash of the name.
+        let hash = name.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+        NonZeroU32::new(hash).map_or(AppId::LocallyUnique, AppId::Fixed)
+    }
+}
+
+/// Derive the app ID from the first four bytes (little endian) of the
+/// accepted credentials. With signature credentials that include the public
+/// key this ties the app ID to the signer; with hash credentials it ties the
+/// app ID to one exact build. Processes approved without credentials have no
+/// persistent identity.
+pub struct AppIdFromCredentials;
+
+impl AppIdPolicy for AppIdFromCredentials {
+    fn app_id(&self, _: &dyn Process, credentials: Option<&TbfFooterV2Credentials>) -> AppId {
+        credentials
+            .and_then(|credentials| credentials.data().get(0..4))
+            .and_then(|bytes| {
+                NonZeroU32::new(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            })
+            .map_or(AppId::LocallyUnique, AppId::Fixed)
+    }
+}
+
+/// Kernel state machine that checks the credentials of every process in the
+/// `CredentialsUnchecked` state, one credentials footer at a time.
+pub struct ProcessCheckerMachine {
+    kernel: &'static Kernel,
+    policy: OptionalCell<&'static dyn AppCredentialsChecker<'static>>,
+    app_id_policy: OptionalCell<&'static dyn AppIdPolicy>,
+    /// The process currently being checked, if a check is in progress.
+    process: OptionalCell<ProcessId>,
+    /// Offset into the footers of `process` of the next footer to check.
+    footer_offset: Cell<usize>,
+}
+
+impl ProcessCheckerMachine {
+    pub fn new(
+        kernel: &'static Kernel,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> ProcessCheckerMachine {
+        ProcessCheckerMachine {
+            kernel,
+            policy: OptionalCell::empty(),
+            app_id_policy: OptionalCell::empty(),
+            process: OptionalCell::empty(),
+            footer_offset: Cell::new(0),
+        }
+    }
+
+    /// Set the policy used to check credentials. The caller should also set
+    /// this machine as the client of the policy.
+    pub fn set_policy(&self, policy: &'static dyn AppCredentialsChecker<'static>) {
+        self.policy.replace(policy);
+    }
+
+    /// Set the policy used to assign app IDs to approved processes. Without
+    /// one, processes have no persistent identity.
+    pub fn set_app_id_policy(&self, policy: &'static dyn AppIdPolicy) {
+        self.app_id_policy.replace(policy);
+    }
+
+    /// Returns whether a process other than `processid` that is approved, or
+    /// was approved before, has the persistent app ID `app_id`.
+    fn app_id_in_use(&self, processid: ProcessId, app_id: AppId) -> bool {
+        if app_id == AppId::LocallyUnique {
+            return false;
+        }
+        self.kernel
+            .process_until(|process| {
+                let approved = !matches!(
+                    process.get_state(),
+                    State::CredentialsUnchecked | State::CredentialsFailed
+                );
+                if process.processid() != processid && approved && process.get_app_id() == app_id {
+                    Some(())
+                } else {
+                    None
+                }
+            })
+            .is_some()
+    }
+
+    /// Start checking all processes in the `CredentialsUnchecked` state.
+    ///
+    /// If a check is already in progress this does nothing: processes that
+    /// became unchecked in the meantime are picked up when that check
+    /// finishes.
+    pub fn check_all(&self) {
+        if self.process.is_none() {
+            self.next_process();
+        }
+    }
+
+    /// Find the next process to check and start on its first footer.
+    fn next_process(&self) {
+        self.process.clear();
+        let next = self.kernel.process_until(|process| {
+            if process.get_state() == State::CredentialsUnchecked {
+                Some(process.processid())
+            } else {
+                None
+            }
+        });
+        if let Some(processid) = next {
+            self.process.set(processid);
+            self.footer_offset.set(0);
+            self.next_footer();
+        }
+    }
+
+    /// Check footers of the current process until one check is started, or
+    /// until a decision can be made without the policy.
+    fn next_footer(&self) {
+        loop {
+            let processid = match self.process.extract() {
+                Some(processid) => processid,
+                None => return,
+            };
+            let policy = match self.policy.extract() {
+                Some(policy) => policy,
+                None => {
+                    // Without a policy nothing can be approved.
+                    self.decide(processid, None, false);
+                    return;
+                }
+            };
+
+            let footer = self.kernel.process_map_or(None, processid, |process| {
+                let footers = process.get_footers();
+                let remaining = footers.get(self.footer_offset.get()..)?;
+                if remaining.is_empty() {
+                    return None;
+                }
+                Some((
+                    tock_tbf::parse::parse_tbf_footer(remaining).ok()?,
+                    process.get_integrity_region(),
+                ))
+            });
+
+            match footer {
+                None => {
+                    // Out of footers (or the footers could not be parsed, in
+                    // which case the rest cannot be trusted either). No
+                    // credentials were accepted.
+                    self.decide(processid, None, !policy.require_credentials());
+                    return;
+                }
+                Some(((credentials, footer_len), binary)) => {
+                    self.footer_offset
+                        .set(self.footer_offset.get() + footer_len as usize);
+                    if credentials.format() == TbfFooterV2CredentialsType::Reserved {
+                        // Reserved space does not hold credentials.
+                        continue;
+                    }
+                    match policy.check_credentials(credentials, binary) {
+                        Ok(()) => return,
+                        Err(_) => {
+                            // The policy cannot check these credentials, so
+                            // move on to the next footer.
+                            continue;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Approve or reject the process and move on to the next one.
+    fn decide(
+        &self,
+        processid: ProcessId,
+        credentials: Option<TbfFooterV2Credentials>,
+        approve: bool,
+    ) {
+        self.kernel.process_map_or((), processid, |process| {
+            let mut approve = approve;
+            if approve {
+                let app_id = self.app_id_policy.map_or(AppId::LocallyUnique, |policy| {
+                    policy.app_id(process, credentials.as_ref())
+                });
+                if self.app_id_in_use(processid, app_id) {
+                    // Two processes with the same app ID would be
+                    // indistinguishable to capsules; keep the first one.
+                    if config::CONFIG.debug_load_processes {
+                        debug!(
+                            "Process {:?} has the same app ID ({:?}) as a loaded process",
+                            process.get_process_name(),
+                            app_id
+                        );
+                    }
+                    approve = false;
+                } else {
+                    process.set_app_id(app_id);
+                }
+            }
+            if config::CONFIG.debug_load_processes {
+                debug!(
+                    "Credentials of process {:?} {}",
+                    process.get_process_name(),
+                    if approve { "approved" } else { "rejected" }
+                );
+            }
+            let _ = if approve {
+                process.mark_credentials_pass(credentials)
+            } else {
+                process.mark_credentials_fail()
+            };
+        });
+        self.next_process();
+    }
+}
+
+impl Client<'static> for ProcessCheckerMachine {
+    fn check_done(
+        &self,
+        result: Result<CheckResult, ErrorCode>,
+        credentials: TbfFooterV2Credentials,
+        _binary: &'static [u8],
+    ) {
+        let processid = match self.process.extract() {
+            Some(processid) => processid,
+            None => return,
+        };
+        match result {
+            Ok(CheckResult::Accept) => self.decide(processid, Some(credentials), true),
+            Ok(CheckResult::Reject) => self.decide(processid, None, false),
+            Ok(CheckResult::Pass) | Err(_) => self.next_footer(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::process::{ProcessSlot, EMPTY_PROCESS_SLOT};
+    use crate::process_policies::PanicFaultPolicy;
+    use crate::process_utilities::load_and_check_processes;
+    use crate::test_support::{self, ManagementCap, FLAG_ENABLED, IMAGE_MIN_RAM_SIZE};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Length of each test image in flash.
+    const IMAGE_LEN: usize = 256;
+
+    /// A footer in a test image.
+    #[derive(Clone, Copy)]
+    enum Footer {
+        /// Reserved space for credentials.
+        Reserved,
+        /// SHA-256 credentials, which `TestPolicy` checks.
+        Sha256,
+        /// SHA-512 credentials, which `TestPolicy` cannot check.
+        Sha512,
+    }
+
+    impl Footer {
+        fn encode(self, footers: &mut Vec<u8>) {
+            let (format, len) = match self {
+                Footer::Reserved => (0u32, 8),
+                Footer::Sha256 => (3, 32),
+                Footer::Sha512 => (5, 64),
+            };
+            footers.extend_from_slice(&128u16.to_le_bytes());
+            footers.extend_from_slice(&(4 + len as u16).to_le_bytes());
+            footers.extend_from_slice(&format.to_le_bytes());
+            footers.extend(vec![0xC5; len]);
+        }
+    }
+
+    /// A policy that starts every SHA-256 check and finishes it only when
+    /// the test calls `finish()`.
+    struct TestPolicy {
+        require_credentials: bool,
+        client: OptionalCell<&'static dyn Client<'static>>,
+        pending: Cell<Option<(TbfFooterV2Credentials, &'static [u8])>>,
+        checks: Cell<usize>,
+    }
+
+    impl TestPolicy {
+        /// Finish the check in progress with `result`.
+        fn finish(&self, result: CheckResult) {
+            let (credentials, binary) = self.pending.take().expect("no check in progress");
+            self.client
+                .map(|client| client.check_done(Ok(result), credentials, binary));
+        }
+    }
+
+    impl AppCredentialsChecker<'static> for TestPolicy {
+        fn set_client(&self, client: &'static dyn Client<'static>) {
+            self.client.set(client);
+        }
+
+        fn require_credentials(&self) -> bool {
+            self.require_credentials
+        }
+
+        fn check_credentials(
+            &self,
+            credentials: TbfFooterV2Credentials,
+            binary: &'static [u8],
+        ) -> Result<(), (ErrorCode, TbfFooterV2Credentials, &'static [u8])> {
+            if credentials.format() != TbfFooterV2CredentialsType::SHA256 {
+                return Err((ErrorCode::NOSUPPORT, credentials, binary));
+            }
+            assert!(self.pending.get().is_none());
+            self.checks.set(self.checks.get() + 1);
+            self.pending.set(Some((credentials, binary)));
+            Ok(())
+        }
+    }
+
+    /// Write an image named `name` with a program header, so that it has
+    /// `footers` after its (empty) binary.
+    fn write_image(flash: &mut [u8], name: &str, footers: &[Footer]) {
+        let binary_end = 36 + ((name.len() + 3) & !3) + 24;
+        let mut program = Vec::new();
+        program.extend_from_slice(&9u16.to_le_bytes());
+        program.extend_from_slice(&20u16.to_le_bytes());
+        for word in [binary_end, 0, IMAGE_MIN_RAM_SIZE, binary_end, 0] {
+            program.extend_from_slice(&(word as u32).to_le_bytes());
+        }
+        test_support::write_image_with_tlvs(flash, FLAG_ENABLED, name, &program);
+
+        let mut encoded = Vec::new();
+        for footer in footers {
+            footer.encode(&mut encoded);
+        }
+        flash[binary_end..binary_end + encoded.len()].copy_from_slice(&encoded);
+    }
+
+    /// Load one process per entry of `images`, each with a name and footers,
+    /// and start checking them with `policy`.
+    fn check(
+        images: &[(&str, &[Footer])],
+        policy: &'static TestPolicy,
+        app_id_policy: Option<&'static dyn AppIdPolicy>,
+    ) -> Vec<&'static dyn Process> {
+        test_support::debug_writer();
+        let procs: &'static [ProcessSlot] =
+            Box::leak(vec![EMPTY_PROCESS_SLOT; images.len()].into_boxed_slice());
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(procs)));
+        let flash = Box::leak(vec![0u8; (images.len() + 1) * IMAGE_LEN].into_boxed_slice());
+        for (image, (name, footers)) in flash.chunks_mut(IMAGE_LEN).zip(images.iter()) {
+            write_image(image, name, footers);
+        }
+
+        let machine: &'static ProcessCheckerMachine =
+            Box::leak(Box::new(ProcessCheckerMachine::new(kernel, &ManagementCap)));
+        machine.set_policy(policy);
+        policy.set_client(machine);
+        if let Some(app_id_policy) = app_id_policy {
+            machine.set_app_id_policy(app_id_policy);
+        }
+        load_and_check_processes(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(32768),
+            procs,
+            &PanicFaultPolicy {},
+            false,
+            machine,
+            &ManagementCap,
+        )
+        .unwrap();
+
+        procs
+            .iter()
+            .map(|slot| slot.get().expect("process not loaded"))
+            .collect()
+    }
+
+    fn policy(require_credentials: bool) -> &'static TestPolicy {
+        Box::leak(Box::new(TestPolicy {
+            require_credentials,
+            client: OptionalCell::empty(),
+            pending: Cell::new(None),
+            checks: Cell::new(0),
+        }))
+    }
+
+    fn approved(process: &dyn Process) -> bool {
+        process.get_state() == State::Unstarted
+    }
+
+    fn failed(process: &dyn Process) -> bool {
+        process.get_state() == State::CredentialsFailed
+    }
+
+    #[test]
+    fn decisions() {
+        let policy = policy(true);
+        let processes = check(
+            &[
+                ("accept", &[Footer::Sha256]),
+                ("reject", &[Footer::Sha256, Footer::Sha256]),
+                (
+                    "pass",
+                    &[
+                        Footer::Reserved,
+                        Footer::Sha512,
+                        Footer::Sha256,
+                        Footer::Sha256,
+                    ],
+                ),
+                ("all-pass", &[Footer::Sha256]),
+                ("none", &[]),
+            ],
+            policy,
+            None,
+        );
+        // Processes are checked one at a time, in order.
+        assert!(processes
+            .iter()
+            .all(|process| process.get_state() == State::CredentialsUnchecked));
+
+        policy.finish(CheckResult::Accept);
+        assert!(approved(processes[0]));
+        assert!(processes[0].get_credentials().is_some());
+
+        // A rejection is final, even if there are more footers. The machine
+        // moves on to the next process right away: its reserved footer and
+        // the footer the policy cannot check are skipped, and the check of
+        // its first SHA-256 footer starts.
+        policy.finish(CheckResult::Reject);
+        assert!(failed(processes[1]));
+        assert_eq!(policy.checks.get(), 3);
+
+        // A pass moves on to the next footer.
+        assert!(processes[2].get_state() == State::CredentialsUnchecked);
+        policy.finish(CheckResult::Pass);
+        assert_eq!(policy.checks.get(), 4);
+        policy.finish(CheckResult::Accept);
+        assert!(approved(processes[2]));
+
+        // Without accepted credentials a process does not run when the
+        // policy requires credentials. A process without footers is decided
+        // without asking the policy.
+        policy.finish(CheckResult::Pass);
+        assert!(failed(processes[3]));
+        assert!(failed(processes[4]));
+        assert_eq!(policy.checks.get(), 5);
+        assert!(policy.pending.take().is_none());
+    }
+
+    #[test]
+    fn credentials_not_required() {
+        let policy = policy(false);
+        let processes = check(
+            &[
+                ("pass", &[Footer::Sha256]),
+                ("none", &[]),
+                ("reject", &[Footer::Sha256]),
+            ],
+            policy,
+            None,
+        );
+
+        policy.finish(CheckResult::Pass);
+        assert!(approved(processes[0]));
+        assert!(processes[0].get_credentials().is_none());
+        assert!(approved(processes[1]));
+        // Rejected credentials still stop a process.
+        policy.finish(CheckResult::Reject);
+        assert!(failed(processes[2]));
+    }
+
+    #[test]
+    fn duplicate_app_ids() {
+        let policy = policy(true);
+        let processes = check(
+            &[
+                ("dup", &[Footer::Sha256]),
+                ("dup", &[Footer::Sha256]),
+                ("other", &[Footer::Sha256]),
+            ],
+            policy,
+            Some(&AppIdFromPackageName),
+        );
+
+        policy.finish(CheckResult::Accept);
+        policy.finish(CheckResult::Accept);
+        policy.finish(CheckResult::Accept);
+        assert!(approved(processes[0]));
+        assert!(matches!(processes[0].get_app_id(), AppId::Fixed(_)));
+        // The second process with the same app ID is rejected even though its
+        // credentials were accepted.
+        assert!(failed(processes[1]));
+        assert!(approved(processes[2]));
+        assert!(processes[2].get_app_id() != processes[0].get_app_id());
+    }
+}
@@ -4,8 +4,13 @@
 //! kernel can use when managing processes. For example, these policies control
 //! decisions such as whether a specific process should be restarted.
 
+use core::cell::Cell;
+
+use crate::hil::time::{self, ConvertTicks, Ticks};
+use crate::kernel::Kernel;
 use crate::process;
-use crate::process::Process;
+use crate::process::{Process, ProcessId, State};
+use crate::utilities::cells::OptionalCell;
 
 /// Generic trait for implementing a policy on what to do when a process faults.
 ///
@@ -105,3 +110,251 @@ impl ProcessFaultPolicy for ThresholdRestartThenPanicFaultPolicy {
         }
     }
 }
+
+/// A group of processes that are restarted together.
+///
+/// When the `service` process faults and is restarted, every running or
+/// faulted `dependents` process is restarted along with it. This is useful for IPC
+/// services: clients of a service lose their connection to it when it
+/// restarts, and the simplest way for them to recover is to start over and
+/// discover the new instance of the service. Processes are matched by their
+/// package name.
+pub struct RestartGroup {
+    pub service: &'static str,
+    pub dependents: &'static [&'static str],
+}
+
+/// Restart bookkeeping for one slot of the processes array.
+struct RestartState<T: Ticks> {
+    /// How many times in a row the process faulted soon after a restart.
+    consecutive_faults: Cell<u32>,
+    /// When the process was last restarted.
+    last_restart: OptionalCell<T>,
+    /// Start of the current rate limiting window, and how many restarts
+    /// happened in it.
+    window_start: Cell<T>,
+    restarts_in_window: Cell<usize>,
+    /// The faulted process waiting to be restarted, when it was scheduled,
+    /// and how long after that it restarts.
+    pending: OptionalCell<(ProcessId, T, T)>,
+}
+
+/// Implementation of `ProcessFaultPolicy` that restarts faulted processes
+/// after a delay, so that a process that keeps faulting cannot starve the
+/// rest of the system.
+///
+/// - A faulted process is stopped and restarted after `initial_delay_ms`.
+///   Every time it faults again within `max_delay_ms` of being restarted the
+///   delay doubles, up to `max_delay_ms`. A process that ran for longer than
+///   that before faulting starts over with the initial delay.
+/// - If a process faults more than `max_restarts` times within a window of
+///   `window_ms`, it is not restarted again and stays faulted.
+/// - When a process that is the service of a `RestartGroup` is restarted,
+///   its running and faulted dependents are restarted as well. Dependents
+///   that were stopped stay stopped.
+///
+/// Usage
+/// -----
+///
+/// ```rust,ignore
+/// static RESTART_GROUPS: [kernel::process::RestartGroup; 1] =
+///     [kernel::process::RestartGroup {
+///         service: "sensor_service",
+///         dependents: &["logger", "display"],
+///     }];
+///
+/// let restart_alarm = static_init!(
+///     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+///     VirtualMuxAlarm::new(mux_alarm)
+/// );
+/// restart_alarm.setup();
+/// let fault_policy = static_init!(
+///     kernel::process::BackoffRestartFaultPolicy<
+///         'static,
+///         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+///         NUM_PROCS,
+///     >,
+///     kernel::process::BackoffRestartFaultPolicy::new(
+///         board_kernel,
+///         restart_alarm,
+///         100,   // first restart after 100 ms
+///         10000, // back off to at most 10 s
+///         5,     // at most 5 restarts...
+///         60000, // ...per minute
+///         &RESTART_GROUPS,
+///     )
+/// );
+/// restart_alarm.set_alarm_client(fault_policy);
+/// ```
+pub struct BackoffRestartFaultPolicy<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> {
+    kernel: &'static Kernel,
+    alarm: &'a A,
+    initial_delay_ms: u32,
+    max_delay_ms: u32,
+    max_restarts: usize,
+    window_ms: u32,
+    groups: &'static [RestartGroup],
+    states: [RestartState<A::Ticks>; NUM_PROCS],
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> BackoffRestartFaultPolicy<'a, A, NUM_PROCS> {
+    pub fn new(
+        kernel: &'static Kernel,
+        alarm: &'a A,
+        initial_delay_ms: u32,
+        max_delay_ms: u32,
+        max_restarts: usize,
+        window_ms: u32,
+        groups: &'static [RestartGroup],
+    ) -> BackoffRestartFaultPolicy<'a, A, NUM_PROCS> {
+        BackoffRestartFaultPolicy {
+            kernel,
+            alarm,
+            initial_delay_ms,
+            max_delay_ms,
+            max_restarts,
+            window_ms,
+            groups,
+            states: [(); NUM_PROCS].map(|_| RestartState {
+                consecutive_faults: Cell::new(0),
+                last_restart: OptionalCell::empty(),
+                window_start: Cell::new(A::Ticks::from(0)),
+                restarts_in_window: Cell::new(0),
+                pending: OptionalCell::empty(),
+            }),
+        }
+    }
+
+    /// Arm the alarm for the earliest pending restart, if there is one.
+    fn arm_for_next_restart(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .states
+            .iter()
+            .filter_map(|state| state.pending.extract())
+            .map(|(_, scheduled, delay)| {
+                let elapsed = now.wrapping_sub(scheduled);
+                if elapsed >= delay {
+                    A::Ticks::from(0)
+                } else {
+                    delay.wrapping_sub(elapsed)
+                }
+            })
+            .min();
+        match next {
+            Some(remaining) => self.alarm.set_alarm(now, remaining),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Restart `processid` if it is still waiting to be restarted, and then
+    /// the dependents in its restart groups that are running or faulted.
+    /// Dependents that were stopped on purpose stay stopped.
+    fn restart(&self, processid: ProcessId) {
+        let now = self.alarm.now();
+        let name = self.kernel.process_map_or(None, processid, |process| {
+            if process.get_state() != State::Faulted {
+                return None;
+            }
+            process.try_restart(None);
+            Some(process.get_process_name())
+        });
+        let name = match name {
+            Some(name) => name,
+            None => return,
+        };
+        self.states[processid.index].last_restart.set(now);
+
+        for group in self.groups.iter().filter(|group| group.service == name) {
+            self.kernel.process_each(|process| {
+                let restartable = matches!(
+                    process.get_state(),
+                    State::Running | State::Yielded | State::Faulted
+                );
+                if restartable && group.dependents.contains(&process.get_process_name()) {
+                    if let Some(state) = self.states.get(process.processid().index) {
+                        state.pending.clear();
+                        state.last_restart.set(now);
+                    }
+                    process.try_restart(None);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> ProcessFaultPolicy
+    for BackoffRestartFaultPolicy<'a, A, NUM_PROCS>
+{
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let state = match self.states.get(process.processid().index) {
+            Some(state) => state,
+            None => return process::FaultAction::Stop,
+        };
+        let now = self.alarm.now();
+
+        // Limit how often the process can be restarted.
+        if now.wrapping_sub(state.window_start.get()) >= self.alarm.ticks_from_ms(self.window_ms) {
+            state.window_start.set(now);
+            state.restarts_in_window.set(0);
+        }
+        if state.restarts_in_window.get() >= self.max_restarts {
+            crate::debug!(
+                "Process {} faulted too often and was stopped.",
+                process.get_process_name()
+            );
+            return process::FaultAction::Stop;
+        }
+        state
+            .restarts_in_window
+            .set(state.restarts_in_window.get() + 1);
+
+        // Back off exponentially while the process keeps faulting soon after
+        // it is restarted.
+        let ran_long_enough = state.last_restart.map_or(true, |last_restart| {
+            now.wrapping_sub(*last_restart) >= self.alarm.ticks_from_ms(self.max_delay_ms)
+        });
+        if ran_long_enough {
+            state.consecutive_faults.set(0);
+        }
+        let delay_ms = self
+            .initial_delay_ms
+            .checked_shl(state.consecutive_faults.get())
+            .map_or(self.max_delay_ms, |delay_ms| {
+                core::cmp::min(delay_ms, self.max_delay_ms)
+            });
+        state
+            .consecutive_faults
+            .set(state.consecutive_faults.get().saturating_add(1));
+
+        state
+            .pending
+            .set((process.processid(), now, self.alarm.ticks_from_ms(delay_ms)));
+        self.arm_for_next_restart();
+
+        // Leave the process faulted until the alarm restarts it.
+        process::FaultAction::Stop
+    }
+}
+
+impl<'a, A: time::Alarm<'a>, const NUM_PROCS: usize> time::AlarmClient
+    for BackoffRestartFaultPolicy<'a, A, NUM_PROCS>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for state in self.states.iter() {
+            let due = state.pending.map_or(false, |(_, scheduled, delay)| {
+                now.wrapping_sub(*scheduled) >= *delay
+            });
+            if due {
+                state
+                    .pending
+                    .take()
+                    .map(|(processid, _, _)| self.restart(processid));
+            }
+        }
+        self.arm_for_next_restart();
+    }
+}
@@ -107,6 +107,8 @@ impl ProcessPrinter for ProcessPrinterText {
         let syscall_count = process.debug_syscall_count();
         let dropped_upcall_count = process.debug_dropped_upcall_count();
         let restart_count = process.get_restart_count();
+        let deadline_miss_count = process.debug_deadline_miss_count();
+        let cpu_time_us = process.debug_cpu_time_us();
 
         let addresses = process.get_addresses();
         let sizes = process.get_sizes();
@@ -124,7 +126,8 @@ impl ProcessPrinter for ProcessPrinterText {
             "\
                  𝐀𝐩𝐩: {}   -   [{:?}]\
                  \r\n Events Queued: {}   Syscall Count: {}   Dropped Upcall Count: {}\
-                 \r\n Restart Count: {}\
+                 \r\n Restart Count: {}   Deadline Misses: {}\
+                 \r\n CPU Time: {} us\
                  \r\n",
             process.get_process_name(),
             process.get_state(),
@@ -132,6 +135,8 @@ impl ProcessPrinter for ProcessPrinterText {
             syscall_count,
             dropped_upcall_count,
             restart_count,
+            deadline_miss_count,
+            cpu_time_us,
         ));
 
         let _ = match process.debug_syscall_last() {
@@ -147,6 +152,10 @@ impl ProcessPrinter for ProcessPrinterText {
             None => bww.write_str(" Completion Code: None\r\n"),
         };
 
+        if let Some(reason) = process.get_fault_reason() {
+            let _ = bww.write_fmt(format_args!(" Last Fault: {:?}\r\n", reason));
+        }
+
         let _ = bww.write_fmt(format_args!(
             "\
                  \r\n\
@@ -225,7 +234,11 @@ impl ProcessPrinter for ProcessPrinterText {
         if !bww.bytes_remaining() {
             match (addresses.sram_stack_top, addresses.sram_stack_bottom) {
                 (Some(sram_stack_top), Some(sram_stack_bottom)) => {
-                    let sram_stack_size = sram_stack_top - sram_stack_bottom;
+                    // The high-water mark also counts stack used between
+                    // syscalls, so prefer it if the kernel tracks it.
+                    let sram_stack_size = sizes
+                        .stack_high_water_mark
+                        .unwrap_or(sram_stack_top - sram_stack_bottom);
                     let sram_stack_allocated = sram_stack_top - addresses.sram_start;
 
                     let _ = bww.write_fmt(format_args!(
@@ -6,6 +6,7 @@
 use core::cell::Cell;
 use core::cmp;
 use core::fmt::Write;
+use core::num::NonZeroU32;
 use core::ptr::NonNull;
 use core::{mem, ptr, slice, str};
 
@@ -17,16 +18,18 @@ use crate::errorcode::ErrorCode;
 use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
+use crate::process::{AppId, ProcessAddresses, ProcessSizes};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
-use crate::process::{FaultAction, ProcessCustomGrantIdentifer, ProcessId, ProcessStateCell};
-use crate::process::{ProcessAddresses, ProcessSizes};
+use crate::process::{FaultAction, FaultReason, ProcessCustomGrantIdentifer};
+use crate::process::{ProcessId, ProcessStateCell};
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_utilities::ProcessLoadError;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
+use crate::storage_permissions::StoragePermissions;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
 use crate::upcall::UpcallId;
 use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfFooterV2Credentials, TbfHeaderV2RealTime};
 
 /// State for helping with debugging apps.
 ///
@@ -67,6 +70,13 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How much CPU time the process has used, in microseconds.
+    cpu_time_us: u64,
+
+    /// How many periods of a real-time process ended with work of that
+    /// period still not done.
+    deadline_miss_count: usize,
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -88,6 +98,11 @@ struct GrantPointerEntry {
     /// The start of the memory location where the grant has been allocated, or
     /// null if the grant has not been allocated.
     grant_ptr: *mut u8,
+
+    /// The number of grant bytes allocated for the driver, which is the size
+    /// of the grant plus the size of the custom grants allocated while it was
+    /// entered.
+    size: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -134,13 +149,22 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     ///  ╚═ ╘════════ ← memory_start            ═╝
     /// ```
     ///
+    /// Processes with growable memory keep the grant pointers, the upcall
+    /// queue and the process control block in front of `memory_start`
+    /// instead, so only the grant regions are at the end of their memory.
+    ///
     /// The start of process memory. We store this as a pointer and length and
     /// not a slice due to Rust aliasing rules. If we were to store a slice,
     /// then any time another slice to the same memory or an ProcessBuffer is
     /// used in the kernel would be undefined behavior.
     memory_start: *const u8,
-    /// Number of bytes of memory allocated to this process.
-    memory_len: usize,
+    /// Number of bytes of memory allocated to this process. This grows when a
+    /// process with growable memory runs out of memory.
+    memory_len: Cell<usize>,
+    /// Number of bytes of memory allocated to this process when it was
+    /// loaded. The memory shrinks back to this size when the process
+    /// restarts.
+    initial_memory_len: usize,
 
     /// Reference to the slice of `GrantPointerEntry`s stored in the process's
     /// memory reserved for the kernel. These driver numbers are zero and
@@ -154,6 +178,10 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
+    /// Number of custom grants of this process that are entered. The grant
+    /// region cannot move while this is not zero.
+    custom_grants_entered: Cell<usize>,
+
     /// Pointer to the end of process RAM that has been sbrk'd to the process.
     app_break: Cell<*const u8>,
 
@@ -189,8 +217,20 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Configuration data for the MPU
     mpu_config: MapCell<<<C as Chip>::MPU as MPU>::MpuConfig>,
 
-    /// MPU regions are saved as a pointer-size pair.
-    mpu_regions: [Cell<Option<mpu::Region>>; 6],
+    /// MPU regions are saved as a pointer-size pair, along with the
+    /// permissions they were allocated with.
+    mpu_regions: [Cell<Option<(mpu::Region, mpu::Permissions)>>; 6],
+
+    /// The MPU guard region at the end of the process's stack, if the kernel
+    /// added one.
+    stack_guard: Cell<Option<mpu::Region>>,
+
+    /// Whether the process overflowed its stack when it last faulted while
+    /// running, until the fault is handled.
+    stack_overflowed: Cell<bool>,
+
+    /// Why the process last faulted, if it ever did.
+    fault_reason: OptionalCell<FaultReason>,
 
     /// Essentially a list of upcalls that want to call functions in the
     /// process.
@@ -214,6 +254,20 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Name of the app.
     process_name: &'static str,
 
+    /// Whether the credentials of this process have been approved, either by
+    /// a credentials checking policy or because the process was loaded without
+    /// checking. A process that has not been approved is never started or
+    /// restarted.
+    credentials_approved: Cell<bool>,
+
+    /// The credentials that were accepted when the process was checked, if
+    /// any.
+    credentials: OptionalCell<TbfFooterV2Credentials>,
+
+    /// Persistent identifier of the application. Unlike `process_id` this is
+    /// kept when the process restarts.
+    app_id: Cell<AppId>,
+
     /// Values kept so that we can print useful debug messages when apps fault.
     debug: MapCell<ProcessStandardDebug>,
 }
@@ -231,10 +285,31 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         }
 
         let ret = self.tasks.map_or(Err(ErrorCode::FAIL), |tasks| {
+            // An upcall from a driver that coalesces its upcalls replaces a
+            // pending upcall with the same ID, which is then no new work.
+            if let Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Driver(upcall_id),
+                ..
+            }) = task
+            {
+                if self.kernel.coalesces_upcalls(upcall_id.driver_num) {
+                    let replaced = tasks.replace_first(task, |pending| match pending {
+                        Task::FunctionCall(function_call) => match function_call.source {
+                            FunctionCallSource::Driver(id) => id == upcall_id,
+                            FunctionCallSource::Kernel => false,
+                        },
+                        _ => false,
+                    });
+                    if replaced.is_some() {
+                        return Ok(false);
+                    }
+                }
+            }
+
             match tasks.enqueue(task) {
                 true => {
                     // The task has been successfully enqueued.
-                    Ok(())
+                    Ok(true)
                 }
                 false => {
                     // The task could not be enqueued as there is
@@ -244,8 +319,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             }
         });
 
-        if ret.is_ok() {
-            self.kernel.increment_work();
+        if let Ok(new_task) = ret {
+            if new_task {
+                self.kernel.increment_work();
+            }
         } else {
             // On any error we were unable to enqueue the task. Record the
             // error, but importantly do _not_ increment kernel work.
@@ -254,7 +331,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             });
         }
 
-        ret
+        ret.map(|_| ())
     }
 
     fn ready(&self) -> bool {
@@ -321,6 +398,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn set_fault_state(&self) {
+        // Faults detected while the process was running say whether the stack
+        // overflowed, any other fault has a different reason.
+        let reason = if self.stack_overflowed.replace(false) {
+            FaultReason::StackOverflow
+        } else {
+            FaultReason::Other
+        };
+        self.fault_reason.set(reason);
+
         // Use the per-process fault policy to determine what action the kernel
         // should take since the process faulted.
         let action = self.fault_policy.action(self);
@@ -329,7 +415,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             FaultAction::Panic => {
                 // process faulted. Panic and print status
                 self.state.update(State::Faulted);
-                panic!("Process {} had a fault", self.process_name);
+                match reason {
+                    FaultReason::StackOverflow => {
+                        panic!("Process {} overflowed its stack", self.process_name)
+                    }
+                    FaultReason::Other => panic!("Process {} had a fault", self.process_name),
+                }
             }
             FaultAction::Restart => {
                 self.try_restart(None);
@@ -346,7 +437,17 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         }
     }
 
+    fn get_fault_reason(&self) -> Option<FaultReason> {
+        self.fault_reason.extract()
+    }
+
     fn try_restart(&self, completion_code: Option<u32>) {
+        // A process whose credentials were never approved must not be
+        // started, so leave it as it is.
+        if !self.credentials_approved.get() {
+            return;
+        }
+
         // Terminate the process, freeing its state and removing any
         // pending tasks from the scheduler's queue.
         self.terminate(completion_code);
@@ -388,6 +489,92 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.restart_count.get()
     }
 
+    fn mark_credentials_pass(
+        &self,
+        credentials: Option<TbfFooterV2Credentials>,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::CredentialsUnchecked {
+            return Err(ErrorCode::INVAL);
+        }
+        self.credentials.insert(credentials);
+        self.credentials_approved.set(true);
+
+        // Now that the process is approved, queue its init function so the
+        // scheduler starts it.
+        let init_fn = unsafe {
+            self.flash_start()
+                .offset(self.header.get_init_function_offset() as isize) as usize
+        };
+        self.state.update(State::Unstarted);
+        self.enqueue_init_task(init_fn);
+        Ok(())
+    }
+
+    fn mark_credentials_fail(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::CredentialsUnchecked {
+            return Err(ErrorCode::INVAL);
+        }
+        self.state.update(State::CredentialsFailed);
+        Ok(())
+    }
+
+    fn get_credentials(&self) -> Option<TbfFooterV2Credentials> {
+        self.credentials.extract()
+    }
+
+    fn get_app_id(&self) -> AppId {
+        self.app_id.get()
+    }
+
+    fn set_app_id(&self, app_id: AppId) {
+        self.app_id.set(app_id);
+    }
+
+    fn get_tbf_app_id(&self) -> Option<NonZeroU32> {
+        self.header.get_fixed_app_id()
+    }
+
+    fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime> {
+        self.header.get_real_time_parameters()
+    }
+
+    fn get_storage_permissions(&self) -> Option<StoragePermissions> {
+        // Nothing authenticates the TBF header or the app ID of a process
+        // that was approved without credentials, so it could claim the
+        // storage of any other app.
+        if self.credentials.is_none() {
+            return None;
+        }
+        match self.header.get_storage_write_id() {
+            Some(write_id) => {
+                let (read_count, read_ids) =
+                    self.header.get_storage_read_ids().unwrap_or((0, [0; 8]));
+                let (modify_count, modify_ids) =
+                    self.header.get_storage_modify_ids().unwrap_or((0, [0; 8]));
+                // A write id of 0 means the app may not write.
+                let write_id = if write_id == 0 { None } else { Some(write_id) };
+                Some(StoragePermissions::new(
+                    write_id,
+                    read_count,
+                    read_ids,
+                    modify_count,
+                    modify_ids,
+                ))
+            }
+            None => StoragePermissions::for_app_id(self.app_id.get()),
+        }
+    }
+
+    fn get_integrity_region(&self) -> &'static [u8] {
+        let binary_end = self.header.get_binary_end() as usize;
+        self.flash.get(0..binary_end).unwrap_or(self.flash)
+    }
+
+    fn get_footers(&self) -> &'static [u8] {
+        let binary_end = self.header.get_binary_end() as usize;
+        self.flash.get(binary_end..).unwrap_or(&[])
+    }
+
     fn has_tasks(&self) -> bool {
         self.tasks.map_or(false, |tasks| tasks.has_elements())
     }
@@ -426,6 +613,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 // value we had could be entirely wrong by now.
                 debug.app_stack_min_pointer = Some(stack_pointer);
             });
+
+            // The process has set up its stack, which ends at the start of
+            // its memory, so the guard can go there without getting in the way
+            // of the stack the process started with.
+            if config::CONFIG.process_stack_guard && self.stack_guard.get().is_none() {
+                self.add_stack_guard(stack_pointer);
+            }
         }
     }
 
@@ -464,7 +658,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
             for region in self.mpu_regions.iter() {
                 if region.get().is_none() {
-                    region.set(new_region);
+                    region.set(new_region.map(|r| (r, mpu::Permissions::ReadWriteOnly)));
                     return new_region;
                 }
             }
@@ -474,13 +668,44 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         })
     }
 
+    fn add_exact_mpu_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        permissions: mpu::Permissions,
+    ) -> Option<mpu::Region> {
+        // Make sure there is room to remember the region before changing the
+        // MPU configuration.
+        let slot = self.mpu_regions.iter().find(|r| r.get().is_none())?;
+        self.mpu_config.and_then(|mut config| {
+            let new_region =
+                self.chip
+                    .mpu()
+                    .allocate_region(start, size, size, permissions, &mut config)?;
+
+            // The MPU is free to move and grow the region to meet its
+            // alignment rules, but then it would cover memory that is not
+            // meant to be accessible.
+            if new_region != mpu::Region::new(start, size) {
+                let _ = self
+                    .chip
+                    .mpu()
+                    .remove_memory_region(new_region, &mut config);
+                return None;
+            }
+
+            slot.set(Some((new_region, permissions)));
+            Some(new_region)
+        })
+    }
+
     fn remove_mpu_region(&self, region: mpu::Region) -> Result<(), ErrorCode> {
         self.mpu_config.map_or(Err(ErrorCode::INVAL), |mut config| {
             // Find the existing mpu region that we are removing; it needs to match exactly.
             if let Some(internal_region) = self
                 .mpu_regions
                 .iter()
-                .find(|r| r.get().map_or(false, |r| r == region))
+                .find(|r| r.get().map_or(false, |(r, _)| r == region))
             {
                 self.chip
                     .mpu()
@@ -512,6 +737,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return Err(Error::InactiveApp);
         }
 
+        // A process with growable memory gets more memory instead of running
+        // out.
+        let kernel_memory_break = self.kernel_memory_break.get();
+        if new_break > kernel_memory_break && Self::memory_growable(&self.header) {
+            let _ = self.grow_memory(new_break as usize - kernel_memory_break as usize);
+        }
+
         self.mpu_config
             .map_or(Err(Error::KernelError), |mut config| {
                 if new_break < self.allow_high_water_mark.get() || new_break >= self.mem_end() {
@@ -740,6 +972,11 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return None;
         }
 
+        // Respect the board's limit on grant memory for the driver.
+        if !self.grant_quota_allows(driver_num, size) {
+            return None;
+        }
+
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
         if let Some(grant_ptr) = self.allocate_in_grant_region_internal(size, align) {
@@ -753,6 +990,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                         // Actually set the driver num and grant pointer.
                         grant_entry.driver_num = driver_num;
                         grant_entry.grant_ptr = grant_ptr.as_ptr() as *mut u8;
+                        grant_entry.size = size;
 
                         // If all of this worked, return the allocated pointer.
                         Some(grant_ptr)
@@ -766,6 +1004,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
 
     fn allocate_custom_grant(
         &self,
+        grant_num: usize,
         size: usize,
         align: usize,
     ) -> Option<(ProcessCustomGrantIdentifer, NonNull<u8>)> {
@@ -774,9 +1013,27 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             return None;
         }
 
+        // The memory is counted towards the driver of the grant, which must
+        // be allocated, and must stay within the driver's quota.
+        let (driver_num, used) = self.grant_pointers.map_or(None, |grant_pointers| {
+            grant_pointers
+                .get(grant_num)
+                .filter(|grant_entry| !grant_entry.grant_ptr.is_null())
+                .map(|grant_entry| (grant_entry.driver_num, grant_entry.size))
+        })?;
+        if !self.grant_quota_allows(driver_num, used.checked_add(size)?) {
+            return None;
+        }
+
         // Use the shared grant allocator function to actually allocate memory.
         // Returns `None` if the allocation cannot be created.
         if let Some(ptr) = self.allocate_in_grant_region_internal(size, align) {
+            self.grant_pointers.map(|grant_pointers| {
+                if let Some(grant_entry) = grant_pointers.get_mut(grant_num) {
+                    grant_entry.size += size;
+                }
+            });
+
             // Create the identifier that the caller will use to get access to
             // this custom grant in the future.
             let identifier = self.create_custom_grant_identifier(ptr);
@@ -840,11 +1097,20 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         // Get the address of the custom grant based on the identifier.
         let custom_grant_address = self.get_custom_grant_address(identifier);
 
+        // The grant region must not move while the custom grant is entered.
+        self.custom_grants_entered
+            .set(self.custom_grants_entered.get() + 1);
+
         // We never deallocate custom grants and only we can change the
         // `identifier` so we know this is a valid address for the custom grant.
         Ok(custom_grant_address as *mut u8)
     }
 
+    fn leave_custom_grant(&self, _identifier: ProcessCustomGrantIdentifer) {
+        self.custom_grants_entered
+            .set(self.custom_grants_entered.get().saturating_sub(1));
+    }
+
     fn leave_grant(&self, grant_num: usize) {
         // Do not modify an inactive process.
         if !self.is_active() {
@@ -902,6 +1168,29 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             })
     }
 
+    fn get_grant_allocation(&self, grant_num: usize) -> Option<(usize, usize, usize)> {
+        if !self.is_active() {
+            return None;
+        }
+
+        self.grant_pointers.map_or(None, |grant_pointers| {
+            // Implement `grant_pointers[grant_num]` without a chance of a
+            // panic.
+            grant_pointers.get(grant_num).and_then(|grant_entry| {
+                if grant_entry.grant_ptr.is_null() {
+                    None
+                } else {
+                    // Do not report whether the grant is entered.
+                    Some((
+                        grant_entry.driver_num,
+                        grant_entry.grant_ptr as usize & !0x1,
+                        grant_entry.size,
+                    ))
+                }
+            })
+        })
+    }
+
     fn is_valid_upcall_function_pointer(&self, upcall_fn: NonNull<()>) -> bool {
         let ptr = upcall_fn.as_ptr() as *const u8;
         let size = mem::size_of::<*const u8>();
@@ -1021,6 +1310,13 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
                 }
             });
 
+        // A fault with the stack pointer below the end of the stack is a stack
+        // overflow.
+        if let Some(syscall::ContextSwitchReason::Fault) = switch_reason {
+            self.stack_overflowed
+                .set(stack_pointer.map_or(false, |sp| sp < self.stack_limit()));
+        }
+
         // If the UKB implementation passed us a stack pointer, update our
         // debugging state. This is completely optional.
         stack_pointer.map(|sp| {
@@ -1058,6 +1354,22 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_cpu_time_used(&self, time_us: u32) {
+        self.debug.map(|debug| debug.cpu_time_us += time_us as u64);
+    }
+
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -1074,6 +1386,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             flash_start: self.flash_start() as usize,
             flash_non_protected_start: self.flash_non_protected_start() as usize,
             flash_end: self.flash_end() as usize,
+            sram_block_start: self.memory_block_start() as usize,
             sram_start: self.mem_start() as usize,
             sram_app_brk: self.app_memory_break() as usize,
             sram_grant_start: self.kernel_memory_break() as usize,
@@ -1094,8 +1407,9 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         ProcessSizes {
             grant_pointers: mem::size_of::<GrantPointerEntry>()
                 * self.kernel.get_grant_count_and_finalize(),
-            upcall_list: Self::CALLBACKS_OFFSET,
+            upcall_list: Self::upcall_queue_size(&self.header),
             process_control_block: Self::PROCESS_STRUCT_OFFSET,
+            stack_high_water_mark: self.stack_high_water_mark(),
         }
     }
 
@@ -1200,16 +1514,196 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             })
             .unwrap_or(Err(ErrorCode::FAIL))
     }
+
+    fn restore_stored_state(&self, state: &[u8]) -> Result<(), ErrorCode> {
+        if !self.can_restore() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.stored_state
+            .map_or(Err(ErrorCode::FAIL), |stored_state| {
+                self.chip
+                    .userspace_kernel_boundary()
+                    .restore_context(stored_state, state)
+            })
+            .map_err(|_| ErrorCode::INVAL)?;
+
+        // Tasks that were queued for the process, such as the call to the
+        // init function of a process that was just loaded, belong to the
+        // state we just replaced.
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+
+        self.state.update(State::StoppedRunning);
+        Ok(())
+    }
+
+    fn get_ram_snapshot(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        if !self.is_active() {
+            return Err(ErrorCode::FAIL);
+        }
+        let ram_len = self.app_break.get() as usize - self.memory_start as usize;
+        let out = out
+            .get_mut(..Self::RAM_SNAPSHOT_HEADER_LEN + ram_len)
+            .ok_or(ErrorCode::SIZE)?;
+        let (header, data) = out.split_at_mut(Self::RAM_SNAPSHOT_HEADER_LEN);
+
+        let fields = [
+            u32::from_le_bytes(Self::RAM_SNAPSHOT_TAG),
+            Self::RAM_SNAPSHOT_VERSION,
+            self.memory_start as u32,
+            self.flash_start() as u32,
+            self.header.get_binary_version(),
+            ram_len as u32,
+        ];
+        for (chunk, field) in header.chunks_exact_mut(4).zip(fields.iter()) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+
+        // ### Safety
+        //
+        // The memory from `memory_start` to `app_break` belongs to this
+        // process, and the process is not executing while the kernel is.
+        let ram = unsafe { slice::from_raw_parts(self.memory_start, ram_len) };
+        data.copy_from_slice(ram);
+        Ok(out.len())
+    }
+
+    fn debug_read_memory(&self, address: usize, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        if !self.is_active() {
+            return Err(ErrorCode::FAIL);
+        }
+        let start = self.memory_start as usize;
+        let end = self.app_break.get() as usize;
+        if address < start || address >= end {
+            return Err(ErrorCode::INVAL);
+        }
+        let len = cmp::min(buf.len(), end - address);
+
+        // ### Safety
+        //
+        // `address..address + len` is within the memory from `memory_start`
+        // to `app_break`, which belongs to this process, and the process is
+        // not executing while the kernel is.
+        let memory = unsafe { slice::from_raw_parts(address as *const u8, len) };
+        buf[..len].copy_from_slice(memory);
+        Ok(len)
+    }
+
+    fn restore_ram_snapshot(&self, snapshot: &[u8]) -> Result<(), ErrorCode> {
+        if !self.can_restore() {
+            return Err(ErrorCode::INVAL);
+        }
+        let header = snapshot
+            .get(..Self::RAM_SNAPSHOT_HEADER_LEN)
+            .ok_or(ErrorCode::SIZE)?;
+        let mut fields = header
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        let expected = [
+            u32::from_le_bytes(Self::RAM_SNAPSHOT_TAG),
+            Self::RAM_SNAPSHOT_VERSION,
+            self.memory_start as u32,
+            self.flash_start() as u32,
+            self.header.get_binary_version(),
+        ];
+        if !expected.iter().all(|field| fields.next() == Some(*field)) {
+            return Err(ErrorCode::INVAL);
+        }
+        let ram_len = fields.next().ok_or(ErrorCode::SIZE)? as usize;
+        let data = &snapshot[Self::RAM_SNAPSHOT_HEADER_LEN..];
+        if data.len() != ram_len {
+            return Err(ErrorCode::SIZE);
+        }
+
+        self.brk(self.memory_start.wrapping_add(ram_len))
+            .map_err(|_| ErrorCode::NOMEM)?;
+
+        // ### Safety
+        //
+        // `brk()` succeeded, so the memory from `memory_start` to
+        // `memory_start + ram_len` is accessible to this process and not used
+        // by the kernel. The process is not running.
+        let ram = unsafe { slice::from_raw_parts_mut(self.memory_start as *mut u8, ram_len) };
+        ram.copy_from_slice(data);
+        Ok(())
+    }
 }
 
 impl<C: 'static + Chip> ProcessStandard<'_, C> {
-    // Memory offset for upcall ring buffer (10 element length).
-    const CALLBACK_LEN: usize = 10;
-    const CALLBACKS_OFFSET: usize = mem::size_of::<Task>() * Self::CALLBACK_LEN;
+    // Length of the upcall ring buffer of processes without an `UpcallQueue`
+    // TBF header. The ring buffer holds one task less than its length.
+    const DEFAULT_UPCALL_QUEUE_LEN: usize = 10;
+    // Deepest upcall queue a process can ask for in its TBF header.
+    const MAX_UPCALL_QUEUE_DEPTH: usize = 64;
+
+    /// Length of the upcall ring buffer of the process with TBF header
+    /// `header`.
+    fn upcall_queue_len(header: &tock_tbf::types::TbfHeader) -> usize {
+        header
+            .get_upcall_queue_depth()
+            .map_or(Self::DEFAULT_UPCALL_QUEUE_LEN, |depth| {
+                cmp::min(depth as usize, Self::MAX_UPCALL_QUEUE_DEPTH) + 1
+            })
+    }
+
+    /// Memory needed for the upcall ring buffer of the process with TBF
+    /// header `header`.
+    fn upcall_queue_size(header: &tock_tbf::types::TbfHeader) -> usize {
+        mem::size_of::<Task>() * Self::upcall_queue_len(header)
+    }
+
+    /// Memory needed for the grant pointer table.
+    fn grant_ptrs_size(kernel: &Kernel) -> usize {
+        kernel.get_grant_count_and_finalize() * mem::size_of::<GrantPointerEntry>()
+    }
+
+    /// Memory needed for the kernel-owned state of a process: the grant
+    /// pointer table, the upcall queue and the process struct.
+    fn kernel_state_size(kernel: &Kernel, header: &tock_tbf::types::TbfHeader) -> usize {
+        Self::grant_ptrs_size(kernel)
+            + Self::upcall_queue_size(header)
+            + Self::PROCESS_STRUCT_OFFSET
+    }
+
+    /// Size of the kernel-owned memory at the end of process memory before
+    /// any grants are allocated. This is the kernel-owned state of the
+    /// process, except for processes with growable memory, which keep it in
+    /// front of process memory.
+    fn initial_kernel_memory_size(kernel: &Kernel, header: &tock_tbf::types::TbfHeader) -> usize {
+        if Self::memory_growable(header) {
+            0
+        } else {
+            Self::kernel_state_size(kernel, header)
+        }
+    }
+
+    /// Whether the kernel may grow the memory of a process with this header.
+    /// Processes that need their memory at a fixed address cannot grow, since
+    /// there may be no room for their kernel-owned state in front of it.
+    fn memory_growable(header: &tock_tbf::types::TbfHeader) -> bool {
+        header.memory_growable() && header.get_fixed_address_ram().is_none()
+    }
+
+    // Header of a RAM snapshot: tag, version, RAM start, flash start, binary
+    // version and RAM length, each a little endian u32.
+    const RAM_SNAPSHOT_TAG: [u8; 4] = [b'p', b'r', b'a', b'm'];
+    const RAM_SNAPSHOT_VERSION: u32 = 1;
+    const RAM_SNAPSHOT_HEADER_LEN: usize = 6 * 4;
 
     // Memory offset to make room for this process's metadata.
     const PROCESS_STRUCT_OFFSET: usize = mem::size_of::<ProcessStandard<C>>();
 
+    // Minimum size of the MPU guard region at the end of the stack.
+    const STACK_GUARD_SIZE: usize = 32;
+
+    // Value process memory is filled with to find how much stack is used.
+    const STACK_CANARY: u32 = 0xDEAD_BEEF;
+
     pub(crate) unsafe fn create<'a>(
         kernel: &'static Kernel,
         chip: &'static C,
@@ -1219,6 +1713,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         remaining_memory: &'a mut [u8],
         fault_policy: &'static dyn ProcessFaultPolicy,
         require_kernel_version: bool,
+        check_credentials: bool,
         index: usize,
     ) -> Result<(Option<&'static dyn Process>, &'a mut [u8]), ProcessLoadError> {
         // Get a slice for just the app header.
@@ -1342,15 +1837,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // enough memory just for that.
 
         // Make room for grant pointers.
-        let grant_ptr_size = mem::size_of::<GrantPointerEntry>();
         let grant_ptrs_num = kernel.get_grant_count_and_finalize();
-        let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
+        let grant_ptrs_offset = Self::grant_ptrs_size(kernel);
+
+        // The process may ask for a deeper or shallower upcall queue than
+        // the default.
+        let upcall_queue_len = Self::upcall_queue_len(&tbf_header);
+        let upcall_queue_size = Self::upcall_queue_size(&tbf_header);
 
         // Initial size of the kernel-owned part of process memory can be
         // calculated directly based on the initial size of all kernel-owned
         // data structures.
-        let initial_kernel_memory_size =
-            grant_ptrs_offset + Self::CALLBACKS_OFFSET + Self::PROCESS_STRUCT_OFFSET;
+        let initial_kernel_memory_size = Self::initial_kernel_memory_size(kernel, &tbf_header);
 
         // By default we start with the initial size of process-accessible
         // memory set to 0. This maximizes the flexibility that processes have
@@ -1379,6 +1877,33 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // Minimum memory size for the process.
         let min_total_memory_size = min_process_ram_size + initial_kernel_memory_size;
 
+        // A process with growable memory keeps its kernel-owned state in
+        // front of its memory instead of at the end, so that only its grants
+        // have to move when the memory grows.
+        let (kernel_state_memory, remaining_memory) = if Self::memory_growable(&tbf_header) {
+            let kernel_state_size = remaining_memory
+                .as_ptr()
+                .align_offset(mem::align_of::<Self>())
+                + Self::kernel_state_size(kernel, &tbf_header);
+            if kernel_state_size > remaining_memory.len() {
+                if config::CONFIG.debug_load_processes {
+                    debug!(
+                        "[!] flash={:#010X}-{:#010X} process={:?} - couldn't allocate {:#X} bytes of kernel memory",
+                        app_flash.as_ptr() as usize,
+                        app_flash.as_ptr() as usize + app_flash.len() - 1,
+                        process_name,
+                        kernel_state_size
+                    );
+                }
+                return Err(ProcessLoadError::NotEnoughMemory);
+            }
+            let (kernel_state_memory, remaining_memory) =
+                remaining_memory.split_at_mut(kernel_state_size);
+            (Some(kernel_state_memory), remaining_memory)
+        } else {
+            (None, remaining_memory)
+        };
+
         // Check if this process requires a fixed memory start address. If so,
         // try to adjust the memory region to work for this process.
         //
@@ -1490,12 +2015,19 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // since no `allow` calls have been made yet.
         let initial_allow_high_water_mark = app_memory.as_ptr();
 
-        // Set up initial grant region.
-        let mut kernel_memory_break = app_memory.as_mut_ptr().add(app_memory.len());
+        // The kernel-owned state is placed from the end of process memory
+        // down, or from the end of the memory reserved for it in front of
+        // process memory.
+        let mut kernel_state_break = match kernel_state_memory {
+            Some(kernel_state_memory) => kernel_state_memory
+                .as_mut_ptr()
+                .add(kernel_state_memory.len()),
+            None => app_memory.as_mut_ptr().add(app_memory.len()),
+        };
 
         // Now that we know we have the space we can setup the grant
         // pointers.
-        kernel_memory_break = kernel_memory_break.offset(-(grant_ptrs_offset as isize));
+        kernel_state_break = kernel_state_break.offset(-(grant_ptrs_offset as isize));
 
         // This is safe today, as MPU constraints ensure that `memory_start`
         // will always be aligned on at least a word boundary, and that
@@ -1507,18 +2039,17 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // TODO: https://github.com/tock/tock/issues/1739
         #[allow(clippy::cast_ptr_alignment)]
         // Set all grant pointers to null.
-        let grant_pointers = slice::from_raw_parts_mut(
-            kernel_memory_break as *mut GrantPointerEntry,
-            grant_ptrs_num,
-        );
+        let grant_pointers =
+            slice::from_raw_parts_mut(kernel_state_break as *mut GrantPointerEntry, grant_ptrs_num);
         for grant_entry in grant_pointers.iter_mut() {
             grant_entry.driver_num = 0;
             grant_entry.grant_ptr = ptr::null_mut();
+            grant_entry.size = 0;
         }
 
         // Now that we know we have the space we can setup the memory for the
         // upcalls.
-        kernel_memory_break = kernel_memory_break.offset(-(Self::CALLBACKS_OFFSET as isize));
+        kernel_state_break = kernel_state_break.offset(-(upcall_queue_size as isize));
 
         // This is safe today, as MPU constraints ensure that `memory_start`
         // will always be aligned on at least a word boundary, and that
@@ -1531,12 +2062,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         #[allow(clippy::cast_ptr_alignment)]
         // Set up ring buffer for upcalls to the process.
         let upcall_buf =
-            slice::from_raw_parts_mut(kernel_memory_break as *mut Task, Self::CALLBACK_LEN);
+            slice::from_raw_parts_mut(kernel_state_break as *mut Task, upcall_queue_len);
         let tasks = RingBuffer::new(upcall_buf);
 
         // Last thing in the kernel region of process RAM is the process struct.
-        kernel_memory_break = kernel_memory_break.offset(-(Self::PROCESS_STRUCT_OFFSET as isize));
-        let process_struct_memory_location = kernel_memory_break;
+        kernel_state_break = kernel_state_break.offset(-(Self::PROCESS_STRUCT_OFFSET as isize));
+        let process_struct_memory_location = kernel_state_break;
+
+        // Set up initial grant region, which starts below the kernel-owned
+        // state if it is at the end of process memory.
+        let kernel_memory_break = app_memory
+            .as_ptr()
+            .add(app_memory.len() - initial_kernel_memory_size);
 
         // Create the Process struct in the app grant region.
         let mut process: &mut ProcessStandard<C> =
@@ -1558,9 +2095,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.chip = chip;
         process.allow_high_water_mark = Cell::new(initial_allow_high_water_mark);
         process.memory_start = app_memory.as_ptr();
-        process.memory_len = app_memory.len();
+        process.memory_len = Cell::new(app_memory.len());
+        process.initial_memory_len = app_memory.len();
         process.header = tbf_header;
         process.kernel_memory_break = Cell::new(kernel_memory_break);
+        process.custom_grants_entered = Cell::new(0);
         process.app_break = Cell::new(initial_app_brk);
         process.grant_pointers = MapCell::new(grant_pointers);
 
@@ -1582,8 +2121,14 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             Cell::new(None),
             Cell::new(None),
         ];
+        process.stack_guard = Cell::new(None);
+        process.stack_overflowed = Cell::new(false);
+        process.fault_reason = OptionalCell::empty();
         process.tasks = MapCell::new(tasks);
         process.process_name = process_name.unwrap_or("");
+        process.credentials_approved = Cell::new(!check_credentials);
+        process.credentials = OptionalCell::empty();
+        process.app_id = Cell::new(AppId::LocallyUnique);
 
         process.debug = MapCell::new(ProcessStandardDebug {
             fixed_address_flash: fixed_address_flash,
@@ -1595,21 +2140,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             last_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            cpu_time_us: 0,
+            deadline_miss_count: 0,
         });
 
-        let flash_protected_size = process.header.get_protected_size() as usize;
-        let flash_app_start_addr = app_flash.as_ptr() as usize + flash_protected_size;
-
-        process.tasks.map(|tasks| {
-            tasks.enqueue(Task::FunctionCall(FunctionCall {
-                source: FunctionCallSource::Kernel,
-                pc: init_fn,
-                argument0: flash_app_start_addr,
-                argument1: process.memory_start as usize,
-                argument2: process.memory_len,
-                argument3: process.app_break.get() as usize,
-            }));
-        });
+        if config::CONFIG.process_stack_watermark {
+            Self::fill_stack_canary(app_memory_start, kernel_memory_break);
+        }
 
         // Handle any architecture-specific requirements for a new process.
         //
@@ -1640,7 +2177,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
-        kernel.increment_work();
+        if check_credentials {
+            // The process must not run until its credentials are approved,
+            // which will queue the init function.
+            process.state.update(State::CredentialsUnchecked);
+        } else {
+            process.enqueue_init_task(init_fn);
+        }
 
         // Return the process object and a remaining memory for processes slice.
         Ok((Some(process), unused_memory))
@@ -1667,6 +2210,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.cpu_time_us = 0;
+            debug.deadline_miss_count = 0;
         });
 
         // FLASH
@@ -1703,6 +2248,9 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
 
         // RAM
 
+        // Give back any memory the process grew into.
+        self.memory_len.set(self.initial_memory_len);
+
         // Re-determine the minimum amount of RAM the kernel must allocate to
         // the process based on the specific requirements of the syscall
         // implementation.
@@ -1712,17 +2260,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             .initial_process_app_brk_size();
 
         // Recalculate initial_kernel_memory_size as was done in create()
-        let grant_ptr_size = mem::size_of::<(usize, *mut u8)>();
-        let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
-        let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
-
         let initial_kernel_memory_size =
-            grant_ptrs_offset + Self::CALLBACKS_OFFSET + Self::PROCESS_STRUCT_OFFSET;
+            Self::initial_kernel_memory_size(self.kernel, &self.header);
 
         let app_mpu_mem = self.chip.mpu().allocate_app_memory_region(
             self.mem_start(),
-            self.memory_len,
-            self.memory_len, //we want exactly as much as we had before restart
+            self.memory_len.get(),
+            self.memory_len.get(), //we want exactly as much as we had before restart
             min_process_memory_size,
             initial_kernel_memory_size,
             mpu::Permissions::ReadWriteOnly,
@@ -1756,8 +2300,19 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // process's memory region.
         self.allow_high_water_mark.set(app_mpu_mem_start);
 
-        // Drop the old config and use the clean one
+        // Drop the old config and use the clean one, along with any regions
+        // that were added to it.
         self.mpu_config.replace(mpu_config);
+        for region in self.mpu_regions.iter() {
+            region.set(None);
+        }
+        self.stack_guard.set(None);
+        self.stack_overflowed.set(false);
+        if config::CONFIG.process_stack_watermark {
+            // The process has not started yet, and `app_mpu_mem_start` is
+            // aligned like when the process was created.
+            unsafe { Self::fill_stack_canary(app_mpu_mem_start, kernel_brk) };
+        }
 
         // Handle any architecture-specific requirements for a process when it
         // first starts (as it would when it is new).
@@ -1780,32 +2335,37 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
-        // And queue up this app to be restarted.
-        let flash_protected_size = self.header.get_protected_size() as usize;
-        let flash_app_start = app_flash_address as usize + flash_protected_size;
-
         // Mark the state as `Unstarted` for the scheduler.
         self.state.update(State::Unstarted);
 
         // Mark that we restarted this process.
         self.restart_count.increment();
 
-        // Enqueue the initial function.
+        // And queue up this app to be restarted.
+        self.enqueue_init_task(init_fn);
+
+        Ok(())
+    }
+
+    /// Enqueue the task that calls the process's init function at `init_fn`
+    /// and mark that the process is ready to run.
+    fn enqueue_init_task(&self, init_fn: usize) {
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = self.flash_start() as usize + flash_protected_size;
+
         self.tasks.map(|tasks| {
             tasks.enqueue(Task::FunctionCall(FunctionCall {
                 source: FunctionCallSource::Kernel,
                 pc: init_fn,
                 argument0: flash_app_start,
                 argument1: self.mem_start() as usize,
-                argument2: self.memory_len,
+                argument2: self.memory_len.get(),
                 argument3: self.app_break.get() as usize,
             }));
         });
 
         // Mark that the process is ready to run.
         self.kernel.increment_work();
-
-        Ok(())
     }
 
     /// Checks if the buffer represented by the passed in base pointer and size
@@ -1833,25 +2393,131 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             && buf_end_addr <= self.flash_end()
     }
 
+    /// Fill the memory from `start` to `end` with the stack canary, so that
+    /// `stack_high_water_mark()` can find how much of it the stack used.
+    ///
+    /// ### Safety
+    ///
+    /// The memory must be process memory the process has not started using,
+    /// and `start` must be word aligned.
+    unsafe fn fill_stack_canary(start: *const u8, end: *const u8) {
+        let words = (end as usize).saturating_sub(start as usize) / mem::size_of::<u32>();
+        #[allow(clippy::cast_ptr_alignment)]
+        let memory = slice::from_raw_parts_mut(start as *mut u32, words);
+        for word in memory.iter_mut() {
+            ptr::write_volatile(word, Self::STACK_CANARY);
+        }
+    }
+
+    /// Returns the lowest address the process's stack may use. The stack ends
+    /// at the start of process memory, or at the end of the stack guard.
+    fn stack_limit(&self) -> *const u8 {
+        self.stack_guard.get().map_or(self.mem_start(), |guard| {
+            guard.start_address().wrapping_add(guard.size())
+        })
+    }
+
+    /// Protect the end of the stack that starts at `stack_pointer` with an MPU
+    /// region the process cannot access.
+    fn add_stack_guard(&self, stack_pointer: *const u8) {
+        self.mpu_config.map(|config| {
+            // The guard must leave some of the stack usable. If it does not,
+            // the process keeps its stack unguarded.
+            let guard = self.chip.mpu().allocate_guard_region(
+                self.mem_start(),
+                Self::STACK_GUARD_SIZE,
+                config,
+            );
+            match guard {
+                Some(guard) if guard.start_address().wrapping_add(guard.size()) < stack_pointer => {
+                    self.stack_guard.set(Some(guard));
+                }
+                Some(guard) => {
+                    let _ = self.chip.mpu().remove_memory_region(guard, config);
+                }
+                None => {}
+            }
+        });
+    }
+
+    /// Returns the largest number of bytes of stack the process has used,
+    /// found from how much of the stack canary it overwrote.
+    ///
+    /// Returns `None` if the kernel does not fill process memory with the
+    /// canary, or does not know where the stack starts.
+    fn stack_high_water_mark(&self) -> Option<usize> {
+        if !config::CONFIG.process_stack_watermark {
+            return None;
+        }
+        let stack_top = self
+            .debug
+            .map_or(None, |debug| debug.app_stack_start_pointer)?;
+        let stack_bottom = self.mem_start();
+        let words = (stack_top as usize - stack_bottom as usize) / mem::size_of::<u32>();
+
+        // ### Safety
+        //
+        // The stack start pointer is checked to be inside of process memory,
+        // which starts word aligned, and process memory stays valid while the
+        // process exists. The process is not running while the kernel is, so
+        // the memory does not change while we read it.
+        #[allow(clippy::cast_ptr_alignment)]
+        let stack = unsafe { slice::from_raw_parts(stack_bottom as *const u32, words) };
+        // The stack grows down, so the lowest overwritten word is the deepest
+        // point it reached.
+        let unused = stack
+            .iter()
+            .take_while(|word| unsafe { ptr::read_volatile(*word) } == Self::STACK_CANARY)
+            .count();
+        Some((words - unused) * mem::size_of::<u32>())
+    }
+
     /// Reset all `grant_ptr`s to NULL.
     unsafe fn grant_ptrs_reset(&self) {
         self.grant_pointers.map(|grant_pointers| {
             for grant_entry in grant_pointers.iter_mut() {
                 grant_entry.driver_num = 0;
                 grant_entry.grant_ptr = ptr::null_mut();
+                grant_entry.size = 0;
             }
         });
     }
 
+    /// Whether the process can use `size` bytes of grant memory for the
+    /// driver `driver_num` without exceeding the board's quota for it.
+    fn grant_quota_allows(&self, driver_num: usize, size: usize) -> bool {
+        self.kernel
+            .grant_quota(driver_num)
+            .map_or(true, |max_bytes| size <= max_bytes)
+    }
+
     /// Allocate memory in a process's grant region.
     ///
     /// Ensures that the allocation is of `size` bytes and aligned to `align`
     /// bytes.
     ///
+    /// If the grant region is full and the process has growable memory, the
+    /// process memory is grown to make room for the allocation.
+    fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        self.allocate_below_kernel_memory_break(size, align)
+            .or_else(|| {
+                // Aligning the allocation uses up to `align - 1` more bytes.
+                let additional = size.checked_add(align)?;
+                if Self::memory_growable(&self.header) && self.grow_memory(additional).is_ok() {
+                    self.allocate_below_kernel_memory_break(size, align)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Allocate memory in the grant region inside of process memory by
+    /// moving the kernel memory break down.
+    ///
     /// If there is not enough memory, or the MPU cannot isolate the process
     /// accessible region from the new kernel memory break after doing the
     /// allocation, then this will return `None`.
-    fn allocate_in_grant_region_internal(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+    fn allocate_below_kernel_memory_break(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
         self.mpu_config.and_then(|mut config| {
             // First, compute the candidate new pointer. Note that at this point
             // we have not yet checked whether there is space for this
@@ -1905,11 +2571,149 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         })
     }
 
+    /// Whether any grant or custom grant of the process is entered.
+    fn grants_entered(&self) -> bool {
+        self.custom_grants_entered.get() > 0
+            || self.grant_pointers.map_or(true, |grant_pointers| {
+                grant_pointers
+                    .iter()
+                    .any(|grant_entry| (grant_entry.grant_ptr as usize) & 0x1 == 0x1)
+            })
+    }
+
+    /// Grow process memory into the unused app memory directly after it, so
+    /// that there are at least `additional` more bytes between the app break
+    /// and the kernel memory break.
+    ///
+    /// The MPU region for process memory is allocated again for the larger
+    /// memory block, and the grant region is moved up to the new end of
+    /// process memory. The memory it moved out of can then be used by the
+    /// process through `brk` or for new grants. The rest of the kernel-owned
+    /// state of the process does not move, since for processes with growable
+    /// memory it is in front of process memory.
+    ///
+    /// Moving the grant region would invalidate references into it, so this
+    /// fails while any grant or custom grant of the process is entered. In
+    /// particular, allocating a custom grant never grows process memory. This
+    /// also fails if there is not enough unused memory, or if the MPU cannot
+    /// cover the larger memory block starting at the same address.
+    fn grow_memory(&self, additional: usize) -> Result<(), ()> {
+        if self.grants_entered() {
+            return Err(());
+        }
+
+        let memory_start = self.mem_start();
+        let memory_len = self.memory_len.get();
+        let memory_end = self.mem_end() as usize;
+        let app_break = self.app_break.get();
+        let kernel_memory_break = self.kernel_memory_break.get() as usize;
+        let kernel_memory_size = memory_end - kernel_memory_break;
+        let unused_memory_size = self
+            .kernel
+            .unused_app_memory_end(memory_end)
+            .saturating_sub(memory_start as usize);
+
+        // Set up the MPU configuration from scratch, as `restart()` does,
+        // keeping the current breaks and regions of the process.
+        let mpu = self.chip.mpu();
+        let mut mpu_config: <<C as Chip>::MPU as MPU>::MpuConfig = Default::default();
+        mpu.allocate_region(
+            self.flash.as_ptr(),
+            self.flash.len(),
+            self.flash.len(),
+            mpu::Permissions::ReadExecuteOnly,
+            &mut mpu_config,
+        )
+        .ok_or(())?;
+        let (new_memory_start, new_memory_len) = mpu
+            .allocate_app_memory_region(
+                memory_start,
+                unused_memory_size,
+                memory_len.checked_add(additional).ok_or(())?,
+                app_break as usize - memory_start as usize,
+                kernel_memory_size,
+                mpu::Permissions::ReadWriteOnly,
+                &mut mpu_config,
+            )
+            .ok_or(())?;
+        if new_memory_start != memory_start || new_memory_len <= memory_len {
+            return Err(());
+        }
+        let offset = new_memory_len - memory_len;
+        let new_kernel_memory_break = (kernel_memory_break + offset) as *const u8;
+        mpu.update_app_memory_region(
+            app_break,
+            new_kernel_memory_break,
+            mpu::Permissions::ReadWriteOnly,
+            &mut mpu_config,
+        )?;
+        for (region, permissions) in self.mpu_regions.iter().filter_map(Cell::get) {
+            let new_region = mpu.allocate_region(
+                region.start_address(),
+                region.size(),
+                region.size(),
+                permissions,
+                &mut mpu_config,
+            );
+            if new_region != Some(region) {
+                return Err(());
+            }
+        }
+        if let Some(guard) = self.stack_guard.get() {
+            let new_guard =
+                mpu.allocate_guard_region(memory_start, Self::STACK_GUARD_SIZE, &mut mpu_config);
+            if new_guard != Some(guard) {
+                return Err(());
+            }
+        }
+
+        // ### Safety
+        //
+        // The MPU allocation checked that the grown memory block is unused,
+        // and no grant is entered, so nothing references grant memory while
+        // it moves. The part of the old grant region that is now below the
+        // kernel memory break is cleared, so that the process cannot read
+        // kernel state from it.
+        unsafe {
+            ptr::copy(
+                kernel_memory_break as *const u8,
+                new_kernel_memory_break as *mut u8,
+                kernel_memory_size,
+            );
+            ptr::write_bytes(
+                kernel_memory_break as *mut u8,
+                0,
+                cmp::min(offset, kernel_memory_size),
+            );
+        }
+        self.grant_pointers.map(|grant_pointers| {
+            for grant_entry in grant_pointers.iter_mut() {
+                if !grant_entry.grant_ptr.is_null() {
+                    grant_entry.grant_ptr = grant_entry.grant_ptr.wrapping_add(offset);
+                }
+            }
+        });
+        self.memory_len.set(new_memory_len);
+        self.kernel_memory_break.set(new_kernel_memory_break);
+        self.mpu_config.replace(mpu_config);
+
+        if config::CONFIG.debug_load_processes {
+            debug!(
+                "[{:?}] grew process memory to {:#010X}",
+                self.process_name,
+                memory_start as usize + new_memory_len
+            );
+        }
+        Ok(())
+    }
+
     /// Create the identifier for a custom grant that grant.rs uses to access
     /// the custom grant.
     ///
     /// We create this identifier by calculating the number of bytes between
-    /// where the custom grant starts and the end of the process memory.
+    /// where the custom grant starts and the end of the process memory. When
+    /// process memory grows the grant region moves with its end, so this does
+    /// not change.
     fn create_custom_grant_identifier(&self, ptr: NonNull<u8>) -> ProcessCustomGrantIdentifer {
         let custom_grant_address = ptr.as_ptr() as usize;
         let process_memory_end = self.mem_end() as usize;
@@ -1940,10 +2744,23 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
     ///
     /// A process is inactive if the kernel cannot resume its execution, such as
     /// if the process faults and is in an invalid state, or if the process
-    /// explicitly exits.
+    /// explicitly exits. A process whose credentials have not been approved is
+    /// also inactive.
     fn is_active(&self) -> bool {
         let current_state = self.state.get();
-        current_state != State::Terminated && current_state != State::Faulted
+        current_state != State::Terminated
+            && current_state != State::Faulted
+            && current_state != State::CredentialsUnchecked
+            && current_state != State::CredentialsFailed
+    }
+
+    /// Whether the process state can be replaced with a checkpoint, which is
+    /// only allowed while the process cannot run.
+    fn can_restore(&self) -> bool {
+        matches!(
+            self.state.get(),
+            State::Unstarted | State::StoppedRunning | State::StoppedYielded
+        )
     }
 
     /// The start address of allocated RAM for this process.
@@ -1951,9 +2768,20 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.memory_start
     }
 
+    /// The start address of the memory used for this process, including the
+    /// kernel-owned state in front of process memory if the process has
+    /// growable memory.
+    fn memory_block_start(&self) -> *const u8 {
+        if Self::memory_growable(&self.header) {
+            self as *const Self as *const u8
+        } else {
+            self.mem_start()
+        }
+    }
+
     /// The first address after the end of the allocated RAM for this process.
     fn mem_end(&self) -> *const u8 {
-        self.memory_start.wrapping_add(self.memory_len)
+        self.memory_start.wrapping_add(self.memory_len.get())
     }
 
     /// The start address of the flash region allocated for this process.
@@ -1986,3 +2814,173 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.app_break.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::{ptr, slice};
+
+    use crate::dynamic_process_loader::DynamicProcessLoading;
+    use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+    use crate::test_support::{
+        self, MemoryCap, TestMpuConfig, FLAG_ENABLED, FLAG_MEMORY_GROWABLE, IMAGE_LEN,
+    };
+
+    type TestGrant = Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>;
+
+    #[test]
+    fn restart_keeps_kernel_memory_layout() {
+        let kernel = test_support::kernel(1);
+        let chip = test_support::chip();
+        // Grants make the grant pointer table non-empty, so a restart that
+        // sizes it differently than loading would move the grant region.
+        let _first: TestGrant = kernel.create_grant(0x1, &MemoryCap);
+        let _second: TestGrant = kernel.create_grant(0x2, &MemoryCap);
+        let flash = test_support::app_flash(&[FLAG_ENABLED]);
+        let loader = test_support::loader(kernel, chip, flash, test_support::app_memory(8192));
+
+        let process = kernel
+            .get_process(loader.load_process(flash.as_ptr() as usize).unwrap())
+            .unwrap();
+        let loaded = process.get_addresses();
+
+        process.try_restart(None);
+        let restarted = process.get_addresses();
+        assert_eq!(restarted.sram_grant_start, loaded.sram_grant_start);
+        assert_eq!(restarted.sram_app_brk, loaded.sram_app_brk);
+        assert_eq!(restarted.sram_end, loaded.sram_end);
+    }
+
+    #[test]
+    fn grow_memory() {
+        let kernel = test_support::kernel(2);
+        let chip = test_support::chip();
+        let _first: TestGrant = kernel.create_grant(0x1, &MemoryCap);
+        let _second: TestGrant = kernel.create_grant(0x2, &MemoryCap);
+        let _third: TestGrant = kernel.create_grant(0x3, &MemoryCap);
+        let flash = test_support::app_flash(&[FLAG_ENABLED | FLAG_MEMORY_GROWABLE, FLAG_ENABLED]);
+        let flash_start = flash.as_ptr() as usize;
+        let loader = test_support::loader(kernel, chip, flash, test_support::app_memory(32768));
+
+        let process = kernel
+            .get_process(loader.load_process(flash_start).unwrap())
+            .unwrap();
+        let loaded = process.get_addresses();
+        let memory_len = loaded.sram_end - loaded.sram_start;
+
+        // The kernel-owned state of the process is in front of its memory, so
+        // there is no kernel-owned memory in it until grants are allocated.
+        assert!(loaded.sram_block_start < loaded.sram_start);
+        assert_eq!(loaded.sram_grant_start, loaded.sram_end);
+        process.setup_mpu();
+        let loaded_mpu = chip.mpu.configured.get().unwrap();
+        assert_eq!(
+            loaded_mpu,
+            TestMpuConfig {
+                app_memory: Some((loaded.sram_start, memory_len)),
+                app_break: loaded.sram_app_brk,
+                kernel_break: loaded.sram_end,
+                regions: 1,
+            }
+        );
+
+        // A grant that fits is allocated at the end of process memory.
+        let first = process.allocate_grant(0, 0x1, 16, 8).unwrap().as_ptr();
+        unsafe { ptr::write_bytes(first, 0xAB, 16) };
+        assert_eq!(process.get_addresses().sram_grant_start, first as usize);
+
+        // A grant that does not fit grows process memory. The grant region
+        // moves up with the end of process memory and the MPU covers the
+        // grown memory.
+        let second = process
+            .allocate_grant(1, 0x2, memory_len, 8)
+            .unwrap()
+            .as_ptr() as usize;
+        let grown = process.get_addresses();
+        let offset = grown.sram_end - loaded.sram_end;
+        assert!(offset >= memory_len);
+        assert_eq!(grown.sram_start, loaded.sram_start);
+        assert_eq!(grown.sram_app_brk, loaded.sram_app_brk);
+        assert_eq!(grown.sram_grant_start, second);
+        assert_eq!(second + memory_len, first as usize + offset);
+        let moved = process.enter_grant(0).unwrap();
+        assert_eq!(moved as usize, first as usize + offset);
+        assert!(unsafe { slice::from_raw_parts(moved, 16) }
+            .iter()
+            .all(|byte| *byte == 0xAB));
+        process.leave_grant(0);
+        // Grant memory the process can now use is cleared.
+        assert_eq!(unsafe { *first }, 0);
+        process.setup_mpu();
+        assert_eq!(
+            chip.mpu.configured.get().unwrap(),
+            TestMpuConfig {
+                app_memory: Some((grown.sram_start, grown.sram_end - grown.sram_start)),
+                app_break: grown.sram_app_brk,
+                kernel_break: grown.sram_grant_start,
+                regions: 1,
+            }
+        );
+
+        // The process can grow its memory with `brk` too, past where its
+        // memory used to end.
+        assert!(process.brk(grown.sram_end as *const u8).is_ok());
+        let grown_by_brk = process.get_addresses();
+        assert_eq!(grown_by_brk.sram_app_brk, grown.sram_end);
+        assert!(grown_by_brk.sram_grant_start >= grown.sram_end);
+        assert_eq!(
+            process.enter_grant(1).unwrap() as usize,
+            grown_by_brk.sram_grant_start
+        );
+        process.leave_grant(1);
+        assert_eq!(
+            chip.mpu.configured.get().unwrap(),
+            TestMpuConfig {
+                app_memory: Some((
+                    grown_by_brk.sram_start,
+                    grown_by_brk.sram_end - grown_by_brk.sram_start
+                )),
+                app_break: grown_by_brk.sram_app_brk,
+                kernel_break: grown_by_brk.sram_grant_start,
+                regions: 1,
+            }
+        );
+
+        // Grant memory cannot move while a grant is entered.
+        process.enter_grant(0).unwrap();
+        assert!(process.allocate_grant(2, 0x3, memory_len, 8).is_none());
+        process.leave_grant(0);
+        assert_eq!(process.get_addresses().sram_end, grown_by_brk.sram_end);
+
+        // Restarting the process frees its grants and the memory it grew
+        // into, and sets up the MPU like when it was loaded.
+        process.try_restart(None);
+        let restarted = process.get_addresses();
+        assert_eq!(process.grant_is_allocated(0), Some(false));
+        assert_eq!(restarted.sram_end, loaded.sram_end);
+        assert_eq!(restarted.sram_grant_start, loaded.sram_grant_start);
+        assert_eq!(restarted.sram_app_brk, loaded.sram_app_brk);
+        process.setup_mpu();
+        assert_eq!(chip.mpu.configured.get().unwrap(), loaded_mpu);
+
+        // The grown memory is not given to other processes.
+        process.allocate_grant(0, 0x1, memory_len, 8).unwrap();
+        let fixed = kernel
+            .get_process(loader.load_process(flash_start + IMAGE_LEN).unwrap())
+            .unwrap();
+        let fixed_addresses = fixed.get_addresses();
+        let before = process.get_addresses();
+        assert_eq!(fixed_addresses.sram_block_start, fixed_addresses.sram_start);
+        assert!(fixed_addresses.sram_start >= before.sram_end);
+
+        // And the process cannot grow into the memory of the next process.
+        let unused = before.sram_grant_start - before.sram_app_brk;
+        assert!(process.allocate_grant(1, 0x2, unused + 1, 8).is_none());
+        assert_eq!(process.get_addresses().sram_end, before.sram_end);
+
+        // A process whose memory is not growable can't allocate more grant
+        // memory than it was loaded with.
+        let fixed_memory_len = fixed_addresses.sram_end - fixed_addresses.sram_start;
+        assert!(fixed.allocate_grant(0, 0x1, fixed_memory_len, 8).is_none());
+        assert_eq!(fixed.get_addresses().sram_end, fixed_addresses.sram_end);
+    }
+}
@@ -8,7 +8,8 @@ use crate::config;
 use crate::debug;
 use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
-use crate::process::Process;
+use crate::process::ProcessSlot;
+use crate::process_checker::ProcessCheckerMachine;
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_standard::ProcessStandard;
 
@@ -59,6 +60,17 @@ pub enum ProcessLoadError {
     /// KernelVersion TBF header.
     IncompatibleKernelVersion { version: Option<(u16, u16)> },
 
+    /// A process was requested to be loaded from an address that is not the
+    /// start of a valid TBF image inside of app flash, or the image overlaps
+    /// the flash of a process that is already loaded.
+    InvalidFlashLocation,
+
+    /// There is no empty slot in the processes array to hold a new process.
+    NoProcessSlotAvailable,
+
+    /// The TBF image is padding or a disabled app, and no process was created.
+    ProcessNotEnabled,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -124,6 +136,18 @@ impl fmt::Debug for ProcessLoadError {
                 None => write!(f, "Process did not provide a TBF kernel version header"),
             },
 
+            ProcessLoadError::InvalidFlashLocation => {
+                write!(f, "No loadable app image at the requested flash address")
+            }
+
+            ProcessLoadError::NoProcessSlotAvailable => {
+                write!(f, "No empty slot in the processes array")
+            }
+
+            ProcessLoadError::ProcessNotEnabled => {
+                write!(f, "App image is padding or a disabled app")
+            }
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -157,10 +181,71 @@ pub fn load_processes_advanced<C: Chip>(
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
-    procs: &'static mut [Option<&'static dyn Process>],
+    procs: &'static [ProcessSlot],
     fault_policy: &'static dyn ProcessFaultPolicy,
     require_kernel_version: bool,
     _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        require_kernel_version,
+        false,
+    )
+}
+
+/// Load processes like `load_processes_advanced()`, but do not allow any of
+/// them to run until their credentials have been approved.
+///
+/// Every loaded process starts in the `CredentialsUnchecked` state. Once all
+/// processes are loaded, `checker` is started and checks the credentials
+/// footers of each process with its `AppCredentialsChecker` policy. Processes
+/// that are approved are then scheduled as usual; processes that are rejected
+/// remain loaded in the `CredentialsFailed` state and never run.
+#[inline(always)]
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    procs: &'static [ProcessSlot],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    require_kernel_version: bool,
+    checker: &'static ProcessCheckerMachine,
+    _capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    let result = load_processes_from_flash(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        procs,
+        fault_policy,
+        require_kernel_version,
+        true,
+    );
+    // Check whatever was loaded, even if loading stopped early on an error.
+    checker.check_all();
+    result
+}
+
+/// Walk the linked list of TBF objects in `app_flash` and create a process
+/// for each app. If `check_credentials` is set, processes are created in the
+/// `CredentialsUnchecked` state.
+#[inline(always)]
+fn load_processes_from_flash<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8],
+    procs: &'static [ProcessSlot],
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    require_kernel_version: bool,
+    check_credentials: bool,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
         debug!(
@@ -172,6 +257,8 @@ pub fn load_processes_advanced<C: Chip>(
         );
     }
 
+    kernel.set_app_memory_end(app_memory.as_ptr() as usize + app_memory.len());
+
     let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
 
@@ -248,6 +335,7 @@ pub fn load_processes_advanced<C: Chip>(
                     remaining_memory,
                     fault_policy,
                     require_kernel_version,
+                    check_credentials,
                     index,
                 )?
             };
@@ -266,7 +354,7 @@ pub fn load_processes_advanced<C: Chip>(
                 }
 
                 // Save the reference to this process in the processes array.
-                procs[index] = Some(process);
+                procs[index].set(Some(process));
                 // Can now increment index to use the next spot in the processes
                 // array. Padding apps mean we might detect valid headers but
                 // not actually insert a new process in the array.
@@ -294,7 +382,7 @@ pub fn load_processes<C: Chip>(
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
-    procs: &'static mut [Option<&'static dyn Process>],
+    procs: &'static [ProcessSlot],
     fault_policy: &'static dyn ProcessFaultPolicy,
     capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
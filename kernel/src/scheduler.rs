@@ -1,13 +1,17 @@
 //! Interface for Tock kernel schedulers.
 
 pub mod cooperative;
+pub mod edf;
 pub mod mlfq;
 pub mod priority;
+pub mod rate_monotonic;
+pub mod real_time;
 pub mod round_robin;
 
 use crate::dynamic_deferred_call::DynamicDeferredCall;
 use crate::kernel::StoppedExecutingReason;
 use crate::platform::chip::Chip;
+use crate::platform::platform::Tracer;
 use crate::process::ProcessId;
 use crate::Kernel;
 
@@ -44,10 +48,11 @@ pub trait Scheduler<C: Chip> {
     /// complete at any time to meet power requirements.
     ///
     /// Custom implementations of this function must be very careful, however,
-    /// as this function is called in the core kernel loop.
-    unsafe fn execute_kernel_work(&self, chip: &C) {
-        chip.service_pending_interrupts();
-        DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts());
+    /// as this function is called in the core kernel loop. Each interrupt and
+    /// deferred call they run should be reported to `tracer`.
+    unsafe fn execute_kernel_work(&self, chip: &C, tracer: &dyn Tracer) {
+        chip.service_pending_interrupts(tracer);
+        DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts(), tracer);
     }
 
     /// Ask the scheduler whether to take a break from executing userspace
@@ -0,0 +1,26 @@
+//! Earliest deadline first scheduler for Tock
+//!
+//! Among the real-time processes (processes with a TBF `RealTime` header) that
+//! are ready and have budget left, this scheduler runs the one whose current
+//! deadline is earliest. A process that has already missed its deadline is
+//! the most urgent of all. Processes without a `RealTime` header run in the
+//! time left over. See the `real_time` module for how periods, budgets and
+//! deadline misses are handled.
+//!
+//! EDF can meet all deadlines as long as the total utilization (the sum of
+//! budget divided by period over all real-time processes) is at most 1 and
+//! every deadline equals its period.
+
+use crate::scheduler::real_time::{RealTimePolicy, RealTimeSched};
+
+/// Orders real-time processes by their absolute deadline.
+pub struct EarliestDeadlineFirst;
+
+impl RealTimePolicy for EarliestDeadlineFirst {
+    fn urgency(_period_us: u32, time_to_deadline_us: i32) -> i64 {
+        time_to_deadline_us as i64
+    }
+}
+
+/// Earliest deadline first scheduler.
+pub type EDFSched<'a, A> = RealTimeSched<'a, A, EarliestDeadlineFirst>;
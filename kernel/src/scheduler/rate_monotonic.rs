@@ -0,0 +1,27 @@
+//! Rate-monotonic scheduler for Tock
+//!
+//! This is a fixed priority scheduler for real-time processes (processes with
+//! a TBF `RealTime` header): the shorter the period of a process, the higher
+//! its priority. Among the real-time processes that are ready and have budget
+//! left, the one with the shortest period runs. Processes with the same period
+//! run in the order of the `PROCESSES` array. Processes without a `RealTime`
+//! header run in the time left over. See the `real_time` module for how
+//! periods, budgets and deadline misses are handled.
+//!
+//! Unlike EDF, priorities never change, which makes the behavior under
+//! overload predictable: only the processes with the longest periods miss
+//! their deadlines.
+
+use crate::scheduler::real_time::{RealTimePolicy, RealTimeSched};
+
+/// Orders real-time processes by their period.
+pub struct RateMonotonic;
+
+impl RealTimePolicy for RateMonotonic {
+    fn urgency(period_us: u32, _time_to_deadline_us: i32) -> i64 {
+        period_us as i64
+    }
+}
+
+/// Rate-monotonic scheduler.
+pub type RateMonotonicSched<'a, A> = RealTimeSched<'a, A, RateMonotonic>;
@@ -0,0 +1,555 @@
+//! Shared implementation of the periodic real-time schedulers for Tock.
+//!
+//! Processes with a TBF `RealTime` header are periodic real-time processes.
+//! Each period starts with a release, and the work of that period must be
+//! done by the deadline, which is `deadline_us` after the release. Periods
+//! start when the scheduler first sees the process (or sees it again after it
+//! was restarted) and follow each other back to back.
+//!
+//! This module implements the bookkeeping both real-time schedulers share, and
+//! the `RealTimePolicy` decides which ready process is most urgent. The
+//! `edf` module provides earliest-deadline-first scheduling and the
+//! `rate_monotonic` module provides fixed priority rate-monotonic scheduling.
+//!
+//! The schedulers work as follows:
+//!
+//! - A ready real-time process that has budget left in its current period
+//!   always runs before processes without a `RealTime` header. Among those,
+//!   the most urgent one according to the policy runs.
+//! - Each process is preempted with the `SchedulerTimer` at the next release
+//!   or deadline of any real-time process, so that a newly released, more
+//!   urgent process runs on time. A real-time process is also preempted when
+//!   it has used up its budget for the current period, and then does not run
+//!   again until its next release.
+//! - If a real-time process is still ready to run when its deadline passes,
+//!   it missed that deadline. This is counted in the process's
+//!   `debug_deadline_miss_count()`. At most one miss is counted per period.
+//! - Processes without a `RealTime` header run in round-robin order in the
+//!   time no real-time process needs.
+//!
+//! Time is tracked in microseconds with a wrapping 32-bit counter, so periods
+//! must be shorter than 2^31 microseconds (about 35 minutes).
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use crate::collections::list::{List, ListLink, ListNode};
+use crate::dynamic_deferred_call::DynamicDeferredCall;
+use crate::hil::time::{self, ConvertTicks, Ticks};
+use crate::kernel::{Kernel, StoppedExecutingReason};
+use crate::platform::chip::Chip;
+use crate::process::{Process, ProcessId, ProcessSlot};
+use crate::scheduler::{Scheduler, SchedulingDecision};
+use crate::utilities::cells::OptionalCell;
+use tock_tbf::types::TbfHeaderV2RealTime;
+
+/// Decides which of several ready real-time processes runs first.
+pub trait RealTimePolicy {
+    /// Returns the urgency of a ready process with period `period_us` whose
+    /// current deadline is `time_to_deadline_us` from now (negative if the
+    /// deadline has passed). Processes with lower values run first. Processes
+    /// with equal values run in the order of the process list.
+    fn urgency(period_us: u32, time_to_deadline_us: i32) -> i64;
+}
+
+/// Nodes store per-process state
+pub struct RealTimeProcessNode<'a> {
+    proc: &'static ProcessSlot,
+    /// The process the timing state below belongs to. The state is reset
+    /// when a different process (or a restarted one) is in this slot.
+    processid: OptionalCell<ProcessId>,
+    /// Start of the current period, in scheduler time.
+    release_us: Cell<u32>,
+    /// CPU time the process has used in the current period.
+    budget_used_us: Cell<u32>,
+    /// Whether the deadline of the current period has passed.
+    deadline_passed: Cell<bool>,
+    next: ListLink<'a, RealTimeProcessNode<'a>>,
+}
+
+impl<'a> RealTimeProcessNode<'a> {
+    pub fn new(proc: &'static ProcessSlot) -> RealTimeProcessNode<'a> {
+        RealTimeProcessNode {
+            proc,
+            processid: OptionalCell::empty(),
+            release_us: Cell::new(0),
+            budget_used_us: Cell::new(0),
+            deadline_passed: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Bring the period of the process up to date with `now`, counting a
+    /// deadline miss if the deadline passed while the process was still ready.
+    fn update(&self, proc: &dyn Process, params: &TbfHeaderV2RealTime, now: u32) {
+        if self.processid.extract() != Some(proc.processid()) {
+            // First time we see this process: its first period starts now.
+            self.processid.set(proc.processid());
+            self.release_us.set(now);
+            self.budget_used_us.set(0);
+            self.deadline_passed.set(false);
+            return;
+        }
+
+        self.check_deadline(proc, params, now);
+        let since_release = now.wrapping_sub(self.release_us.get());
+        let period = params.period_us();
+        if since_release >= period {
+            let elapsed_periods = since_release / period;
+            self.release_us
+                .set(self.release_us.get().wrapping_add(elapsed_periods * period));
+            self.budget_used_us.set(0);
+            self.deadline_passed.set(false);
+            // The deadline of the new period may have passed already.
+            self.check_deadline(proc, params, now);
+        }
+    }
+
+    /// Note if the deadline of the current period has passed by `now`, and
+    /// count a miss the first time it has if the process is still ready.
+    fn check_deadline(&self, proc: &dyn Process, params: &TbfHeaderV2RealTime, now: u32) {
+        let since_release = now.wrapping_sub(self.release_us.get());
+        if !self.deadline_passed.get() && since_release >= params.deadline_us() {
+            self.deadline_passed.set(true);
+            if proc.ready() {
+                proc.debug_deadline_missed();
+            }
+        }
+    }
+
+    /// Microseconds until the deadline of the current period. Negative if it
+    /// has already passed.
+    fn time_to_deadline(&self, params: &TbfHeaderV2RealTime, now: u32) -> i32 {
+        self.release_us
+            .get()
+            .wrapping_add(params.deadline_us())
+            .wrapping_sub(now) as i32
+    }
+
+    /// Microseconds until the next release or deadline of this process.
+    fn time_to_next_event(&self, params: &TbfHeaderV2RealTime, now: u32) -> u32 {
+        let next_release = self
+            .release_us
+            .get()
+            .wrapping_add(params.period_us())
+            .wrapping_sub(now);
+        if self.deadline_passed.get() {
+            next_release
+        } else {
+            core::cmp::min(
+                next_release,
+                self.time_to_deadline(params, now).max(0) as u32,
+            )
+        }
+    }
+
+    /// CPU time the process may still use in the current period, or `None`
+    /// if it has no budget.
+    fn budget_remaining(&self, params: &TbfHeaderV2RealTime) -> Option<u32> {
+        params
+            .budget_us()
+            .map(|budget| budget.saturating_sub(self.budget_used_us.get()))
+    }
+}
+
+impl<'a> ListNode<'a, RealTimeProcessNode<'a>> for RealTimeProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, RealTimeProcessNode<'a>> {
+        &self.next
+    }
+}
+
+/// Periodic real-time scheduler, parameterized by the policy that orders
+/// ready real-time processes.
+pub struct RealTimeSched<'a, A: 'static + time::Alarm<'static>, P: RealTimePolicy> {
+    alarm: &'static A,
+    pub processes: List<'a, RealTimeProcessNode<'a>>,
+    /// Scheduler time in microseconds, and the alarm ticks it corresponds to.
+    now_us: Cell<u32>,
+    now_ticks: Cell<A::Ticks>,
+    /// The process that was last scheduled.
+    last: OptionalCell<&'a RealTimeProcessNode<'a>>,
+    policy: PhantomData<P>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, P: RealTimePolicy> RealTimeSched<'a, A, P> {
+    /// Longest timeslice given to any process, so that deadlines of
+    /// processes that become ready are noticed in time.
+    pub const MAX_TIMESLICE_US: u32 = 10000;
+    /// Shortest timeslice given to any process. This must be longer than the
+    /// minimum quantum the kernel will start a process with.
+    pub const MIN_TIMESLICE_US: u32 = 1000;
+
+    pub fn new(alarm: &'static A) -> Self {
+        Self {
+            alarm,
+            processes: List::new(),
+            now_us: Cell::new(0),
+            now_ticks: Cell::new(A::Ticks::from(0)),
+            last: OptionalCell::empty(),
+            policy: PhantomData,
+        }
+    }
+
+    /// Advance scheduler time to the current alarm time and return it.
+    fn update_clock(&self) -> u32 {
+        let now = self.alarm.now();
+        let elapsed_us = self
+            .alarm
+            .ticks_to_us(now.wrapping_sub(self.now_ticks.get()));
+        // Only advance the tick count by the ticks that made up whole
+        // microseconds, so that rounding does not make the clock drift.
+        self.now_ticks.set(
+            self.now_ticks
+                .get()
+                .wrapping_add(self.alarm.ticks_from_us(elapsed_us)),
+        );
+        self.now_us.set(self.now_us.get().wrapping_add(elapsed_us));
+        self.now_us.get()
+    }
+
+    /// Returns the urgency of `node` if it is a real-time process that is
+    /// ready and has budget left in the current period.
+    fn eligible_urgency(&self, node: &RealTimeProcessNode<'a>, now: u32) -> Option<i64> {
+        let proc = node.proc.get()?;
+        let params = proc.get_real_time_parameters()?;
+        if !proc.ready() || node.budget_remaining(&params) == Some(0) {
+            return None;
+        }
+        Some(P::urgency(
+            params.period_us(),
+            node.time_to_deadline(&params, now),
+        ))
+    }
+
+    /// Move `node` to the tail of the list, along with every node before it,
+    /// so that background processes take turns in round-robin order.
+    fn rotate_past(&self, node: &RealTimeProcessNode<'a>) {
+        while let Some(head) = self.processes.pop_head() {
+            self.processes.push_tail(head);
+            if core::ptr::eq(head, node) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, P: RealTimePolicy> time::AlarmClient
+    for RealTimeSched<'a, A, P>
+{
+    fn alarm(&self) {
+        // The alarm only wakes the chip when a throttled process is released;
+        // the next call to `next()` schedules it.
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, P: RealTimePolicy, C: Chip> Scheduler<C>
+    for RealTimeSched<'a, A, P>
+{
+    fn next(&self, _kernel: &Kernel) -> SchedulingDecision {
+        let now = self.update_clock();
+
+        // Most urgent ready real-time process, and its remaining budget.
+        let mut best: Option<(&RealTimeProcessNode<'a>, i64, Option<u32>)> = None;
+        // First ready process without a `RealTime` header.
+        let mut background: Option<&RealTimeProcessNode<'a>> = None;
+        // Time until the next release or deadline of any real-time process.
+        let mut next_event = Self::MAX_TIMESLICE_US;
+        let mut throttled = false;
+
+        for node in self.processes.iter() {
+            let proc = match node.proc.get() {
+                Some(proc) => proc,
+                None => continue,
+            };
+            let params = match proc.get_real_time_parameters() {
+                Some(params) => params,
+                None => {
+                    if background.is_none() && proc.ready() {
+                        background = Some(node);
+                    }
+                    continue;
+                }
+            };
+            // Deadlines and releases are tracked even while the scheduler
+            // sleeps, so update every real-time process, not only ready ones.
+            node.update(proc, &params, now);
+            next_event = core::cmp::min(next_event, node.time_to_next_event(&params, now));
+            if !proc.ready() {
+                continue;
+            }
+            let remaining = node.budget_remaining(&params);
+            if remaining == Some(0) {
+                throttled = true;
+                continue;
+            }
+            let urgency = P::urgency(params.period_us(), node.time_to_deadline(&params, now));
+            if best.map_or(true, |(_, best_urgency, _)| urgency < best_urgency) {
+                best = Some((node, urgency, remaining));
+            }
+        }
+
+        let (node, timeslice) = match (best, background) {
+            (Some((node, _, remaining)), _) => (
+                node,
+                core::cmp::min(next_event, remaining.unwrap_or(u32::MAX)),
+            ),
+            (None, Some(node)) => (node, next_event),
+            (None, None) => {
+                if throttled {
+                    // Processes have work but have used up their budget. Wake
+                    // up for the next release.
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_us(core::cmp::max(next_event, 1)),
+                    );
+                }
+                return SchedulingDecision::TrySleep;
+            }
+        };
+
+        self.last.set(node);
+        // Panic if fail: only nodes with a process are chosen.
+        let processid = node.proc.get().unwrap().processid();
+        SchedulingDecision::RunProcess((
+            processid,
+            Some(core::cmp::max(timeslice, Self::MIN_TIMESLICE_US)),
+        ))
+    }
+
+    fn result(&self, _: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        let execution_time_us = execution_time_us.unwrap_or(0);
+        self.last.take().map(|node| {
+            node.budget_used_us
+                .set(node.budget_used_us.get().saturating_add(execution_time_us));
+            let real_time = node
+                .proc
+                .get()
+                .map_or(false, |proc| proc.get_real_time_parameters().is_some());
+            if !real_time {
+                self.rotate_past(node);
+            }
+        });
+    }
+
+    unsafe fn continue_process(&self, _: ProcessId, chip: &C) -> bool {
+        if chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+        {
+            return false;
+        }
+        // A syscall of the running process (for example IPC) can make a more
+        // urgent real-time process ready. If so, switch to it.
+        let now = self.update_clock();
+        self.last.extract().map_or(true, |running| {
+            let running_urgency = self.eligible_urgency(running, now);
+            !self.processes.iter().any(|node| {
+                !core::ptr::eq(node, running)
+                    && self.eligible_urgency(node, now).map_or(false, |urgency| {
+                        running_urgency.map_or(true, |running| urgency < running)
+                    })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dynamic_process_loader::DynamicProcessLoading;
+    use crate::process::EMPTY_PROCESS_SLOT;
+    use crate::scheduler::edf::EarliestDeadlineFirst;
+    use crate::scheduler::rate_monotonic::RateMonotonic;
+    use crate::test_support::{self, TestAlarm, TestChip, FLAG_ENABLED, IMAGE_LEN};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A scheduler for one process per entry of `params`, each with a
+    /// `RealTime` header with those period, deadline and budget (in
+    /// milliseconds). The alarm ticks once per millisecond.
+    fn scheduler<P: RealTimePolicy>(
+        params: &[(u32, u32, u32)],
+    ) -> (
+        &'static Kernel,
+        &'static RealTimeSched<'static, TestAlarm, P>,
+        &'static TestAlarm,
+        Vec<&'static RealTimeProcessNode<'static>>,
+    ) {
+        let procs: &'static [ProcessSlot] =
+            Box::leak(vec![EMPTY_PROCESS_SLOT; params.len()].into_boxed_slice());
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(procs)));
+        let flash = Box::leak(vec![0u8; (params.len() + 1) * IMAGE_LEN].into_boxed_slice());
+        for (i, (image, (period, deadline, budget))) in
+            flash.chunks_mut(IMAGE_LEN).zip(params.iter()).enumerate()
+        {
+            let mut real_time = Vec::new();
+            real_time.extend_from_slice(&11u16.to_le_bytes());
+            real_time.extend_from_slice(&12u16.to_le_bytes());
+            for ms in [period, deadline, budget] {
+                real_time.extend_from_slice(&(ms * 1000).to_le_bytes());
+            }
+            test_support::write_image_with_tlvs(
+                image,
+                FLAG_ENABLED,
+                &std::format!("rt{}", i),
+                &real_time,
+            );
+        }
+        let flash: &'static [u8] = flash;
+        let loader = test_support::loader(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(16384),
+        );
+        for i in 0..params.len() {
+            loader
+                .load_process(flash.as_ptr() as usize + i * IMAGE_LEN)
+                .unwrap();
+        }
+
+        let alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm::default()));
+        let sched: &'static RealTimeSched<'static, TestAlarm, P> =
+            Box::leak(Box::new(RealTimeSched::new(alarm)));
+        let nodes: Vec<&'static RealTimeProcessNode<'static>> = procs
+            .iter()
+            .map(|slot| &*Box::leak(Box::new(RealTimeProcessNode::new(slot))))
+            .collect();
+        for node in nodes.iter() {
+            sched.processes.push_tail(node);
+        }
+        (kernel, sched, alarm, nodes)
+    }
+
+    fn next<P: RealTimePolicy>(
+        sched: &RealTimeSched<'static, TestAlarm, P>,
+        kernel: &Kernel,
+    ) -> SchedulingDecision {
+        Scheduler::<TestChip>::next(sched, kernel)
+    }
+
+    fn runs(decision: SchedulingDecision, node: &RealTimeProcessNode, timeslice_us: u32) -> bool {
+        match decision {
+            SchedulingDecision::RunProcess((processid, timeslice)) => {
+                node.proc.get().map(|proc| proc.processid()) == Some(processid)
+                    && timeslice == Some(timeslice_us)
+            }
+            SchedulingDecision::TrySleep => false,
+        }
+    }
+
+    fn misses(node: &RealTimeProcessNode) -> usize {
+        node.proc.get().unwrap().debug_deadline_miss_count()
+    }
+
+    #[test]
+    fn period_release() {
+        // A 10 ms period with a 2 ms budget.
+        let (kernel, sched, alarm, nodes) = scheduler::<EarliestDeadlineFirst>(&[(10, 10, 2)]);
+        let rt = nodes[0];
+
+        assert!(runs(next(sched, kernel), rt, 2000));
+        Scheduler::<TestChip>::result(sched, StoppedExecutingReason::TimesliceExpired, Some(2000));
+
+        // Out of budget: sleep until the next release at 10 ms.
+        alarm.advance(2);
+        assert!(matches!(next(sched, kernel), SchedulingDecision::TrySleep));
+        assert_eq!(alarm.alarm.get(), 10);
+
+        // The release restores the budget.
+        alarm.advance(8);
+        assert!(runs(next(sched, kernel), rt, 2000));
+        assert_eq!(rt.release_us.get(), 10_000);
+        Scheduler::<TestChip>::result(sched, StoppedExecutingReason::TimesliceExpired, Some(500));
+        assert_eq!(rt.budget_used_us.get(), 500);
+
+        // After several periods without scheduling, the release moves forward
+        // by whole periods.
+        alarm.advance(35);
+        assert!(runs(next(sched, kernel), rt, 2000));
+        assert_eq!(rt.release_us.get(), 40_000);
+        assert_eq!(rt.budget_used_us.get(), 0);
+    }
+
+    #[test]
+    fn deadline_misses() {
+        // Two processes with a 10 ms period and a 5 ms deadline. The second
+        // is not ready, so it never misses its deadline.
+        let (kernel, sched, alarm, nodes) =
+            scheduler::<EarliestDeadlineFirst>(&[(10, 5, 0), (10, 5, 0)]);
+        nodes[1].proc.get().unwrap().dequeue_task();
+
+        next(sched, kernel);
+        alarm.advance(4);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 0);
+
+        alarm.advance(2);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 1);
+
+        // At most one miss per period.
+        alarm.advance(1);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 1);
+
+        // The next period is released and its deadline passes between two
+        // updates.
+        alarm.advance(9);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 2);
+        assert_eq!(nodes[0].release_us.get(), 10_000);
+
+        alarm.advance(4);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 2);
+        alarm.advance(5);
+        next(sched, kernel);
+        assert_eq!(misses(nodes[0]), 3);
+        assert_eq!(misses(nodes[1]), 0);
+    }
+
+    #[test]
+    fn clock_wraps() {
+        let (kernel, sched, alarm, nodes) = scheduler::<RateMonotonic>(&[(10, 5, 0)]);
+        let rt = nodes[0];
+        let params = rt.proc.get().unwrap().get_real_time_parameters().unwrap();
+
+        // The first period starts 7.295 ms before the microsecond clock
+        // wraps.
+        alarm.advance(4_294_960);
+        next(sched, kernel);
+        assert_eq!(rt.release_us.get(), 4_294_960_000);
+
+        alarm.advance(6);
+        next(sched, kernel);
+        assert_eq!(misses(rt), 1);
+
+        // The clock wraps during the second period, which starts 2.704 ms
+        // after the wrap.
+        alarm.advance(5);
+        next(sched, kernel);
+        assert_eq!(sched.now_us.get(), 3_704);
+        assert_eq!(rt.release_us.get(), 2_704);
+        assert_eq!(rt.time_to_deadline(&params, 3_704), 4_000);
+        assert_eq!(rt.time_to_next_event(&params, 3_704), 4_000);
+        assert_eq!(misses(rt), 1);
+
+        alarm.advance(5);
+        next(sched, kernel);
+        assert_eq!(misses(rt), 2);
+    }
+
+    #[test]
+    fn policies() {
+        // The first process has the earlier deadline, the second the shorter
+        // period.
+        let params = [(20, 4, 0), (10, 10, 0)];
+        let (kernel, sched, _, nodes) = scheduler::<EarliestDeadlineFirst>(&params);
+        assert!(runs(next(sched, kernel), nodes[0], 4000));
+        let (kernel, sched, _, nodes) = scheduler::<RateMonotonic>(&params);
+        assert!(runs(next(sched, kernel), nodes[1], 4000));
+    }
+}
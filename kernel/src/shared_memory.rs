@@ -0,0 +1,283 @@
+//! Memory sharing between processes using the MPU.
+//!
+//! This is a syscall driver that lets a process give another process direct
+//! access to part of its RAM, so that large buffers can be passed between
+//! processes without copying them. Unlike the `ipc` driver, which only maps
+//! the shared buffer while it notifies the other process, a share stays in
+//! place across context switches until the owner revokes it.
+//!
+//! The owner selects the memory to share with a read-write allow, which makes
+//! sure it belongs to the owner and keeps the owner from giving it back to
+//! the kernel with `brk`. It then shares it with a process, found for example
+//! with the discovery command of the IPC drivers, either read-only or
+//! read-write. The kernel adds an MPU region for exactly that memory to the
+//! receiving process, so the memory must meet the alignment rules of the MPU:
+//! on Cortex-M the start and size must work with a power of two sized region
+//! or its subregions, with PMP they must be multiples of 4 bytes and at least
+//! 8 bytes. A share that the MPU cannot protect exactly fails with `INVAL`.
+//!
+//! The receiving process gets an upcall when memory is shared with it and
+//! when a share is revoked. Shares end when the owner revokes them, and also
+//! when the owner or the receiving process stops or restarts. This is checked
+//! before each switch to a process, so the driver must be the board's
+//! `ContextSwitchCallback`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let shared_memory = static_init!(
+//!     kernel::shared_memory::SharedMemory<4>,
+//!     kernel::shared_memory::SharedMemory::new(
+//!         board_kernel,
+//!         kernel::shared_memory::DRIVER_NUM,
+//!         &memory_allocation_capability,
+//!     )
+//! );
+//! ```
+//!
+//! and return `shared_memory` from `KernelResources::context_switch_callback()`.
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use crate::kernel::Kernel;
+use crate::platform::mpu;
+use crate::platform::ContextSwitchCallback;
+use crate::process::{self, ProcessId, State};
+use crate::processbuffer::ReadableProcessBuffer;
+use crate::syscall_driver::{CommandReturn, SyscallDriver};
+use crate::utilities::cells::OptionalCell;
+use crate::ErrorCode;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10002;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Memory to share.
+    pub(super) const SHARE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub(super) const COUNT: usize = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// Memory was shared with the process.
+    pub(super) const SHARED: usize = 0;
+    /// A share with the process ended.
+    pub(super) const REVOKED: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub(super) const COUNT: usize = 2;
+}
+
+/// Access mode argument of the share command for read-only access.
+const MODE_READ_ONLY: usize = 0;
+/// Access mode argument of the share command for read-write access.
+const MODE_READ_WRITE: usize = 1;
+
+/// Per-process state of the driver, which only needs the upcalls and allow.
+#[derive(Default)]
+struct SharedMemoryData;
+
+/// Memory that one process shares with another.
+#[derive(Clone, Copy)]
+struct Share {
+    owner: ProcessId,
+    receiver: ProcessId,
+    region: mpu::Region,
+}
+
+/// The shared memory mechanism struct.
+pub struct SharedMemory<const NUM_SHARES: usize> {
+    data: Grant<
+        SharedMemoryData,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// Active shares. The index of a share is its handle.
+    shares: [OptionalCell<Share>; NUM_SHARES],
+}
+
+impl<const NUM_SHARES: usize> SharedMemory<NUM_SHARES> {
+    pub fn new(
+        kernel: &'static Kernel,
+        driver_num: usize,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> Self {
+        const NO_SHARE: OptionalCell<Share> = OptionalCell::empty();
+        Self {
+            data: kernel.create_grant(driver_num, capability),
+            shares: [NO_SHARE; NUM_SHARES],
+        }
+    }
+
+    /// Whether `processid` still refers to a process that can use or own
+    /// shared memory.
+    fn is_alive(&self, processid: ProcessId) -> bool {
+        self.data
+            .kernel
+            .process_map_or(false, processid, |process| {
+                !matches!(
+                    process.get_state(),
+                    State::Terminated | State::Faulted | State::Unstarted
+                )
+            })
+    }
+
+    /// Share the memory `owner` allowed with the process with identifier
+    /// `receiver_id`. Returns the handle of the share.
+    fn share(&self, owner: ProcessId, receiver_id: usize, mode: usize) -> Result<usize, ErrorCode> {
+        let permissions = match mode {
+            MODE_READ_ONLY => mpu::Permissions::ReadOnly,
+            MODE_READ_WRITE => mpu::Permissions::ReadWriteOnly,
+            _ => return Err(ErrorCode::INVAL),
+        };
+        let receiver = self
+            .data
+            .kernel
+            .process_until(|p| {
+                if p.processid().id() == receiver_id {
+                    Some(p.processid())
+                } else {
+                    None
+                }
+            })
+            .ok_or(ErrorCode::INVAL)?;
+        if receiver == owner {
+            return Err(ErrorCode::INVAL);
+        }
+        let handle = self
+            .shares
+            .iter()
+            .position(|share| share.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+
+        let (start, size) = self
+            .data
+            .enter(owner, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::SHARE)
+                    .map(|buffer| (buffer.ptr(), buffer.len()))
+            })
+            .and_then(|buffer| buffer)
+            .map_err(ErrorCode::from)?;
+        if size == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let region = self
+            .data
+            .kernel
+            .process_map_or(None, receiver, |process| {
+                process.add_exact_mpu_region(start, size, permissions)
+            })
+            .ok_or(ErrorCode::INVAL)?;
+        self.shares[handle].set(Share {
+            owner,
+            receiver,
+            region,
+        });
+
+        let _ = self.data.enter(receiver, |_, kernel_data| {
+            kernel_data.schedule_upcall(upcall::SHARED, (owner.id(), start as usize, size))
+        });
+        Ok(handle)
+    }
+
+    /// End the share with handle `handle`, if `owner` owns it.
+    fn revoke(&self, owner: ProcessId, handle: usize) -> Result<(), ErrorCode> {
+        let share = self
+            .shares
+            .get(handle)
+            .and_then(|share| share.extract())
+            .ok_or(ErrorCode::INVAL)?;
+        if share.owner != owner {
+            return Err(ErrorCode::INVAL);
+        }
+        self.end_share(handle, share);
+        Ok(())
+    }
+
+    /// Remove the MPU region of `share` from the receiving process and let
+    /// it know.
+    fn end_share(&self, handle: usize, share: Share) {
+        self.shares[handle].clear();
+        self.data
+            .kernel
+            .process_map_or((), share.receiver, |process| {
+                let _ = process.remove_mpu_region(share.region);
+            });
+        let _ = self.data.enter(share.receiver, |_, kernel_data| {
+            kernel_data.schedule_upcall(
+                upcall::REVOKED,
+                (
+                    share.owner.id(),
+                    share.region.start_address() as usize,
+                    share.region.size(),
+                ),
+            )
+        });
+    }
+}
+
+impl<const NUM_SHARES: usize> ContextSwitchCallback for SharedMemory<NUM_SHARES> {
+    fn context_switch_hook(&self, process: &dyn process::Process) {
+        let processid = process.processid();
+        for (handle, share) in self.shares.iter().enumerate() {
+            let share = match share.extract() {
+                Some(share) if share.receiver == processid => share,
+                _ => continue,
+            };
+            // Memory of an owner that has stopped or restarted may be reused
+            // at any time, so the receiver must lose access before it runs.
+            // A receiver that restarted already lost its MPU regions.
+            if !self.is_alive(share.owner) {
+                self.end_share(handle, share);
+            }
+        }
+        // Also forget shares whose receiver is gone, so their handles can be
+        // reused.
+        for share in self.shares.iter() {
+            if share.map_or(false, |share| share.receiver.index().is_none()) {
+                share.clear();
+            }
+        }
+    }
+}
+
+impl<const NUM_SHARES: usize> SyscallDriver for SharedMemory<NUM_SHARES> {
+    /// Share memory with other processes and revoke shares.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Share the memory passed to `allow_readwrite` 0 with the process
+    ///        with identifier `arg1`, read-only if `arg2` is 0 and read-write
+    ///        if `arg2` is 1. Returns a handle for the share, `INVAL` if the
+    ///        process does not exist or the MPU cannot protect exactly the
+    ///        memory, and `NOMEM` if there are too many shares.
+    /// - `2`: Revoke the share with handle `arg1`. Returns `INVAL` if the
+    ///        calling process does not own a share with that handle.
+    fn command(
+        &self,
+        command_number: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => match self.share(processid, arg1, arg2) {
+                Ok(handle) => CommandReturn::success_u32(handle as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+            2 => self.revoke(processid, arg1).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), crate::process::Error> {
+        self.data.enter(processid, |_, _| {})
+    }
+}
@@ -0,0 +1,81 @@
+//! Permissions of a process to access persistent storage.
+//!
+//! Persistent data (for example key-value pairs or log entries) is tagged with
+//! a 32-bit storage identifier when it is written. A process's storage
+//! permissions say which identifier its writes are tagged with, and which
+//! identifiers it may read or modify.
+//!
+//! Permissions come from the `PersistentAcl` TBF header if the app has one.
+//! Otherwise, an app with a persistent `AppId` gets access to only its own
+//! data, using its app ID as its storage identifier. Apps with neither have no
+//! access to persistent storage, since nothing stable ties data they write in
+//! one boot to the same app in the next.
+//!
+//! Only processes whose credentials were checked and approved have storage
+//! permissions. Without credentials nothing authenticates the TBF header, so
+//! an app could claim the identifiers of another app.
+
+use crate::process::AppId;
+
+/// The maximum number of read and modify identifiers a process can have.
+pub const MAX_STORAGE_IDS: usize = 8;
+
+/// Storage permissions of a process.
+#[derive(Clone, Copy, Debug)]
+pub struct StoragePermissions {
+    write_id: Option<u32>,
+    read_count: usize,
+    read_ids: [u32; MAX_STORAGE_IDS],
+    modify_count: usize,
+    modify_ids: [u32; MAX_STORAGE_IDS],
+}
+
+impl StoragePermissions {
+    /// Permissions from an explicit access control list.
+    ///
+    /// Only the first `read_count` entries of `read_ids` and the first
+    /// `modify_count` entries of `modify_ids` are used.
+    pub fn new(
+        write_id: Option<u32>,
+        read_count: usize,
+        read_ids: [u32; MAX_STORAGE_IDS],
+        modify_count: usize,
+        modify_ids: [u32; MAX_STORAGE_IDS],
+    ) -> StoragePermissions {
+        StoragePermissions {
+            write_id,
+            read_count: core::cmp::min(read_count, MAX_STORAGE_IDS),
+            read_ids,
+            modify_count: core::cmp::min(modify_count, MAX_STORAGE_IDS),
+            modify_ids,
+        }
+    }
+
+    /// Permissions to read, write and modify only the data of the app with
+    /// persistent identifier `app_id`. Returns `None` if `app_id` is not
+    /// persistent.
+    pub fn for_app_id(app_id: AppId) -> Option<StoragePermissions> {
+        let id = app_id.persistent_id()?.get();
+        let mut ids = [0; MAX_STORAGE_IDS];
+        ids[0] = id;
+        Some(StoragePermissions::new(Some(id), 1, ids, 1, ids))
+    }
+
+    /// The identifier new data written by this process is tagged with, or
+    /// `None` if the process may not write persistent data.
+    pub fn write_id(&self) -> Option<u32> {
+        self.write_id
+    }
+
+    /// Whether this process may read data tagged with `storage_id`.
+    pub fn can_read(&self, storage_id: u32) -> bool {
+        self.read_ids[..self.read_count].contains(&storage_id)
+    }
+
+    /// Whether this process may modify or delete existing data tagged with
+    /// `storage_id`. A process may always modify data it wrote.
+    pub fn can_modify(&self, storage_id: u32) -> bool {
+        self.write_id == Some(storage_id)
+            || self.modify_ids[..self.modify_count].contains(&storage_id)
+    }
+}
@@ -453,6 +453,27 @@ impl SyscallReturn {
     }
 }
 
+/// What the kernel does with a process that makes more system calls in one
+/// timeslice than its `SyscallRateLimit` allows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SyscallRateLimitAction {
+    /// End the timeslice of the process as if it had expired, so that other
+    /// processes and the kernel get to run.
+    Yield,
+    /// Keep running the process, but fail its further system calls in this
+    /// timeslice with `BUSY`. Yield and exit are never throttled.
+    Throttle,
+}
+
+/// Limit on how many system calls a process can make in one timeslice, to
+/// keep a process that floods the kernel with system calls from starving
+/// other processes and the kernel's own work.
+#[derive(Copy, Clone, Debug)]
+pub struct SyscallRateLimit {
+    pub max_syscalls: usize,
+    pub action: SyscallRateLimitAction,
+}
+
 // ---------- USERSPACE KERNEL BOUNDARY ----------
 
 /// `ContentSwitchReason` specifies why the process stopped executing and
@@ -652,4 +673,17 @@ pub trait UserspaceKernelBoundary {
     /// Store architecture specific (e.g. CPU registers or status flags) data
     /// for a process. On success returns the number of elements written to out.
     fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Restore architecture specific (e.g. CPU registers or status flags) data
+    /// for a process from `stored`, which must have been written by
+    /// `store_context()` for the same architecture. On failure `state` is not
+    /// changed.
+    ///
+    /// The restored state is not trusted: as with any process state, the
+    /// kernel checks it against the process's memory before using it.
+    fn restore_context(
+        &self,
+        state: &mut Self::StoredState,
+        stored: &[u8],
+    ) -> Result<(), ErrorCode>;
 }
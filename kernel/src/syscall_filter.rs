@@ -0,0 +1,403 @@
+//! System call filtering based on TBF permissions and a board access list.
+//!
+//! `AclSyscallFilter` decides whether a process may make a system call by
+//! combining two sources of permissions:
+//!
+//! - The `Permissions` TBF header of the process, if it has one. Commands are
+//!   allowed only if their command number is in the header's mask for the
+//!   driver. Subscribe and allow calls are allowed if the header has any
+//!   permissions for the driver.
+//! - A list of `AppSyscallAcl` entries provided by the board, keyed by the
+//!   package name of the process. An entry lists the drivers the app may use
+//!   and the memop operations it may call. Apps without an entry may call
+//!   every memop, and either use every driver or none, as chosen by the
+//!   board.
+//!
+//! A system call must be allowed by both to run. Yield and exit are never
+//! filtered, but memop is, so an access list entry must list the memop
+//! operations the app needs, or `ALL_MEMOPS`. In `FilterMode::Report` the
+//! filter lets the calls it would deny run, which together with the
+//! `debug_syscall_filter` kernel feature is useful to find out what an access
+//! list is missing before enforcing it. With that feature every denied call
+//! is logged with `debug!`.
+//!
+//! Package names are not authenticated, so boards should only rely on the
+//! access list together with a credentials checking policy that restricts
+//! which apps can run.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! static SYSCALL_ACL: [kernel::syscall_filter::AppSyscallAcl; 2] = [
+//!     kernel::syscall_filter::AppSyscallAcl {
+//!         package_name: "shell",
+//!         drivers: &[capsules::console::DRIVER_NUM, capsules::led::DRIVER_NUM],
+//!         memops: kernel::syscall_filter::ALL_MEMOPS,
+//!     },
+//!     kernel::syscall_filter::AppSyscallAcl {
+//!         package_name: "untrusted",
+//!         drivers: &[capsules::alarm::DRIVER_NUM],
+//!         memops: kernel::syscall_filter::ALL_MEMOPS,
+//!     },
+//! ];
+//!
+//! let syscall_filter = static_init!(
+//!     kernel::syscall_filter::AclSyscallFilter,
+//!     kernel::syscall_filter::AclSyscallFilter::new(
+//!         &SYSCALL_ACL,
+//!         false, // apps without an entry may not use any driver
+//!         kernel::syscall_filter::FilterMode::Enforce,
+//!     )
+//! );
+//! ```
+
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::platform::SyscallFilter;
+use crate::process::Process;
+use crate::syscall::Syscall;
+use tock_tbf::types::CommandPermissions;
+
+/// Bitmask that allows every memop operation.
+pub const ALL_MEMOPS: u32 = 0xFFFFFFFF;
+
+/// The system calls an app may make, as set by the board.
+pub struct AppSyscallAcl {
+    /// The package name of the app this entry applies to.
+    pub package_name: &'static str,
+    /// Driver numbers the app may use with command, subscribe and allow.
+    pub drivers: &'static [usize],
+    /// Bitmask of the memop operations the app may call: bit `n` allows
+    /// memop operation `n`.
+    pub memops: u32,
+}
+
+/// Whether denied system calls are blocked or only reported.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FilterMode {
+    /// Denied system calls fail.
+    Enforce,
+    /// Denied system calls still run, and are only logged.
+    Report,
+}
+
+/// System call filter that combines the `Permissions` TBF header with a
+/// board access list.
+pub struct AclSyscallFilter {
+    acl: &'static [AppSyscallAcl],
+    allow_unlisted: bool,
+    mode: FilterMode,
+}
+
+impl AclSyscallFilter {
+    /// Create a filter using the access list `acl`. `allow_unlisted` sets
+    /// whether apps without an entry in `acl` may use any driver (limited
+    /// only by their TBF header) or none at all.
+    pub fn new(acl: &'static [AppSyscallAcl], allow_unlisted: bool, mode: FilterMode) -> Self {
+        AclSyscallFilter {
+            acl,
+            allow_unlisted,
+            mode,
+        }
+    }
+
+    /// Returns the access list entry of `process`, if it has one.
+    fn entry(&self, process: &dyn Process) -> Option<&'static AppSyscallAcl> {
+        let name = process.get_process_name();
+        self.acl.iter().find(|entry| entry.package_name == name)
+    }
+
+    /// Whether the board access list allows `process` to use `driver_number`.
+    fn acl_allows_driver(&self, process: &dyn Process, driver_number: usize) -> bool {
+        self.entry(process).map_or(self.allow_unlisted, |entry| {
+            entry.drivers.contains(&driver_number)
+        })
+    }
+
+    /// Whether the board access list allows `process` to call memop
+    /// `operand`.
+    fn acl_allows_memop(&self, process: &dyn Process, operand: usize) -> bool {
+        self.entry(process).map_or(true, |entry| {
+            operand < 32 && entry.memops & (1 << operand) != 0
+        })
+    }
+
+    /// Whether the TBF header of `process` allows it to use `driver_number`
+    /// at all.
+    fn tbf_allows_driver(process: &dyn Process, driver_number: usize) -> bool {
+        !matches!(
+            process.get_command_permissions(driver_number, 0),
+            CommandPermissions::NoPermsThisDriver
+        )
+    }
+
+    /// Whether the TBF header of `process` allows command `command_number`
+    /// of `driver_number`.
+    fn tbf_allows_command(
+        process: &dyn Process,
+        driver_number: usize,
+        command_number: usize,
+    ) -> bool {
+        match process.get_command_permissions(driver_number, command_number / 64) {
+            CommandPermissions::NoPermsAtAll => true,
+            CommandPermissions::NoPermsThisDriver => false,
+            CommandPermissions::Mask(allowed) => (1 << (command_number % 64)) & allowed != 0,
+        }
+    }
+
+    /// Decide on `syscall` without logging.
+    fn check(&self, process: &dyn Process, syscall: &Syscall) -> Result<(), ErrorCode> {
+        let (allowed, error) = match *syscall {
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (
+                self.acl_allows_driver(process, driver_number)
+                    && Self::tbf_allows_command(process, driver_number, subdriver_number),
+                ErrorCode::NODEVICE,
+            ),
+            Syscall::Subscribe { driver_number, .. }
+            | Syscall::ReadWriteAllow { driver_number, .. }
+            | Syscall::UserspaceReadableAllow { driver_number, .. }
+            | Syscall::ReadOnlyAllow { driver_number, .. } => (
+                self.acl_allows_driver(process, driver_number)
+                    && Self::tbf_allows_driver(process, driver_number),
+                ErrorCode::NODEVICE,
+            ),
+            Syscall::Memop { operand, .. } => (
+                self.acl_allows_memop(process, operand),
+                ErrorCode::NOSUPPORT,
+            ),
+            Syscall::Yield { .. } | Syscall::Exit { .. } => (true, ErrorCode::FAIL),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+impl SyscallFilter for AclSyscallFilter {
+    fn filter_syscall(&self, process: &dyn Process, syscall: &Syscall) -> Result<(), ErrorCode> {
+        match self.check(process, syscall) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let enforce = self.mode == FilterMode::Enforce;
+                if config::CONFIG.debug_syscall_filter {
+                    debug!(
+                        "Syscall filter: {} {:?} from {:?}",
+                        if enforce { "denied" } else { "would deny" },
+                        syscall,
+                        process.get_process_name()
+                    );
+                }
+                if enforce {
+                    Err(e)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dynamic_process_loader::DynamicProcessLoading;
+    use crate::test_support::{self, FLAG_ENABLED};
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Length of each test image in flash.
+    const IMAGE_LEN: usize = 128;
+
+    const LISTED_DRIVER: usize = 1;
+    const OTHER_LISTED_DRIVER: usize = 2;
+    const UNLISTED_DRIVER: usize = 3;
+
+    static ACL: [AppSyscallAcl; 1] = [AppSyscallAcl {
+        package_name: "shell",
+        drivers: &[LISTED_DRIVER, OTHER_LISTED_DRIVER],
+        // brk and sbrk only.
+        memops: 0b11,
+    }];
+
+    /// A Permissions TLV that allows commands 0 and 2 of `LISTED_DRIVER`.
+    fn permissions_tlv() -> Vec<u8> {
+        let mut tlv = Vec::new();
+        tlv.extend_from_slice(&6u16.to_le_bytes());
+        tlv.extend_from_slice(&18u16.to_le_bytes());
+        tlv.extend_from_slice(&1u16.to_le_bytes());
+        tlv.extend_from_slice(&(LISTED_DRIVER as u32).to_le_bytes());
+        tlv.extend_from_slice(&0u32.to_le_bytes());
+        tlv.extend_from_slice(&0b101u64.to_le_bytes());
+        // Pad the TLV to a multiple of 4 bytes.
+        tlv.extend_from_slice(&[0, 0]);
+        tlv
+    }
+
+    /// Load `shell`, which has an access list entry and a Permissions
+    /// header, and `other`, which has neither.
+    fn processes() -> (&'static dyn Process, &'static dyn Process) {
+        test_support::debug_writer();
+        let kernel = test_support::kernel(2);
+        let flash = Box::leak(vec![0u8; 3 * IMAGE_LEN].into_boxed_slice());
+        test_support::write_image_with_tlvs(
+            &mut flash[..IMAGE_LEN],
+            FLAG_ENABLED,
+            "shell",
+            &permissions_tlv(),
+        );
+        test_support::write_image(&mut flash[IMAGE_LEN..2 * IMAGE_LEN], FLAG_ENABLED, "other");
+        let flash: &'static [u8] = flash;
+        let loader = test_support::loader(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(8192),
+        );
+        let shell = loader.load_process(flash.as_ptr() as usize).unwrap();
+        let other = loader
+            .load_process(flash.as_ptr() as usize + IMAGE_LEN)
+            .unwrap();
+        (
+            kernel.get_process(shell).unwrap(),
+            kernel.get_process(other).unwrap(),
+        )
+    }
+
+    fn command(driver_number: usize, subdriver_number: usize) -> Syscall {
+        Syscall::Command {
+            driver_number,
+            subdriver_number,
+            arg0: 0,
+            arg1: 0,
+        }
+    }
+
+    fn subscribe(driver_number: usize) -> Syscall {
+        Syscall::Subscribe {
+            driver_number,
+            subdriver_number: 0,
+            upcall_ptr: core::ptr::null_mut(),
+            appdata: 0,
+        }
+    }
+
+    fn memop(operand: usize) -> Syscall {
+        Syscall::Memop { operand, arg0: 0 }
+    }
+
+    #[test]
+    fn drivers() {
+        let (shell, other) = processes();
+        let filter = AclSyscallFilter::new(&ACL, false, FilterMode::Enforce);
+        let denied = Err(ErrorCode::NODEVICE);
+
+        // The access list and the TBF header both allow these.
+        assert_eq!(
+            filter.filter_syscall(shell, &command(LISTED_DRIVER, 0)),
+            Ok(())
+        );
+        assert_eq!(
+            filter.filter_syscall(shell, &command(LISTED_DRIVER, 2)),
+            Ok(())
+        );
+        assert_eq!(
+            filter.filter_syscall(shell, &subscribe(LISTED_DRIVER)),
+            Ok(())
+        );
+        // The TBF header does not allow this command.
+        assert_eq!(
+            filter.filter_syscall(shell, &command(LISTED_DRIVER, 1)),
+            denied
+        );
+        // The access list allows the driver but the TBF header does not.
+        assert_eq!(
+            filter.filter_syscall(shell, &command(OTHER_LISTED_DRIVER, 0)),
+            denied
+        );
+        assert_eq!(
+            filter.filter_syscall(shell, &subscribe(OTHER_LISTED_DRIVER)),
+            denied
+        );
+        // The access list does not allow the driver.
+        assert_eq!(
+            filter.filter_syscall(shell, &command(UNLISTED_DRIVER, 0)),
+            denied
+        );
+
+        // Apps without an entry may use no driver, or every driver their TBF
+        // header allows.
+        assert_eq!(
+            filter.filter_syscall(other, &command(LISTED_DRIVER, 0)),
+            denied
+        );
+        assert_eq!(
+            filter.filter_syscall(other, &subscribe(UNLISTED_DRIVER)),
+            denied
+        );
+        let allow_unlisted = AclSyscallFilter::new(&ACL, true, FilterMode::Enforce);
+        assert_eq!(
+            allow_unlisted.filter_syscall(other, &command(UNLISTED_DRIVER, 7)),
+            Ok(())
+        );
+        assert_eq!(
+            allow_unlisted.filter_syscall(shell, &command(UNLISTED_DRIVER, 0)),
+            denied
+        );
+    }
+
+    #[test]
+    fn memops() {
+        let (shell, other) = processes();
+        let filter = AclSyscallFilter::new(&ACL, false, FilterMode::Enforce);
+        let denied = Err(ErrorCode::NOSUPPORT);
+
+        assert_eq!(filter.filter_syscall(shell, &memop(0)), Ok(()));
+        assert_eq!(filter.filter_syscall(shell, &memop(1)), Ok(()));
+        assert_eq!(filter.filter_syscall(shell, &memop(2)), denied);
+        // Operations past the end of the mask are never allowed.
+        assert_eq!(filter.filter_syscall(shell, &memop(32)), denied);
+
+        // Apps without an entry may call every memop, even if they may not
+        // use any driver.
+        assert_eq!(filter.filter_syscall(other, &memop(2)), Ok(()));
+        assert_eq!(filter.filter_syscall(other, &memop(40)), Ok(()));
+    }
+
+    #[test]
+    fn report_mode() {
+        let (shell, other) = processes();
+        let filter = AclSyscallFilter::new(&ACL, false, FilterMode::Report);
+
+        // Calls that `Enforce` denies run anyway.
+        assert_eq!(
+            filter.filter_syscall(shell, &command(LISTED_DRIVER, 1)),
+            Ok(())
+        );
+        assert_eq!(
+            filter.filter_syscall(shell, &command(UNLISTED_DRIVER, 0)),
+            Ok(())
+        );
+        assert_eq!(filter.filter_syscall(shell, &memop(2)), Ok(()));
+        assert_eq!(
+            filter.filter_syscall(other, &subscribe(LISTED_DRIVER)),
+            Ok(())
+        );
+        // Yield and exit are not filtered in either mode.
+        let exit = Syscall::Exit {
+            which: 0,
+            completion_code: 0,
+        };
+        assert_eq!(filter.filter_syscall(other, &exit), Ok(()));
+    }
+}
@@ -0,0 +1,427 @@
+//! Fixtures for unit tests that load processes.
+//!
+//! This provides a chip for the host with an MPU that records the memory
+//! layout the kernel configures, a context switch implementation that never
+//! runs the process, and TBF images to load processes from. Tests of code
+//! that prints with `debug!()` also need `debug_writer()`.
+
+extern crate std;
+
+use core::cell::Cell;
+use core::fmt::{self, Display, Write};
+use core::slice;
+use std::boxed::Box;
+use std::format;
+use std::sync::Once;
+use std::vec;
+
+use crate::capabilities::{MemoryAllocationCapability, ProcessManagementCapability};
+use crate::collections::ring_buffer::RingBuffer;
+use crate::debug::{self, DebugWriter, DebugWriterWrapper};
+use crate::dynamic_process_loader::DynamicProcessLoader;
+use crate::errorcode::ErrorCode;
+use crate::hil::time::{Alarm, AlarmClient, Freq1KHz, Ticks, Ticks32, Time};
+use crate::hil::uart;
+use crate::platform::chip::Chip;
+use crate::platform::mpu::{self, MPU};
+use crate::platform::platform::Tracer;
+use crate::process::{ProcessId, ProcessSlot, EMPTY_PROCESS_SLOT};
+use crate::process_policies::PanicFaultPolicy;
+use crate::syscall::{ContextSwitchReason, SyscallReturn, UserspaceKernelBoundary};
+use crate::Kernel;
+
+pub(crate) struct TestBoundary;
+
+impl UserspaceKernelBoundary for TestBoundary {
+    type StoredState = ();
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+        _return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+        _upcall: crate::process::FunctionCall,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        (ContextSwitchReason::Interrupted, None)
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &(),
+        _writer: &mut dyn Write,
+    ) {
+    }
+
+    fn store_context(&self, _state: &(), _out: &mut [u8]) -> Result<usize, ErrorCode> {
+        Ok(0)
+    }
+
+    fn restore_context(&self, _state: &mut (), _stored: &[u8]) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+/// The app memory layout stored in an MPU configuration of `TestMpu`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct TestMpuConfig {
+    /// Start and size of the memory block of the process, if it was
+    /// allocated.
+    pub(crate) app_memory: Option<(usize, usize)>,
+    /// End of the memory the process can access.
+    pub(crate) app_break: usize,
+    /// Start of the kernel-owned memory at the end of the memory block.
+    pub(crate) kernel_break: usize,
+    /// Number of other regions allocated.
+    pub(crate) regions: usize,
+}
+
+impl Display for TestMpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// An MPU without alignment rules that checks that app-owned and
+/// kernel-owned memory stay separate inside of the process memory block.
+#[derive(Default)]
+pub(crate) struct TestMpu {
+    /// The configuration the kernel last applied.
+    pub(crate) configured: Cell<Option<TestMpuConfig>>,
+}
+
+impl MPU for TestMpu {
+    type MpuConfig = TestMpuConfig;
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        _permissions: mpu::Permissions,
+        config: &mut TestMpuConfig,
+    ) -> Option<mpu::Region> {
+        if min_region_size > unallocated_memory_size {
+            None
+        } else {
+            config.regions += 1;
+            Some(mpu::Region::new(unallocated_memory_start, min_region_size))
+        }
+    }
+
+    fn remove_memory_region(
+        &self,
+        _region: mpu::Region,
+        config: &mut TestMpuConfig,
+    ) -> Result<(), ()> {
+        config.regions = config.regions.checked_sub(1).ok_or(())?;
+        Ok(())
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        _permissions: mpu::Permissions,
+        config: &mut TestMpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        let memory_size = core::cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        );
+        if config.app_memory.is_some() || memory_size > unallocated_memory_size {
+            return None;
+        }
+        let start = unallocated_memory_start as usize;
+        config.app_memory = Some((start, memory_size));
+        config.app_break = start + initial_app_memory_size;
+        config.kernel_break = start + memory_size - initial_kernel_memory_size;
+        Some((unallocated_memory_start, memory_size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        _permissions: mpu::Permissions,
+        config: &mut TestMpuConfig,
+    ) -> Result<(), ()> {
+        let (start, size) = config.app_memory.ok_or(())?;
+        let app_break = app_memory_break as usize;
+        let kernel_break = kernel_memory_break as usize;
+        if app_break < start || app_break > kernel_break || kernel_break > start + size {
+            return Err(());
+        }
+        config.app_break = app_break;
+        config.kernel_break = kernel_break;
+        Ok(())
+    }
+
+    fn configure_mpu(&self, config: &TestMpuConfig, _app_id: &ProcessId) {
+        self.configured.set(Some(*config));
+    }
+}
+
+pub(crate) struct TestChip {
+    pub(crate) mpu: TestMpu,
+    pub(crate) userspace_kernel_boundary: TestBoundary,
+}
+
+impl Chip for TestChip {
+    type MPU = TestMpu;
+    type UserspaceKernelBoundary = TestBoundary;
+
+    fn service_pending_interrupts(&self, _tracer: &dyn Tracer) {}
+
+    fn has_pending_interrupts(&self) -> bool {
+        false
+    }
+
+    fn mpu(&self) -> &TestMpu {
+        &self.mpu
+    }
+
+    fn userspace_kernel_boundary(&self) -> &TestBoundary {
+        &self.userspace_kernel_boundary
+    }
+
+    fn sleep(&self) {}
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    unsafe fn print_state(&self, _writer: &mut dyn Write) {}
+}
+
+pub(crate) struct ManagementCap;
+unsafe impl ProcessManagementCapability for ManagementCap {}
+
+pub(crate) struct MemoryCap;
+unsafe impl MemoryAllocationCapability for MemoryCap {}
+
+/// The length of each TBF image in test app flash.
+pub(crate) const IMAGE_LEN: usize = 64;
+
+/// The minimum RAM size in the TBF images.
+pub(crate) const IMAGE_MIN_RAM_SIZE: usize = 1024;
+
+/// TBF header flags.
+pub(crate) const FLAG_ENABLED: u32 = 1 << 0;
+pub(crate) const FLAG_MEMORY_GROWABLE: u32 = 1 << 2;
+
+/// Write a TBF image as long as `flash`, with a base header with `flags`, a
+/// main TLV and a package name TLV with `name`.
+pub(crate) fn write_image(flash: &mut [u8], flags: u32, name: &str) {
+    write_image_with_tlvs(flash, flags, name, &[]);
+}
+
+/// Like `write_image()`, but the header ends with the already encoded TLV
+/// entries `tlvs`.
+pub(crate) fn write_image_with_tlvs(flash: &mut [u8], flags: u32, name: &str, tlvs: &[u8]) {
+    let name_end = 36 + ((name.len() + 3) & !3);
+    let header_len = name_end + tlvs.len();
+    let mut header = vec![0u8; header_len];
+    let mut words = [0u32; 8];
+    // Version 2 and header length
+    words[0] = 2 | ((header_len as u32) << 16);
+    words[1] = flash.len() as u32;
+    words[2] = flags;
+    // Main TLV: init function offset, protected size, minimum RAM size
+    words[4] = 1 | (12 << 16);
+    words[5] = header_len as u32;
+    words[6] = 0;
+    words[7] = IMAGE_MIN_RAM_SIZE as u32;
+    for (i, word) in words.iter().enumerate() {
+        header[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    // Package name TLV
+    header[32..36].copy_from_slice(&(3 | ((name.len() as u32) << 16)).to_le_bytes());
+    header[36..36 + name.len()].copy_from_slice(name.as_bytes());
+    header[name_end..].copy_from_slice(tlvs);
+
+    // The checksum field is still zero, so it does not change the checksum.
+    let checksum = header.chunks(4).fold(0, |checksum, word| {
+        checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+    });
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+    flash[..header_len].copy_from_slice(&header);
+}
+
+/// A kernel with `num_procs` empty process slots.
+pub(crate) fn kernel(num_procs: usize) -> &'static Kernel {
+    let processes: &'static [ProcessSlot] =
+        Box::leak(vec![EMPTY_PROCESS_SLOT; num_procs].into_boxed_slice());
+    Box::leak(Box::new(Kernel::new(processes)))
+}
+
+pub(crate) fn chip() -> &'static TestChip {
+    Box::leak(Box::new(TestChip {
+        mpu: TestMpu::default(),
+        userspace_kernel_boundary: TestBoundary,
+    }))
+}
+
+/// App flash with a TBF image for each entry of `flags`, with those header
+/// flags, followed by `IMAGE_LEN` bytes without an image. The processes are
+/// named `app0`, `app1` and so on.
+pub(crate) fn app_flash(flags: &[u32]) -> &'static [u8] {
+    let flash = Box::leak(vec![0u8; (flags.len() + 1) * IMAGE_LEN].into_boxed_slice());
+    for (i, (image, flags)) in flash.chunks_mut(IMAGE_LEN).zip(flags.iter()).enumerate() {
+        write_image(image, *flags, &format!("app{}", i));
+    }
+    flash
+}
+
+/// Word aligned app memory of `len` bytes.
+pub(crate) fn app_memory(len: usize) -> &'static mut [u8] {
+    let memory = Box::leak(vec![0u64; len / 8].into_boxed_slice());
+    unsafe { slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 8) }
+}
+
+/// A loader for the images in `flash` that places processes in `memory`.
+pub(crate) fn loader(
+    kernel: &'static Kernel,
+    chip: &'static TestChip,
+    flash: &'static [u8],
+    memory: &'static mut [u8],
+) -> DynamicProcessLoader<TestChip> {
+    DynamicProcessLoader::new(
+        kernel,
+        chip,
+        flash,
+        memory,
+        &PanicFaultPolicy {},
+        false,
+        &ManagementCap,
+    )
+}
+
+/// An alarm with a clock that only moves when the test advances it, and
+/// ticks once per millisecond.
+#[derive(Default)]
+pub(crate) struct TestAlarm {
+    pub(crate) now: Cell<u32>,
+    pub(crate) alarm: Cell<u32>,
+}
+
+impl TestAlarm {
+    pub(crate) fn advance(&self, ms: u32) {
+        self.now.set(self.now.get().wrapping_add(ms));
+    }
+}
+
+impl Time for TestAlarm {
+    type Frequency = Freq1KHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        self.now.get().into()
+    }
+}
+
+impl<'a> Alarm<'a> for TestAlarm {
+    fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.alarm.set(reference.wrapping_add(dt).into_u32());
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.alarm.get().into()
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        true
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+/// A UART that accepts the first buffer to transmit and never finishes
+/// sending it, so `debug!()` output is dropped.
+struct DiscardUart;
+
+impl<'a> uart::Transmit<'a> for DiscardUart {
+    fn set_transmit_client(&self, _client: &'a dyn uart::TransmitClient) {}
+
+    fn transmit_buffer(
+        &self,
+        _tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        Ok(())
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::FAIL)
+    }
+
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+/// Set up a debug writer that drops its output, so that `debug!()` does not
+/// panic.
+pub(crate) fn debug_writer() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let ring_buffer = Box::leak(Box::new(RingBuffer::new(Box::leak(Box::new([0u8; 64])))));
+        let debug_writer = Box::leak(Box::new(DebugWriter::new(
+            Box::leak(Box::new(DiscardUart)),
+            Box::leak(Box::new([0u8; 64])),
+            ring_buffer,
+        )));
+        unsafe {
+            debug::set_debug_writer_wrapper(Box::leak(Box::new(DebugWriterWrapper::new(
+                debug_writer,
+            ))));
+        }
+    });
+}
@@ -0,0 +1,341 @@
+//! Binary tracing of kernel events.
+//!
+//! The kernel reports what it is doing to the board's `Tracer` (see
+//! `KernelResources`): switching to processes, system calls, upcalls, and the
+//! interrupts and deferred calls it handles. `RingBufferTracer` stores each
+//! event as a small fixed-size binary record with a timestamp in a
+//! `RingBuffer`, which is cheap enough to leave enabled while measuring
+//! latency. The records are drained separately, for example by
+//! `capsules::kernel_trace`, and decoded on the host with
+//! `tools/trace_decode`. Compare this to the `trace_syscalls` configuration
+//! option, which prints every system call as text as it happens.
+//!
+//! Record format
+//! -------------
+//!
+//! Every record is `TRACE_RECORD_LEN` (16) bytes, with all fields little
+//! endian:
+//!
+//! ```text
+//! 0        1        2                 4                 8                 12                16
+//! +--------+--------+-----------------+-----------------+-----------------+-----------------+
+//! | type   | class  | process id      | timestamp       | arg0            | arg1            |
+//! +--------+--------+-----------------+-----------------+-----------------+-----------------+
+//! ```
+//!
+//! - `type` is a `TraceRecordType`.
+//! - `class` is the `SyscallClass` for `Syscall` records and 0 otherwise.
+//! - `process id` is the low 16 bits of the `ProcessId` identifier, or
+//!   0xFFFF if the event is not about a process.
+//! - `timestamp` is the low 32 bits of the ticks of the tracer's time source.
+//! - `arg0` and `arg1` depend on the type:
+//!   - `Start`: the frequency of the time source in Hz, and 0.
+//!   - `Dropped`: the number of events that were dropped because the ring
+//!     buffer was full, and 0.
+//!   - `Syscall`: the driver number and subdriver number for subscribe,
+//!     command and allow, the operand and argument for memop, and the
+//!     identifier and argument for yield and exit.
+//!   - `Upcall`: the driver number and subscribe number of the upcall, or
+//!     0xFFFFFFFF for both if the kernel started the function itself (for
+//!     example the entry point of the process).
+//!   - `Interrupt`: the number of the interrupt, and 0. Interrupts that do
+//!     not come from the chip's interrupt controller, such as the RISC-V
+//!     machine timer, have `LOCAL_INTERRUPT` set in their number.
+//!   - `DeferredCall`: the handle of the dynamic deferred call, and 0.
+//!   - All others: 0.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! static mut TRACE_RECORDS: [kernel::trace::TraceRecord; 256] =
+//!     [[0; kernel::trace::TRACE_RECORD_LEN]; 256];
+//!
+//! let trace_ring = static_init!(
+//!     RingBuffer<'static, kernel::trace::TraceRecord>,
+//!     RingBuffer::new(&mut TRACE_RECORDS)
+//! );
+//! let tracer = static_init!(
+//!     kernel::trace::RingBufferTracer<'static, sam4l::ast::Ast>,
+//!     kernel::trace::RingBufferTracer::new(&peripherals.ast, trace_ring)
+//! );
+//! ```
+//!
+//! and return `tracer` from `KernelResources::tracer()`.
+
+use core::cell::Cell;
+
+use crate::collections::queue::Queue;
+use crate::collections::ring_buffer::RingBuffer;
+use crate::dynamic_deferred_call::DeferredCallHandle;
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Tracer;
+use crate::process::{FunctionCallSource, ProcessId};
+use crate::syscall::{Syscall, SyscallClass};
+use crate::utilities::cells::TakeCell;
+
+/// Length of one binary trace record in bytes.
+pub const TRACE_RECORD_LEN: usize = 16;
+
+/// One binary trace record.
+pub type TraceRecord = [u8; TRACE_RECORD_LEN];
+
+/// Set in the number of `Interrupt` events for interrupts that are local to
+/// the core rather than numbered by the interrupt controller. The remaining
+/// bits are the bit of the interrupt in the `mip` register on RISC-V.
+pub const LOCAL_INTERRUPT: u32 = 0x8000_0000;
+
+/// Process id field of records that are not about a process.
+const NO_PROCESS: u16 = 0xFFFF;
+
+/// The type of a trace record, stored in its first byte.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TraceRecordType {
+    /// The tracer started.
+    Start = 0,
+    /// Events were dropped because the ring buffer was full.
+    Dropped = 1,
+    /// The kernel switched to a process.
+    ProcessRun = 2,
+    /// A process made a system call.
+    Syscall = 3,
+    /// A process was interrupted by a hardware interrupt.
+    ProcessInterrupted = 4,
+    /// A process faulted.
+    ProcessFaulted = 5,
+    /// A process used up its timeslice.
+    TimesliceExpired = 6,
+    /// The kernel set a process up to run an upcall.
+    Upcall = 7,
+    /// The kernel is servicing an interrupt.
+    Interrupt = 8,
+    /// The kernel is running a dynamic deferred call.
+    DeferredCall = 9,
+    /// The kernel finished servicing interrupts and deferred calls.
+    KernelWorkDone = 10,
+}
+
+/// An event in the kernel that can be traced.
+#[derive(Clone, Copy, Debug)]
+pub enum TraceEvent {
+    /// The kernel is about to switch to the process.
+    ProcessRun { processid: ProcessId },
+    /// The process made the system call `syscall`.
+    Syscall {
+        processid: ProcessId,
+        syscall: Syscall,
+    },
+    /// The process was interrupted by a hardware interrupt.
+    ProcessInterrupted { processid: ProcessId },
+    /// The process faulted.
+    ProcessFaulted { processid: ProcessId },
+    /// The process used up its timeslice.
+    TimesliceExpired { processid: ProcessId },
+    /// The kernel set the process up to run a function, typically an upcall.
+    Upcall {
+        processid: ProcessId,
+        source: FunctionCallSource,
+    },
+    /// The chip is about to service interrupt number `irq`.
+    Interrupt { irq: u32 },
+    /// The kernel is about to run the dynamic deferred call `handle`.
+    DeferredCall { handle: DeferredCallHandle },
+    /// The kernel finished servicing interrupts and deferred calls.
+    KernelWorkDone,
+}
+
+impl TraceEvent {
+    /// Returns the record type, syscall class, process id, `arg0` and `arg1`
+    /// fields of the record for this event.
+    fn fields(&self) -> (TraceRecordType, u8, u16, u32, u32) {
+        let id = |processid: &ProcessId| processid.id() as u16;
+        match self {
+            TraceEvent::ProcessRun { processid } => {
+                (TraceRecordType::ProcessRun, 0, id(processid), 0, 0)
+            }
+            TraceEvent::Syscall { processid, syscall } => {
+                let (class, arg0, arg1) = match *syscall {
+                    Syscall::Yield { which, .. } => (SyscallClass::Yield, which, 0),
+                    Syscall::Subscribe {
+                        driver_number,
+                        subdriver_number,
+                        ..
+                    } => (SyscallClass::Subscribe, driver_number, subdriver_number),
+                    Syscall::Command {
+                        driver_number,
+                        subdriver_number,
+                        ..
+                    } => (SyscallClass::Command, driver_number, subdriver_number),
+                    Syscall::ReadWriteAllow {
+                        driver_number,
+                        subdriver_number,
+                        ..
+                    } => (
+                        SyscallClass::ReadWriteAllow,
+                        driver_number,
+                        subdriver_number,
+                    ),
+                    Syscall::UserspaceReadableAllow {
+                        driver_number,
+                        subdriver_number,
+                        ..
+                    } => (
+                        SyscallClass::UserspaceReadableAllow,
+                        driver_number,
+                        subdriver_number,
+                    ),
+                    Syscall::ReadOnlyAllow {
+                        driver_number,
+                        subdriver_number,
+                        ..
+                    } => (SyscallClass::ReadOnlyAllow, driver_number, subdriver_number),
+                    Syscall::Memop { operand, arg0 } => (SyscallClass::Memop, operand, arg0),
+                    Syscall::Exit {
+                        which,
+                        completion_code,
+                    } => (SyscallClass::Exit, which, completion_code),
+                };
+                (
+                    TraceRecordType::Syscall,
+                    class as u8,
+                    id(processid),
+                    arg0 as u32,
+                    arg1 as u32,
+                )
+            }
+            TraceEvent::ProcessInterrupted { processid } => {
+                (TraceRecordType::ProcessInterrupted, 0, id(processid), 0, 0)
+            }
+            TraceEvent::ProcessFaulted { processid } => {
+                (TraceRecordType::ProcessFaulted, 0, id(processid), 0, 0)
+            }
+            TraceEvent::TimesliceExpired { processid } => {
+                (TraceRecordType::TimesliceExpired, 0, id(processid), 0, 0)
+            }
+            TraceEvent::Upcall { processid, source } => {
+                let (arg0, arg1) = match source {
+                    FunctionCallSource::Kernel => (0xFFFFFFFF, 0xFFFFFFFF),
+                    FunctionCallSource::Driver(upcall_id) => {
+                        (upcall_id.driver_num as u32, upcall_id.subscribe_num as u32)
+                    }
+                };
+                (TraceRecordType::Upcall, 0, id(processid), arg0, arg1)
+            }
+            TraceEvent::Interrupt { irq } => (TraceRecordType::Interrupt, 0, NO_PROCESS, *irq, 0),
+            TraceEvent::DeferredCall { handle } => (
+                TraceRecordType::DeferredCall,
+                0,
+                NO_PROCESS,
+                handle.id() as u32,
+                0,
+            ),
+            TraceEvent::KernelWorkDone => (TraceRecordType::KernelWorkDone, 0, NO_PROCESS, 0, 0),
+        }
+    }
+}
+
+/// Build a binary record from its fields.
+fn encode(
+    record_type: TraceRecordType,
+    class: u8,
+    process: u16,
+    timestamp: u32,
+    arg0: u32,
+    arg1: u32,
+) -> TraceRecord {
+    let mut record = [0; TRACE_RECORD_LEN];
+    record[0] = record_type as u8;
+    record[1] = class;
+    record[2..4].copy_from_slice(&process.to_le_bytes());
+    record[4..8].copy_from_slice(&timestamp.to_le_bytes());
+    record[8..12].copy_from_slice(&arg0.to_le_bytes());
+    record[12..16].copy_from_slice(&arg1.to_le_bytes());
+    record
+}
+
+/// Tracer that stores binary records of kernel events in a ring buffer, with
+/// timestamps from `time`.
+///
+/// When the ring buffer is full new events are dropped, and a `Dropped`
+/// record with the number of lost events is stored once there is room again.
+pub struct RingBufferTracer<'a, T: time::Time> {
+    time: &'a T,
+    records: TakeCell<'static, RingBuffer<'static, TraceRecord>>,
+    /// Number of events dropped since the last `Dropped` record.
+    dropped: Cell<u32>,
+}
+
+impl<'a, T: time::Time> RingBufferTracer<'a, T> {
+    pub fn new(
+        time: &'a T,
+        records: &'static mut RingBuffer<'static, TraceRecord>,
+    ) -> RingBufferTracer<'a, T> {
+        // Start with a record of the timestamp frequency so the decoder can
+        // convert timestamps to time.
+        records.enqueue(encode(
+            TraceRecordType::Start,
+            0,
+            NO_PROCESS,
+            time.now().into_u32(),
+            T::Frequency::frequency(),
+            0,
+        ));
+        RingBufferTracer {
+            time,
+            records: TakeCell::new(records),
+            dropped: Cell::new(0),
+        }
+    }
+
+    /// Move as many whole records as fit into `out`, oldest first. Returns
+    /// the number of bytes written.
+    pub fn drain(&self, out: &mut [u8]) -> usize {
+        self.records.map_or(0, |records| {
+            let mut written = 0;
+            for chunk in out.chunks_exact_mut(TRACE_RECORD_LEN) {
+                match records.dequeue() {
+                    Some(record) => {
+                        chunk.copy_from_slice(&record);
+                        written += TRACE_RECORD_LEN;
+                    }
+                    None => break,
+                }
+            }
+            written
+        })
+    }
+
+    /// Returns whether there are records waiting to be drained.
+    pub fn has_records(&self) -> bool {
+        self.records.map_or(false, |records| records.has_elements())
+    }
+}
+
+impl<'a, T: time::Time> Tracer for RingBufferTracer<'a, T> {
+    fn trace(&self, event: TraceEvent) {
+        let timestamp = self.time.now().into_u32();
+        let (record_type, class, process, arg0, arg1) = event.fields();
+        self.records.map(|records| {
+            let dropped = self.dropped.get();
+            // Leave room for the `Dropped` record before the event.
+            let needed = if dropped > 0 { 2 } else { 1 };
+            if records.available_len() < needed {
+                self.dropped.set(dropped.saturating_add(1));
+                return;
+            }
+            if dropped > 0 {
+                records.enqueue(encode(
+                    TraceRecordType::Dropped,
+                    0,
+                    NO_PROCESS,
+                    timestamp,
+                    dropped,
+                    0,
+                ));
+                self.dropped.set(0);
+            }
+            records.enqueue(encode(record_type, class, process, timestamp, arg0, arg1));
+        });
+    }
+}
@@ -0,0 +1,587 @@
+//! Software supervisor that only feeds the hardware watchdog while the kernel
+//! makes progress.
+//!
+//! The kernel calls `WatchDog::tickle()` on every iteration of its main loop,
+//! so a hardware watchdog on its own only resets the chip when the main loop
+//! stops completely. A capsule whose state machine is wedged, a deferred call
+//! that never gets serviced, or an important process that stopped doing its
+//! job keeps the main loop spinning and never triggers a reset.
+//!
+//! `WatchdogSupervisor` sits between the kernel and the hardware watchdog. It
+//! is the board's `KernelResources::WatchDog`, and it feeds the hardware
+//! watchdog from a periodic check, and only if since the previous check:
+//!
+//! - the main loop has not gone longer than its deadline between iterations,
+//! - the deferred call the supervisor scheduled has been serviced within its
+//!   deadline, and
+//! - each critical process has checked in within its deadline.
+//!
+//! Critical processes check in with command 1 of the supervisor's syscall
+//! driver, `DRIVER_NUM`, whenever they have done their work, which is up to
+//! the process to decide. This way a process that correctly waits in `yield`
+//! for an event is not mistaken for a hung one, as long as it checks in often
+//! enough. A critical process that is not loaded, for example because it was
+//! never installed on the board, is skipped, since it would otherwise reset
+//! the board on every boot. It has its full deadline to check in once it is
+//! loaded.
+//!
+//! When a component misses its deadline the supervisor stops feeding the
+//! hardware watchdog, which then resets the chip, and records which component
+//! it was in a section of RAM that is not initialized at boot (`.noinit`). The
+//! board can read it with `last_culprit()` after the reset. The check period
+//! must be shorter than the period of the hardware watchdog.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let supervisor_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! supervisor_alarm.setup();
+//! let critical = static_init!(
+//!     [kernel::watchdog_supervisor::CriticalProcess; 1],
+//!     [kernel::watchdog_supervisor::CriticalProcess {
+//!         name: "sensor_logger",
+//!         deadline_ms: 5000,
+//!     }]
+//! );
+//! let watchdog = static_init!(
+//!     kernel::watchdog_supervisor::WatchdogSupervisor<
+//!         'static,
+//!         sam4l::wdt::Wdt,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         1,
+//!     >,
+//!     kernel::watchdog_supervisor::WatchdogSupervisor::new(
+//!         board_kernel,
+//!         &peripherals.wdt,
+//!         supervisor_alarm,
+//!         dynamic_deferred_caller,
+//!         100,  // check every 100 ms
+//!         500,  // main loop deadline
+//!         500,  // deferred call deadline
+//!         critical,
+//!     )
+//! );
+//! supervisor_alarm.set_alarm_client(watchdog);
+//! // Then return `watchdog` for `kernel::watchdog_supervisor::DRIVER_NUM` in
+//! // the board's `SyscallDriverLookup`.
+//! watchdog.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(watchdog).unwrap(),
+//! );
+//! if let Some(culprit) = kernel::watchdog_supervisor::last_culprit() {
+//!     debug!("Previous watchdog culprit: {:?}", culprit);
+//! }
+//! ```
+//!
+//! and return `watchdog` from `KernelResources::watchdog()`.
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+
+use crate::debug;
+use crate::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use crate::hil::time::{self, ConvertTicks, Ticks};
+use crate::kernel::Kernel;
+use crate::platform::watchdog::WatchDog;
+use crate::process::ProcessId;
+use crate::syscall_driver::{CommandReturn, SyscallDriver};
+use crate::utilities::cells::OptionalCell;
+use crate::ErrorCode;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10003;
+
+/// The component that missed its deadline.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Culprit {
+    /// The main loop took too long for one iteration, or stopped running the
+    /// supervisor's checks altogether.
+    MainLoop,
+    /// The deferred call scheduled by the supervisor was not serviced in time.
+    DeferredCalls,
+    /// The critical process with this index in the list passed to the
+    /// supervisor did not check in in time.
+    CriticalProcess(usize),
+}
+
+/// A process the supervisor expects to check in at least once every
+/// `deadline_ms` while it is loaded.
+#[derive(Clone, Copy)]
+pub struct CriticalProcess {
+    /// Name of the process in its TBF header.
+    pub name: &'static str,
+    pub deadline_ms: u32,
+}
+
+/// Marks a valid record, so that RAM that was never written (for example
+/// after power-on) is not mistaken for a culprit.
+const RECORD_MAGIC: u32 = 0x5744_4f47;
+
+const CULPRIT_MAIN_LOOP: u32 = 0;
+const CULPRIT_DEFERRED_CALLS: u32 = 1;
+const CULPRIT_CRITICAL_PROCESS: u32 = 2;
+
+/// Culprit record that survives resets.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    culprit: u32,
+    index: u32,
+    /// Bitwise complement of `culprit ^ index`, to catch a partially
+    /// overwritten record.
+    check: u32,
+}
+
+/// The record is in a section that the startup code does not zero, so it
+/// keeps its contents across a watchdog reset.
+#[link_section = ".noinit"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+impl Record {
+    fn new(culprit: Culprit) -> Record {
+        let (culprit, index) = match culprit {
+            Culprit::MainLoop => (CULPRIT_MAIN_LOOP, 0),
+            Culprit::DeferredCalls => (CULPRIT_DEFERRED_CALLS, 0),
+            Culprit::CriticalProcess(index) => (CULPRIT_CRITICAL_PROCESS, index as u32),
+        };
+        Record {
+            magic: RECORD_MAGIC,
+            culprit,
+            index,
+            check: !(culprit ^ index),
+        }
+    }
+
+    fn culprit(&self) -> Option<Culprit> {
+        if self.magic != RECORD_MAGIC || self.check != !(self.culprit ^ self.index) {
+            return None;
+        }
+        match self.culprit {
+            CULPRIT_MAIN_LOOP => Some(Culprit::MainLoop),
+            CULPRIT_DEFERRED_CALLS => Some(Culprit::DeferredCalls),
+            CULPRIT_CRITICAL_PROCESS => Some(Culprit::CriticalProcess(self.index as usize)),
+            _ => None,
+        }
+    }
+}
+
+fn write_record(culprit: Culprit) {
+    unsafe {
+        // Volatile so the write is not optimized away: nothing in this boot
+        // reads it back.
+        core::ptr::write_volatile(RECORD.as_mut_ptr(), Record::new(culprit));
+    }
+}
+
+/// Returns the component recorded by the supervisor before the last reset.
+///
+/// While all components meet their deadlines the record names the main loop,
+/// because then the hardware watchdog can only fire if the main loop stops
+/// running the supervisor's checks. The result is therefore only meaningful
+/// if the chip reports that the last reset was caused by the watchdog. Call
+/// this before starting the supervisor, which rewrites the record.
+pub fn last_culprit() -> Option<Culprit> {
+    unsafe { core::ptr::read_volatile(RECORD.as_ptr()) }.culprit()
+}
+
+/// Feeds a hardware watchdog only while the main loop, the deferred calls
+/// and the critical processes make progress.
+pub struct WatchdogSupervisor<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize> {
+    kernel: &'static Kernel,
+    watchdog: &'a W,
+    alarm: &'a A,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    check_period_ms: u32,
+    main_loop_deadline_ms: u32,
+    deferred_call_deadline_ms: u32,
+    critical: &'a [CriticalProcess; NUM_CRITICAL],
+    /// When the main loop last called `tickle()`.
+    last_loop: Cell<A::Ticks>,
+    /// Number of checks the supervisor's deferred call has been pending for.
+    deferred_call_checks: Cell<u32>,
+    /// Whether each critical process has checked in since the last check.
+    checked_in: [Cell<bool>; NUM_CRITICAL],
+    /// Number of checks each critical process has not checked in for.
+    idle_checks: [Cell<u32>; NUM_CRITICAL],
+    /// The component that missed its deadline, after which the hardware
+    /// watchdog is no longer fed.
+    failed: OptionalCell<Culprit>,
+}
+
+impl<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize>
+    WatchdogSupervisor<'a, W, A, NUM_CRITICAL>
+{
+    pub fn new(
+        kernel: &'static Kernel,
+        watchdog: &'a W,
+        alarm: &'a A,
+        deferred_caller: &'a DynamicDeferredCall,
+        check_period_ms: u32,
+        main_loop_deadline_ms: u32,
+        deferred_call_deadline_ms: u32,
+        critical: &'a [CriticalProcess; NUM_CRITICAL],
+    ) -> WatchdogSupervisor<'a, W, A, NUM_CRITICAL> {
+        const NOT_CHECKED_IN: Cell<bool> = Cell::new(false);
+        const ZERO_CHECKS: Cell<u32> = Cell::new(0);
+        WatchdogSupervisor {
+            kernel,
+            watchdog,
+            alarm,
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            check_period_ms,
+            main_loop_deadline_ms,
+            deferred_call_deadline_ms,
+            critical,
+            last_loop: Cell::new(alarm.now()),
+            deferred_call_checks: Cell::new(0),
+            checked_in: [NOT_CHECKED_IN; NUM_CRITICAL],
+            idle_checks: [ZERO_CHECKS; NUM_CRITICAL],
+            failed: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Returns the component that missed its deadline, if any has.
+    pub fn culprit(&self) -> Option<Culprit> {
+        self.failed.extract()
+    }
+
+    /// Stop feeding the hardware watchdog and record `culprit` for after the
+    /// reset. Only the first missed deadline is recorded.
+    fn fail(&self, culprit: Culprit) {
+        if self.failed.is_some() {
+            return;
+        }
+        self.failed.set(culprit);
+        write_record(culprit);
+        match culprit {
+            Culprit::CriticalProcess(index) => debug!(
+                "Watchdog: critical process {} missed its deadline",
+                self.critical[index].name
+            ),
+            _ => debug!("Watchdog: {:?} missed its deadline", culprit),
+        }
+    }
+
+    /// Whether `checks` check periods are longer than `deadline_ms`.
+    fn past_deadline(&self, checks: u32, deadline_ms: u32) -> bool {
+        checks.saturating_mul(self.check_period_ms) > deadline_ms
+    }
+
+    fn check_deferred_call(&self) {
+        let checks = self.deferred_call_checks.get();
+        if checks == 0 {
+            // The previous call was serviced, schedule the next one.
+            self.handle.map(|handle| self.deferred_caller.set(*handle));
+        } else if self.past_deadline(checks, self.deferred_call_deadline_ms) {
+            self.fail(Culprit::DeferredCalls);
+        }
+        self.deferred_call_checks.set(checks + 1);
+    }
+
+    fn check_critical_processes(&self) {
+        for (index, critical) in self.critical.iter().enumerate() {
+            // A process that is not loaded is skipped, and gets its full
+            // deadline once it is loaded.
+            let loaded = self
+                .kernel
+                .process_until(|process| {
+                    if process.get_process_name() == critical.name {
+                        Some(())
+                    } else {
+                        None
+                    }
+                })
+                .is_some();
+            if !loaded || self.checked_in[index].take() {
+                self.idle_checks[index].set(0);
+            } else {
+                let checks = self.idle_checks[index].get() + 1;
+                self.idle_checks[index].set(checks);
+                if self.past_deadline(checks, critical.deadline_ms) {
+                    self.fail(Culprit::CriticalProcess(index));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize> WatchDog
+    for WatchdogSupervisor<'a, W, A, NUM_CRITICAL>
+{
+    fn setup(&self) {
+        // Until a component misses its deadline, a watchdog reset can only
+        // mean that the main loop stopped.
+        write_record(Culprit::MainLoop);
+        self.last_loop.set(self.alarm.now());
+        self.watchdog.setup();
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(self.check_period_ms),
+        );
+    }
+
+    /// Called by the kernel on every iteration of the main loop.
+    fn tickle(&self) {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_loop.get());
+        if elapsed > self.alarm.ticks_from_ms(self.main_loop_deadline_ms) {
+            self.fail(Culprit::MainLoop);
+        }
+        self.last_loop.set(now);
+    }
+
+    fn suspend(&self) {
+        // After a missed deadline the hardware watchdog must keep running so
+        // it resets the chip, even while it sleeps.
+        if self.failed.is_none() {
+            self.watchdog.suspend();
+        }
+    }
+
+    fn resume(&self) {
+        if self.failed.is_none() {
+            self.watchdog.resume();
+        }
+        // Time spent sleeping does not count against the main loop.
+        self.last_loop.set(self.alarm.now());
+    }
+}
+
+impl<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize> time::AlarmClient
+    for WatchdogSupervisor<'a, W, A, NUM_CRITICAL>
+{
+    fn alarm(&self) {
+        self.alarm.set_alarm(
+            self.alarm.get_alarm(),
+            self.alarm.ticks_from_ms(self.check_period_ms),
+        );
+        self.check_deferred_call();
+        self.check_critical_processes();
+        if self.failed.is_none() {
+            self.watchdog.tickle();
+        }
+    }
+}
+
+impl<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize> DynamicDeferredCallClient
+    for WatchdogSupervisor<'a, W, A, NUM_CRITICAL>
+{
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.deferred_call_checks.set(0);
+    }
+}
+
+impl<'a, W: WatchDog, A: time::Alarm<'a>, const NUM_CRITICAL: usize> SyscallDriver
+    for WatchdogSupervisor<'a, W, A, NUM_CRITICAL>
+{
+    /// Check in with the supervisor.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Check in, which tells the supervisor that the calling critical
+    ///        process is making progress. Returns `INVAL` if the calling
+    ///        process is not a critical process.
+    fn command(
+        &self,
+        command_number: usize,
+        _arg1: usize,
+        _arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 => {
+                let name = self
+                    .kernel
+                    .process_map_or("", processid, |process| process.get_process_name());
+                let mut critical = false;
+                for (process, checked_in) in self.critical.iter().zip(self.checked_in.iter()) {
+                    if process.name == name {
+                        checked_in.set(true);
+                        critical = true;
+                    }
+                }
+                if critical {
+                    CommandReturn::success()
+                } else {
+                    CommandReturn::failure(ErrorCode::INVAL)
+                }
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, _processid: ProcessId) -> Result<(), crate::process::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use crate::dynamic_process_loader::DynamicProcessLoading;
+    use crate::hil::time::AlarmClient;
+    use crate::syscall::SyscallReturn;
+    use crate::test_support::{self, TestAlarm, FLAG_ENABLED, IMAGE_LEN};
+    use std::boxed::Box;
+
+    /// A hardware watchdog that counts how often it was fed.
+    #[derive(Default)]
+    struct TestWatchdog {
+        tickles: Cell<usize>,
+    }
+
+    impl WatchDog for TestWatchdog {
+        fn tickle(&self) {
+            self.tickles.set(self.tickles.get() + 1);
+        }
+    }
+
+    type TestSupervisor = WatchdogSupervisor<'static, TestWatchdog, TestAlarm, 2>;
+
+    const CHECK_PERIOD_MS: u32 = 100;
+    const MAIN_LOOP_DEADLINE_MS: u32 = 500;
+    const DEFERRED_CALL_DEADLINE_MS: u32 = 300;
+    const CRITICAL_DEADLINE_MS: u32 = 300;
+
+    /// A supervisor for a kernel with the processes `app0` and `app1`, where
+    /// `app0` and `missing`, which is not loaded, are critical.
+    fn new_supervisor() -> (&'static TestSupervisor, [ProcessId; 2]) {
+        test_support::debug_writer();
+        let kernel = test_support::kernel(2);
+        let flash = test_support::app_flash(&[FLAG_ENABLED; 2]);
+        let loader = test_support::loader(
+            kernel,
+            test_support::chip(),
+            flash,
+            test_support::app_memory(8192),
+        );
+        let processes = [
+            loader.load_process(flash.as_ptr() as usize).unwrap(),
+            loader
+                .load_process(flash.as_ptr() as usize + IMAGE_LEN)
+                .unwrap(),
+        ];
+
+        let critical = Box::leak(Box::new([
+            CriticalProcess {
+                name: "app0",
+                deadline_ms: CRITICAL_DEADLINE_MS,
+            },
+            CriticalProcess {
+                name: "missing",
+                deadline_ms: CRITICAL_DEADLINE_MS,
+            },
+        ]));
+        let client_states = Box::leak(Box::new([DynamicDeferredCallClientState::default()]));
+        let deferred_caller = Box::leak(Box::new(DynamicDeferredCall::new(client_states)));
+        let supervisor: &'static TestSupervisor = Box::leak(Box::new(WatchdogSupervisor::new(
+            kernel,
+            Box::leak(Box::new(TestWatchdog::default())),
+            Box::leak(Box::new(TestAlarm::default())),
+            deferred_caller,
+            CHECK_PERIOD_MS,
+            MAIN_LOOP_DEADLINE_MS,
+            DEFERRED_CALL_DEADLINE_MS,
+            critical,
+        )));
+        supervisor.initialize_callback_handle(deferred_caller.register(supervisor).unwrap());
+        supervisor.setup();
+        (supervisor, processes)
+    }
+
+    /// Run one check period in which the main loop runs and, if
+    /// `service_deferred_call`, services the deferred call. Returns whether
+    /// the hardware watchdog was fed.
+    fn check_period(supervisor: &TestSupervisor, service_deferred_call: bool) -> bool {
+        let tickles = supervisor.watchdog.tickles.get();
+        supervisor.alarm.advance(CHECK_PERIOD_MS);
+        supervisor.tickle();
+        if service_deferred_call {
+            supervisor.handle.map(|handle| supervisor.call(*handle));
+        }
+        supervisor.alarm();
+        supervisor.watchdog.tickles.get() > tickles
+    }
+
+    fn check_in(supervisor: &TestSupervisor, processid: ProcessId) -> SyscallReturn {
+        supervisor.command(1, 0, 0, processid).into_inner()
+    }
+
+    // The deadlines are checked in one test, since the supervisors share the
+    // culprit record that survives resets.
+    #[test]
+    fn deadlines() {
+        // A critical process that checks in within its deadline keeps the
+        // watchdog fed, and the critical process that is not loaded is
+        // skipped.
+        let (supervisor, [app0, app1]) = new_supervisor();
+        for _ in 0..10 {
+            assert!(check_in(supervisor, app0).is_success());
+            assert!(check_period(supervisor, true));
+            assert!(check_period(supervisor, true));
+        }
+        assert_eq!(supervisor.culprit(), None);
+
+        // Only critical processes can check in.
+        assert!(matches!(
+            check_in(supervisor, app1),
+            SyscallReturn::Failure(ErrorCode::INVAL)
+        ));
+
+        // Missing the deadline stops feeding the watchdog for good.
+        assert!(check_in(supervisor, app0).is_success());
+        assert!(check_period(supervisor, true));
+        for _ in 0..CRITICAL_DEADLINE_MS / CHECK_PERIOD_MS {
+            assert!(check_period(supervisor, true));
+        }
+        assert!(!check_period(supervisor, true));
+        assert_eq!(supervisor.culprit(), Some(Culprit::CriticalProcess(0)));
+        assert_eq!(last_culprit(), Some(Culprit::CriticalProcess(0)));
+        assert!(check_in(supervisor, app0).is_success());
+        assert!(!check_period(supervisor, true));
+
+        // A deferred call that is not serviced.
+        let (supervisor, [app0, _]) = new_supervisor();
+        assert_eq!(last_culprit(), Some(Culprit::MainLoop));
+        assert!(check_period(supervisor, true));
+        for _ in 0..DEFERRED_CALL_DEADLINE_MS / CHECK_PERIOD_MS {
+            assert!(check_in(supervisor, app0).is_success());
+            assert!(check_period(supervisor, false));
+        }
+        assert!(check_in(supervisor, app0).is_success());
+        assert!(!check_period(supervisor, false));
+        assert_eq!(supervisor.culprit(), Some(Culprit::DeferredCalls));
+        assert_eq!(last_culprit(), Some(Culprit::DeferredCalls));
+
+        // A main loop iteration that takes too long, measured on a clock that
+        // wraps around. Time spent sleeping does not count.
+        let (supervisor, [app0, _]) = new_supervisor();
+        supervisor.alarm.now.set(u32::MAX - 100);
+        supervisor.resume();
+        supervisor.alarm.advance(MAIN_LOOP_DEADLINE_MS);
+        supervisor.tickle();
+        assert_eq!(supervisor.culprit(), None);
+        supervisor.alarm.advance(MAIN_LOOP_DEADLINE_MS + 1);
+        supervisor.tickle();
+        assert_eq!(supervisor.culprit(), Some(Culprit::MainLoop));
+        assert!(check_in(supervisor, app0).is_success());
+        assert!(!check_period(supervisor, true));
+    }
+}
@@ -100,8 +100,8 @@
 //! // when appending a key:
 //!
 //! // Add a key
-//! static VALUE: [u8; 32] = [0x23; 32];
-//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &VALUE) };
+//! static mut VALUE: [u8; 32] = [0x23; 32];
+//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
 //!
 //! match ret {
 //!     Err(ErrorCode::ReadNotReady(reg)) => {
@@ -126,7 +126,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{InitState, State, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -142,8 +142,11 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     /// The main TicKV struct
     pub tickv: TicKV<'a, C, S>,
     key: Cell<Option<u64>>,
-    value: Cell<Option<&'static [u8]>>,
+    value: Cell<Option<&'static mut [u8]>>,
+    value_length: Cell<usize>,
     buf: Cell<Option<&'static mut [u8]>>,
+    address: Cell<usize>,
+    next_key: Cell<Option<(u64, usize)>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -158,7 +161,36 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             tickv: TicKV::<C, S>::new(controller, read_buffer, flash_size),
             key: Cell::new(None),
             value: Cell::new(None),
+            value_length: Cell::new(0),
             buf: Cell::new(None),
+            address: Cell::new(0),
+            next_key: Cell::new(None),
+        }
+    }
+
+    /// Create a new struct that can compact regions with `compact()`
+    ///
+    /// `C`: An implementation of the `FlashController` trait
+    ///
+    /// `controller`: An new struct implementing `FlashController`
+    /// `flash_size`: The total size of the flash used for TicKV, not
+    ///               including the swap region
+    ///
+    /// The region directly after `flash_size` is reserved as the swap region,
+    /// so the `controller` must provide `flash_size + S` bytes of flash.
+    pub fn new_with_swap_region(
+        controller: C,
+        read_buffer: &'a mut [u8; S],
+        flash_size: usize,
+    ) -> Self {
+        Self {
+            tickv: TicKV::<C, S>::new_with_swap_region(controller, read_buffer, flash_size),
+            key: Cell::new(None),
+            value: Cell::new(None),
+            value_length: Cell::new(0),
+            buf: Cell::new(None),
+            address: Cell::new(0),
+            next_key: Cell::new(None),
         }
     }
 
@@ -182,18 +214,30 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     /// `hash`: A hashed key. This key will be used in future to retrieve
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The length of the data in `value`.
+    ///
+    /// Values that don't fit in a single region are stored in chunks,
+    /// which takes multiple writes. If a write returns `WriteNotReady`,
+    /// `continue_operation()` must be called once the write has completed.
+    ///
+    /// The `value` buffer is kept until it is returned by
+    /// `get_stored_value_buffer()`.
     ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
-    pub fn append_key(&self, hash: u64, value: &'static [u8]) -> Result<SuccessCode, ErrorCode> {
-        match self.tickv.append_key(hash, value) {
-            Ok(code) => Ok(code),
-            Err(e) => {
-                self.key.replace(Some(hash));
-                self.value.replace(Some(value));
-                Err(e)
-            }
+    pub fn append_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        self.value_length.set(length);
+        let ret = self.tickv.append_key(hash, &value[..length]);
+        if ret.is_err() {
+            self.key.replace(Some(hash));
         }
+        self.value.replace(Some(value));
+        ret
     }
 
     /// Retrieves the value from flash storage.
@@ -246,6 +290,57 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Replaces the value of a key in flash storage. See
+    /// `TicKV::update_key()`.
+    ///
+    /// `hash`: A hashed key.
+    /// `value`: A buffer containing the new data to be stored to flash.
+    /// `length`: The length of the data in `value`.
+    ///
+    /// An update performs multiple writes. If a write returns
+    /// `WriteNotReady`, `continue_operation()` must be called once the write
+    /// has completed.
+    ///
+    /// The `value` buffer is kept until it is returned by
+    /// `get_stored_value_buffer()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn update_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        self.value_length.set(length);
+        let ret = self.tickv.update_key(hash, &value[..length]);
+        if ret.is_err() {
+            self.key.replace(Some(hash));
+        }
+        self.value.replace(Some(value));
+        ret
+    }
+
+    /// Find the key of the next valid object. See `TicKV::get_next_key()`.
+    ///
+    /// `address`: Where to start looking. This is 0 for the first key and
+    /// the returned address for the keys that follow.
+    ///
+    /// If the operation has to be continued, the key and address are
+    /// returned by `get_stored_next_key()` once `continue_operation()`
+    /// returns success.
+    ///
+    /// On success the hashed key and the address to continue from will be
+    /// returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn get_next_key(&self, address: usize) -> Result<(u64, usize), ErrorCode> {
+        let ret = self.tickv.get_next_key(address);
+        if let Err(ErrorCode::ReadNotReady(_)) = ret {
+            self.address.set(address);
+        }
+        ret
+    }
+
     /// Perform a garbage collection on TicKV
     ///
     /// On success the number of bytes freed will be returned.
@@ -254,6 +349,19 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.tickv.garbage_collect()
     }
 
+    /// Compact the regions of TicKV, so that the space used by invalid
+    /// objects can be used again. See `TicKV::compact()`.
+    ///
+    /// Unlike other operations, a compaction performs multiple writes. If a
+    /// write returns `WriteNotReady`, `continue_operation()` must be called
+    /// once the write has completed.
+    ///
+    /// On success the number of bytes freed will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn compact(&self) -> Result<usize, ErrorCode> {
+        self.tickv.compact()
+    }
+
     /// Copy data from `read_buffer` argument to the internal read_buffer.
     /// This should be used to copy the data that the implementation wanted
     /// to read when calling `read_region` after the async operation has
@@ -266,7 +374,7 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
 
     /// Get the `value` buffer that was passed in by previous
     /// commands.
-    pub fn get_stored_value_buffer(&self) -> Option<&'static [u8]> {
+    pub fn get_stored_value_buffer(&self) -> Option<&'static mut [u8]> {
         self.value.take()
     }
 
@@ -276,6 +384,18 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.buf.take()
     }
 
+    /// Get the key and address found by a `get_next_key()` that was
+    /// continued.
+    pub fn get_stored_next_key(&self) -> Option<(u64, usize)> {
+        self.next_key.take()
+    }
+
+    /// Whether an operation is waiting for a flash operation to complete
+    /// before it can be continued with `continue_operation()`.
+    pub fn is_operation_pending(&self) -> bool {
+        self.tickv.state.get() != State::None
+    }
+
     /// Continue the last operation after the async operation has completed.
     /// This should be called from a read/erase complete callback.
     /// NOTE: If called from a read callback, `set_read_buffer` should be
@@ -294,20 +414,52 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     pub fn continue_operation(&self) -> ContinueReturn {
         let ret = match self.tickv.state.get() {
             State::Init(_) => self.tickv.initalise(self.key.get().unwrap()),
-            State::AppendKey(_) => self
-                .tickv
-                .append_key(self.key.get().unwrap(), self.value.get().unwrap()),
+            State::AppendKey(_) => {
+                let value = self.value.take().unwrap();
+                let ret = self
+                    .tickv
+                    .append_key(self.key.get().unwrap(), &value[..self.value_length.get()]);
+                self.value.replace(Some(value));
+                ret
+            }
             State::GetKey(_) => {
                 let buf = self.buf.take().unwrap();
                 let ret = self.tickv.get_key(self.key.get().unwrap(), buf);
                 self.buf.replace(Some(buf));
                 ret
             }
-            State::InvalidateKey(_) => self.tickv.invalidate_key(self.key.get().unwrap()),
+            State::Value(..) => match self.buf.take() {
+                Some(buf) => {
+                    let ret = self.tickv.continue_value_operation(&[], buf);
+                    self.buf.replace(Some(buf));
+                    ret
+                }
+                None => match self.value.take() {
+                    Some(value) => {
+                        let ret = self
+                            .tickv
+                            .continue_value_operation(&value[..self.value_length.get()], &mut []);
+                        self.value.replace(Some(value));
+                        ret
+                    }
+                    None => self.tickv.continue_value_operation(&[], &mut []),
+                },
+            },
+            State::NextKey(_) => match self.tickv.get_next_key(self.address.get()) {
+                Ok(next_key) => {
+                    self.next_key.set(Some(next_key));
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
             State::GarbageCollect(_) => match self.tickv.garbage_collect() {
                 Ok(_) => Ok(SuccessCode::Complete),
                 Err(e) => Err(e),
             },
+            State::Compact(_) => match self.tickv.compact() {
+                Ok(_) => Ok(SuccessCode::Complete),
+                Err(e) => Err(e),
+            },
             _ => unreachable!(),
         };
 
@@ -319,7 +471,14 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             Err(e) => match e {
                 ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None),
                 ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
+                    // Operations with multiple writes continue after their
+                    // writes complete
+                    match self.tickv.state.get() {
+                        State::Compact(_)
+                        | State::Init(InitState::Recover(_))
+                        | State::Value(..) => {}
+                        _ => self.tickv.state.set(State::None),
+                    }
                     (ret, None)
                 }
                 _ => {
@@ -339,6 +498,7 @@ mod store_flast_ctrl {
     use crate::flash_controller::FlashController;
     use crate::tickv::{HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
     use core::hash::{Hash, Hasher};
+    use std::boxed::Box;
     use std::cell::Cell;
     use std::cell::RefCell;
     use std::collections::hash_map::DefaultHasher;
@@ -434,6 +594,10 @@ mod store_flast_ctrl {
         hash_function.finish()
     }
 
+    fn value() -> &'static mut [u8] {
+        Box::leak(Box::new([0x23; 32]))
+    }
+
     // An example FlashCtrl implementation
     struct FlashCtrl {
         buf: RefCell<[[u8; 1024]; 64]>,
@@ -542,9 +706,7 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
-
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"ONE"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -555,7 +717,7 @@ mod store_flast_ctrl {
             _ => unreachable!(),
         }
 
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"TWO"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -582,11 +744,10 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"ONE"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -617,7 +778,7 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE again");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"ONE"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -632,7 +793,7 @@ mod store_flast_ctrl {
         }
 
         println!("Add key TWO");
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"TWO"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -707,11 +868,10 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"ONE"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -764,7 +924,6 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Garbage collect empty flash");
@@ -778,7 +937,7 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        let ret = tickv.append_key(get_hashed_key(b"ONE"), value(), 32);
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -860,6 +1019,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add Key ONE");
-        tickv.append_key(get_hashed_key(b"ONE"), &VALUE).unwrap();
+        tickv
+            .append_key(get_hashed_key(b"ONE"), value(), 32)
+            .unwrap();
     }
 }
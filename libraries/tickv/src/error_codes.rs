@@ -40,6 +40,8 @@ pub enum ErrorCode {
     WriteNotReady(usize),
     /// Indicates that the flash erase operation is not yet ready.
     EraseNotReady(usize),
+    /// Unable to compact the regions, as no swap region was reserved.
+    NoSwapRegion,
 }
 
 impl From<ErrorCode> for isize {
@@ -60,6 +62,7 @@ impl From<ErrorCode> for isize {
             ErrorCode::ReadNotReady(_) => -13,
             ErrorCode::WriteNotReady(_) => -14,
             ErrorCode::EraseNotReady(_) => -15,
+            ErrorCode::NoSwapRegion => -16,
         }
     }
 }
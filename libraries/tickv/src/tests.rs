@@ -558,3 +558,776 @@ mod no_check_store_flast_ctrl {
         );
     }
 }
+
+/// Tests using regions too small to store values in chunks
+mod small_region_flash_ctrl {
+    use super::*;
+
+    // An example FlashCtrl implementation
+    struct FlashCtrl<const S: usize> {
+        buf: RefCell<[[u8; S]; 2]>,
+    }
+
+    impl<const S: usize> FlashCtrl<S> {
+        fn new() -> Self {
+            Self {
+                buf: RefCell::new([[0xFF; S]; 2]),
+            }
+        }
+    }
+
+    impl<const S: usize> FlashController<S> for FlashCtrl<S> {
+        fn read_region(
+            &self,
+            region_number: usize,
+            offset: usize,
+            buf: &mut [u8; S],
+        ) -> Result<(), ErrorCode> {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.buf.borrow()[region_number][offset + i]
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            for (i, d) in buf.iter().enumerate() {
+                self.buf.borrow_mut()[address / S][(address % S) + i] = *d;
+            }
+
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            for d in self.buf.borrow_mut()[region_number].iter_mut() {
+                *d = 0xFF;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Append a value that doesn't fit in a region of `S` bytes.
+    fn append_large_value<const S: usize>() -> Result<(), ErrorCode> {
+        let mut read_buf: [u8; S] = [0; S];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl<S>, S>::new(FlashCtrl::new(), &mut read_buf, 2 * S);
+        tickv.initalise(hash).unwrap();
+
+        let value = [0x23; 64];
+        tickv.append_key(get_hashed_key(b"ONE"), &value).map(|_| ())
+    }
+
+    #[test]
+    fn test_large_value_small_region() {
+        // A quarter of these regions is smaller than the object header and
+        // check sum
+        assert_eq!(append_large_value::<32>(), Err(ErrorCode::ObjectTooLarge));
+        // A quarter of these regions has no room for any data
+        assert_eq!(append_large_value::<60>(), Err(ErrorCode::ObjectTooLarge));
+    }
+}
+
+/// Tests using a flash controller that can lose power in the middle of an
+/// operation
+mod power_loss_flash_ctrl {
+    use super::*;
+    use crate::async_ops::AsyncTicKV;
+    use crate::success_codes::SuccessCode;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const REGION_SIZE: usize = 256;
+    const NUM_REGIONS: usize = 4;
+    const FLASH_SIZE: usize = REGION_SIZE * NUM_REGIONS;
+    // The swap region is the last region
+    const SWAP_REGION: usize = NUM_REGIONS;
+
+    type Flash = [[u8; REGION_SIZE]; NUM_REGIONS + 1];
+    type FlashCtrl = PowerLossFlashCtrl<{ NUM_REGIONS + 1 }>;
+
+    // Values stored in chunks need more regions
+    const LARGE_NUM_REGIONS: usize = 32;
+    const LARGE_FLASH_SIZE: usize = REGION_SIZE * LARGE_NUM_REGIONS;
+    type LargeFlashCtrl = PowerLossFlashCtrl<{ LARGE_NUM_REGIONS + 1 }>;
+
+    // An example FlashCtrl implementation
+    struct PowerLossFlashCtrl<const N: usize> {
+        buf: RefCell<[[u8; REGION_SIZE]; N]>,
+        // The number of writes and erases that complete before the power is
+        // lost, `None` if the power is never lost
+        operations_left: Cell<Option<usize>>,
+        powered: Cell<bool>,
+        // Complete writes and erases but report them as not ready
+        async_ops: bool,
+    }
+
+    impl<const N: usize> PowerLossFlashCtrl<N> {
+        fn new(
+            buf: [[u8; REGION_SIZE]; N],
+            operations_left: Option<usize>,
+            async_ops: bool,
+        ) -> Self {
+            Self {
+                buf: RefCell::new(buf),
+                operations_left: Cell::new(operations_left),
+                powered: Cell::new(true),
+                async_ops,
+            }
+        }
+
+        /// Returns true if the power is lost during this operation.
+        fn lose_power(&self) -> bool {
+            match self.operations_left.get() {
+                Some(0) => {
+                    self.powered.set(false);
+                    true
+                }
+                Some(left) => {
+                    self.operations_left.set(Some(left - 1));
+                    false
+                }
+                None => false,
+            }
+        }
+    }
+
+    impl<const N: usize> FlashController<REGION_SIZE> for PowerLossFlashCtrl<N> {
+        fn read_region(
+            &self,
+            region_number: usize,
+            offset: usize,
+            buf: &mut [u8; REGION_SIZE],
+        ) -> Result<(), ErrorCode> {
+            if !self.powered.get() {
+                return Err(ErrorCode::ReadFail);
+            }
+
+            if self.async_ops {
+                // The test copies the data with `set_read_buffer()`
+                return Err(ErrorCode::ReadNotReady(region_number));
+            }
+
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.buf.borrow()[region_number][offset + i]
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            if !self.powered.get() {
+                return Err(ErrorCode::WriteFail);
+            }
+
+            // Only the first half of the data is written if the power is lost
+            let len = if self.lose_power() {
+                buf.len() / 2
+            } else {
+                buf.len()
+            };
+
+            // Writes can only clear bits
+            for (i, d) in buf[..len].iter().enumerate() {
+                self.buf.borrow_mut()[address / REGION_SIZE][(address % REGION_SIZE) + i] &= *d;
+            }
+
+            if !self.powered.get() {
+                Err(ErrorCode::WriteFail)
+            } else if self.async_ops {
+                Err(ErrorCode::WriteNotReady(address))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            if !self.powered.get() {
+                return Err(ErrorCode::EraseFail);
+            }
+
+            // Only the second half of the region is erased if the power is lost
+            let start = if self.lose_power() {
+                REGION_SIZE / 2
+            } else {
+                0
+            };
+
+            for d in self.buf.borrow_mut()[region_number][start..].iter_mut() {
+                *d = 0xFF;
+            }
+
+            if !self.powered.get() {
+                Err(ErrorCode::EraseFail)
+            } else if self.async_ops {
+                Err(ErrorCode::EraseNotReady(region_number))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn get_main_key() -> u64 {
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        hash_function.finish()
+    }
+
+    fn value(key: u8) -> [u8; 48] {
+        [key; 48]
+    }
+
+    /// Fill the flash with keys, then invalidate every other key. Returns the
+    /// flash, the keys that were added and the key that didn't fit.
+    fn setup() -> (Flash, Vec<u8>, u8) {
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+            FlashCtrl::new([[0xFF; REGION_SIZE]; NUM_REGIONS + 1], None, false),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        let mut keys = Vec::new();
+        let mut full_key = 0;
+        for key in 0.. {
+            match tickv.append_key(get_hashed_key(&[key]), &value(key)) {
+                Ok(_) => keys.push(key),
+                Err(ErrorCode::FlashFull) => {
+                    full_key = key;
+                    break;
+                }
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+        assert!(keys.len() > 4);
+
+        for key in keys.iter().step_by(2) {
+            tickv.invalidate_key(get_hashed_key(&[*key])).unwrap();
+        }
+
+        let flash = *tickv.controller.buf.borrow();
+        (flash, keys, full_key)
+    }
+
+    /// Check that every other key is valid and holds its value.
+    fn check_keys(tickv: &TicKV<FlashCtrl, REGION_SIZE>, keys: &[u8]) {
+        let mut buf = [0; 48];
+        for (i, key) in keys.iter().enumerate() {
+            let ret = tickv.get_key(get_hashed_key(&[*key]), &mut buf);
+            if i % 2 == 0 {
+                assert_eq!(ret, Err(ErrorCode::KeyNotFound));
+            } else {
+                assert_eq!(ret, Ok(SuccessCode::Complete));
+                assert_eq!(buf, value(*key));
+            }
+        }
+        tickv.get_key(get_main_key(), &mut []).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let (flash, keys, full_key) = setup();
+
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+            FlashCtrl::new(flash, None, false),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        println!("Add a key to full flash");
+        assert_eq!(
+            tickv.append_key(get_hashed_key(&[full_key]), &value(full_key)),
+            Err(ErrorCode::FlashFull)
+        );
+
+        println!("Garbage collect partially invalid regions");
+        assert_eq!(tickv.garbage_collect(), Ok(0));
+
+        println!("Compact");
+        let freed = tickv.compact().unwrap();
+        assert!(freed >= keys.len() / 2 * (48 + 15));
+        assert!(tickv.controller.buf.borrow()[SWAP_REGION]
+            .iter()
+            .all(|b| *b == 0xFF));
+        check_keys(&tickv, &keys);
+
+        println!("Add a key after compacting");
+        tickv
+            .append_key(get_hashed_key(&[full_key]), &value(full_key))
+            .unwrap();
+
+        println!("Compact without invalid objects");
+        assert_eq!(tickv.compact(), Ok(0));
+        check_keys(&tickv, &keys);
+    }
+
+    #[test]
+    fn test_compact_no_swap_region() {
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new(
+            FlashCtrl::new([[0xFF; REGION_SIZE]; NUM_REGIONS + 1], None, false),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        assert_eq!(tickv.compact(), Err(ErrorCode::NoSwapRegion));
+    }
+
+    /// Lose power during every write and erase of a compaction, and during
+    /// every write and erase when recovering from that. Then check that no
+    /// valid key was lost.
+    #[test]
+    fn test_compact_power_loss() {
+        let (flash, keys, full_key) = setup();
+
+        for compact_operations in 0.. {
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                FlashCtrl::new(flash, Some(compact_operations), false),
+                &mut read_buf,
+                FLASH_SIZE,
+            );
+            tickv.initalise(get_main_key()).unwrap();
+
+            if tickv.compact().is_ok() {
+                // There are enough operations to complete the compaction
+                break;
+            }
+            println!("Power lost after {} operations", compact_operations);
+            let interrupted = *tickv.controller.buf.borrow();
+
+            for recover_operations in 0.. {
+                println!("Recover with {} operations", recover_operations);
+                let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+                let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                    FlashCtrl::new(interrupted, Some(recover_operations), false),
+                    &mut read_buf,
+                    FLASH_SIZE,
+                );
+                if tickv.initalise(get_main_key()).is_ok() {
+                    check_keys(&tickv, &keys);
+                    break;
+                }
+                let interrupted_twice = *tickv.controller.buf.borrow();
+
+                let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+                let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                    FlashCtrl::new(interrupted_twice, None, false),
+                    &mut read_buf,
+                    FLASH_SIZE,
+                );
+                tickv.initalise(get_main_key()).unwrap();
+                check_keys(&tickv, &keys);
+            }
+
+            // Recovery leaves an empty swap region, so compacting again works
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                FlashCtrl::new(interrupted, None, false),
+                &mut read_buf,
+                FLASH_SIZE,
+            );
+            tickv.initalise(get_main_key()).unwrap();
+            tickv.compact().unwrap();
+            check_keys(&tickv, &keys);
+            tickv
+                .append_key(get_hashed_key(&[full_key]), &value(full_key))
+                .unwrap();
+        }
+    }
+
+    /// Run an async operation until it is complete.
+    fn finish_async<const N: usize>(
+        tickv: &AsyncTicKV<PowerLossFlashCtrl<N>, REGION_SIZE>,
+        mut ret: Result<SuccessCode, ErrorCode>,
+    ) -> Result<SuccessCode, ErrorCode> {
+        loop {
+            match ret {
+                Err(ErrorCode::ReadNotReady(reg)) => {
+                    // There is no actual delay in the test, just continue now
+                    tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                }
+                Err(ErrorCode::WriteNotReady(_)) | Err(ErrorCode::EraseNotReady(_)) => {}
+                _ => return ret,
+            }
+            ret = tickv.continue_operation().0;
+        }
+    }
+
+    #[test]
+    fn test_async_compact() {
+        let (flash, keys, full_key) = setup();
+
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = AsyncTicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+            FlashCtrl::new(flash, None, true),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        let ret = tickv.initalise(get_main_key());
+        finish_async(&tickv, ret).unwrap();
+
+        let ret = tickv.compact().map(|_| SuccessCode::Complete);
+        finish_async(&tickv, ret).unwrap();
+
+        assert!(tickv.tickv.controller.buf.borrow()[SWAP_REGION]
+            .iter()
+            .all(|b| *b == 0xFF));
+
+        let flash = *tickv.tickv.controller.buf.borrow();
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+            FlashCtrl::new(flash, None, false),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+        check_keys(&tickv, &keys);
+        tickv
+            .append_key(get_hashed_key(&[full_key]), &value(full_key))
+            .unwrap();
+    }
+
+    /// Lose power during an async compaction, then recover asynchronously.
+    #[test]
+    fn test_async_compact_power_loss() {
+        let (flash, keys, _) = setup();
+
+        for compact_operations in 0.. {
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = AsyncTicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                FlashCtrl::new(flash, Some(compact_operations), true),
+                &mut read_buf,
+                FLASH_SIZE,
+            );
+            let ret = tickv.initalise(get_main_key());
+            finish_async(&tickv, ret).unwrap();
+
+            let ret = tickv.compact().map(|_| SuccessCode::Complete);
+            if finish_async(&tickv, ret).is_ok() {
+                break;
+            }
+            let interrupted = *tickv.tickv.controller.buf.borrow();
+
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = AsyncTicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                FlashCtrl::new(interrupted, None, true),
+                &mut read_buf,
+                FLASH_SIZE,
+            );
+            let ret = tickv.initalise(get_main_key());
+            finish_async(&tickv, ret).unwrap();
+
+            let recovered = *tickv.tickv.controller.buf.borrow();
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+                FlashCtrl::new(recovered, None, false),
+                &mut read_buf,
+                FLASH_SIZE,
+            );
+            tickv.initalise(get_main_key()).unwrap();
+            check_keys(&tickv, &keys);
+        }
+    }
+
+    /// A value that is too large for a single region, so it is stored in
+    /// chunks.
+    fn large_value(key: u8) -> [u8; 300] {
+        let mut value = [0; 300];
+        for (i, b) in value.iter_mut().enumerate() {
+            *b = key.wrapping_add(i as u8);
+        }
+        value
+    }
+
+    /// Collect every key returned by `get_next_key()`.
+    fn all_keys<const N: usize>(tickv: &TicKV<PowerLossFlashCtrl<N>, REGION_SIZE>) -> Vec<u64> {
+        let mut keys = Vec::new();
+        let mut address = 0;
+        loop {
+            match tickv.get_next_key(address) {
+                Ok((key, next)) => {
+                    keys.push(key);
+                    address = next;
+                }
+                Err(ErrorCode::KeyNotFound) => return keys,
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_next_key() {
+        let (flash, keys, _) = setup();
+
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<FlashCtrl, REGION_SIZE>::new_with_swap_region(
+            FlashCtrl::new(flash, None, false),
+            &mut read_buf,
+            FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        let mut found = all_keys(&tickv);
+        found.sort_unstable();
+        let mut expected: Vec<u64> = keys
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|key| get_hashed_key(&[*key]))
+            .collect();
+        expected.push(get_main_key());
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_large_value() {
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+            LargeFlashCtrl::new([[0xFF; REGION_SIZE]; LARGE_NUM_REGIONS + 1], None, false),
+            &mut read_buf,
+            LARGE_FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        let key = get_hashed_key(b"LARGE");
+        tickv.append_key(key, &large_value(1)).unwrap();
+        assert_eq!(
+            tickv.append_key(key, &large_value(2)),
+            Err(ErrorCode::KeyAlreadyExists)
+        );
+
+        let mut buf = [0; 300];
+        assert_eq!(
+            tickv.get_key(key, &mut buf[..100]),
+            Err(ErrorCode::BufferTooSmall(300))
+        );
+        assert_eq!(tickv.get_key(key, &mut buf), Ok(SuccessCode::Complete));
+        assert_eq!(buf, large_value(1));
+
+        // The chunks aren't returned as keys
+        assert_eq!(all_keys(&tickv).len(), 2);
+
+        tickv.invalidate_key(key).unwrap();
+        assert_eq!(tickv.get_key(key, &mut buf), Err(ErrorCode::KeyNotFound));
+        assert_eq!(all_keys(&tickv), [get_main_key()]);
+
+        // Every chunk was invalidated, so all of the space can be reclaimed
+        tickv.compact().unwrap();
+        let flash = tickv.controller.buf.borrow();
+        let used: usize = flash[..LARGE_NUM_REGIONS]
+            .iter()
+            .map(|region| region.iter().filter(|b| **b != 0xFF).count())
+            .sum();
+        assert!(used <= 11 + MAIN_KEY.len() + 4);
+    }
+
+    #[test]
+    fn test_update_key() {
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = TicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+            LargeFlashCtrl::new([[0xFF; REGION_SIZE]; LARGE_NUM_REGIONS + 1], None, false),
+            &mut read_buf,
+            LARGE_FLASH_SIZE,
+        );
+        tickv.initalise(get_main_key()).unwrap();
+
+        let key = get_hashed_key(b"UPDATE");
+        assert_eq!(
+            tickv.update_key(key, &value(1)),
+            Err(ErrorCode::KeyNotFound)
+        );
+        tickv.append_key(key, &value(1)).unwrap();
+
+        let mut buf = [0; 48];
+        let mut large_buf = [0; 300];
+
+        println!("Update a value");
+        assert_eq!(tickv.update_key(key, &value(2)), Ok(SuccessCode::Written));
+        tickv.get_key(key, &mut buf).unwrap();
+        assert_eq!(buf, value(2));
+
+        println!("Update to a value stored in chunks");
+        tickv.update_key(key, &large_value(3)).unwrap();
+        tickv.get_key(key, &mut large_buf).unwrap();
+        assert_eq!(large_buf, large_value(3));
+
+        println!("Update a value stored in chunks");
+        tickv.update_key(key, &large_value(4)).unwrap();
+        tickv.get_key(key, &mut large_buf).unwrap();
+        assert_eq!(large_buf, large_value(4));
+
+        println!("Update to a value in a single object");
+        tickv.update_key(key, &value(5)).unwrap();
+        tickv.get_key(key, &mut buf).unwrap();
+        assert_eq!(buf, value(5));
+        assert_eq!(all_keys(&tickv).len(), 2);
+
+        println!("Fill the region of the key");
+        for i in 6.. {
+            match tickv.update_key(key, &value(i)) {
+                Ok(_) => {}
+                Err(ErrorCode::RegionFull) => {
+                    // The key is unchanged
+                    tickv.get_key(key, &mut buf).unwrap();
+                    assert_eq!(buf, value(i - 1));
+                    break;
+                }
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+
+        println!("Compact and update again");
+        tickv.compact().unwrap();
+        tickv.update_key(key, &value(7)).unwrap();
+        tickv.get_key(key, &mut buf).unwrap();
+        assert_eq!(buf, value(7));
+    }
+
+    /// Lose power during every write of updates, then check that either
+    /// the old or the new value is found, and that later operations on the
+    /// key work.
+    #[test]
+    fn test_update_power_loss() {
+        let key = get_hashed_key(b"UPDATE");
+        let values: [&[u8]; 3] = [&value(1), &large_value(2), &large_value(3)];
+
+        for (old, new) in [(0, 1), (1, 2), (1, 0)] {
+            let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+            let tickv = TicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+                LargeFlashCtrl::new([[0xFF; REGION_SIZE]; LARGE_NUM_REGIONS + 1], None, false),
+                &mut read_buf,
+                LARGE_FLASH_SIZE,
+            );
+            tickv.initalise(get_main_key()).unwrap();
+            tickv.append_key(key, values[old]).unwrap();
+            let flash = *tickv.controller.buf.borrow();
+
+            for operations in 0.. {
+                println!("Update {} to {} with {} operations", old, new, operations);
+                let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+                let tickv = TicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+                    LargeFlashCtrl::new(flash, Some(operations), false),
+                    &mut read_buf,
+                    LARGE_FLASH_SIZE,
+                );
+                tickv.initalise(get_main_key()).unwrap();
+                let updated = tickv.update_key(key, values[new]).is_ok();
+                let interrupted = *tickv.controller.buf.borrow();
+
+                let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+                let tickv = TicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+                    LargeFlashCtrl::new(interrupted, None, false),
+                    &mut read_buf,
+                    LARGE_FLASH_SIZE,
+                );
+                tickv.initalise(get_main_key()).unwrap();
+
+                let mut buf = [0; 300];
+                let len = values[new].len();
+                match tickv.get_key(key, &mut buf) {
+                    Ok(_) if buf[..len] == *values[new] => {}
+                    Ok(_) => {
+                        assert!(!updated);
+                        assert_eq!(buf[..values[old].len()], *values[old]);
+                    }
+                    Err(e) => panic!("Unexpected error: {:?}", e),
+                }
+
+                // Stale objects are invalidated by the next update, and are
+                // never found once the key is invalidated
+                tickv.update_key(key, values[2 - new]).unwrap();
+                tickv.get_key(key, &mut buf).unwrap();
+                assert_eq!(buf[..values[2 - new].len()], *values[2 - new]);
+                assert_eq!(all_keys(&tickv).len(), 2);
+                tickv.invalidate_key(key).unwrap();
+                assert_eq!(tickv.get_key(key, &mut buf), Err(ErrorCode::KeyNotFound));
+                assert_eq!(all_keys(&tickv), [get_main_key()]);
+
+                if updated {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_update() {
+        let mut read_buf: [u8; REGION_SIZE] = [0; REGION_SIZE];
+        let tickv = AsyncTicKV::<LargeFlashCtrl, REGION_SIZE>::new_with_swap_region(
+            LargeFlashCtrl::new([[0xFF; REGION_SIZE]; LARGE_NUM_REGIONS + 1], None, true),
+            &mut read_buf,
+            LARGE_FLASH_SIZE,
+        );
+        let ret = tickv.initalise(get_main_key());
+        finish_async(&tickv, ret).unwrap();
+
+        let key = get_hashed_key(b"UPDATE");
+        let small: &'static mut [u8; 48] = Box::leak(Box::new(value(1)));
+        let large: &'static mut [u8; 300] = Box::leak(Box::new(large_value(2)));
+
+        let ret = tickv.append_key(key, large, 300);
+        finish_async(&tickv, ret).unwrap();
+        // The value buffer is kept until it is asked for
+        let large = tickv.get_stored_value_buffer().unwrap();
+
+        let ret = tickv.update_key(key, small, 48);
+        finish_async(&tickv, ret).unwrap();
+        let ret = tickv.update_key(key, large, 300);
+        finish_async(&tickv, ret).unwrap();
+        let large = tickv.get_stored_value_buffer().unwrap();
+
+        // Reads are never ready straight away, so the buffer is returned by
+        // `continue_operation()`
+        let mut ret = match tickv.get_key(key, Box::leak(Box::new([0; 300]))) {
+            Ok(code) => panic!("Unexpected success: {:?}", code),
+            Err((_, e)) => e,
+        };
+        let buf = loop {
+            if let ErrorCode::ReadNotReady(reg) = ret {
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+            }
+            match tickv.continue_operation() {
+                (Ok(_), buf) => break buf.unwrap(),
+                (Err(e), _) => ret = e,
+            }
+        };
+        assert_eq!(buf, &large[..]);
+
+        let mut keys = Vec::new();
+        let mut address = 0;
+        loop {
+            let next_key = match tickv.get_next_key(address) {
+                Ok(next_key) => next_key,
+                Err(ErrorCode::KeyNotFound) => break,
+                Err(e) => match finish_async(&tickv, Err(e)) {
+                    Ok(_) => tickv.get_stored_next_key().unwrap(),
+                    Err(ErrorCode::KeyNotFound) => break,
+                    Err(e) => panic!("Unexpected error: {:?}", e),
+                },
+            };
+            keys.push(next_key.0);
+            address = next_key.1;
+        }
+        keys.sort_unstable();
+        let mut expected = [key, get_main_key()];
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+
+        let ret = tickv.invalidate_key(key);
+        finish_async(&tickv, ret).unwrap();
+        let ret = match tickv.get_key(key, Box::leak(Box::new([0; 300]))) {
+            Ok(code) => Ok(code),
+            Err((_, e)) => Err(e),
+        };
+        assert_eq!(finish_async(&tickv, ret), Err(ErrorCode::KeyNotFound));
+    }
+}
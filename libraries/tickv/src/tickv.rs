@@ -19,6 +19,8 @@ pub(crate) enum InitState {
     EraseComplete,
     /// Trying to read a region while appending a key
     AppendKeyReadRegion(usize),
+    /// Finishing a compaction that was interrupted by a power loss
+    Recover(CompactState),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -33,6 +35,27 @@ pub(crate) enum RubbishState {
     EraseRegion(usize),
 }
 
+/// The steps of a compaction. Each step performs a single flash operation.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum CompactState {
+    /// Reading the swap region to check for an interrupted compaction
+    ReadSwap,
+    /// Erasing the swap region, then continuing with the region
+    EraseSwap(usize),
+    /// Reading a region to look for invalid objects
+    ReadRegion(usize),
+    /// Erasing a region where every object is invalid
+    EraseRegion(usize),
+    /// Copying the valid objects of a region to the swap region
+    WriteSwap(usize),
+    /// Marking the copy in the swap region as complete
+    CommitSwap(usize),
+    /// Erasing a region whose valid objects are in the swap region
+    EraseOriginal(usize),
+    /// Copying the valid objects back from the swap region
+    WriteOriginal(usize),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// The current state machine when trying to complete a previous operation.
 /// This is used when returning from a complete async `FlashController` call.
@@ -45,10 +68,150 @@ pub(crate) enum State {
     AppendKey(KeyState),
     /// Getting a key
     GetKey(KeyState),
-    /// Invalidating a key
-    InvalidateKey(KeyState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
+    /// Compacting regions
+    Compact(CompactState),
+    /// Updating or invalidating a key, or accessing a value stored in
+    /// chunks. The region is set if the step was waiting for it to be read.
+    Value(ValueState, Option<usize>),
+    /// Looking for the next key
+    NextKey(KeyState),
+}
+
+/// The operations performed by `ValueState` steps
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ValueOp {
+    Get,
+    Append,
+    Update,
+    Invalidate,
+}
+
+/// The steps of an operation on a key that can't be done with a single
+/// object. Each step performs at most one write.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum ValueState {
+    /// Reading a chunk of a value
+    GetChunk(usize),
+    /// Checking that the key doesn't exist before appending it
+    CheckKey,
+    /// Invalidating a chunk left over by an interrupted operation, before
+    /// the chunk is written again
+    ClearChunk(usize),
+    /// Writing a chunk of a value
+    AppendChunk(usize),
+    /// Writing the object that describes the chunks of a new key
+    AppendHead,
+    /// Looking for the current object of the key
+    FindCurrent,
+    /// Invalidating an object left after the current object by an
+    /// interrupted update
+    InvalidateStale,
+    /// Writing the new object of an updated key, after the current object
+    AppendNew,
+    /// Invalidating the current object of the key
+    InvalidateCurrent,
+    /// Invalidating the chunks of the old value, counting down from the
+    /// number of chunks
+    InvalidateChunk(usize),
+}
+
+/// How a value that doesn't fit in a single object is stored. The value is
+/// split into chunks, which are stored as objects with keys derived from the
+/// key of the value.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct LargeValue {
+    /// The length of the value
+    len: usize,
+    /// Changed each time the value is updated, so the chunks of the new
+    /// value have different keys than the chunks of the old value
+    generation: u16,
+    /// The length of every chunk except the last one
+    chunk_len: usize,
+}
+
+// A list of offsets into the value of an object with `FLAGS_LARGE`
+const LARGE_LEN_OFFSET: usize = 0;
+const LARGE_GENERATION_OFFSET: usize = 4;
+const LARGE_CHUNK_LEN_OFFSET: usize = 6;
+const LARGE_HEADER_LEN: usize = 8;
+
+impl LargeValue {
+    fn chunks(&self) -> usize {
+        (self.len + self.chunk_len - 1) / self.chunk_len
+    }
+
+    /// The part of the value stored in `chunk`
+    fn chunk_range(&self, chunk: usize) -> core::ops::Range<usize> {
+        let start = chunk * self.chunk_len;
+        start..core::cmp::min(start + self.chunk_len, self.len)
+    }
+
+    /// The hashed key of `chunk` of the value of `hash`
+    fn chunk_hash(&self, hash: u64, chunk: usize) -> u64 {
+        // Mix the bits, so the chunks are spread over the regions
+        let mut h = hash
+            ^ ((self.generation as u64) << 32 | (chunk as u64 + 1))
+                .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        match h ^ (h >> 31) {
+            // These can't be used as keys
+            0 | 0xFFFF_FFFF_FFFF_FFFF => 1,
+            h => h,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; LARGE_HEADER_LEN] {
+        let mut bytes = [0; LARGE_HEADER_LEN];
+        bytes[LARGE_LEN_OFFSET..LARGE_GENERATION_OFFSET]
+            .copy_from_slice(&(self.len as u32).to_be_bytes());
+        bytes[LARGE_GENERATION_OFFSET..LARGE_CHUNK_LEN_OFFSET]
+            .copy_from_slice(&self.generation.to_be_bytes());
+        bytes[LARGE_CHUNK_LEN_OFFSET..LARGE_HEADER_LEN]
+            .copy_from_slice(&(self.chunk_len as u16).to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; LARGE_HEADER_LEN]) -> Option<Self> {
+        let mut len = [0; 4];
+        len.copy_from_slice(&bytes[LARGE_LEN_OFFSET..LARGE_GENERATION_OFFSET]);
+        let mut generation = [0; 2];
+        generation.copy_from_slice(&bytes[LARGE_GENERATION_OFFSET..LARGE_CHUNK_LEN_OFFSET]);
+        let mut chunk_len = [0; 2];
+        chunk_len.copy_from_slice(&bytes[LARGE_CHUNK_LEN_OFFSET..LARGE_HEADER_LEN]);
+
+        let large = Self {
+            len: u32::from_be_bytes(len) as usize,
+            generation: u16::from_be_bytes(generation),
+            chunk_len: u16::from_be_bytes(chunk_len) as usize,
+        };
+        if large.len == 0 || large.chunk_len == 0 {
+            return None;
+        }
+        Some(large)
+    }
+}
+
+/// The key and progress of the operation run by `ValueState` steps
+#[derive(Clone, Copy)]
+pub(crate) struct ValueOperation {
+    op: ValueOp,
+    hash: u64,
+    /// The region and offset of the current object of the key
+    region: usize,
+    offset: usize,
+    /// How the current value is stored, if it is stored in chunks
+    current: Option<LargeValue>,
+    /// How the new value is stored, if it is stored in chunks
+    new: Option<LargeValue>,
+}
+
+/// What follows a `ValueState` step
+enum Next {
+    Step(ValueState),
+    Done(SuccessCode),
 }
 
 /// The struct storing all of the TicKV information.
@@ -56,8 +219,11 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     /// The controller used for flash commands
     pub controller: C,
     flash_size: usize,
+    /// The region reserved for compaction, if there is one
+    swap_region: Option<usize>,
     pub(crate) read_buffer: Cell<Option<&'a mut [u8; S]>>,
     pub(crate) state: Cell<State>,
+    value_op: Cell<Option<ValueOperation>>,
 }
 
 /// This is the current object header used for TicKV objects
@@ -71,13 +237,17 @@ struct ObjectHeader {
 }
 
 pub(crate) const FLAGS_VALID: u8 = 8;
+/// The value of the object describes the chunks of a larger value
+pub(crate) const FLAGS_LARGE: u8 = 2;
+/// The object is a chunk of a larger value
+pub(crate) const FLAGS_CHUNK: u8 = 1;
 
 impl ObjectHeader {
-    fn new(hashed_key: u64, len: u16) -> Self {
+    fn new(hashed_key: u64, len: u16, flags: u8) -> Self {
         assert!(len < 0xFFF);
         Self {
             version: VERSION,
-            flags: FLAGS_VALID,
+            flags: FLAGS_VALID | flags,
             len,
             hashed_key,
         }
@@ -95,6 +265,20 @@ pub(crate) const CHECK_SUM_LEN: usize = 4;
 /// `initalise()`.
 pub const MAIN_KEY: &[u8; 15] = b"tickv-super-key";
 
+// The layout of the swap region used by `compact()`. The valid objects of the
+// region being compacted start at `SWAP_HEADER_LEN`. The commit byte is
+// padded to its own word, as it is written separately from the rest of the
+// header.
+const SWAP_MAGIC: [u8; 4] = *b"TKVS";
+const SWAP_MAGIC_OFFSET: usize = 0;
+const SWAP_REGION_OFFSET: usize = 4;
+const SWAP_LEN_OFFSET: usize = 8;
+const SWAP_CHECK_SUM_OFFSET: usize = 12;
+const SWAP_COMMIT_OFFSET: usize = 16;
+const SWAP_HEADER_LEN: usize = 20;
+/// The value of the commit byte once the copy in the swap region is complete
+const SWAP_COMMITTED: u8 = 0;
+
 /// This is the main TicKV struct.
 impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// Create a new struct
@@ -107,8 +291,35 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         Self {
             controller,
             flash_size,
+            swap_region: None,
+            read_buffer: Cell::new(Some(read_buffer)),
+            state: Cell::new(State::None),
+            value_op: Cell::new(None),
+        }
+    }
+
+    /// Create a new struct that can compact regions with `compact()`
+    ///
+    /// `C`: An implementation of the `FlashController` trait
+    ///
+    /// `controller`: An new struct implementing `FlashController`
+    /// `flash_size`: The total size of the flash used for TicKV, not
+    ///               including the swap region
+    ///
+    /// The region directly after `flash_size` is reserved as the swap region,
+    /// so the `controller` must provide `flash_size + S` bytes of flash.
+    pub fn new_with_swap_region(
+        controller: C,
+        read_buffer: &'a mut [u8; S],
+        flash_size: usize,
+    ) -> Self {
+        Self {
+            controller,
+            flash_size,
+            swap_region: Some(flash_size / S),
             read_buffer: Cell::new(Some(read_buffer)),
             state: Cell::new(State::None),
+            value_op: Cell::new(None),
         }
     }
 
@@ -120,11 +331,30 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// If the specified region has not already been setup for TicKV
     /// the entire region will be erased.
     ///
+    /// If there is a swap region, a compaction that was interrupted by a
+    /// power loss is finished first.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn initalise(&self, hashed_main_key: u64) -> Result<SuccessCode, ErrorCode> {
         let mut buf: [u8; 0] = [0; 0];
 
+        if self.swap_region.is_some() {
+            let recover = match self.state.get() {
+                State::None => Some((CompactState::ReadSwap, false)),
+                State::Init(InitState::Recover(step)) => Some((step, true)),
+                _ => None,
+            };
+            if let Some((step, resumed)) = recover {
+                self.run_compaction(
+                    step,
+                    resumed,
+                    |step| State::Init(InitState::Recover(step)),
+                    true,
+                )?;
+            }
+        }
+
         let key_ret = match self.state.get() {
             State::None => self.get_key(hashed_main_key, &mut buf),
             State::Init(state) => match state {
@@ -323,10 +553,61 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
     ///
+    /// Values that don't fit in a single region are split into chunks,
+    /// which are stored in any region that has space. The chunks are
+    /// written before the object that describes them, so a value is never
+    /// found with chunks missing.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
-        let region = self.get_region(hash);
+        if Self::fits_in_object(value.len()) {
+            return match self.append_object(hash, value, 0, None) {
+                Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+                ret => ret,
+            };
+        }
+
+        let large = self.large_value(value.len(), 0)?;
+        self.start_value_operation(ValueOp::Append, hash, Some(large));
+        self.run_value_operation(ValueState::CheckKey, None, value, &mut [])
+    }
+
+    /// Whether a value of `len` bytes fits in a single object
+    fn fits_in_object(len: usize) -> bool {
+        HEADER_LENGTH + len + CHECK_SUM_LEN < 0xFFF && HEADER_LENGTH + len < S
+    }
+
+    /// Describe how a value of `len` bytes is split into chunks
+    fn large_value(&self, len: usize, generation: u16) -> Result<LargeValue, ErrorCode> {
+        // Use chunks of a quarter of a region, so they fit in partially
+        // used regions. Small regions can't hold chunks at all.
+        let chunk_len = core::cmp::min(S / 4, 0xFFF - 1)
+            .checked_sub(HEADER_LENGTH + CHECK_SUM_LEN)
+            .filter(|&chunk_len| chunk_len > 0)
+            .ok_or(ErrorCode::ObjectTooLarge)?;
+        let large = LargeValue {
+            len,
+            generation,
+            chunk_len,
+        };
+        if len > u32::MAX as usize || large.chunks() > u16::MAX as usize {
+            return Err(ErrorCode::ObjectTooLarge);
+        }
+        Ok(large)
+    }
+
+    /// Append an object with `flags` to flash storage. If `only_region` is
+    /// set the object is added to that region, even if the key already
+    /// exists there.
+    fn append_object(
+        &self,
+        hash: u64,
+        value: &[u8],
+        flags: u8,
+        only_region: Option<usize>,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let region = only_region.unwrap_or_else(|| self.get_region(hash));
         let crc = crc32::Crc::new();
         let mut check_sum = crc.digest();
 
@@ -339,7 +620,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
 
         // Create the header:
-        let header = ObjectHeader::new(hash, object_length as u16);
+        let header = ObjectHeader::new(hash, object_length as u16, flags);
 
         let mut region_offset: isize = 0;
 
@@ -382,7 +663,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 };
             }
 
-            if self.find_key_offset(hash, region_data).is_ok() {
+            if only_region.is_none() && self.find_key_offset(hash, region_data).is_ok() {
                 // Check to make sure we don't already have this key
                 self.read_buffer.replace(Some(region_data));
                 return Err(ErrorCode::KeyAlreadyExists);
@@ -398,9 +679,15 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     // Replace the buffer
                     self.read_buffer.replace(Some(region_data));
 
+                    if only_region.is_some() {
+                        return Err(ErrorCode::RegionFull);
+                    }
+
                     match self.increment_region_offset(new_region) {
                         Some(o) => {
                             region_offset = o;
+                            // The next region hasn't been read yet
+                            self.state.set(State::None);
                         }
                         None => {
                             return Err(ErrorCode::FlashFull);
@@ -503,10 +790,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     &region_data[offset..(offset + package_length + CHECK_SUM_LEN)],
                 ) {
                     self.read_buffer.replace(Some(region_data));
-                    match e {
-                        ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                        _ => return Err(e),
-                    }
+                    return Err(e);
                 }
 
                 self.read_buffer.replace(Some(region_data));
@@ -531,8 +815,6 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         let mut region_offset: isize = 0;
 
         loop {
-            let crc = crc32::Crc::new();
-            let mut check_sum = crc.digest();
             let new_region = match self.state.get() {
                 State::None => region as isize + region_offset,
                 State::Init(state) => {
@@ -572,37 +854,25 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
             match self.find_key_offset(hash, region_data) {
                 Ok((offset, total_length)) => {
-                    // Add the header data to the check hash
-                    check_sum.update(&region_data[offset..(HEADER_LENGTH + offset)]);
-
-                    // Make sure if will fit in the buffer
-                    if buf.len() < (total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN) {
+                    if region_data[offset + LEN_OFFSET] & (FLAGS_LARGE << 4) != 0 {
+                        // The value is stored in chunks
+                        let ret = Self::read_large_value(region_data, offset, total_length);
                         self.read_buffer.replace(Some(region_data));
-                        return Err(ErrorCode::BufferTooSmall(
-                            total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN,
-                        ));
-                    }
-
-                    // Copy in the value
-                    for i in 0..(total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN) {
-                        buf[i] = region_data[offset + HEADER_LENGTH + i];
-                        check_sum.update(&[buf[i]])
-                    }
-
-                    // Check the hash
-                    let check_sum = check_sum.finalise();
-                    let check_sum = check_sum.to_ne_bytes();
+                        let large = ret?;
+                        if buf.len() < large.len {
+                            return Err(ErrorCode::BufferTooSmall(large.len));
+                        }
 
-                    if check_sum[3] != region_data[offset + total_length as usize - 1]
-                        || check_sum[2] != region_data[offset + total_length as usize - 2]
-                        || check_sum[1] != region_data[offset + total_length as usize - 3]
-                        || check_sum[0] != region_data[offset + total_length as usize - 4]
-                    {
-                        self.read_buffer.replace(Some(region_data));
-                        return Err(ErrorCode::InvalidCheckSum);
+                        self.start_value_operation(ValueOp::Get, hash, None);
+                        let mut op = self.value_op.get().unwrap();
+                        op.current = Some(large);
+                        self.value_op.set(Some(op));
+                        return self.run_value_operation(ValueState::GetChunk(0), None, &[], buf);
                     }
 
+                    let ret = Self::read_value(region_data, offset, total_length, buf);
                     self.read_buffer.replace(Some(region_data));
+                    ret?;
                     return Ok(SuccessCode::Complete);
                 }
                 Err((cont, e)) => {
@@ -612,6 +882,8 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         match self.increment_region_offset(new_region) {
                             Some(o) => {
                                 region_offset = o;
+                                // The next region hasn't been read yet
+                                self.state.set(State::None);
                             }
                             None => {
                                 return Err(e);
@@ -629,81 +901,58 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///
     /// `hash`: A hashed key.
     ///
+    /// If the value is stored in chunks, the chunks are invalidated after
+    /// the key.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     ///
     /// If a power loss occurs before success is returned the data is
     /// assumed to be lost.
     pub fn invalidate_key(&self, hash: u64) -> Result<SuccessCode, ErrorCode> {
-        let region = self.get_region(hash);
-
-        let mut region_offset: isize = 0;
-
-        loop {
-            // Get the data from that region
-            let new_region = match self.state.get() {
-                State::None => region as isize + region_offset,
-                State::InvalidateKey(key_state) => match key_state {
-                    KeyState::ReadRegion(reg) => reg as isize,
-                },
-                _ => unreachable!(),
-            };
-
-            // Get the data from that region
-            let mut region_data = self.read_buffer.take().unwrap();
-            if self.state.get() != State::InvalidateKey(KeyState::ReadRegion(new_region as usize)) {
-                match self
-                    .controller
-                    .read_region(new_region as usize, 0, &mut region_data)
-                {
-                    Ok(()) => {}
-                    Err(e) => {
-                        self.read_buffer.replace(Some(region_data));
-                        if let ErrorCode::ReadNotReady(reg) = e {
-                            self.state
-                                .set(State::InvalidateKey(KeyState::ReadRegion(reg)));
-                        }
-                        return Err(e);
-                    }
-                };
-            }
-
-            match self.find_key_offset(hash, region_data) {
-                Ok((offset, _data_len)) => {
-                    // We found a key, let's delete it
-                    region_data[offset + LEN_OFFSET] &= !0x80;
-
-                    if let Err(e) = self.controller.write(
-                        S * new_region as usize + offset + LEN_OFFSET,
-                        &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
-                    ) {
-                        self.read_buffer.replace(Some(region_data));
-                        match e {
-                            ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                            _ => return Err(e),
-                        }
-                    }
+        self.start_value_operation(ValueOp::Invalidate, hash, None);
+        self.run_value_operation(ValueState::FindCurrent, None, &[], &mut [])
+    }
 
-                    self.read_buffer.replace(Some(region_data));
-                    return Ok(SuccessCode::Written);
-                }
-                Err((cont, e)) => {
-                    self.read_buffer.replace(Some(region_data));
+    /// Replaces the value of a key in flash storage.
+    ///
+    /// `hash`: A hashed key.
+    /// `value`: A buffer containing the new data to be stored to flash.
+    ///
+    /// The new object is added to the region of the current object, after
+    /// it, and only then is the current object invalidated. Until that
+    /// single write completes, the current value is found. If a power loss
+    /// occurs before success is returned, either the old or the new value
+    /// is found, never a mix of the two.
+    ///
+    /// If there isn't space in the region for the new object,
+    /// `ErrorCode::RegionFull` is returned and the key is unchanged.
+    /// `compact()` can be used to free space in the region.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn update_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        // The generation is set once the current value is found
+        let large = if Self::fits_in_object(value.len()) {
+            None
+        } else {
+            Some(self.large_value(value.len(), 0)?)
+        };
+        self.start_value_operation(ValueOp::Update, hash, large);
+        self.run_value_operation(ValueState::FindCurrent, None, value, &mut [])
+    }
 
-                    if cont {
-                        match self.increment_region_offset(new_region) {
-                            Some(o) => {
-                                region_offset = o;
-                            }
-                            None => {
-                                return Err(e);
-                            }
-                        }
-                    } else {
-                        return Err(e);
-                    }
-                }
-            }
+    /// Continue an operation started by `append_key()`, `get_key()`,
+    /// `invalidate_key()` or `update_key()` that is run in steps. The same
+    /// `value` or `buf` must be passed again.
+    pub(crate) fn continue_value_operation(
+        &self,
+        value: &[u8],
+        buf: &mut [u8],
+    ) -> Result<SuccessCode, ErrorCode> {
+        match self.state.get() {
+            State::Value(step, resumed) => self.run_value_operation(step, resumed, value, buf),
+            _ => unreachable!(),
         }
     }
 
@@ -817,4 +1066,800 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         Ok(flash_freed)
     }
+
+    /// Read `region` into the read buffer, unless the read was already
+    /// started before the operation was resumed.
+    fn compact_read(&self, region: usize, resumed: bool) -> Result<&'a mut [u8; S], ErrorCode> {
+        let region_data = self.read_buffer.take().unwrap();
+        if !resumed {
+            if let Err(e) = self.controller.read_region(region, 0, region_data) {
+                self.read_buffer.replace(Some(region_data));
+                return Err(e);
+            }
+        }
+        Ok(region_data)
+    }
+
+    /// Write `range` of the read buffer to `address`, unless the write was
+    /// already started before the operation was resumed.
+    fn compact_write(
+        &self,
+        address: usize,
+        range: core::ops::Range<usize>,
+        resumed: bool,
+    ) -> Result<(), ErrorCode> {
+        if resumed {
+            return Ok(());
+        }
+        let region_data = self.read_buffer.take().unwrap();
+        let ret = self.controller.write(address, &region_data[range]);
+        self.read_buffer.replace(Some(region_data));
+        ret
+    }
+
+    /// Erase `region`, unless the erase was already started before the
+    /// operation was resumed.
+    fn compact_erase(&self, region: usize, resumed: bool) -> Result<(), ErrorCode> {
+        if resumed {
+            return Ok(());
+        }
+        self.controller.erase_region(region)
+    }
+
+    /// The length of the objects in the swap region data.
+    fn swap_len(region_data: &[u8; S]) -> usize {
+        let mut len = [0; 4];
+        len.copy_from_slice(&region_data[SWAP_LEN_OFFSET..SWAP_CHECK_SUM_OFFSET]);
+        u32::from_be_bytes(len) as usize
+    }
+
+    /// The length of the objects in the swap region data in the read buffer.
+    fn buffered_swap_len(&self) -> usize {
+        let region_data = self.read_buffer.take().unwrap();
+        let len = Self::swap_len(region_data);
+        self.read_buffer.replace(Some(region_data));
+        len
+    }
+
+    /// The check sum of the swap region header and the `len` bytes of
+    /// objects that follow it.
+    fn swap_check_sum(region_data: &[u8; S], len: usize) -> u32 {
+        let crc = crc32::Crc::new();
+        let mut check_sum = crc.digest();
+        check_sum.update(&region_data[SWAP_MAGIC_OFFSET..SWAP_CHECK_SUM_OFFSET]);
+        check_sum.update(&region_data[SWAP_HEADER_LEN..(SWAP_HEADER_LEN + len)]);
+        check_sum.finalise()
+    }
+
+    /// If the swap region data holds a complete copy of the valid objects of
+    /// a region, return the number of that region.
+    fn committed_swap_region(&self, region_data: &[u8; S]) -> Option<usize> {
+        if region_data[SWAP_MAGIC_OFFSET..SWAP_REGION_OFFSET] != SWAP_MAGIC
+            || region_data[SWAP_COMMIT_OFFSET] != SWAP_COMMITTED
+        {
+            return None;
+        }
+
+        let mut region = [0; 4];
+        region.copy_from_slice(&region_data[SWAP_REGION_OFFSET..SWAP_LEN_OFFSET]);
+        let region = u32::from_be_bytes(region) as usize;
+        let len = Self::swap_len(region_data);
+        if region >= self.flash_size / S || len > S - SWAP_HEADER_LEN {
+            return None;
+        }
+
+        let mut check_sum = [0; 4];
+        check_sum.copy_from_slice(&region_data[SWAP_CHECK_SUM_OFFSET..SWAP_COMMIT_OFFSET]);
+        if u32::from_be_bytes(check_sum) != Self::swap_check_sum(region_data, len) {
+            return None;
+        }
+
+        Some(region)
+    }
+
+    /// Look for invalid objects in the region data. If there are any, move
+    /// the valid objects to the start of the buffer and return the length of
+    /// the valid objects and the length of all objects.
+    fn defragment_region(region_data: &mut [u8; S]) -> Result<Option<(usize, usize)>, ErrorCode> {
+        let mut offset: usize = 0;
+        let mut valid_length: usize = 0;
+        let mut invalid_found = false;
+
+        while offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] != 0xFF {
+            // We found a version, check that we support it
+            if region_data[offset + VERSION_OFFSET] != VERSION {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            // Find this entries length
+            let total_length = (((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+                | region_data[offset + LEN_OFFSET + 1] as u16)
+                as usize;
+
+            if total_length == 0 || offset + total_length > S {
+                // We can't tell where the objects are, leave the region alone
+                return Ok(None);
+            }
+
+            if region_data[offset + LEN_OFFSET] & 0x80 == 0x80 {
+                region_data.copy_within(offset..(offset + total_length), valid_length);
+                valid_length += total_length;
+            } else {
+                invalid_found = true;
+            }
+
+            offset += total_length;
+        }
+
+        if invalid_found {
+            Ok(Some((valid_length, offset)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Perform a single step of a compaction and return the next step.
+    ///
+    /// `resumed` indicates that the flash operation of this step was started
+    /// before the operation was resumed, so it must not be started again.
+    /// `freed` is incremented by the number of bytes of invalid objects
+    /// that are removed.
+    fn compact_step(
+        &self,
+        step: CompactState,
+        resumed: bool,
+        freed: &mut usize,
+    ) -> Result<CompactState, ErrorCode> {
+        let swap_region = self.swap_region.unwrap();
+
+        match step {
+            CompactState::ReadSwap => {
+                let region_data = self.compact_read(swap_region, resumed)?;
+                let next = if region_data.iter().all(|b| *b == 0xFF) {
+                    CompactState::ReadRegion(0)
+                } else if let Some(region) = self.committed_swap_region(region_data) {
+                    // The valid objects of the region were copied, but they
+                    // might not have been written back. Write them back again.
+                    CompactState::EraseOriginal(region)
+                } else {
+                    // The copy was interrupted, so the original region was
+                    // never changed.
+                    CompactState::EraseSwap(0)
+                };
+                self.read_buffer.replace(Some(region_data));
+                Ok(next)
+            }
+            CompactState::EraseSwap(region) => {
+                self.compact_erase(swap_region, resumed)?;
+                Ok(CompactState::ReadRegion(region))
+            }
+            CompactState::ReadRegion(region) => {
+                let region_data = self.compact_read(region, resumed)?;
+                let next = match Self::defragment_region(region_data) {
+                    Ok(None) => CompactState::ReadRegion(region + 1),
+                    Ok(Some((0, total_length))) => {
+                        *freed += total_length;
+                        CompactState::EraseRegion(region)
+                    }
+                    Ok(Some((valid_length, _))) if valid_length + SWAP_HEADER_LEN > S => {
+                        // The valid objects don't fit in the swap region, but
+                        // then there isn't anything to gain either.
+                        CompactState::ReadRegion(region + 1)
+                    }
+                    Ok(Some((valid_length, total_length))) => {
+                        region_data.copy_within(0..valid_length, SWAP_HEADER_LEN);
+                        region_data[SWAP_MAGIC_OFFSET..SWAP_REGION_OFFSET]
+                            .copy_from_slice(&SWAP_MAGIC);
+                        region_data[SWAP_REGION_OFFSET..SWAP_LEN_OFFSET]
+                            .copy_from_slice(&(region as u32).to_be_bytes());
+                        region_data[SWAP_LEN_OFFSET..SWAP_CHECK_SUM_OFFSET]
+                            .copy_from_slice(&(valid_length as u32).to_be_bytes());
+                        let check_sum = Self::swap_check_sum(region_data, valid_length);
+                        region_data[SWAP_CHECK_SUM_OFFSET..SWAP_COMMIT_OFFSET]
+                            .copy_from_slice(&check_sum.to_be_bytes());
+                        region_data[SWAP_COMMIT_OFFSET..SWAP_HEADER_LEN].fill(0xFF);
+
+                        *freed += total_length - valid_length;
+                        CompactState::WriteSwap(region)
+                    }
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        return Err(e);
+                    }
+                };
+                self.read_buffer.replace(Some(region_data));
+                Ok(next)
+            }
+            CompactState::EraseRegion(region) => {
+                self.compact_erase(region, resumed)?;
+                Ok(CompactState::ReadRegion(region + 1))
+            }
+            CompactState::WriteSwap(region) => {
+                let len = self.buffered_swap_len();
+                self.compact_write(S * swap_region, 0..(SWAP_HEADER_LEN + len), resumed)?;
+                Ok(CompactState::CommitSwap(region))
+            }
+            CompactState::CommitSwap(region) => {
+                let region_data = self.read_buffer.take().unwrap();
+                region_data[SWAP_COMMIT_OFFSET] = SWAP_COMMITTED;
+                self.read_buffer.replace(Some(region_data));
+                self.compact_write(
+                    S * swap_region + SWAP_COMMIT_OFFSET,
+                    SWAP_COMMIT_OFFSET..(SWAP_COMMIT_OFFSET + 1),
+                    resumed,
+                )?;
+                Ok(CompactState::EraseOriginal(region))
+            }
+            CompactState::EraseOriginal(region) => {
+                self.compact_erase(region, resumed)?;
+                Ok(CompactState::WriteOriginal(region))
+            }
+            CompactState::WriteOriginal(region) => {
+                let len = self.buffered_swap_len();
+                self.compact_write(
+                    S * region,
+                    SWAP_HEADER_LEN..(SWAP_HEADER_LEN + len),
+                    resumed,
+                )?;
+                Ok(CompactState::EraseSwap(region + 1))
+            }
+        }
+    }
+
+    /// Run the compaction steps starting at `step`, until every region has
+    /// been compacted, or until the swap region is empty if `recover_only`
+    /// is set.
+    ///
+    /// If a flash operation isn't ready, `state` is called to create the
+    /// state the operation is resumed from.
+    fn run_compaction(
+        &self,
+        mut step: CompactState,
+        mut resumed: bool,
+        state: fn(CompactState) -> State,
+        recover_only: bool,
+    ) -> Result<usize, ErrorCode> {
+        let num_region = self.flash_size / S;
+        let mut flash_freed = 0;
+
+        loop {
+            if let CompactState::ReadRegion(region) = step {
+                if recover_only || region >= num_region {
+                    self.state.set(State::None);
+                    return Ok(flash_freed);
+                }
+            }
+
+            match self.compact_step(step, resumed, &mut flash_freed) {
+                Ok(next) => step = next,
+                Err(e) => {
+                    match e {
+                        ErrorCode::ReadNotReady(_)
+                        | ErrorCode::WriteNotReady(_)
+                        | ErrorCode::EraseNotReady(_) => self.state.set(state(step)),
+                        _ => self.state.set(State::None),
+                    }
+                    return Err(e);
+                }
+            }
+            resumed = false;
+        }
+    }
+
+    /// Compact the regions of TicKV, so that the space used by invalid
+    /// objects can be used again.
+    ///
+    /// `garbage_collect()` only erases regions where every object is
+    /// invalid. This also frees the space of invalid objects in regions
+    /// that still have valid objects, by copying the valid objects to the
+    /// swap region, erasing the region and copying them back. This requires
+    /// the struct to be created with `new_with_swap_region()`.
+    ///
+    /// A compaction that is interrupted by a power loss is finished by
+    /// `initalise()`. If a flash operation fails, `compact()` should be
+    /// called again before any other operation.
+    ///
+    /// On success the number of bytes freed will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn compact(&self) -> Result<usize, ErrorCode> {
+        if self.swap_region.is_none() {
+            return Err(ErrorCode::NoSwapRegion);
+        }
+
+        let (step, resumed) = match self.state.get() {
+            State::None => (CompactState::ReadSwap, false),
+            State::Compact(step) => (step, true),
+            _ => unreachable!(),
+        };
+
+        self.run_compaction(step, resumed, State::Compact, false)
+    }
+
+    /// The total length of the object at `offset` in the region data
+    fn object_length(region_data: &[u8], offset: usize) -> usize {
+        (((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+            | region_data[offset + LEN_OFFSET + 1] as u16) as usize
+    }
+
+    /// The hashed key of the object at `offset` in the region data
+    fn object_hash(region_data: &[u8], offset: usize) -> u64 {
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&region_data[(offset + HASH_OFFSET)..(offset + HEADER_LENGTH)]);
+        u64::from_be_bytes(hash)
+    }
+
+    /// Copy the value of the object at `offset` in the region data to `buf`
+    /// and check the check sum.
+    ///
+    /// On success the length of the value is returned.
+    fn read_value(
+        region_data: &[u8; S],
+        offset: usize,
+        total_length: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let total_length = total_length as usize;
+        if total_length < HEADER_LENGTH + CHECK_SUM_LEN || offset + total_length > S {
+            return Err(ErrorCode::CorruptData);
+        }
+        let value_length = total_length - HEADER_LENGTH - CHECK_SUM_LEN;
+
+        // Make sure if will fit in the buffer
+        if buf.len() < value_length {
+            return Err(ErrorCode::BufferTooSmall(value_length));
+        }
+
+        // Copy in the value
+        let value = &region_data[(offset + HEADER_LENGTH)..(offset + HEADER_LENGTH + value_length)];
+        buf[..value_length].copy_from_slice(value);
+
+        // Check the hash
+        let crc = crc32::Crc::new();
+        let mut check_sum = crc.digest();
+        check_sum.update(&region_data[offset..(offset + HEADER_LENGTH)]);
+        check_sum.update(value);
+        let check_sum = check_sum.finalise();
+
+        if check_sum.to_ne_bytes()[..]
+            != region_data[(offset + total_length - CHECK_SUM_LEN)..(offset + total_length)]
+        {
+            return Err(ErrorCode::InvalidCheckSum);
+        }
+
+        Ok(value_length)
+    }
+
+    /// Read the description of the chunks of a value from the object at
+    /// `offset` in the region data.
+    fn read_large_value(
+        region_data: &[u8; S],
+        offset: usize,
+        total_length: u16,
+    ) -> Result<LargeValue, ErrorCode> {
+        let mut bytes = [0; LARGE_HEADER_LEN];
+        match Self::read_value(region_data, offset, total_length, &mut bytes) {
+            Ok(LARGE_HEADER_LEN) => {}
+            Ok(_) | Err(ErrorCode::BufferTooSmall(_)) => return Err(ErrorCode::CorruptData),
+            Err(e) => return Err(e),
+        }
+        LargeValue::from_bytes(&bytes).ok_or(ErrorCode::CorruptData)
+    }
+
+    /// Find a valid object for `hash` after the object at `offset` in the
+    /// region data. Such an object is left behind if an update is
+    /// interrupted before the current object is invalidated.
+    fn find_stale_offset(hash: u64, region_data: &[u8; S], offset: usize) -> Option<usize> {
+        let mut offset = offset + Self::object_length(region_data, offset);
+
+        while offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] == VERSION {
+            let total_length = Self::object_length(region_data, offset);
+            if total_length == 0 {
+                return None;
+            }
+
+            if region_data[offset + LEN_OFFSET] & 0x80 == 0x80
+                && Self::object_hash(region_data, offset) == hash
+            {
+                return Some(offset);
+            }
+
+            offset += total_length;
+        }
+
+        None
+    }
+
+    fn start_value_operation(&self, op: ValueOp, hash: u64, new: Option<LargeValue>) {
+        self.value_op.set(Some(ValueOperation {
+            op,
+            hash,
+            region: 0,
+            offset: 0,
+            current: None,
+            new,
+        }));
+    }
+
+    /// Take the read buffer holding the data of `region`. The region is
+    /// only read if `buffered` shows that the buffer holds another region.
+    fn value_read(
+        &self,
+        region: usize,
+        buffered: &mut Option<usize>,
+    ) -> Result<&'a mut [u8; S], ErrorCode> {
+        let region_data = self.read_buffer.take().unwrap();
+        if *buffered != Some(region) {
+            *buffered = None;
+            if let Err(e) = self.controller.read_region(region, 0, region_data) {
+                self.read_buffer.replace(Some(region_data));
+                return Err(e);
+            }
+            *buffered = Some(region);
+        }
+        Ok(region_data)
+    }
+
+    /// Find the first valid object for `hash`, searching the regions in the
+    /// same order as `get_key()`. The search starts at `resumed` if the
+    /// step was waiting for that region to be read.
+    ///
+    /// On success the region, the offset and the total length of the
+    /// object are returned and the region data is left in the read buffer.
+    fn locate_key(
+        &self,
+        hash: u64,
+        resumed: Option<usize>,
+        buffered: &mut Option<usize>,
+    ) -> Result<(usize, usize, u16), ErrorCode> {
+        let region = self.get_region(hash);
+        let mut new_region = resumed.unwrap_or(region);
+
+        loop {
+            let region_data = self.value_read(new_region, buffered)?;
+            let ret = self.find_key_offset(hash, region_data);
+            self.read_buffer.replace(Some(region_data));
+
+            match ret {
+                Ok((offset, total_length)) => return Ok((new_region, offset, total_length)),
+                Err((true, e)) => match self.increment_region_offset(new_region as isize) {
+                    Some(o) => new_region = (region as isize + o) as usize,
+                    None => return Err(e),
+                },
+                Err((false, e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Handle the result of the write of a step. If the write isn't ready
+    /// the operation continues with the `next` step once it is, unless the
+    /// write was the last one.
+    fn value_written(&self, ret: Result<(), ErrorCode>, next: Next) -> Result<Next, ErrorCode> {
+        match ret {
+            Ok(()) => Ok(next),
+            Err(ErrorCode::WriteNotReady(address)) => match next {
+                Next::Done(_) => Ok(Next::Done(SuccessCode::Queued)),
+                Next::Step(step) => {
+                    self.state.set(State::Value(step, None));
+                    Err(ErrorCode::WriteNotReady(address))
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Invalidate the object at `offset` in `region`, which must be in the
+    /// read buffer.
+    fn value_invalidate(
+        &self,
+        region: usize,
+        offset: usize,
+        next: Next,
+    ) -> Result<Next, ErrorCode> {
+        let region_data = self.read_buffer.take().unwrap();
+        region_data[offset + LEN_OFFSET] &= !0x80;
+        let ret = self.controller.write(
+            S * region + offset + LEN_OFFSET,
+            &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
+        );
+        self.read_buffer.replace(Some(region_data));
+        self.value_written(ret, next)
+    }
+
+    /// Append an object as a step, see `append_object()`.
+    fn value_append(
+        &self,
+        hash: u64,
+        value: &[u8],
+        flags: u8,
+        only_region: Option<usize>,
+        resumed: Option<usize>,
+        buffered: &mut Option<usize>,
+        next: Next,
+    ) -> Result<Next, ErrorCode> {
+        self.state.set(match resumed {
+            Some(region) => State::AppendKey(KeyState::ReadRegion(region)),
+            None => State::None,
+        });
+        let ret = self.append_object(hash, value, flags, only_region);
+        // On success the buffer holds the region the object was added to
+        *buffered = only_region.filter(|_| ret.is_ok());
+        self.value_written(ret.map(|_| ()), next)
+    }
+
+    /// Perform a single step of an operation on a value.
+    ///
+    /// `resumed` is set if the step was waiting for that region to be read.
+    /// `buffered` is the region that is in the read buffer, if any.
+    fn value_step(
+        &self,
+        step: ValueState,
+        resumed: Option<usize>,
+        buffered: &mut Option<usize>,
+        value: &[u8],
+        buf: &mut [u8],
+    ) -> Result<Next, ErrorCode> {
+        let mut op = self.value_op.get().unwrap();
+
+        match step {
+            ValueState::GetChunk(chunk) => {
+                let large = op.current.unwrap();
+                let (_, offset, total_length) = self
+                    .locate_key(large.chunk_hash(op.hash, chunk), resumed, buffered)
+                    .map_err(|e| match e {
+                        ErrorCode::KeyNotFound => ErrorCode::CorruptData,
+                        e => e,
+                    })?;
+
+                let range = large.chunk_range(chunk);
+                if total_length as usize != HEADER_LENGTH + range.len() + CHECK_SUM_LEN {
+                    return Err(ErrorCode::CorruptData);
+                }
+                let region_data = self.read_buffer.take().unwrap();
+                let ret = Self::read_value(region_data, offset, total_length, &mut buf[range]);
+                self.read_buffer.replace(Some(region_data));
+                ret?;
+
+                if chunk + 1 < large.chunks() {
+                    Ok(Next::Step(ValueState::GetChunk(chunk + 1)))
+                } else {
+                    Ok(Next::Done(SuccessCode::Complete))
+                }
+            }
+            ValueState::CheckKey => match self.locate_key(op.hash, resumed, buffered) {
+                Ok(_) => Err(ErrorCode::KeyAlreadyExists),
+                Err(ErrorCode::KeyNotFound) => Ok(Next::Step(ValueState::ClearChunk(0))),
+                Err(e) => Err(e),
+            },
+            ValueState::ClearChunk(chunk) => {
+                let large = op.new.unwrap();
+                let next = Next::Step(ValueState::AppendChunk(chunk));
+                match self.locate_key(large.chunk_hash(op.hash, chunk), resumed, buffered) {
+                    Ok((region, offset, _)) => self.value_invalidate(region, offset, next),
+                    Err(ErrorCode::KeyNotFound) => Ok(next),
+                    Err(e) => Err(e),
+                }
+            }
+            ValueState::AppendChunk(chunk) => {
+                let large = op.new.unwrap();
+                let next = if chunk + 1 < large.chunks() {
+                    ValueState::ClearChunk(chunk + 1)
+                } else if op.op == ValueOp::Update {
+                    ValueState::AppendNew
+                } else {
+                    ValueState::AppendHead
+                };
+                self.value_append(
+                    large.chunk_hash(op.hash, chunk),
+                    &value[large.chunk_range(chunk)],
+                    FLAGS_CHUNK,
+                    None,
+                    resumed,
+                    buffered,
+                    Next::Step(next),
+                )
+            }
+            ValueState::AppendHead => self.value_append(
+                op.hash,
+                &op.new.unwrap().to_bytes(),
+                FLAGS_LARGE,
+                None,
+                resumed,
+                buffered,
+                Next::Done(SuccessCode::Written),
+            ),
+            ValueState::FindCurrent => {
+                let (region, offset, total_length) = self.locate_key(op.hash, resumed, buffered)?;
+                op.region = region;
+                op.offset = offset;
+
+                let region_data = self.read_buffer.take().unwrap();
+                let current = if region_data[offset + LEN_OFFSET] & (FLAGS_LARGE << 4) != 0 {
+                    Self::read_large_value(region_data, offset, total_length).map(Some)
+                } else {
+                    Ok(None)
+                };
+                self.read_buffer.replace(Some(region_data));
+                op.current = current?;
+
+                if let Some(new) = op.new.as_mut() {
+                    new.generation = op
+                        .current
+                        .map_or(0, |current| current.generation.wrapping_add(1));
+                }
+                self.value_op.set(Some(op));
+                Ok(Next::Step(ValueState::InvalidateStale))
+            }
+            ValueState::InvalidateStale => {
+                let region_data = self.value_read(op.region, buffered)?;
+                let stale = Self::find_stale_offset(op.hash, region_data, op.offset);
+                self.read_buffer.replace(Some(region_data));
+
+                match stale {
+                    Some(offset) => self.value_invalidate(
+                        op.region,
+                        offset,
+                        Next::Step(ValueState::InvalidateStale),
+                    ),
+                    None if op.op == ValueOp::Update && op.new.is_some() => {
+                        Ok(Next::Step(ValueState::ClearChunk(0)))
+                    }
+                    None if op.op == ValueOp::Update => Ok(Next::Step(ValueState::AppendNew)),
+                    None => Ok(Next::Step(ValueState::InvalidateCurrent)),
+                }
+            }
+            ValueState::AppendNew => {
+                let next = Next::Step(ValueState::InvalidateCurrent);
+                match op.new {
+                    Some(large) => self.value_append(
+                        op.hash,
+                        &large.to_bytes(),
+                        FLAGS_LARGE,
+                        Some(op.region),
+                        resumed,
+                        buffered,
+                        next,
+                    ),
+                    None => self.value_append(
+                        op.hash,
+                        value,
+                        0,
+                        Some(op.region),
+                        resumed,
+                        buffered,
+                        next,
+                    ),
+                }
+            }
+            ValueState::InvalidateCurrent => {
+                let region_data = self.value_read(op.region, buffered)?;
+                self.read_buffer.replace(Some(region_data));
+
+                let next = match op.current {
+                    Some(large) => Next::Step(ValueState::InvalidateChunk(large.chunks())),
+                    None => Next::Done(SuccessCode::Written),
+                };
+                self.value_invalidate(op.region, op.offset, next)
+            }
+            ValueState::InvalidateChunk(chunks) => {
+                let large = op.current.unwrap();
+                let next = if chunks > 1 {
+                    Next::Step(ValueState::InvalidateChunk(chunks - 1))
+                } else {
+                    Next::Done(SuccessCode::Written)
+                };
+                match self.locate_key(large.chunk_hash(op.hash, chunks - 1), resumed, buffered) {
+                    Ok((region, offset, _)) => self.value_invalidate(region, offset, next),
+                    Err(ErrorCode::KeyNotFound) => Ok(next),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Run the steps of an operation on a value, starting at `step`, until
+    /// it is complete.
+    fn run_value_operation(
+        &self,
+        mut step: ValueState,
+        mut resumed: Option<usize>,
+        value: &[u8],
+        buf: &mut [u8],
+    ) -> Result<SuccessCode, ErrorCode> {
+        let mut buffered = resumed;
+
+        loop {
+            match self.value_step(step, resumed, &mut buffered, value, buf) {
+                Ok(Next::Step(next)) => step = next,
+                Ok(Next::Done(code)) => {
+                    self.state.set(State::None);
+                    return Ok(code);
+                }
+                Err(e) => {
+                    match e {
+                        ErrorCode::ReadNotReady(region) => {
+                            self.state.set(State::Value(step, Some(region)))
+                        }
+                        // The step that follows the write was already saved
+                        ErrorCode::WriteNotReady(_) => {}
+                        _ => self.state.set(State::None),
+                    }
+                    return Err(e);
+                }
+            }
+            resumed = None;
+        }
+    }
+
+    /// Find the key of the next valid object, to walk through every key
+    /// that is stored.
+    ///
+    /// `address`: Where to start looking. This is 0 for the first key and
+    /// the returned address for the keys that follow.
+    ///
+    /// The chunks of values that are stored in chunks are skipped, as are
+    /// objects left behind by interrupted updates, so each key is only
+    /// returned once. The key of `MAIN_KEY` is returned as well.
+    ///
+    /// On success the hashed key and the address to continue from will be
+    /// returned.
+    /// On error a `ErrorCode` will be returned. `ErrorCode::KeyNotFound` is
+    /// returned when there are no more keys.
+    pub fn get_next_key(&self, address: usize) -> Result<(u64, usize), ErrorCode> {
+        let num_region = self.flash_size / S;
+        let (mut region, mut start) = match self.state.get() {
+            // Continue from the region that was being read
+            State::NextKey(KeyState::ReadRegion(reg)) if reg > address / S => (reg, 0),
+            _ => (address / S, address % S),
+        };
+
+        while region < num_region {
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::NextKey(KeyState::ReadRegion(region)) {
+                if let Err(e) = self.controller.read_region(region, 0, region_data) {
+                    self.read_buffer.replace(Some(region_data));
+                    if let ErrorCode::ReadNotReady(reg) = e {
+                        self.state.set(State::NextKey(KeyState::ReadRegion(reg)));
+                    }
+                    return Err(e);
+                }
+            }
+
+            let mut offset: usize = 0;
+            while offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] != 0xFF {
+                if region_data[offset + VERSION_OFFSET] != VERSION {
+                    self.read_buffer.replace(Some(region_data));
+                    self.state.set(State::None);
+                    return Err(ErrorCode::UnsupportedVersion);
+                }
+
+                let total_length = Self::object_length(region_data, offset);
+                if total_length == 0 {
+                    // We can't tell where the next object is
+                    break;
+                }
+
+                let flags = region_data[offset + LEN_OFFSET] >> 4;
+                if offset >= start && flags & FLAGS_VALID != 0 && flags & FLAGS_CHUNK == 0 {
+                    // Only the first valid object of a key is current
+                    let hash = Self::object_hash(region_data, offset);
+                    if let Ok((first, _)) = self.find_key_offset(hash, region_data) {
+                        if first == offset {
+                            self.read_buffer.replace(Some(region_data));
+                            self.state.set(State::None);
+                            return Ok((hash, S * region + offset + total_length));
+                        }
+                    }
+                }
+
+                offset += total_length;
+            }
+
+            self.read_buffer.replace(Some(region_data));
+            self.state.set(State::None);
+            region += 1;
+            start = 0;
+        }
+
+        Err(ErrorCode::KeyNotFound)
+    }
 }
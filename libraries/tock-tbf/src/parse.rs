@@ -126,6 +126,7 @@ pub fn parse_tbf_header(
                 // Places to save fields that we parse out of the header
                 // options.
                 let mut main_pointer: Option<types::TbfHeaderV2Main> = None;
+                let mut program_pointer: Option<types::TbfHeaderV2Program> = None;
                 let mut wfr_pointer: [Option<types::TbfHeaderV2WriteableFlashRegion>; 4] =
                     Default::default();
                 let mut app_name_str = "";
@@ -133,6 +134,9 @@ pub fn parse_tbf_header(
                 let mut permissions_pointer: Option<types::TbfHeaderV2Permissions<8>> = None;
                 let mut persistent_acls_pointer: Option<types::TbfHeaderV2PersistentAcl<8>> = None;
                 let mut kernel_version: Option<types::TbfHeaderV2KernelVersion> = None;
+                let mut app_id: Option<types::TbfHeaderV2AppId> = None;
+                let mut real_time: Option<types::TbfHeaderV2RealTime> = None;
+                let mut upcall_queue: Option<types::TbfHeaderV2UpcallQueue> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -160,6 +164,18 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderProgram => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2Program>();
+                            if tlv_header.length as usize == entry_len {
+                                let program: types::TbfHeaderV2Program = remaining.try_into()?;
+                                program_pointer = Some(program);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         types::TbfHeaderTypes::TbfHeaderWriteableFlashRegions => {
                             // Length must be a multiple of the size of a region definition.
                             if tlv_header.length as usize
@@ -241,6 +257,39 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderAppId => {
+                            let entry_len = 4;
+                            if tlv_header.length as usize == entry_len {
+                                app_id = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderRealTime => {
+                            let entry_len = 12;
+                            if tlv_header.length as usize == entry_len {
+                                real_time = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderUpcallQueue => {
+                            let entry_len = 4;
+                            if tlv_header.length as usize == entry_len {
+                                upcall_queue = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -255,12 +304,16 @@ pub fn parse_tbf_header(
                 let tbf_header = types::TbfHeaderV2 {
                     base: tbf_header_base,
                     main: main_pointer,
+                    program: program_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
                     permissions: permissions_pointer,
                     persistent_acls: persistent_acls_pointer,
                     kernel_version: kernel_version,
+                    app_id: app_id,
+                    real_time: real_time,
+                    upcall_queue: upcall_queue,
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -269,3 +322,162 @@ pub fn parse_tbf_header(
         _ => Err(types::TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+/// Parse one footer from the footer region of a TBF object.
+///
+/// `footers` must start at a footer TLV, for example the slice from
+/// `get_binary_end()` to the end of the TBF object. Footers are only valid
+/// for apps with a program header.
+///
+/// ## Return
+///
+/// If parsing is successful, returns the credentials and the number of bytes
+/// of `footers` the footer (including its TLV header) occupies, so the caller
+/// can advance to the next footer. Footers of unknown types are returned as
+/// `TbfFooterV2CredentialsType::Reserved` credentials so they can be skipped.
+pub fn parse_tbf_footer(
+    footers: &'static [u8],
+) -> Result<(types::TbfFooterV2Credentials, u32), types::TbfParseError> {
+    // Footers use the same TLV layout as header entries, but their type
+    // numbers are in a separate space.
+    let tlv = footers
+        .get(0..4)
+        .ok_or(types::TbfParseError::NotEnoughFlash)?;
+    let footer_type: types::TbfFooterTypes = u16::from_le_bytes([tlv[0], tlv[1]]).try_into()?;
+    let footer_len = u16::from_le_bytes([tlv[2], tlv[3]]) as usize;
+    let footer = footers
+        .get(4..(4 + footer_len))
+        .ok_or(types::TbfParseError::NotEnoughFlash)?;
+    let credentials = match footer_type {
+        types::TbfFooterTypes::TbfFooterCredentials => footer.try_into()?,
+        types::TbfFooterTypes::Unknown => types::TbfFooterV2Credentials {
+            format: types::TbfFooterV2CredentialsType::Reserved,
+            data: footer,
+        },
+    };
+    Ok((credentials, (align4!(footer_len) + 4) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::parse_tbf_footer;
+    use crate::types::{TbfFooterV2CredentialsType, TbfParseError};
+    use std::vec::Vec;
+
+    const CREDENTIALS: u16 = 128;
+    const SHA256: u32 = 3;
+
+    /// Footer bytes with a TLV header of type `tipe` and length `length`,
+    /// followed by `body`.
+    fn tlv(tipe: u16, length: u16, body: &[u8]) -> &'static [u8] {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&tipe.to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes.leak()
+    }
+
+    /// The body of a credentials footer with `format` and `data`.
+    fn credentials(format: u32, data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&format.to_le_bytes());
+        body.extend_from_slice(data);
+        body
+    }
+
+    #[test]
+    fn credentials_footer() {
+        let body = credentials(SHA256, &[0xAB; 32]);
+        let (footer, len) = parse_tbf_footer(tlv(CREDENTIALS, 36, &body)).unwrap();
+        assert_eq!(footer.format(), TbfFooterV2CredentialsType::SHA256);
+        assert_eq!(footer.data(), &[0xAB; 32]);
+        assert_eq!(len, 40);
+    }
+
+    #[test]
+    fn consecutive_footers() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(tlv(CREDENTIALS, 36, &credentials(SHA256, &[1; 32])));
+        bytes.extend_from_slice(tlv(0x1234, 0, &[]));
+        let footers: &'static [u8] = bytes.leak();
+
+        let (first, len) = parse_tbf_footer(footers).unwrap();
+        assert_eq!(first.format(), TbfFooterV2CredentialsType::SHA256);
+        let (second, second_len) = parse_tbf_footer(&footers[len as usize..]).unwrap();
+        assert_eq!(second.format(), TbfFooterV2CredentialsType::Reserved);
+        assert_eq!(len + second_len, footers.len() as u32);
+    }
+
+    #[test]
+    fn truncated() {
+        // Not even a whole TLV header.
+        assert!(matches!(
+            parse_tbf_footer(&[128, 0]),
+            Err(TbfParseError::NotEnoughFlash)
+        ));
+        // The TLV header claims more bytes than there are.
+        let body = credentials(SHA256, &[0; 16]);
+        assert!(matches!(
+            parse_tbf_footer(tlv(CREDENTIALS, 36, &body)),
+            Err(TbfParseError::NotEnoughFlash)
+        ));
+        // The footer is too short for its credentials format.
+        assert!(matches!(
+            parse_tbf_footer(tlv(CREDENTIALS, 20, &body)),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+    }
+
+    #[test]
+    fn oversized() {
+        // A length that runs past the end of the footers.
+        let body = credentials(SHA256, &[0; 32]);
+        assert!(matches!(
+            parse_tbf_footer(tlv(CREDENTIALS, 0xFFFF, &body)),
+            Err(TbfParseError::NotEnoughFlash)
+        ));
+        // A footer longer than its credentials: the credentials are the start
+        // of the footer and the rest is skipped.
+        let body = credentials(SHA256, &[7; 42]);
+        let (footer, len) = parse_tbf_footer(tlv(CREDENTIALS, 46, &body)).unwrap();
+        assert_eq!(footer.data(), &[7; 32]);
+        assert_eq!(len, 52);
+    }
+
+    #[test]
+    fn zero_length() {
+        // Credentials footers need at least a format.
+        assert!(matches!(
+            parse_tbf_footer(tlv(CREDENTIALS, 0, &[])),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+        // Footers of unknown types can be empty, and are skipped as reserved
+        // space.
+        let (footer, len) = parse_tbf_footer(tlv(0x1234, 0, &[])).unwrap();
+        assert_eq!(footer.format(), TbfFooterV2CredentialsType::Reserved);
+        assert!(footer.data().is_empty());
+        assert_eq!(len, 4);
+    }
+
+    #[test]
+    fn reserved_and_unknown() {
+        let body = credentials(0, &[0; 8]);
+        let (footer, len) = parse_tbf_footer(tlv(CREDENTIALS, 12, &body)).unwrap();
+        assert_eq!(footer.format(), TbfFooterV2CredentialsType::Reserved);
+        assert_eq!(footer.data().len(), 8);
+        assert_eq!(len, 16);
+
+        // Unknown credentials formats are an error.
+        let body = credentials(99, &[0; 8]);
+        assert!(matches!(
+            parse_tbf_footer(tlv(CREDENTIALS, 12, &body)),
+            Err(TbfParseError::BadTlvEntry(128))
+        ));
+
+        // Lengths that are not a multiple of 4 are padded.
+        let (_, len) = parse_tbf_footer(tlv(0x1234, 5, &[0; 8])).unwrap();
+        assert_eq!(len, 12);
+    }
+}
@@ -3,6 +3,7 @@
 use core::convert::TryInto;
 use core::fmt;
 use core::mem::size_of;
+use core::num::NonZeroU32;
 
 /// Error when parsing just the beginning of the TBF header. This is only used
 /// when establishing the linked list structure of apps installed in flash.
@@ -122,6 +123,10 @@ pub enum TbfHeaderTypes {
     TbfHeaderPermissions = 6,
     TbfHeaderPersistentAcl = 7,
     TbfHeaderKernelVersion = 8,
+    TbfHeaderProgram = 9,
+    TbfHeaderAppId = 10,
+    TbfHeaderRealTime = 11,
+    TbfHeaderUpcallQueue = 12,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -147,6 +152,22 @@ pub struct TbfHeaderV2Main {
     minimum_ram_size: u32,
 }
 
+/// The v2 program section for apps.
+///
+/// This is a superset of the main section. In addition to the main section
+/// fields it specifies where the application binary ends, so that footers
+/// (for example credentials) can be placed between the end of the binary and
+/// the end of the TBF object. If an app has both a program and a main section,
+/// the program section takes precedence.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Program {
+    init_fn_offset: u32,
+    protected_size: u32,
+    minimum_ram_size: u32,
+    binary_end_offset: u32,
+    version: u32,
+}
+
 /// Writeable flash regions only need an offset and size.
 ///
 /// There can be multiple (or zero) flash regions defined, so this is its own
@@ -211,6 +232,117 @@ pub struct TbfHeaderV2KernelVersion {
     minor: u16,
 }
 
+/// A persistent identifier for the application, chosen when the app is built.
+///
+/// A value of 0 is not a valid identifier and is treated as if the header
+/// were absent.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2AppId {
+    app_id: u32,
+}
+
+/// How many upcalls the kernel queues for the application before it drops
+/// them.
+///
+/// A depth of 0 is treated as if the header were absent.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2UpcallQueue {
+    depth: u32,
+}
+
+/// Timing requirements of a periodic real-time application.
+///
+/// The application is released once every `period_us` microseconds and must
+/// finish the work of each release within `deadline_us` of the release. A
+/// deadline of 0 means the deadline is the end of the period. If `budget_us`
+/// is not 0, the application may use at most that much CPU time per period.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2RealTime {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+impl TbfHeaderV2RealTime {
+    /// The period of the application in microseconds.
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// The relative deadline of the application in microseconds. This is
+    /// never longer than the period.
+    pub fn deadline_us(&self) -> u32 {
+        if self.deadline_us == 0 || self.deadline_us > self.period_us {
+            self.period_us
+        } else {
+            self.deadline_us
+        }
+    }
+
+    /// The CPU time budget of the application per period in microseconds, if
+    /// it has one.
+    pub fn budget_us(&self) -> Option<u32> {
+        if self.budget_us == 0 {
+            None
+        } else {
+            Some(self.budget_us)
+        }
+    }
+}
+
+/// Types of footers that can follow the application binary.
+#[derive(Clone, Copy, Debug)]
+pub enum TbfFooterTypes {
+    TbfFooterCredentials = 128,
+    Unknown,
+}
+
+/// The format of a credentials footer.
+///
+/// The format determines the length and the meaning of the credentials data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfFooterV2CredentialsType {
+    /// Space reserved for credentials that have not been written yet.
+    Reserved = 0,
+    /// RSA-3072 public key (384 bytes) followed by a signature (384 bytes)
+    /// over the SHA-256 hash of the integrity region.
+    Rsa3072Key = 1,
+    /// RSA-4096 public key (512 bytes) followed by a signature (512 bytes)
+    /// over the SHA-256 hash of the integrity region.
+    Rsa4096Key = 2,
+    /// SHA-256 hash (32 bytes) of the integrity region.
+    SHA256 = 3,
+    /// SHA-384 hash (48 bytes) of the integrity region.
+    SHA384 = 4,
+    /// SHA-512 hash (64 bytes) of the integrity region.
+    SHA512 = 5,
+    /// ECDSA signature over the NIST P-256 curve (64 bytes, `r` followed by
+    /// `s`) of the SHA-256 hash of the integrity region.
+    EcdsaNistP256 = 6,
+}
+
+/// A credentials footer.
+///
+/// `data` is the credential itself. Its length depends on `format`, except
+/// for `Reserved` credentials which may have any length.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfFooterV2Credentials {
+    pub(crate) format: TbfFooterV2CredentialsType,
+    pub(crate) data: &'static [u8],
+}
+
+impl TbfFooterV2Credentials {
+    /// The format of these credentials.
+    pub fn format(&self) -> TbfFooterV2CredentialsType {
+        self.format
+    }
+
+    /// The credentials data, excluding the format field.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -259,6 +391,10 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
             7 => Ok(TbfHeaderTypes::TbfHeaderPersistentAcl),
             8 => Ok(TbfHeaderTypes::TbfHeaderKernelVersion),
+            9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
+            10 => Ok(TbfHeaderTypes::TbfHeaderAppId),
+            11 => Ok(TbfHeaderTypes::TbfHeaderRealTime),
+            12 => Ok(TbfHeaderTypes::TbfHeaderUpcallQueue),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -308,6 +444,150 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Main {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Program {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Program, Self::Error> {
+        Ok(TbfHeaderV2Program {
+            init_fn_offset: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            protected_size: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            minimum_ram_size: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            binary_end_offset: u32::from_le_bytes(
+                b.get(12..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            version: u32::from_le_bytes(
+                b.get(16..20)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2AppId {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2AppId, Self::Error> {
+        Ok(TbfHeaderV2AppId {
+            app_id: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2UpcallQueue {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2UpcallQueue, Self::Error> {
+        Ok(TbfHeaderV2UpcallQueue {
+            depth: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2RealTime {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2RealTime, Self::Error> {
+        Ok(TbfHeaderV2RealTime {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<u16> for TbfFooterTypes {
+    type Error = TbfParseError;
+
+    fn try_from(h: u16) -> Result<TbfFooterTypes, Self::Error> {
+        match h {
+            128 => Ok(TbfFooterTypes::TbfFooterCredentials),
+            _ => Ok(TbfFooterTypes::Unknown),
+        }
+    }
+}
+
+impl core::convert::TryFrom<u32> for TbfFooterV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(h: u32) -> Result<TbfFooterV2CredentialsType, Self::Error> {
+        match h {
+            0 => Ok(TbfFooterV2CredentialsType::Reserved),
+            1 => Ok(TbfFooterV2CredentialsType::Rsa3072Key),
+            2 => Ok(TbfFooterV2CredentialsType::Rsa4096Key),
+            3 => Ok(TbfFooterV2CredentialsType::SHA256),
+            4 => Ok(TbfFooterV2CredentialsType::SHA384),
+            5 => Ok(TbfFooterV2CredentialsType::SHA512),
+            6 => Ok(TbfFooterV2CredentialsType::EcdsaNistP256),
+            _ => Err(TbfParseError::BadTlvEntry(
+                TbfFooterTypes::TbfFooterCredentials as usize,
+            )),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfFooterV2Credentials, Self::Error> {
+        let format: TbfFooterV2CredentialsType = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::BadTlvEntry(
+                    TbfFooterTypes::TbfFooterCredentials as usize,
+                ))?
+                .try_into()?,
+        )
+        .try_into()?;
+        let length = match format {
+            TbfFooterV2CredentialsType::Reserved => b.len() - 4,
+            TbfFooterV2CredentialsType::Rsa3072Key => 768,
+            TbfFooterV2CredentialsType::Rsa4096Key => 1024,
+            TbfFooterV2CredentialsType::SHA256 => 32,
+            TbfFooterV2CredentialsType::SHA384 => 48,
+            TbfFooterV2CredentialsType::SHA512 => 64,
+            TbfFooterV2CredentialsType::EcdsaNistP256 => 64,
+        };
+        let data = b.get(4..(length + 4)).ok_or(TbfParseError::BadTlvEntry(
+            TbfFooterTypes::TbfFooterCredentials as usize,
+        ))?;
+        Ok(TbfFooterV2Credentials { format, data })
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2WriteableFlashRegion {
     type Error = TbfParseError;
 
@@ -529,12 +809,16 @@ pub enum CommandPermissions {
 pub struct TbfHeaderV2 {
     pub(crate) base: TbfHeaderV2Base,
     pub(crate) main: Option<TbfHeaderV2Main>,
+    pub(crate) program: Option<TbfHeaderV2Program>,
     pub(crate) package_name: Option<&'static str>,
     pub(crate) writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     pub(crate) fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
     pub(crate) permissions: Option<TbfHeaderV2Permissions<8>>,
     pub(crate) persistent_acls: Option<TbfHeaderV2PersistentAcl<8>>,
     pub(crate) kernel_version: Option<TbfHeaderV2KernelVersion>,
+    pub(crate) app_id: Option<TbfHeaderV2AppId>,
+    pub(crate) real_time: Option<TbfHeaderV2RealTime>,
+    pub(crate) upcall_queue: Option<TbfHeaderV2UpcallQueue>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -570,12 +854,30 @@ impl TbfHeader {
         }
     }
 
+    /// Return whether the kernel may grow the memory of the application
+    /// beyond the size it was loaded with when it runs out of memory.
+    pub fn memory_growable(&self) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => {
+                // Bit 2 of flags is the growable memory bit.
+                hd.base.flags & 0x00000004 != 0
+            }
+            TbfHeader::Padding(_) => false,
+        }
+    }
+
     /// Add up all of the relevant fields in header version 1, or just used the
     /// app provided value in version 2 to get the total amount of RAM that is
     /// needed for this app.
     pub fn get_minimum_app_ram_size(&self) -> u32 {
         match *self {
-            TbfHeader::TbfHeaderV2(hd) => hd.main.map_or(0, |m| m.minimum_ram_size),
+            TbfHeader::TbfHeaderV2(hd) => {
+                if let Some(p) = hd.program {
+                    p.minimum_ram_size
+                } else {
+                    hd.main.map_or(0, |m| m.minimum_ram_size)
+                }
+            }
             _ => 0,
         }
     }
@@ -585,7 +887,11 @@ impl TbfHeader {
     pub fn get_protected_size(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                if let Some(p) = hd.program {
+                    p.protected_size + (hd.base.header_size as u32)
+                } else {
+                    hd.main.map_or(0, |m| m.protected_size) + (hd.base.header_size as u32)
+                }
             }
             _ => 0,
         }
@@ -596,12 +902,38 @@ impl TbfHeader {
     pub fn get_init_function_offset(&self) -> u32 {
         match *self {
             TbfHeader::TbfHeaderV2(hd) => {
-                hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                if let Some(p) = hd.program {
+                    p.init_fn_offset + (hd.base.header_size as u32)
+                } else {
+                    hd.main.map_or(0, |m| m.init_fn_offset) + (hd.base.header_size as u32)
+                }
             }
             _ => 0,
         }
     }
 
+    /// Get the offset from the beginning of the app's flash region where the
+    /// application binary ends and footers begin. If the app does not have a
+    /// program header there are no footers, so the binary extends to the end
+    /// of the TBF object.
+    pub fn get_binary_end(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .program
+                .map_or(hd.base.total_size, |p| p.binary_end_offset),
+            TbfHeader::Padding(base) => base.total_size,
+        }
+    }
+
+    /// Get the version of the application binary, as specified in the program
+    /// header. Returns 0 if the app does not have a program header.
+    pub fn get_binary_version(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.program.map_or(0, |p| p.version),
+            _ => 0,
+        }
+    }
+
     /// Get the name of the app.
     pub fn get_package_name(&self) -> Option<&'static str> {
         match *self {
@@ -702,6 +1034,70 @@ impl TbfHeader {
         }
     }
 
+    /// Get the persistent application identifier from the `AppId` header.
+    /// Returns `None` if the header is not included or the identifier is 0.
+    pub fn get_fixed_app_id(&self) -> Option<NonZeroU32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.app_id.and_then(|id| NonZeroU32::new(id.app_id)),
+            _ => None,
+        }
+    }
+
+    /// Get the timing requirements of the app from the `RealTime` header.
+    /// Returns `None` if the header is not included or the period is 0.
+    pub fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.real_time.filter(|rt| rt.period_us != 0),
+            _ => None,
+        }
+    }
+
+    /// Get how many upcalls the kernel should queue for the app from the
+    /// `UpcallQueue` header. Returns `None` if the header is not included or
+    /// the depth is 0.
+    pub fn get_upcall_queue_depth(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .upcall_queue
+                .map(|queue| queue.depth)
+                .filter(|depth| *depth != 0),
+            _ => None,
+        }
+    }
+
+    /// Get the storage identifier this app writes new persistent data with.
+    /// Returns `None` if the header does not include a persistent ACL.
+    pub fn get_storage_write_id(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.persistent_acls.map(|acl| acl.write_id),
+            _ => None,
+        }
+    }
+
+    /// Get the storage identifiers of persistent data this app may read, as
+    /// the number of valid entries and the array holding them. Returns `None`
+    /// if the header does not include a persistent ACL.
+    pub fn get_storage_read_ids(&self) -> Option<(usize, [u32; 8])> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .persistent_acls
+                .map(|acl| (acl.read_length as usize, acl.read_ids)),
+            _ => None,
+        }
+    }
+
+    /// Get the storage identifiers of persistent data this app may modify, as
+    /// the number of valid entries and the array holding them. Returns `None`
+    /// if the header does not include a persistent ACL.
+    pub fn get_storage_modify_ids(&self) -> Option<(usize, [u32; 8])> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .persistent_acls
+                .map(|acl| (acl.access_length as usize, acl.access_ids)),
+            _ => None,
+        }
+    }
+
     /// Get the minimum compatible kernel version this process requires.
     /// Returns `None` if the kernel compatibility header is not included.
     pub fn get_kernel_version(&self) -> Option<(u16, u16)> {
@@ -0,0 +1,205 @@
+//! Decode binary kernel trace records.
+//!
+//! Reads the records written by `kernel::trace::RingBufferTracer`, either as
+//! the raw binary stream sent by `capsules::kernel_trace::TraceTransmit`, or
+//! as the `TRACE <hex>` lines printed by `capsules::kernel_trace::TraceDebug`
+//! mixed in with other console output, and prints one line per event with
+//! the time since the previous event.
+
+use std::fs;
+use std::io::{self, Read};
+
+/// Length of one binary trace record in bytes.
+const TRACE_RECORD_LEN: usize = 16;
+
+/// Process id field of records that are not about a process.
+const NO_PROCESS: u16 = 0xFFFF;
+
+/// Prints an error message and usage string.
+fn usage_error(message: &str) {
+    eprintln!(
+        "{}
+
+Usage: trace_decode [--text] [--frequency HZ] [FILE]
+Decode kernel trace records from FILE, or from stdin if FILE is omitted.
+
+  --text          The input is console output with `TRACE <hex>` lines,
+                  instead of the raw binary records.
+  --frequency HZ  Timestamp frequency to use until a `Start` record is seen.
+                  Without it, timestamps are printed in ticks.",
+        message
+    );
+}
+
+struct Options {
+    text: bool,
+    frequency: Option<u32>,
+    path: Option<String>,
+}
+
+fn parse_args() -> Result<Options, ()> {
+    let mut options = Options {
+        text: false,
+        frequency: None,
+        path: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--text" => options.text = true,
+            "--frequency" => match args.next().and_then(|hz| hz.parse().ok()) {
+                Some(hz) if hz > 0 => options.frequency = Some(hz),
+                _ => {
+                    usage_error("--frequency needs a frequency in Hz");
+                    return Err(());
+                }
+            },
+            "-h" | "--help" => {
+                usage_error("Decode kernel trace records.");
+                return Err(());
+            }
+            _ if options.path.is_none() => options.path = Some(arg),
+            _ => {
+                usage_error("Too many arguments");
+                return Err(());
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Extract the records from `TRACE <hex>` lines, ignoring all other lines.
+fn records_from_text(input: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in String::from_utf8_lossy(input).lines() {
+        let hex = match line.find("TRACE ") {
+            Some(start) => line[start + "TRACE ".len()..].trim(),
+            None => continue,
+        };
+        if hex.len() != 2 * TRACE_RECORD_LEN {
+            eprintln!("Skipping malformed line: {}", line);
+            continue;
+        }
+        let record: Result<Vec<u8>, _> = (0..TRACE_RECORD_LEN)
+            .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16))
+            .collect();
+        match record {
+            Ok(record) => bytes.extend(record),
+            Err(_) => eprintln!("Skipping malformed line: {}", line),
+        }
+    }
+    bytes
+}
+
+fn syscall_class_name(class: u8) -> &'static str {
+    match class {
+        0 => "yield",
+        1 => "subscribe",
+        2 => "command",
+        3 => "allow-rw",
+        4 => "allow-ro",
+        5 => "memop",
+        6 => "exit",
+        7 => "allow-userspace-readable",
+        _ => "unknown-syscall",
+    }
+}
+
+/// Describe the event of one record.
+fn describe(record_type: u8, class: u8, arg0: u32, arg1: u32) -> String {
+    match record_type {
+        0 => format!("start, timestamp frequency {} Hz", arg0),
+        1 => format!("dropped {} events", arg0),
+        2 => "run".to_string(),
+        3 => match class {
+            0 | 5 | 6 => format!("{} {} {:#x}", syscall_class_name(class), arg0, arg1),
+            _ => format!(
+                "{} driver {:#x} number {}",
+                syscall_class_name(class),
+                arg0,
+                arg1
+            ),
+        },
+        4 => "interrupted".to_string(),
+        5 => "faulted".to_string(),
+        6 => "timeslice expired".to_string(),
+        7 if arg0 == 0xFFFFFFFF => "function call from kernel".to_string(),
+        7 => format!("upcall driver {:#x} number {}", arg0, arg1),
+        8 if arg0 & 0x8000_0000 != 0 => format!("local interrupt {}", arg0 & !0x8000_0000),
+        8 => format!("interrupt {}", arg0),
+        9 => format!("deferred call {}", arg0),
+        10 => "kernel work done".to_string(),
+        _ => format!("unknown record type {}", record_type),
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(()) => std::process::exit(1),
+    };
+
+    let mut input = Vec::new();
+    let read = match &options.path {
+        Some(path) => fs::File::open(path).and_then(|mut file| file.read_to_end(&mut input)),
+        None => io::stdin().read_to_end(&mut input),
+    };
+    if let Err(e) = read {
+        eprintln!("Unable to read input: {}", e);
+        std::process::exit(1);
+    }
+    let bytes = if options.text {
+        records_from_text(&input)
+    } else {
+        input
+    };
+
+    let mut frequency = options.frequency;
+    let mut previous: Option<u32> = None;
+    // Time of the previous event in ticks since the first one, so the
+    // 32-bit timestamps can wrap around.
+    let mut elapsed: u64 = 0;
+    for record in bytes.chunks_exact(TRACE_RECORD_LEN) {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([record[i], record[i + 1], record[i + 2], record[i + 3]]);
+        let record_type = record[0];
+        let class = record[1];
+        let process = u16::from_le_bytes([record[2], record[3]]);
+        let timestamp = u32_at(4);
+        let arg0 = u32_at(8);
+        let arg1 = u32_at(12);
+
+        if record_type == 0 && arg0 > 0 {
+            frequency = Some(arg0);
+        }
+        let delta = previous.map_or(0, |previous| timestamp.wrapping_sub(previous));
+        elapsed += delta as u64;
+        previous = Some(timestamp);
+
+        let time = match frequency {
+            Some(hz) => format!(
+                "{:>14.3} us (+{:>10.3})",
+                elapsed as f64 * 1e6 / hz as f64,
+                delta as f64 * 1e6 / hz as f64
+            ),
+            None => format!("{:>12} ticks (+{:>8})", elapsed, delta),
+        };
+        let process = if process == NO_PROCESS {
+            "kernel".to_string()
+        } else {
+            format!("pid {}", process)
+        };
+        println!(
+            "{}  {:<8} {}",
+            time,
+            process,
+            describe(record_type, class, arg0, arg1)
+        );
+    }
+    if bytes.len() % TRACE_RECORD_LEN != 0 {
+        eprintln!(
+            "Ignoring {} trailing bytes of an incomplete record",
+            bytes.len() % TRACE_RECORD_LEN
+        );
+    }
+}